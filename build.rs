@@ -1,4 +1,5 @@
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    prost_build::compile_protos(&["siscom.proto"], &["."])?;
+    // Messages plus the DeviceStateService server and client
+    tonic_build::configure().compile_protos(&["siscom.proto"], &["."])?;
     Ok(())
 }
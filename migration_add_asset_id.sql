@@ -0,0 +1,14 @@
+-- Migration to report trips by vehicle/asset instead of hardware id.
+-- device_assets maps each device to the asset it is installed in; a trip
+-- stores the asset at its start, or its device_id when the device is not
+-- mapped. Swapping a device between vehicles only needs an UPDATE here.
+
+CREATE TABLE IF NOT EXISTS device_assets (
+    device_id varchar NOT NULL,
+    asset_id varchar NOT NULL,
+    updated_at timestamptz DEFAULT now() NOT NULL,
+    CONSTRAINT device_assets_pkey PRIMARY KEY (device_id)
+);
+
+ALTER TABLE trips ADD COLUMN IF NOT EXISTS asset_id varchar NULL;
+CREATE INDEX IF NOT EXISTS idx_trips_asset_start ON trips USING btree (asset_id, start_time);
@@ -0,0 +1,6 @@
+-- Migration to accumulate the haversine route of the open trip over every
+-- received point, including the ones not stored (POINT_SAMPLE_EVERY_N)
+
+ALTER TABLE trip_current_state ADD COLUMN IF NOT EXISTS current_trip_route_meters float8 DEFAULT 0 NOT NULL;
+ALTER TABLE trip_current_state ADD COLUMN IF NOT EXISTS current_trip_route_lat float8 NULL;
+ALTER TABLE trip_current_state ADD COLUMN IF NOT EXISTS current_trip_route_lng float8 NULL;
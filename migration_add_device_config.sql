@@ -0,0 +1,12 @@
+-- Migration to add per-device overrides of the global thresholds
+-- (DEVICE_CONFIG_OVERRIDES); NULL columns use the global value
+
+CREATE TABLE IF NOT EXISTS device_config (
+    device_id varchar NOT NULL,
+    min_trip_distance_meters float8 NULL,
+    state_update_min_interval_seconds int4 NULL,
+    idle_stop_threshold_seconds float8 NULL,
+    point_sample_every_n int4 NULL,
+    updated_at timestamptz DEFAULT now() NOT NULL,
+    CONSTRAINT device_config_pkey PRIMARY KEY (device_id)
+);
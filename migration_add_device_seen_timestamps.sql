@@ -0,0 +1,7 @@
+-- Migration to track when each device first and most recently reported
+
+ALTER TABLE devices ADD COLUMN IF NOT EXISTS first_seen_at timestamptz NULL;
+ALTER TABLE devices ADD COLUMN IF NOT EXISTS last_seen_at timestamptz NULL;
+
+UPDATE devices SET first_seen_at = registered_at, last_seen_at = registered_at
+WHERE first_seen_at IS NULL;
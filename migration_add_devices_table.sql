@@ -0,0 +1,7 @@
+-- Migration to add the devices registry used by DEVICE_FILTER_MODE=auto_register
+
+CREATE TABLE IF NOT EXISTS devices (
+    device_id varchar NOT NULL,
+    registered_at timestamptz DEFAULT now() NOT NULL,
+    CONSTRAINT devices_pkey PRIMARY KEY (device_id)
+);
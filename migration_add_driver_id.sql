@@ -0,0 +1,6 @@
+-- Migration to split trips on driver change (SPLIT_TRIP_ON_DRIVER_CHANGE).
+-- trips.driver_id is the DRIVER_ID/IBUTTON the trip was driven with;
+-- trip_current_state.current_driver_id is the driver of the open trip.
+
+ALTER TABLE trips ADD COLUMN IF NOT EXISTS driver_id varchar NULL;
+ALTER TABLE trip_current_state ADD COLUMN IF NOT EXISTS current_driver_id varchar NULL;
@@ -0,0 +1,6 @@
+-- Migration to keep the reported speed and heading on idle activity, so a
+-- parked vehicle that is moving (towed or stolen) can be spotted: non-zero
+-- speed while the ignition is off.
+
+ALTER TABLE device_idle_activity ADD COLUMN IF NOT EXISTS speed float8 NULL;
+ALTER TABLE device_idle_activity ADD COLUMN IF NOT EXISTS heading float8 NULL;
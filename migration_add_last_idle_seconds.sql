@@ -0,0 +1,3 @@
+-- Migration to remember the last IDLE_TIME per device so idle_stop alerts fire once per stop
+
+ALTER TABLE trip_current_state ADD COLUMN IF NOT EXISTS last_idle_seconds float8 NULL;
@@ -0,0 +1,3 @@
+-- Migration to track the last MSG_COUNTER per device for duplicate point detection
+
+ALTER TABLE trip_current_state ADD COLUMN IF NOT EXISTS last_msg_counter int8 NULL;
@@ -0,0 +1,6 @@
+-- Migration to record where a stored position came from: 'gps', or 'cell'
+-- when it was approximated from MCC/MNC/LAC/CID by a cell locator.
+-- Existing rows keep location_source NULL (GPS).
+
+ALTER TABLE trip_points ADD COLUMN IF NOT EXISTS location_source varchar NULL;
+ALTER TABLE device_idle_activity ADD COLUMN IF NOT EXISTS location_source varchar NULL;
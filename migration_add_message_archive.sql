@@ -0,0 +1,12 @@
+-- Migration to add the raw payload archive used by RAW_PAYLOAD_RETENTION=true
+
+CREATE TABLE IF NOT EXISTS message_archive (
+    message_uuid uuid NOT NULL,
+    device_id varchar NOT NULL,
+    "timestamp" timestamptz NOT NULL,
+    raw text NULL,
+    payload bytea NOT NULL,
+    archived_at timestamptz DEFAULT now() NOT NULL,
+    CONSTRAINT message_archive_pkey PRIMARY KEY (message_uuid, device_id)
+);
+CREATE INDEX IF NOT EXISTS idx_message_archive_device_time ON message_archive USING btree (device_id, "timestamp" DESC);
@@ -0,0 +1,3 @@
+-- Migration to keep the vendor alert text next to the canonical alert_type
+
+ALTER TABLE trip_alerts ADD COLUMN IF NOT EXISTS raw_alert_type varchar NULL;
@@ -0,0 +1,4 @@
+-- Migration to flag open trips whose device stopped reporting (STALE_TRIP_SECONDS)
+-- until the device resumes or the stale-trip scanner closes them
+
+ALTER TABLE trip_current_state ADD COLUMN IF NOT EXISTS stale_since timestamptz NULL;
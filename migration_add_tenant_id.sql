@@ -0,0 +1,10 @@
+-- Migration to tag trips, points, alerts and idle activity with the tenant
+-- (account) that owns the device, resolved from TENANT/ACCOUNT_ID, TENANT_MAP
+-- or TENANT_DEFAULT. Existing rows keep tenant_id NULL.
+
+ALTER TABLE trips ADD COLUMN IF NOT EXISTS tenant_id varchar NULL;
+ALTER TABLE trip_points ADD COLUMN IF NOT EXISTS tenant_id varchar NULL;
+ALTER TABLE trip_alerts ADD COLUMN IF NOT EXISTS tenant_id varchar NULL;
+ALTER TABLE device_idle_activity ADD COLUMN IF NOT EXISTS tenant_id varchar NULL;
+
+CREATE INDEX IF NOT EXISTS idx_trips_tenant_start ON trips USING btree (tenant_id, start_time);
@@ -0,0 +1,19 @@
+-- Migration to make alert inserts idempotent (IDEMPOTENT_ALERTS=true).
+-- A redelivered alert has the same trip, timestamp, alert_type and
+-- raw_alert_type as the stored one and is skipped with ON CONFLICT DO
+-- NOTHING. raw_alert_type keeps apart alerts of the same type at the same
+-- instant, e.g. entering two geofences (it holds the fence name); NULLS NOT
+-- DISTINCT (PostgreSQL 15+) makes alerts without it still deduplicate.
+-- Run it only together with IDEMPOTENT_ALERTS: without the flag a duplicate
+-- fails the whole message instead of being skipped. Remove existing
+-- duplicates first:
+--
+--   DELETE FROM trip_alerts a USING trip_alerts b
+--   WHERE a.trip_id = b.trip_id AND a."timestamp" = b."timestamp"
+--     AND a.alert_type = b.alert_type
+--     AND a.raw_alert_type IS NOT DISTINCT FROM b.raw_alert_type
+--     AND a.created_at > b.created_at;
+
+DROP INDEX IF EXISTS idx_trip_alerts_natural_key;
+CREATE UNIQUE INDEX idx_trip_alerts_natural_key
+    ON trip_alerts USING btree (trip_id, "timestamp", alert_type, raw_alert_type) NULLS NOT DISTINCT;
@@ -0,0 +1,9 @@
+-- Migration to store the net bearing (start -> end, degrees from north) and
+-- the bounding box of the start, end and stored points of each closed trip.
+-- bearing_degrees stays NULL when the trip starts and ends at the same spot.
+
+ALTER TABLE trips ADD COLUMN IF NOT EXISTS bearing_degrees float8 NULL;
+ALTER TABLE trips ADD COLUMN IF NOT EXISTS bbox_min_lat float8 NULL;
+ALTER TABLE trips ADD COLUMN IF NOT EXISTS bbox_min_lng float8 NULL;
+ALTER TABLE trips ADD COLUMN IF NOT EXISTS bbox_max_lat float8 NULL;
+ALTER TABLE trips ADD COLUMN IF NOT EXISTS bbox_max_lng float8 NULL;
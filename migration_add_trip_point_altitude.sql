@@ -0,0 +1,3 @@
+-- Migration to keep the reported altitude on each trip point
+
+ALTER TABLE trip_points ADD COLUMN IF NOT EXISTS altitude float8 NULL;
@@ -0,0 +1,5 @@
+-- Migration to track the point count of the open trip in the current state
+-- and keep it on each closed trip
+
+ALTER TABLE trip_current_state ADD COLUMN IF NOT EXISTS current_trip_point_count int4 DEFAULT 0 NOT NULL;
+ALTER TABLE trips ADD COLUMN IF NOT EXISTS point_count int4 NULL;
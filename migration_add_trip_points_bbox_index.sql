@@ -0,0 +1,3 @@
+-- Migration to support viewport (bounding box) queries on trip points
+
+CREATE INDEX IF NOT EXISTS idx_trip_points_time_lat_lng ON trip_points USING btree ("timestamp", lat, lng);
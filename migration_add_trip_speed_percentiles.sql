@@ -0,0 +1,5 @@
+-- Migration to keep point speed percentiles on each closed trip
+
+ALTER TABLE trips ADD COLUMN IF NOT EXISTS speed_p50 float8 NULL;
+ALTER TABLE trips ADD COLUMN IF NOT EXISTS speed_p85 float8 NULL;
+ALTER TABLE trips ADD COLUMN IF NOT EXISTS speed_p95 float8 NULL;
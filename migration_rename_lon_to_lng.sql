@@ -0,0 +1,16 @@
+-- Migration to name longitude columns `lng` everywhere, as in trips and
+-- trip_points. Readers of trip_alerts.lon or device_idle_activity.lon (reports,
+-- dashboards) must switch to `lng`; apply together with the release whose
+-- INSERTs write `lng`. Safe to run twice.
+
+DO $$
+BEGIN
+    IF EXISTS (SELECT 1 FROM information_schema.columns
+               WHERE table_name = 'trip_alerts' AND column_name = 'lon') THEN
+        ALTER TABLE trip_alerts RENAME COLUMN lon TO lng;
+    END IF;
+    IF EXISTS (SELECT 1 FROM information_schema.columns
+               WHERE table_name = 'device_idle_activity' AND column_name = 'lon') THEN
+        ALTER TABLE device_idle_activity RENAME COLUMN lon TO lng;
+    END IF;
+END $$;
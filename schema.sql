@@ -1,44 +1,64 @@
--- public.trip definition
-CREATE TABLE IF NOT EXISTS trip (
+-- trips definition
+CREATE TABLE IF NOT EXISTS trips (
     trip_id uuid NOT NULL,
-    device_id uuid NOT NULL,
+    device_id varchar NOT NULL,
     start_time timestamptz NOT NULL,
     end_time timestamptz NULL,
     start_lat float8 NULL,
     start_lng float8 NULL,
     end_lat float8 NULL,
     end_lng float8 NULL,
-    distance_m float8 DEFAULT 0 NULL,
+    distance_meters float8 DEFAULT 0 NULL,
+    start_odometer_meters int4 NULL,
+    end_odometer_meters int4 NULL,
+    speed_p50 float8 NULL,
+    speed_p85 float8 NULL,
+    speed_p95 float8 NULL,
+    point_count int4 NULL,
+    tenant_id varchar NULL,
+    bearing_degrees float8 NULL,
+    bbox_min_lat float8 NULL,
+    bbox_min_lng float8 NULL,
+    bbox_max_lat float8 NULL,
+    bbox_max_lng float8 NULL,
+    asset_id varchar NULL,
+    driver_id varchar NULL,
     created_at timestamptz DEFAULT now() NOT NULL,
-    CONSTRAINT trip_pkey PRIMARY KEY (trip_id)
+    CONSTRAINT trips_pkey PRIMARY KEY (trip_id)
 );
-CREATE INDEX IF NOT EXISTS idx_trip_device ON public.trip USING btree (device_id);
-CREATE INDEX IF NOT EXISTS idx_trip_start ON public.trip USING btree (start_time);
-
--- public.trip_alerts definition
-
+CREATE INDEX IF NOT EXISTS idx_trips_device ON trips USING btree (device_id);
+CREATE INDEX IF NOT EXISTS idx_trips_start ON trips USING btree (start_time);
+CREATE INDEX IF NOT EXISTS idx_trips_tenant_start ON trips USING btree (tenant_id, start_time);
+CREATE INDEX IF NOT EXISTS idx_trips_asset_start ON trips USING btree (asset_id, start_time);
 
+-- trip_alerts definition
 CREATE TABLE IF NOT EXISTS trip_alerts (
     alert_id uuid NOT NULL,
     trip_id uuid NOT NULL,
     "timestamp" timestamptz NOT NULL,
     lat float8 NULL,
-    lon float8 NULL,
+    lng float8 NULL,
     alert_type varchar NOT NULL,
     raw_code int4 NULL,
     severity int2 DEFAULT 1 NULL,
     metadata jsonb NULL,
     created_at timestamptz DEFAULT now() NULL,
     device_id varchar NOT NULL,
-    correlation_id uuid NULL
+    correlation_id uuid NULL,
+    raw_alert_type varchar NULL,
+    tenant_id varchar NULL
 ) PARTITION BY RANGE ("timestamp");
-CREATE INDEX IF NOT EXISTS idx_trip_alert_device ON ONLY public.trip_alerts USING btree (device_id);
-CREATE INDEX IF NOT EXISTS idx_trip_alert_trip ON ONLY public.trip_alerts USING btree (trip_id);
-CREATE UNIQUE INDEX IF NOT EXISTS idx_trip_alerts_corr_unique ON ONLY public.trip_alerts USING btree (device_id, correlation_id, "timestamp");
-CREATE INDEX IF NOT EXISTS idx_trip_alerts_device_time ON ONLY public.trip_alerts USING btree (device_id, "timestamp" DESC);
-CREATE INDEX IF NOT EXISTS idx_trip_alerts_type ON ONLY public.trip_alerts USING btree (alert_type);
+CREATE TABLE IF NOT EXISTS trip_alerts_default PARTITION OF trip_alerts DEFAULT;
+CREATE INDEX IF NOT EXISTS idx_trip_alert_device ON ONLY trip_alerts USING btree (device_id);
+CREATE INDEX IF NOT EXISTS idx_trip_alert_trip ON ONLY trip_alerts USING btree (trip_id);
+CREATE UNIQUE INDEX IF NOT EXISTS idx_trip_alerts_corr_unique ON ONLY trip_alerts USING btree (device_id, correlation_id, "timestamp");
+CREATE INDEX IF NOT EXISTS idx_trip_alerts_device_time ON ONLY trip_alerts USING btree (device_id, "timestamp" DESC);
+CREATE INDEX IF NOT EXISTS idx_trip_alerts_type ON ONLY trip_alerts USING btree (alert_type);
+-- The ON ONLY index above does not reach the existing default partition; build and attach it there so the constraint is enforced
+CREATE UNIQUE INDEX IF NOT EXISTS trip_alerts_default_corr_unique ON trip_alerts_default USING btree (device_id, correlation_id, "timestamp");
+ALTER INDEX idx_trip_alerts_corr_unique ATTACH PARTITION trip_alerts_default_corr_unique;
 
--- public.trip_current_state definition
+-- trip_current_state definition
 CREATE TABLE IF NOT EXISTS trip_current_state (
     device_id varchar NOT NULL,
     current_trip_id uuid NULL,
@@ -49,11 +69,19 @@ CREATE TABLE IF NOT EXISTS trip_current_state (
     last_speed float8 NULL,
     last_odometer_meters int4 NULL,
     last_correlation_id uuid NULL,
+    last_msg_counter int8 NULL,
+    last_idle_seconds float8 NULL,
+    current_trip_point_count int4 DEFAULT 0 NOT NULL,
+    current_trip_route_meters float8 DEFAULT 0 NOT NULL,
+    current_trip_route_lat float8 NULL,
+    current_trip_route_lng float8 NULL,
+    stale_since timestamptz NULL,
+    current_driver_id varchar NULL,
     last_updated_at timestamptz DEFAULT now() NOT NULL,
     CONSTRAINT trip_current_state_pkey PRIMARY KEY (device_id)
 );
 
--- public.trip_points definition
+-- trip_points definition
 CREATE TABLE IF NOT EXISTS trip_points (
     point_id bigserial NOT NULL,
     trip_id uuid NOT NULL,
@@ -63,10 +91,76 @@ CREATE TABLE IF NOT EXISTS trip_points (
     lng float8 NOT NULL,
     speed float8 NULL,
     heading float8 NULL,
+    odometer_meters int4 NULL,
     correlation_id uuid NOT NULL,
+    altitude float8 NULL,
+    tenant_id varchar NULL,
+    location_source varchar NULL,
     CONSTRAINT trip_points_pkey PRIMARY KEY (device_id, "timestamp", correlation_id)
 );
-CREATE UNIQUE INDEX IF NOT EXISTS idx_trip_points_corr_unique ON public.trip_points USING btree (device_id, correlation_id, "timestamp");
-CREATE INDEX IF NOT EXISTS idx_trip_points_device_time ON public.trip_points USING btree (device_id, "timestamp" DESC);
-CREATE INDEX IF NOT EXISTS idx_trip_points_time ON public.trip_points USING btree ("timestamp" DESC);
-CREATE INDEX IF NOT EXISTS trip_points_timestamp_idx ON public.trip_points USING btree ("timestamp" DESC);
+CREATE UNIQUE INDEX IF NOT EXISTS idx_trip_points_corr_unique ON trip_points USING btree (device_id, correlation_id, "timestamp");
+CREATE INDEX IF NOT EXISTS idx_trip_points_device_time ON trip_points USING btree (device_id, "timestamp" DESC);
+CREATE INDEX IF NOT EXISTS idx_trip_points_time ON trip_points USING btree ("timestamp" DESC);
+CREATE INDEX IF NOT EXISTS idx_trip_points_trip ON trip_points USING btree (trip_id);
+CREATE INDEX IF NOT EXISTS idx_trip_points_time_lat_lng ON trip_points USING btree ("timestamp", lat, lng);
+
+-- device_idle_activity definition
+CREATE TABLE IF NOT EXISTS device_idle_activity (
+    idle_id uuid NOT NULL,
+    device_id varchar NOT NULL,
+    "timestamp" timestamptz NOT NULL,
+    lat float8 NULL,
+    lng float8 NULL,
+    activity_type varchar NOT NULL,
+    raw_code int4 NULL,
+    severity int2 DEFAULT 1 NULL,
+    metadata jsonb NULL,
+    correlation_id uuid NULL,
+    tenant_id varchar NULL,
+    location_source varchar NULL,
+    speed float8 NULL,
+    heading float8 NULL,
+    created_at timestamptz DEFAULT now() NOT NULL,
+    CONSTRAINT device_idle_activity_pkey PRIMARY KEY (idle_id)
+);
+CREATE INDEX IF NOT EXISTS idx_device_idle_activity_device_time ON device_idle_activity USING btree (device_id, "timestamp" DESC);
+
+-- devices definition
+CREATE TABLE IF NOT EXISTS devices (
+    device_id varchar NOT NULL,
+    registered_at timestamptz DEFAULT now() NOT NULL,
+    first_seen_at timestamptz NULL,
+    last_seen_at timestamptz NULL,
+    CONSTRAINT devices_pkey PRIMARY KEY (device_id)
+);
+
+-- device_config definition (DEVICE_CONFIG_OVERRIDES): NULL columns use the global value
+CREATE TABLE IF NOT EXISTS device_config (
+    device_id varchar NOT NULL,
+    min_trip_distance_meters float8 NULL,
+    state_update_min_interval_seconds int4 NULL,
+    idle_stop_threshold_seconds float8 NULL,
+    point_sample_every_n int4 NULL,
+    updated_at timestamptz DEFAULT now() NOT NULL,
+    CONSTRAINT device_config_pkey PRIMARY KEY (device_id)
+);
+
+-- device_assets definition: vehicle/asset currently carrying each device
+CREATE TABLE IF NOT EXISTS device_assets (
+    device_id varchar NOT NULL,
+    asset_id varchar NOT NULL,
+    updated_at timestamptz DEFAULT now() NOT NULL,
+    CONSTRAINT device_assets_pkey PRIMARY KEY (device_id)
+);
+
+-- message_archive definition (RAW_PAYLOAD_RETENTION)
+CREATE TABLE IF NOT EXISTS message_archive (
+    message_uuid uuid NOT NULL,
+    device_id varchar NOT NULL,
+    "timestamp" timestamptz NOT NULL,
+    raw text NULL,
+    payload bytea NOT NULL,
+    archived_at timestamptz DEFAULT now() NOT NULL,
+    CONSTRAINT message_archive_pkey PRIMARY KEY (message_uuid, device_id)
+);
+CREATE INDEX IF NOT EXISTS idx_message_archive_device_time ON message_archive USING btree (device_id, "timestamp" DESC);
@@ -0,0 +1,79 @@
+use rand::Rng;
+use std::time::Duration;
+
+/// Exponential backoff with "equal jitter": each delay is drawn from
+/// `[d/2, d]`, where `d = min(base * 2^attempt, max)`.
+#[derive(Debug, Clone)]
+pub struct Backoff {
+    base: Duration,
+    max: Duration,
+    attempt: u32,
+}
+
+impl Backoff {
+    pub fn new(base: Duration, max: Duration) -> Self {
+        Self {
+            base,
+            max: max.max(base),
+            attempt: 0,
+        }
+    }
+
+    /// Upper bound of the next delay, before jitter.
+    pub fn ceiling(&self) -> Duration {
+        let factor = 1u32.checked_shl(self.attempt).unwrap_or(u32::MAX);
+        self.base.saturating_mul(factor).min(self.max)
+    }
+
+    /// Returns the delay to wait before the next attempt and advances the sequence.
+    pub fn next_delay(&mut self) -> Duration {
+        let ceiling = self.ceiling();
+        self.attempt = self.attempt.saturating_add(1);
+        let half = ceiling / 2;
+        half + half.mul_f64(rand::thread_rng().gen_range(0.0..=1.0))
+    }
+
+    pub fn reset(&mut self) {
+        self.attempt = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ceiling_doubles_until_cap() {
+        let mut backoff = Backoff::new(Duration::from_millis(500), Duration::from_secs(5));
+        let ceilings: Vec<u128> = (0..7)
+            .map(|_| {
+                let ceiling = backoff.ceiling().as_millis();
+                backoff.next_delay();
+                ceiling
+            })
+            .collect();
+
+        assert_eq!(ceilings, vec![500, 1000, 2000, 4000, 5000, 5000, 5000]);
+    }
+
+    #[test]
+    fn test_jitter_stays_within_bounds() {
+        let mut backoff = Backoff::new(Duration::from_millis(500), Duration::from_secs(5));
+        for _ in 0..50 {
+            let ceiling = backoff.ceiling();
+            let delay = backoff.next_delay();
+            assert!(delay >= ceiling / 2, "{:?} below {:?}", delay, ceiling / 2);
+            assert!(delay <= ceiling, "{:?} above {:?}", delay, ceiling);
+        }
+    }
+
+    #[test]
+    fn test_reset_restarts_sequence() {
+        let mut backoff = Backoff::new(Duration::from_millis(500), Duration::from_secs(5));
+        backoff.next_delay();
+        backoff.next_delay();
+        backoff.reset();
+
+        assert_eq!(backoff.ceiling(), Duration::from_millis(500));
+    }
+}
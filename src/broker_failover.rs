@@ -0,0 +1,75 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Kafka clusters to fail over between. `KAFKA_BOOTSTRAP_SERVERS` holds one
+/// or more `;`-separated clusters, each a regular comma-separated broker list
+/// (`a1:9092,a2:9092;b1:9092`). librdkafka already fails over between the
+/// brokers of one cluster; this rotates round-robin to the next cluster when
+/// the consumer's circuit breaker trips.
+#[derive(Debug)]
+pub struct BrokerRotation {
+    clusters: Vec<String>,
+    active: AtomicUsize,
+}
+
+impl BrokerRotation {
+    pub fn parse(value: &str) -> Self {
+        let mut clusters: Vec<String> = value
+            .split(';')
+            .map(|s| s.trim().to_string())
+            .filter(|s| !s.is_empty())
+            .collect();
+        if clusters.is_empty() {
+            clusters.push(value.trim().to_string());
+        }
+        Self {
+            clusters,
+            active: AtomicUsize::new(0),
+        }
+    }
+
+    /// Bootstrap servers of the cluster currently in use.
+    pub fn active(&self) -> &str {
+        &self.clusters[self.active.load(Ordering::Relaxed)]
+    }
+
+    pub fn cluster_count(&self) -> usize {
+        self.clusters.len()
+    }
+
+    /// Switches to the next cluster (wrapping around) and returns its index;
+    /// 0 means every cluster has been tried once more.
+    pub fn rotate(&self) -> usize {
+        let next = (self.active.load(Ordering::Relaxed) + 1) % self.clusters.len();
+        self.active.store(next, Ordering::Relaxed);
+        next
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_single_cluster_keeps_comma_list() {
+        let brokers = BrokerRotation::parse("k1:9092,k2:9092");
+
+        assert_eq!(brokers.cluster_count(), 1);
+        assert_eq!(brokers.active(), "k1:9092,k2:9092");
+        assert_eq!(brokers.rotate(), 0);
+        assert_eq!(brokers.active(), "k1:9092,k2:9092");
+    }
+
+    #[test]
+    fn test_rotation_is_round_robin() {
+        let brokers = BrokerRotation::parse(" a1:9092,a2:9092 ; b1:9092 ;; c1:9092 ");
+
+        assert_eq!(brokers.cluster_count(), 3);
+        assert_eq!(brokers.active(), "a1:9092,a2:9092");
+        assert_eq!(brokers.rotate(), 1);
+        assert_eq!(brokers.active(), "b1:9092");
+        assert_eq!(brokers.rotate(), 2);
+        assert_eq!(brokers.active(), "c1:9092");
+        assert_eq!(brokers.rotate(), 0);
+        assert_eq!(brokers.active(), "a1:9092,a2:9092");
+    }
+}
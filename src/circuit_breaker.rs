@@ -0,0 +1,134 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BreakerState {
+    Closed,
+    Open,
+}
+
+impl BreakerState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BreakerState::Closed => "closed",
+            BreakerState::Open => "open",
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    consecutive_failures: u32,
+    open_until: Option<Instant>,
+}
+
+/// Shared circuit breaker: after `max_failures` consecutive failures it opens
+/// for `cooldown`, then closes again and lets the next attempt through.
+#[derive(Debug)]
+pub struct CircuitBreaker {
+    max_failures: u32,
+    cooldown: Duration,
+    inner: Mutex<Inner>,
+}
+
+impl CircuitBreaker {
+    pub fn new(max_failures: u32, cooldown: Duration) -> Self {
+        Self {
+            max_failures: max_failures.max(1),
+            cooldown,
+            inner: Mutex::new(Inner::default()),
+        }
+    }
+
+    /// Records a failure. Returns true if this failure tripped the breaker.
+    pub fn record_failure(&self, now: Instant) -> bool {
+        let mut inner = self.inner.lock().unwrap();
+        inner.consecutive_failures += 1;
+        if inner.open_until.is_none() && inner.consecutive_failures >= self.max_failures {
+            inner.open_until = Some(now + self.cooldown);
+            return true;
+        }
+        false
+    }
+
+    pub fn record_success(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.consecutive_failures = 0;
+        inner.open_until = None;
+    }
+
+    /// Time left until the breaker closes, or `None` if it is closed.
+    /// Once the cooldown has elapsed the breaker resets.
+    pub fn remaining_open(&self, now: Instant) -> Option<Duration> {
+        let mut inner = self.inner.lock().unwrap();
+        match inner.open_until {
+            Some(until) if until > now => Some(until - now),
+            Some(_) => {
+                *inner = Inner::default();
+                None
+            }
+            None => None,
+        }
+    }
+
+    pub fn state(&self, now: Instant) -> BreakerState {
+        match self.remaining_open(now) {
+            Some(_) => BreakerState::Open,
+            None => BreakerState::Closed,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_trips_after_max_failures() {
+        let now = Instant::now();
+        let breaker = CircuitBreaker::new(3, Duration::from_secs(30));
+
+        assert!(!breaker.record_failure(now));
+        assert!(!breaker.record_failure(now));
+        assert_eq!(breaker.state(now), BreakerState::Closed);
+
+        assert!(breaker.record_failure(now));
+        assert_eq!(breaker.state(now), BreakerState::Open);
+        assert_eq!(
+            breaker.remaining_open(now + Duration::from_secs(10)),
+            Some(Duration::from_secs(20))
+        );
+    }
+
+    #[test]
+    fn test_success_resets_failure_count() {
+        let now = Instant::now();
+        let breaker = CircuitBreaker::new(3, Duration::from_secs(30));
+
+        breaker.record_failure(now);
+        breaker.record_failure(now);
+        breaker.record_success();
+
+        assert!(!breaker.record_failure(now));
+        assert_eq!(breaker.state(now), BreakerState::Closed);
+    }
+
+    #[test]
+    fn test_resets_after_cooldown() {
+        let now = Instant::now();
+        let breaker = CircuitBreaker::new(1, Duration::from_secs(30));
+
+        assert!(breaker.record_failure(now));
+        assert_eq!(
+            breaker.state(now + Duration::from_secs(29)),
+            BreakerState::Open
+        );
+        assert_eq!(
+            breaker.state(now + Duration::from_secs(30)),
+            BreakerState::Closed
+        );
+
+        // After the reset a single failure trips it again
+        assert!(breaker.record_failure(now + Duration::from_secs(31)));
+    }
+}
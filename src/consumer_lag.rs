@@ -0,0 +1,81 @@
+use crate::metrics;
+use rdkafka::consumer::{Consumer, StreamConsumer};
+use rdkafka::error::KafkaResult;
+use rdkafka::Offset;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::error;
+
+/// Timeout for each broker query (committed offsets, watermarks).
+const QUERY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Lag of one assigned partition: messages between the committed offset and the high-water mark.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PartitionLag {
+    pub topic: String,
+    pub partition: i32,
+    pub lag: i64,
+}
+
+/// Messages still to be consumed in a partition. Without a committed offset
+/// the whole retained range (`high - low`) is pending.
+pub fn partition_lag(committed: Offset, low_watermark: i64, high_watermark: i64) -> i64 {
+    let position = match committed {
+        Offset::Offset(offset) => offset,
+        _ => low_watermark,
+    };
+    (high_watermark - position).max(0)
+}
+
+/// Queries the committed offsets of the assigned partitions and their watermarks.
+/// Blocking: call it from `spawn_blocking`.
+fn collect_lag(consumer: &StreamConsumer) -> KafkaResult<Vec<PartitionLag>> {
+    let committed = consumer.committed(QUERY_TIMEOUT)?;
+    let mut lags = Vec::new();
+    for elem in committed.elements() {
+        let (low, high) =
+            consumer.fetch_watermarks(elem.topic(), elem.partition(), QUERY_TIMEOUT)?;
+        lags.push(PartitionLag {
+            topic: elem.topic().to_string(),
+            partition: elem.partition(),
+            lag: partition_lag(elem.offset(), low, high),
+        });
+    }
+    Ok(lags)
+}
+
+/// Periodically refreshes the `kafka_consumer_lag` gauge for the consumer's assigned partitions.
+pub async fn refresh_consumer_lag(consumer: Arc<StreamConsumer>, interval: Duration) {
+    loop {
+        tokio::time::sleep(interval).await;
+        let consumer = consumer.clone();
+        match tokio::task::spawn_blocking(move || collect_lag(&consumer)).await {
+            Ok(Ok(lags)) => metrics::set_consumer_lag(lags),
+            Ok(Err(e)) => error!("Failed to refresh kafka_consumer_lag gauge: {}", e),
+            Err(e) => error!("Consumer lag task failed: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lag_from_committed_offset() {
+        assert_eq!(partition_lag(Offset::Offset(90), 0, 100), 10);
+        assert_eq!(partition_lag(Offset::Offset(100), 0, 100), 0);
+    }
+
+    #[test]
+    fn test_lag_without_commit_counts_retained_range() {
+        assert_eq!(partition_lag(Offset::Invalid, 40, 100), 60);
+        assert_eq!(partition_lag(Offset::Beginning, 40, 100), 60);
+    }
+
+    #[test]
+    fn test_lag_is_never_negative() {
+        // Committed ahead of a stale high-water mark
+        assert_eq!(partition_lag(Offset::Offset(105), 0, 100), 0);
+    }
+}
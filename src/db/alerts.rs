@@ -0,0 +1,12 @@
+use crate::db::{queries, DbPool};
+use crate::models::trip_alerts::TripAlert;
+use uuid::Uuid;
+
+/// Every alert of a trip, oldest first.
+pub async fn select_trip_alerts(pool: &DbPool, trip_id: Uuid) -> anyhow::Result<Vec<TripAlert>> {
+    let alerts = sqlx::query_as(queries::SELECT_TRIP_ALERTS_BY_TRIP)
+        .bind(trip_id)
+        .fetch_all(pool)
+        .await?;
+    Ok(alerts)
+}
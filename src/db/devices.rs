@@ -0,0 +1,278 @@
+use crate::db::sharding::ShardedPool;
+use crate::db::{queries, DbPool};
+use crate::models::current_state::{CurrentState, DeviceHealth};
+use crate::processor::state::DeviceState;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use sqlx::{PgConnection, Row};
+use tracing::warn;
+
+/// First and most recent report of a device, independent of trips.
+#[cfg(all(test, feature = "db-tests"))]
+#[derive(Debug, Clone, PartialEq, sqlx::FromRow)]
+pub struct DeviceSeen {
+    pub first_seen_at: Option<DateTime<Utc>>,
+    pub last_seen_at: Option<DateTime<Utc>>,
+}
+
+/// Registers the device if it is new and advances `last_seen_at`.
+/// `first_seen_at` is only written on insert; late messages never move `last_seen_at` back.
+pub async fn record_device_seen(
+    conn: &mut PgConnection,
+    device_id: &str,
+    seen_at: NaiveDateTime,
+) -> sqlx::Result<()> {
+    sqlx::query(queries::UPSERT_DEVICE_SEEN)
+        .bind(device_id)
+        .bind(seen_at)
+        .execute(conn)
+        .await?;
+    Ok(())
+}
+
+/// Reads the device's first and last report, `None` if it never reported.
+#[cfg(all(test, feature = "db-tests"))]
+pub async fn fetch_device_seen(
+    pool: &DbPool,
+    device_id: &str,
+) -> anyhow::Result<Option<DeviceSeen>> {
+    let seen = sqlx::query_as::<_, DeviceSeen>(queries::SELECT_DEVICE_SEEN)
+        .bind(device_id)
+        .fetch_optional(pool)
+        .await?;
+    Ok(seen)
+}
+
+/// Locks and reads the device's `trip_current_state` row, `None` if it never
+/// reported. Should a bad migration have left several rows for the device,
+/// the most recently updated one is used and the anomaly is logged.
+pub async fn lock_device_state(
+    conn: &mut PgConnection,
+    device_id: &str,
+) -> anyhow::Result<Option<DeviceState>> {
+    let Some(row) = sqlx::query(queries::SELECT_ACTIVE_TRIP_ID)
+        .bind(device_id)
+        .fetch_optional(conn)
+        .await?
+    else {
+        return Ok(None);
+    };
+    let state_rows: i64 = row.try_get("state_rows")?;
+    if state_rows > 1 {
+        warn!(
+            "Device {} has {} trip_current_state rows, using the most recently updated",
+            device_id, state_rows
+        );
+    }
+    Ok(Some(DeviceState::from_row(&row)))
+}
+
+/// Reads the device's state without a transaction or row lock, to decide
+/// whether a message needs one at all. Only a hint: the decision is retaken
+/// under [`lock_device_state`] for messages that do write.
+pub async fn peek_device_state(
+    pool: &DbPool,
+    device_id: &str,
+) -> anyhow::Result<Option<DeviceState>> {
+    let row = sqlx::query(queries::SELECT_DEVICE_STATE_SNAPSHOT)
+        .bind(device_id)
+        .fetch_optional(pool)
+        .await?;
+    Ok(row.as_ref().map(DeviceState::from_row))
+}
+
+/// Current state row of a device, `None` if it never reported.
+pub async fn fetch_current_state(
+    pool: &DbPool,
+    device_id: &str,
+) -> anyhow::Result<Option<CurrentState>> {
+    let state = sqlx::query_as::<_, CurrentState>(queries::SELECT_CURRENT_STATE)
+        .bind(device_id)
+        .fetch_optional(pool)
+        .await?;
+    Ok(state)
+}
+
+/// One page of the fleet health report, ordered by device id: devices after
+/// `after` (exclusive), at most `limit`. `now` is the reference for the age
+/// of the last point.
+pub async fn select_fleet_health(
+    pool: &DbPool,
+    after: &str,
+    limit: i64,
+    now: DateTime<Utc>,
+) -> anyhow::Result<Vec<DeviceHealth>> {
+    let devices = sqlx::query_as::<_, DeviceHealth>(queries::SELECT_FLEET_HEALTH)
+        .bind(after)
+        .bind(limit)
+        .bind(now)
+        .fetch_all(pool)
+        .await?;
+    Ok(devices)
+}
+
+/// Same as [`select_fleet_health`] across every shard, merged by device id
+/// and capped at `limit` overall.
+pub async fn select_fleet_health_all_shards(
+    pools: &ShardedPool,
+    after: &str,
+    limit: i64,
+    now: DateTime<Utc>,
+) -> anyhow::Result<Vec<DeviceHealth>> {
+    let mut devices = Vec::new();
+    for pool in pools.all() {
+        devices.extend(select_fleet_health(pool, after, limit, now).await?);
+    }
+    devices.sort_by(|a, b| a.device_id.cmp(&b.device_id));
+    devices.truncate(limit.max(0) as usize);
+    Ok(devices)
+}
+
+#[cfg(all(test, feature = "db-tests"))]
+mod tests {
+    use super::*;
+    use crate::db::test_support::TestDb;
+    use chrono::TimeZone;
+
+    fn at(epoch: i64) -> NaiveDateTime {
+        Utc.timestamp_opt(epoch, 0).single().unwrap().naive_utc()
+    }
+
+    #[tokio::test]
+    async fn test_duplicate_state_rows_pick_most_recent() {
+        let db = TestDb::new().await;
+        // Simulates a database migrated without the primary key
+        sqlx::query("ALTER TABLE trip_current_state DROP CONSTRAINT trip_current_state_pkey")
+            .execute(&db.pool)
+            .await
+            .unwrap();
+        let trip_id = uuid::Uuid::new_v4();
+        for (ignition_on, current_trip_id, updated_at) in [
+            (true, Some(trip_id), 1_700_000_600),
+            (false, None, 1_700_000_000),
+            (false, None, 1_700_000_300),
+        ] {
+            sqlx::query(
+                "INSERT INTO trip_current_state (device_id, ignition_on, current_trip_id, last_updated_at) \
+                 VALUES ($1, $2, $3, $4)",
+            )
+            .bind("DUP-STATE-1")
+            .bind(ignition_on)
+            .bind(current_trip_id)
+            .bind(at(updated_at).and_utc())
+            .execute(&db.pool)
+            .await
+            .unwrap();
+        }
+
+        let mut tx = db.pool.begin().await.unwrap();
+        let state = lock_device_state(&mut tx, "DUP-STATE-1")
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(state.ignition_on);
+        assert_eq!(state.current_trip_id, Some(trip_id));
+        assert!(lock_device_state(&mut tx, "DUP-STATE-2")
+            .await
+            .unwrap()
+            .is_none());
+        tx.rollback().await.unwrap();
+
+        let current = fetch_current_state(&db.pool, "DUP-STATE-1")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(current.current_trip_id, Some(trip_id));
+
+        db.cleanup().await;
+    }
+
+    #[tokio::test]
+    async fn test_fleet_health_joins_open_trip_and_pages() {
+        let db = TestDb::new().await;
+        let t0 = at(1_700_000_000).and_utc();
+        let open_trip = uuid::Uuid::new_v4();
+        let closed_trip = uuid::Uuid::new_v4();
+        sqlx::query(
+            "INSERT INTO trips (trip_id, device_id, start_time, end_time) \
+             VALUES ($1, 'FLEET-A', $3, NULL), ($2, 'FLEET-B', $3, $3 + interval '10 minutes')",
+        )
+        .bind(open_trip)
+        .bind(closed_trip)
+        .bind(t0)
+        .execute(&db.pool)
+        .await
+        .unwrap();
+        for (device_id, ignition_on, trip_id, last_point_at) in [
+            ("FLEET-A", true, Some(open_trip), Some(t0)),
+            // Stale reference to an already closed trip
+            ("FLEET-B", false, Some(closed_trip), Some(t0)),
+            ("FLEET-C", false, None, None),
+        ] {
+            sqlx::query(
+                "INSERT INTO trip_current_state (device_id, ignition_on, current_trip_id, last_point_at, last_lat, last_lng, last_updated_at) \
+                 VALUES ($1, $2, $3, $4, 20.65, -100.39, now())",
+            )
+            .bind(device_id)
+            .bind(ignition_on)
+            .bind(trip_id)
+            .bind(last_point_at)
+            .execute(&db.pool)
+            .await
+            .unwrap();
+        }
+        let now = t0 + chrono::Duration::seconds(90);
+
+        let page = select_fleet_health_all_shards(&db.sharded(), "FLEET-", 2, now)
+            .await
+            .unwrap();
+        assert_eq!(
+            page.iter()
+                .map(|d| d.device_id.as_str())
+                .collect::<Vec<_>>(),
+            vec!["FLEET-A", "FLEET-B"]
+        );
+        assert!(page[0].ignition_on);
+        assert_eq!(page[0].active_trip_id, Some(open_trip));
+        assert_eq!(page[0].active_trip_started_at, Some(t0));
+        assert_eq!(page[0].last_point_age_seconds, Some(90.0));
+        assert_eq!(page[0].last_lat, Some(20.65));
+        assert_eq!(page[1].active_trip_id, None);
+
+        let rest = select_fleet_health_all_shards(&db.sharded(), "FLEET-B", 2, now)
+            .await
+            .unwrap();
+        assert_eq!(rest.len(), 1);
+        assert_eq!(rest[0].device_id, "FLEET-C");
+        assert_eq!(rest[0].last_point_at, None);
+        assert_eq!(rest[0].last_point_age_seconds, None);
+
+        db.cleanup().await;
+    }
+
+    #[tokio::test]
+    async fn test_first_seen_is_preserved_while_last_seen_advances() {
+        let db = TestDb::new().await;
+        let mut conn = db.pool.acquire().await.unwrap();
+
+        record_device_seen(&mut conn, "SEEN-1", at(1_700_000_000))
+            .await
+            .unwrap();
+        record_device_seen(&mut conn, "SEEN-1", at(1_700_000_600))
+            .await
+            .unwrap();
+        // A late message must not move last_seen_at back
+        record_device_seen(&mut conn, "SEEN-1", at(1_700_000_300))
+            .await
+            .unwrap();
+        drop(conn);
+
+        let seen = fetch_device_seen(&db.pool, "SEEN-1")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(seen.first_seen_at, Some(at(1_700_000_000).and_utc()));
+        assert_eq!(seen.last_seen_at, Some(at(1_700_000_600).and_utc()));
+
+        db.cleanup().await;
+    }
+}
@@ -0,0 +1,544 @@
+use crate::db::sharding::ShardedPool;
+use crate::db::{queries, DbPool};
+use crate::event_sink::OutboundEvent;
+use crate::processor::context::ProcessorContext;
+use crate::processor::distance::{route_step_meters, DistanceSource};
+use crate::processor::message_processor::{finish_closed_trip, ClosedTrip};
+use crate::processor::trip_stats::{speed_percentiles, SpeedPercentiles};
+use chrono::{DateTime, Utc};
+use sqlx::postgres::PgRow;
+use sqlx::{PgConnection, Row};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+/// Closes every open trip (`end_time IS NULL`) at its device's last known
+/// point and clears the device's ignition state. Returns how many trips were
+/// closed; running it again closes nothing.
+pub async fn close_open_trips(
+    pool: &DbPool,
+    ctx: &ProcessorContext,
+    now: DateTime<Utc>,
+) -> anyhow::Result<u64> {
+    let mut tx = pool.begin().await?;
+    let open_trips = sqlx::query(queries::SELECT_OPEN_TRIPS_FOR_CLOSE)
+        .fetch_all(&mut *tx)
+        .await?;
+
+    let mut kept = Vec::with_capacity(open_trips.len());
+    for row in &open_trips {
+        kept.extend(close_trip_at_last_point(&mut tx, ctx, row, now).await?);
+    }
+
+    tx.commit().await?;
+    emit_completed(ctx, &kept).await;
+    Ok(open_trips.len() as u64)
+}
+
+/// Closes the trip of a `SELECT_*_TRIPS_FOR_CLOSE` row and clears the device's
+/// ignition state. Distance, minimum-distance discard, speed percentiles and
+/// route summary are derived as on an ignition off. Returns the trip and its
+/// device, or `None` if the trip was discarded.
+async fn close_trip_at_last_point(
+    conn: &mut PgConnection,
+    ctx: &ProcessorContext,
+    row: &PgRow,
+    now: DateTime<Utc>,
+) -> anyhow::Result<Option<(Uuid, String)>> {
+    let trip_id: Uuid = row.try_get("trip_id")?;
+    let device_id: String = row.try_get("device_id")?;
+    let end_lat = row.try_get::<Option<f64>, _>("end_lat")?;
+    let end_lng = row.try_get::<Option<f64>, _>("end_lng")?;
+    let ended = sqlx::query(queries::UPDATE_TRIP_END)
+        .bind(row.try_get::<DateTime<Utc>, _>("end_time")?)
+        .bind(end_lat)
+        .bind(end_lng)
+        .bind(row.try_get::<Option<i32>, _>("end_odometer_meters")?)
+        .bind(trip_id)
+        .fetch_optional(&mut *conn)
+        .await?;
+    let closed = ClosedTrip::from_end_row(
+        trip_id,
+        ended.as_ref(),
+        row.try_get::<Option<f64>, _>("route_meters")?
+            .unwrap_or(0.0),
+        end_lat.zip(end_lng),
+    );
+    let settings = ctx
+        .device_config
+        .settings(&mut *conn, &ctx.config, &device_id)
+        .await?;
+    sqlx::query(queries::RESET_CURRENT_STATE_IGNITION)
+        .bind(&device_id)
+        .bind(now)
+        .execute(&mut *conn)
+        .await?;
+    let kept = finish_closed_trip(&mut *conn, ctx, &settings, &closed).await?;
+    info!("Closed open trip {} for device {}", trip_id, device_id);
+    Ok(kept.then_some((trip_id, device_id)))
+}
+
+/// Emits `trip_completed` for trips closed by a committed maintenance run.
+async fn emit_completed(ctx: &ProcessorContext, kept: &[(Uuid, String)]) {
+    for (trip_id, device_id) in kept {
+        ctx.event_sink
+            .emit(&OutboundEvent::trip("trip_completed", *trip_id, device_id))
+            .await;
+    }
+}
+
+/// Flags open trips whose device has not reported for `stale_after` and
+/// closes, at their last known point, the ones that stayed flagged for
+/// `resume_window`. A device that reports again in between clears the flag
+/// and keeps its trip. Returns `(flagged, closed)`.
+pub async fn flag_and_close_stale_trips(
+    pool: &DbPool,
+    ctx: &ProcessorContext,
+    now: DateTime<Utc>,
+    stale_after: chrono::Duration,
+    resume_window: chrono::Duration,
+) -> anyhow::Result<(u64, u64)> {
+    let mut tx = pool.begin().await?;
+    let flagged = sqlx::query(queries::FLAG_STALE_TRIPS)
+        .bind(now)
+        .bind(now - stale_after)
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+    // Re-checked under the row lock, so a trip resumed meanwhile is skipped
+    let stale_trips = sqlx::query(queries::SELECT_STALE_TRIPS_FOR_CLOSE)
+        .bind(now - resume_window)
+        .fetch_all(&mut *tx)
+        .await?;
+    let mut kept = Vec::with_capacity(stale_trips.len());
+    for row in &stale_trips {
+        kept.extend(close_trip_at_last_point(&mut tx, ctx, row, now).await?);
+    }
+    tx.commit().await?;
+    emit_completed(ctx, &kept).await;
+    Ok((flagged, stale_trips.len() as u64))
+}
+
+/// Runs [`flag_and_close_stale_trips`] on every shard every `interval`.
+pub async fn close_stale_trips_periodically(
+    pools: ShardedPool,
+    ctx: Arc<ProcessorContext>,
+    stale_after: chrono::Duration,
+    resume_window: chrono::Duration,
+    interval: Duration,
+) {
+    loop {
+        tokio::time::sleep(interval).await;
+        for pool in pools.all() {
+            let now = ctx.clock.now();
+            match flag_and_close_stale_trips(pool, &ctx, now, stale_after, resume_window).await {
+                Ok((flagged, closed)) if flagged > 0 || closed > 0 => info!(
+                    "Flagged {} stale trip(s), closed {} stale trip(s)",
+                    flagged, closed
+                ),
+                Ok(_) => {}
+                Err(e) => error!("Failed to close stale trips: {}", e),
+            }
+        }
+    }
+}
+
+/// Merges overlapping open trips of a device into the earliest one: points and
+/// alerts of the extra trips are moved to it, the extras are deleted and the
+/// current state points at the survivor. Returns the surviving trip, if any.
+pub async fn reconcile_open_trips(
+    conn: &mut PgConnection,
+    device_id: &str,
+) -> anyhow::Result<Option<Uuid>> {
+    let open_trips: Vec<Uuid> = sqlx::query_scalar(queries::SELECT_OPEN_TRIPS_BY_DEVICE)
+        .bind(device_id)
+        .fetch_all(&mut *conn)
+        .await?;
+    let Some((&survivor, extras)) = open_trips.split_first() else {
+        return Ok(None);
+    };
+    if extras.is_empty() {
+        return Ok(Some(survivor));
+    }
+
+    warn!(
+        "Merging {} overlapping open trip(s) {:?} of device {} into {}",
+        extras.len(),
+        extras,
+        device_id,
+        survivor
+    );
+    sqlx::query(queries::REASSIGN_TRIP_POINTS)
+        .bind(survivor)
+        .bind(extras)
+        .execute(&mut *conn)
+        .await?;
+    sqlx::query(queries::REASSIGN_TRIP_ALERTS)
+        .bind(survivor)
+        .bind(extras)
+        .execute(&mut *conn)
+        .await?;
+    sqlx::query(queries::DELETE_TRIPS)
+        .bind(extras)
+        .execute(&mut *conn)
+        .await?;
+    sqlx::query(queries::UPDATE_CURRENT_STATE_TRIP_ID)
+        .bind(device_id)
+        .bind(survivor)
+        .execute(&mut *conn)
+        .await?;
+    Ok(Some(survivor))
+}
+
+/// Reconciles every device with more than one open trip. Returns how many
+/// devices were fixed.
+pub async fn reconcile_all_open_trips(pool: &DbPool) -> anyhow::Result<u64> {
+    let devices: Vec<String> = sqlx::query_scalar(queries::SELECT_DEVICES_WITH_OVERLAPPING_TRIPS)
+        .fetch_all(pool)
+        .await?;
+    for device_id in &devices {
+        let mut tx = pool.begin().await?;
+        reconcile_open_trips(&mut tx, device_id).await?;
+        tx.commit().await?;
+    }
+    Ok(devices.len() as u64)
+}
+
+/// [`reconcile_all_open_trips`] on every shard.
+pub async fn reconcile_all_open_trips_all_shards(pools: &ShardedPool) -> anyhow::Result<u64> {
+    let mut reconciled = 0;
+    for pool in pools.all() {
+        reconciled += reconcile_all_open_trips(pool).await?;
+    }
+    Ok(reconciled)
+}
+
+/// [`close_open_trips`] on every shard.
+pub async fn close_open_trips_all_shards(
+    pools: &ShardedPool,
+    ctx: &ProcessorContext,
+    now: DateTime<Utc>,
+) -> anyhow::Result<u64> {
+    let mut closed = 0;
+    for pool in pools.all() {
+        closed += close_open_trips(pool, ctx, now).await?;
+    }
+    Ok(closed)
+}
+
+/// Derived fields of a trip as written by [`recompute_trip`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecomputedTrip {
+    pub distance_meters: f64,
+    /// `end_time - start_time`; `None` while the trip is open.
+    pub duration_seconds: Option<i64>,
+    pub speed: SpeedPercentiles,
+    pub point_count: i32,
+}
+
+/// Recomputes distance, speed percentiles and point count of a trip from its
+/// stored `trip_points` (start and end positions included in the route) and
+/// updates the `trips` row. Returns `None` if the trip is not in this database.
+///
+/// The route only covers the stored points, so with `POINT_SAMPLE_EVERY_N`
+/// the haversine distance can be shorter than the one computed live.
+pub async fn recompute_trip(
+    pool: &DbPool,
+    trip_id: Uuid,
+    source: DistanceSource,
+) -> anyhow::Result<Option<RecomputedTrip>> {
+    let mut tx = pool.begin().await?;
+    let Some(trip) = sqlx::query(queries::SELECT_TRIP_FOR_RECOMPUTE)
+        .bind(trip_id)
+        .fetch_optional(&mut *tx)
+        .await?
+    else {
+        return Ok(None);
+    };
+    let points = sqlx::query(queries::SELECT_TRIP_POINTS_FOR_RECOMPUTE)
+        .bind(trip_id)
+        .fetch_all(&mut *tx)
+        .await?;
+
+    let position = |lat: &str, lng: &str| -> anyhow::Result<Option<(f64, f64)>> {
+        Ok(trip
+            .try_get::<Option<f64>, _>(lat)?
+            .zip(trip.try_get::<Option<f64>, _>(lng)?))
+    };
+    let mut route = Vec::with_capacity(points.len() + 2);
+    route.extend(position("start_lat", "start_lng")?);
+    let mut speeds = Vec::with_capacity(points.len());
+    for point in &points {
+        route.push((point.try_get("lat")?, point.try_get("lng")?));
+        speeds.extend(point.try_get::<Option<f64>, _>("speed")?);
+    }
+    route.extend(position("end_lat", "end_lng")?);
+    let route_meters: f64 = route
+        .windows(2)
+        .map(|pair| route_step_meters(Some(pair[0]), pair[1]))
+        .sum();
+
+    let odometer_delta = trip
+        .try_get::<Option<i32>, _>("end_odometer_meters")?
+        .zip(trip.try_get::<Option<i32>, _>("start_odometer_meters")?)
+        .map(|(end, start)| f64::from(end - start));
+    let end_time: Option<DateTime<Utc>> = trip.try_get("end_time")?;
+    let start_time: DateTime<Utc> = trip.try_get("start_time")?;
+
+    let recomputed = RecomputedTrip {
+        distance_meters: source.trip_distance(route_meters, odometer_delta),
+        duration_seconds: end_time.map(|end| (end - start_time).num_seconds()),
+        speed: speed_percentiles(&speeds),
+        point_count: points.len() as i32,
+    };
+    sqlx::query(queries::UPDATE_TRIP_RECOMPUTED)
+        .bind(trip_id)
+        .bind(recomputed.distance_meters)
+        .bind(recomputed.speed.p50)
+        .bind(recomputed.speed.p85)
+        .bind(recomputed.speed.p95)
+        .bind(recomputed.point_count)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+    Ok(Some(recomputed))
+}
+
+/// [`recompute_trip`] on whichever shard holds the trip.
+pub async fn recompute_trip_all_shards(
+    pools: &ShardedPool,
+    trip_id: Uuid,
+    source: DistanceSource,
+) -> anyhow::Result<Option<RecomputedTrip>> {
+    for pool in pools.all() {
+        if let Some(recomputed) = recompute_trip(pool, trip_id, source).await? {
+            return Ok(Some(recomputed));
+        }
+    }
+    Ok(None)
+}
+
+#[cfg(all(test, feature = "db-tests"))]
+mod tests {
+    use super::*;
+    use crate::config::AppConfig;
+    use crate::db::test_support::TestDb;
+    use crate::event_sink::RecordingEventSink;
+    use chrono::{Duration, TimeZone};
+
+    #[tokio::test]
+    async fn test_close_open_trips_is_idempotent() {
+        let db = TestDb::new().await;
+        let t0 = Utc.timestamp_opt(1_700_000_000, 0).single().unwrap();
+
+        // DEV-1: open trip with a last known point; DEV-2: open trip without
+        // current state; DEV-3: already closed
+        for (device, ended) in [("DEV-1", false), ("DEV-2", false), ("DEV-3", true)] {
+            let trip_id = Uuid::new_v4();
+            sqlx::query(
+                "INSERT INTO trips (trip_id, device_id, start_time, start_lat, start_lng, start_odometer_meters, end_time) \
+                 VALUES ($1, $2, $3, 20.0, -100.0, 1000, CASE WHEN $4 THEN $3 END)",
+            )
+            .bind(trip_id)
+            .bind(device)
+            .bind(t0)
+            .bind(ended)
+            .execute(&db.pool)
+            .await
+            .unwrap();
+            if device == "DEV-1" {
+                sqlx::query(
+                    "INSERT INTO trip_current_state (device_id, current_trip_id, ignition_on, last_point_at, last_lat, last_lng, last_odometer_meters) \
+                     VALUES ($1, $2, true, $3, 20.5, -100.5, 2500)",
+                )
+                .bind(device)
+                .bind(trip_id)
+                .bind(t0 + Duration::minutes(30))
+                .execute(&db.pool)
+                .await
+                .unwrap();
+            }
+        }
+
+        let now = t0 + Duration::hours(2);
+        let events = Arc::new(RecordingEventSink::default());
+        let ctx = ProcessorContext::new(AppConfig::development()).with_event_sink(events.clone());
+        assert_eq!(
+            close_open_trips_all_shards(&db.sharded(), &ctx, now)
+                .await
+                .unwrap(),
+            2
+        );
+        assert_eq!(
+            close_open_trips_all_shards(&db.sharded(), &ctx, now)
+                .await
+                .unwrap(),
+            0
+        );
+        // One trip_completed per closed trip, none on the idempotent rerun
+        assert_eq!(events.names(), vec!["trip_completed", "trip_completed"]);
+
+        let trip = sqlx::query(
+            "SELECT end_time, end_lat, end_lng, distance_meters FROM trips WHERE device_id = 'DEV-1'",
+        )
+        .fetch_one(&db.pool)
+        .await
+        .unwrap();
+        assert_eq!(
+            trip.get::<Option<DateTime<Utc>>, _>("end_time"),
+            Some(t0 + Duration::minutes(30))
+        );
+        assert_eq!(trip.get::<Option<f64>, _>("end_lat"), Some(20.5));
+        assert_eq!(trip.get::<Option<f64>, _>("distance_meters"), Some(1500.0));
+
+        // Without current state the trip closes at its start point
+        let end_time: Option<DateTime<Utc>> =
+            sqlx::query_scalar("SELECT end_time FROM trips WHERE device_id = 'DEV-2'")
+                .fetch_one(&db.pool)
+                .await
+                .unwrap();
+        assert_eq!(end_time, Some(t0));
+
+        let state = sqlx::query(
+            "SELECT ignition_on, current_trip_id FROM trip_current_state WHERE device_id = 'DEV-1'",
+        )
+        .fetch_one(&db.pool)
+        .await
+        .unwrap();
+        assert!(!state.get::<bool, _>("ignition_on"));
+        assert_eq!(state.get::<Option<Uuid>, _>("current_trip_id"), None);
+
+        db.cleanup().await;
+    }
+
+    #[tokio::test]
+    async fn test_reconcile_merges_overlapping_open_trips() {
+        let db = TestDb::new().await;
+        let t0 = Utc.timestamp_opt(1_700_000_000, 0).single().unwrap();
+        let first = Uuid::new_v4();
+        let second = Uuid::new_v4();
+
+        for (trip_id, start) in [(first, t0), (second, t0 + Duration::minutes(10))] {
+            sqlx::query(
+                "INSERT INTO trips (trip_id, device_id, start_time) VALUES ($1, 'DUP-1', $2)",
+            )
+            .bind(trip_id)
+            .bind(start)
+            .execute(&db.pool)
+            .await
+            .unwrap();
+            sqlx::query(
+                "INSERT INTO trip_points (trip_id, device_id, \"timestamp\", lat, lng, correlation_id) \
+                 VALUES ($1, 'DUP-1', $2, 20.0, -100.0, $3)",
+            )
+            .bind(trip_id)
+            .bind(start + Duration::minutes(1))
+            .bind(Uuid::new_v4())
+            .execute(&db.pool)
+            .await
+            .unwrap();
+        }
+
+        assert_eq!(
+            reconcile_all_open_trips_all_shards(&db.sharded())
+                .await
+                .unwrap(),
+            1
+        );
+        assert_eq!(
+            reconcile_all_open_trips_all_shards(&db.sharded())
+                .await
+                .unwrap(),
+            0
+        );
+
+        let trips: Vec<Uuid> =
+            sqlx::query_scalar("SELECT trip_id FROM trips WHERE device_id = 'DUP-1'")
+                .fetch_all(&db.pool)
+                .await
+                .unwrap();
+        assert_eq!(trips, vec![first]);
+
+        let points: Vec<Uuid> =
+            sqlx::query_scalar("SELECT trip_id FROM trip_points WHERE device_id = 'DUP-1'")
+                .fetch_all(&db.pool)
+                .await
+                .unwrap();
+        assert_eq!(points, vec![first, first]);
+
+        db.cleanup().await;
+    }
+
+    #[tokio::test]
+    async fn test_recompute_trip_from_stored_points() {
+        let db = TestDb::new().await;
+        let t0 = Utc.timestamp_opt(1_700_000_000, 0).single().unwrap();
+        let trip_id = Uuid::new_v4();
+
+        // Stale derived fields, as left by an older version
+        sqlx::query(
+            "INSERT INTO trips (trip_id, device_id, start_time, end_time, start_lat, start_lng, end_lat, end_lng, \
+             start_odometer_meters, end_odometer_meters, distance_meters, point_count) \
+             VALUES ($1, 'RECOMPUTE-1', $2, $3, 0.0, 0.0, 0.03, 0.0, 1000, 500, 99999, 0)",
+        )
+        .bind(trip_id)
+        .bind(t0)
+        .bind(t0 + Duration::minutes(30))
+        .execute(&db.pool)
+        .await
+        .unwrap();
+        for (i, (lat, speed)) in [(0.01, 40.0), (0.02, 60.0)].into_iter().enumerate() {
+            sqlx::query(
+                "INSERT INTO trip_points (trip_id, device_id, \"timestamp\", lat, lng, speed, correlation_id) \
+                 VALUES ($1, 'RECOMPUTE-1', $2, $3, 0.0, $4, $5)",
+            )
+            .bind(trip_id)
+            .bind(t0 + Duration::minutes(10 * (i as i64 + 1)))
+            .bind(lat)
+            .bind(speed)
+            .bind(Uuid::new_v4())
+            .execute(&db.pool)
+            .await
+            .unwrap();
+        }
+
+        // The odometer went backwards (reset), so haversine is used: 0.03 deg ~ 3336 m
+        let recomputed =
+            recompute_trip_all_shards(&db.sharded(), trip_id, DistanceSource::Odometer)
+                .await
+                .unwrap()
+                .unwrap();
+        assert!(
+            (recomputed.distance_meters - 3336.0).abs() < 5.0,
+            "{}",
+            recomputed.distance_meters
+        );
+        assert_eq!(recomputed.duration_seconds, Some(1800));
+        assert_eq!(recomputed.speed.p50, Some(50.0));
+        assert_eq!(recomputed.point_count, 2);
+
+        let row = sqlx::query(
+            "SELECT distance_meters, speed_p50, point_count FROM trips WHERE trip_id = $1",
+        )
+        .bind(trip_id)
+        .fetch_one(&db.pool)
+        .await
+        .unwrap();
+        assert_eq!(
+            row.get::<Option<f64>, _>("distance_meters"),
+            Some(recomputed.distance_meters)
+        );
+        assert_eq!(row.get::<Option<f64>, _>("speed_p50"), Some(50.0));
+        assert_eq!(row.get::<Option<i32>, _>("point_count"), Some(2));
+
+        assert_eq!(
+            recompute_trip_all_shards(&db.sharded(), Uuid::new_v4(), DistanceSource::Odometer)
+                .await
+                .unwrap(),
+            None
+        );
+
+        db.cleanup().await;
+    }
+}
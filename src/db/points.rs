@@ -0,0 +1,170 @@
+use crate::db::sharding::ShardedPool;
+use crate::db::{queries, DbPool};
+use crate::models::trip_points::TripPoint;
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+/// Viewport in plain lat/lng degrees (the schema has no PostGIS).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BoundingBox {
+    pub min_lat: f64,
+    pub min_lon: f64,
+    pub max_lat: f64,
+    pub max_lon: f64,
+}
+
+impl BoundingBox {
+    /// Latitudes within ±90, longitudes within ±180 and min <= max. Boxes
+    /// crossing the antimeridian are not supported.
+    pub fn is_valid(&self) -> bool {
+        let lat = -90.0..=90.0;
+        let lon = -180.0..=180.0;
+        lat.contains(&self.min_lat)
+            && lat.contains(&self.max_lat)
+            && lon.contains(&self.min_lon)
+            && lon.contains(&self.max_lon)
+            && self.min_lat <= self.max_lat
+            && self.min_lon <= self.max_lon
+    }
+}
+
+/// Trip points inside `bbox` between `from` and `to` (inclusive), oldest
+/// first, capped at `limit`. With `tenant`, only that tenant's points.
+pub async fn select_points_in_bbox(
+    pool: &DbPool,
+    bbox: &BoundingBox,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+    limit: i64,
+    tenant: Option<&str>,
+) -> anyhow::Result<Vec<TripPoint>> {
+    let points = sqlx::query_as(queries::SELECT_POINTS_IN_BBOX)
+        .bind(bbox.min_lat)
+        .bind(bbox.max_lat)
+        .bind(bbox.min_lon)
+        .bind(bbox.max_lon)
+        .bind(from)
+        .bind(to)
+        .bind(limit)
+        .bind(tenant)
+        .fetch_all(pool)
+        .await?;
+    Ok(points)
+}
+
+/// Same as [`select_points_in_bbox`] across every shard, merged by timestamp
+/// and capped at `limit` overall.
+pub async fn select_points_in_bbox_all_shards(
+    pools: &ShardedPool,
+    bbox: &BoundingBox,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+    limit: i64,
+    tenant: Option<&str>,
+) -> anyhow::Result<Vec<TripPoint>> {
+    let mut points = Vec::new();
+    for pool in pools.all() {
+        points.extend(select_points_in_bbox(pool, bbox, from, to, limit, tenant).await?);
+    }
+    points.sort_by_key(|point| point.timestamp);
+    points.truncate(limit.max(0) as usize);
+    Ok(points)
+}
+
+/// Every stored point of a trip, oldest first.
+pub async fn select_trip_points(pool: &DbPool, trip_id: Uuid) -> anyhow::Result<Vec<TripPoint>> {
+    let points = sqlx::query_as(queries::SELECT_TRIP_POINTS_BY_TRIP)
+        .bind(trip_id)
+        .fetch_all(pool)
+        .await?;
+    Ok(points)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bbox(min_lat: f64, min_lon: f64, max_lat: f64, max_lon: f64) -> BoundingBox {
+        BoundingBox {
+            min_lat,
+            min_lon,
+            max_lat,
+            max_lon,
+        }
+    }
+
+    #[test]
+    fn test_bbox_validation() {
+        assert!(bbox(20.0, -101.0, 21.0, -100.0).is_valid());
+        assert!(bbox(20.0, -100.0, 20.0, -100.0).is_valid());
+        assert!(!bbox(21.0, -101.0, 20.0, -100.0).is_valid());
+        assert!(!bbox(20.0, -100.0, 21.0, -101.0).is_valid());
+        assert!(!bbox(-91.0, -101.0, 21.0, -100.0).is_valid());
+        assert!(!bbox(20.0, -181.0, 21.0, -100.0).is_valid());
+        assert!(!bbox(f64::NAN, -101.0, 21.0, -100.0).is_valid());
+    }
+}
+
+#[cfg(all(test, feature = "db-tests"))]
+mod db_tests {
+    use super::*;
+    use crate::db::test_support::TestDb;
+    use chrono::{Duration, TimeZone};
+
+    #[tokio::test]
+    async fn test_select_points_in_bbox() {
+        let db = TestDb::new().await;
+        let t0 = Utc.timestamp_opt(1_700_000_000, 0).single().unwrap();
+        let trip_id = Uuid::new_v4();
+
+        for (offset, lat, lng, tenant) in [
+            (0, 20.50, -100.50, "acme"),    // inside
+            (60, 20.90, -100.10, "beta"),   // inside
+            (120, 21.50, -100.50, "acme"),  // north of the box
+            (180, 20.50, -99.50, "acme"),   // east of the box
+            (7200, 20.60, -100.40, "acme"), // inside the box, after `to`
+        ] {
+            sqlx::query(
+                "INSERT INTO trip_points (trip_id, device_id, \"timestamp\", lat, lng, correlation_id, tenant_id) \
+                 VALUES ($1, 'BBOX-001', $2, $3, $4, $5, $6)",
+            )
+            .bind(trip_id)
+            .bind(t0 + Duration::seconds(offset))
+            .bind(lat)
+            .bind(lng)
+            .bind(Uuid::new_v4())
+            .bind(tenant)
+            .execute(&db.pool)
+            .await
+            .unwrap();
+        }
+
+        let viewport = BoundingBox {
+            min_lat: 20.0,
+            min_lon: -101.0,
+            max_lat: 21.0,
+            max_lon: -100.0,
+        };
+        let to = t0 + Duration::hours(1);
+
+        let points = select_points_in_bbox(&db.pool, &viewport, t0, to, 100, None)
+            .await
+            .unwrap();
+        let coords: Vec<(f64, f64)> = points.iter().map(|p| (p.lat, p.lng)).collect();
+        assert_eq!(coords, vec![(20.50, -100.50), (20.90, -100.10)]);
+
+        let beta = select_points_in_bbox(&db.pool, &viewport, t0, to, 100, Some("beta"))
+            .await
+            .unwrap();
+        assert_eq!(beta.len(), 1);
+        assert_eq!(beta[0].tenant_id.as_deref(), Some("beta"));
+
+        let capped = select_points_in_bbox_all_shards(&db.sharded(), &viewport, t0, to, 1, None)
+            .await
+            .unwrap();
+        assert_eq!(capped.len(), 1);
+        assert_eq!(capped[0].timestamp, t0);
+
+        db.cleanup().await;
+    }
+}
@@ -1,14 +1,64 @@
+// One row per device is guaranteed by the primary key; a database migrated
+// without it may hold several, so the most recently updated one wins and
+// state_rows reports the anomaly
 pub const SELECT_ACTIVE_TRIP_ID: &str = r#"
-SELECT current_trip_id, ignition_on FROM trip_current_state WHERE device_id = $1 FOR UPDATE;
+SELECT current_trip_id, ignition_on, last_point_at, last_lat, last_lng, last_speed,
+       last_odometer_meters, last_msg_counter, last_idle_seconds, current_trip_route_meters,
+       current_trip_route_lat, current_trip_route_lng, stale_since, current_driver_id,
+       (SELECT COUNT(*) FROM trip_current_state d WHERE d.device_id = $1) AS state_rows
+FROM trip_current_state WHERE device_id = $1
+ORDER BY last_updated_at DESC
+LIMIT 1
+FOR UPDATE;
 "#;
 
-pub const SELECT_LATEST_OPEN_TRIP: &str = r#"
-SELECT trip_id FROM trips WHERE device_id = $1 AND end_time IS NULL ORDER BY start_time DESC LIMIT 1;
+pub const SELECT_DEVICE_STATE_SNAPSHOT: &str = r#"
+SELECT current_trip_id, ignition_on, last_point_at, last_lat, last_lng, last_speed,
+       last_odometer_meters, last_msg_counter, last_idle_seconds, current_trip_route_meters,
+       current_trip_route_lat, current_trip_route_lng, stale_since, current_driver_id
+FROM trip_current_state WHERE device_id = $1
+ORDER BY last_updated_at DESC
+LIMIT 1;
 "#;
 
+pub const SELECT_OPEN_TRIPS_BY_DEVICE: &str = r#"
+SELECT trip_id FROM trips WHERE device_id = $1 AND end_time IS NULL ORDER BY start_time FOR UPDATE;
+"#;
+
+pub const SELECT_DEVICES_WITH_OVERLAPPING_TRIPS: &str = r#"
+SELECT device_id FROM trips WHERE end_time IS NULL GROUP BY device_id HAVING COUNT(*) > 1;
+"#;
+
+pub const REASSIGN_TRIP_POINTS: &str = r#"
+UPDATE trip_points SET trip_id = $1 WHERE trip_id = ANY($2);
+"#;
+
+pub const REASSIGN_TRIP_ALERTS: &str = r#"
+UPDATE trip_alerts SET trip_id = $1 WHERE trip_id = ANY($2);
+"#;
+
+pub const DELETE_TRIPS: &str = r#"
+DELETE FROM trips WHERE trip_id = ANY($1);
+"#;
+
+pub const UPDATE_CURRENT_STATE_TRIP_ID: &str = r#"
+UPDATE trip_current_state
+SET current_trip_id = $2,
+    current_trip_point_count = (SELECT COUNT(*) FROM trip_points WHERE trip_id = $2)
+WHERE device_id = $1;
+"#;
+
+// asset_id: the device's current asset in device_assets, else the device itself
 pub const INSERT_TRIP: &str = r#"
-INSERT INTO trips (trip_id, device_id, start_time, start_lat, start_lng, start_odometer_meters)
-VALUES ($1, $2, $3, $4, $5, $6);
+INSERT INTO trips (
+    trip_id, device_id, start_time, start_lat, start_lng, start_odometer_meters, tenant_id, asset_id,
+    driver_id
+)
+VALUES (
+    $1, $2, $3, $4, $5, $6, $7,
+    COALESCE((SELECT a.asset_id FROM device_assets a WHERE a.device_id = $2), $2),
+    $8
+);
 "#;
 
 pub const UPDATE_TRIP_END: &str = r#"
@@ -17,58 +67,277 @@ SET end_time = $1,
     end_lat = $2,
     end_lng = $3,
     end_odometer_meters = $4,
-    distance_meters = $4 - start_odometer_meters
-WHERE trip_id = $5;
+    distance_meters = $4 - start_odometer_meters,
+    point_count = (
+        SELECT s.current_trip_point_count FROM trip_current_state s
+        WHERE s.device_id = trips.device_id AND s.current_trip_id = trips.trip_id
+    )
+WHERE trip_id = $5
+RETURNING distance_meters, start_lat, start_lng;
+"#;
+
+pub const UPDATE_TRIP_DISTANCE: &str = r#"
+UPDATE trips SET distance_meters = $2 WHERE trip_id = $1;
+"#;
+
+pub const SELECT_TRIP_POINT_SPEEDS: &str = r#"
+SELECT speed FROM trip_points WHERE trip_id = $1 AND speed IS NOT NULL;
+"#;
+
+pub const UPDATE_TRIP_SPEED_PERCENTILES: &str = r#"
+UPDATE trips SET speed_p50 = $2, speed_p85 = $3, speed_p95 = $4 WHERE trip_id = $1;
+"#;
+
+// Bounding box over the start, end and stored points (NULL coordinates ignored)
+pub const UPDATE_TRIP_ROUTE_SUMMARY: &str = r#"
+UPDATE trips
+SET bearing_degrees = $2,
+    (bbox_min_lat, bbox_min_lng, bbox_max_lat, bbox_max_lng) = (
+        SELECT min(p.lat), min(p.lng), max(p.lat), max(p.lng)
+        FROM (
+            SELECT lat, lng FROM trip_points WHERE trip_id = $1
+            UNION ALL
+            SELECT start_lat, start_lng FROM trips WHERE trip_id = $1
+            UNION ALL
+            SELECT end_lat, end_lng FROM trips WHERE trip_id = $1
+        ) p
+    )
+WHERE trip_id = $1;
+"#;
+
+pub const SELECT_TRIP_FOR_RECOMPUTE: &str = r#"
+SELECT start_time, end_time, start_lat, start_lng, end_lat, end_lng,
+       start_odometer_meters, end_odometer_meters
+FROM trips WHERE trip_id = $1 FOR UPDATE;
+"#;
+
+pub const SELECT_TRIP_POINTS_FOR_RECOMPUTE: &str = r#"
+SELECT lat, lng, speed FROM trip_points WHERE trip_id = $1 ORDER BY "timestamp";
+"#;
+
+pub const UPDATE_TRIP_RECOMPUTED: &str = r#"
+UPDATE trips
+SET distance_meters = $2, speed_p50 = $3, speed_p85 = $4, speed_p95 = $5, point_count = $6
+WHERE trip_id = $1;
+"#;
+
+// Open trips with the last known point of their device (falls back to the trip start)
+pub const SELECT_OPEN_TRIPS_FOR_CLOSE: &str = r#"
+SELECT t.trip_id, t.device_id,
+       COALESCE(s.last_point_at, t.start_time) AS end_time,
+       COALESCE(s.last_lat, t.start_lat) AS end_lat,
+       COALESCE(s.last_lng, t.start_lng) AS end_lng,
+       COALESCE(s.last_odometer_meters, t.start_odometer_meters) AS end_odometer_meters,
+       CASE WHEN s.current_trip_id = t.trip_id THEN s.current_trip_route_meters ELSE 0 END
+           AS route_meters
+FROM trips t
+LEFT JOIN trip_current_state s ON s.device_id = t.device_id
+WHERE t.end_time IS NULL
+FOR UPDATE OF t;
+"#;
+
+pub const FLAG_STALE_TRIPS: &str = r#"
+UPDATE trip_current_state
+SET stale_since = $1
+WHERE ignition_on AND current_trip_id IS NOT NULL AND stale_since IS NULL AND last_point_at < $2;
+"#;
+
+pub const SELECT_STALE_TRIPS_FOR_CLOSE: &str = r#"
+SELECT t.trip_id, t.device_id,
+       COALESCE(s.last_point_at, t.start_time) AS end_time,
+       COALESCE(s.last_lat, t.start_lat) AS end_lat,
+       COALESCE(s.last_lng, t.start_lng) AS end_lng,
+       COALESCE(s.last_odometer_meters, t.start_odometer_meters) AS end_odometer_meters,
+       s.current_trip_route_meters AS route_meters
+FROM trips t
+JOIN trip_current_state s ON s.current_trip_id = t.trip_id
+WHERE t.end_time IS NULL AND s.stale_since <= $1
+FOR UPDATE OF t, s;
+"#;
+
+pub const CLEAR_CURRENT_STATE_STALE: &str = r#"
+UPDATE trip_current_state SET stale_since = NULL WHERE device_id = $1;
+"#;
+
+pub const RESET_CURRENT_STATE_IGNITION: &str = r#"
+UPDATE trip_current_state
+SET current_trip_id = NULL,
+    ignition_on = false,
+    stale_since = NULL,
+    last_updated_at = $2
+WHERE device_id = $1;
+"#;
+
+pub const SELECT_TRIP_BY_ID: &str = r#"
+SELECT trip_id, device_id, start_time, start_lat, start_lng, end_time, end_lat, end_lng,
+       distance_meters, start_odometer_meters, end_odometer_meters, point_count, tenant_id,
+       asset_id
+FROM trips WHERE trip_id = $1;
+"#;
+
+pub const SELECT_TRIP_POINTS_BY_TRIP: &str = r#"
+SELECT point_id, trip_id, device_id, "timestamp", lat, lng, speed, heading,
+       odometer_meters, correlation_id, altitude, tenant_id, location_source
+FROM trip_points
+WHERE trip_id = $1
+ORDER BY "timestamp", point_id;
+"#;
+
+pub const SELECT_TRIP_ALERTS_BY_TRIP: &str = r#"
+SELECT alert_id, trip_id, "timestamp", lat, lng, alert_type, raw_code, severity,
+       device_id, correlation_id, metadata, tenant_id
+FROM trip_alerts
+WHERE trip_id = $1
+ORDER BY "timestamp";
+"#;
+
+// Plain range predicates (no PostGIS); backed by idx_trip_points_time_lat_lng.
+// $8 NULL returns every tenant
+pub const SELECT_POINTS_IN_BBOX: &str = r#"
+SELECT point_id, trip_id, device_id, "timestamp", lat, lng, speed, heading,
+       odometer_meters, correlation_id, altitude, tenant_id, location_source
+FROM trip_points
+WHERE "timestamp" BETWEEN $5 AND $6
+  AND lat BETWEEN $1 AND $2
+  AND lng BETWEEN $3 AND $4
+  AND ($8::varchar IS NULL OR tenant_id = $8)
+ORDER BY "timestamp"
+LIMIT $7;
+"#;
+
+pub const DELETE_TRIP_ALERTS_BY_TRIP: &str = r#"
+DELETE FROM trip_alerts WHERE trip_id = $1;
+"#;
+
+pub const DELETE_TRIP_POINTS_BY_TRIP: &str = r#"
+DELETE FROM trip_points WHERE trip_id = $1;
+"#;
+
+pub const DELETE_TRIP: &str = r#"
+DELETE FROM trips WHERE trip_id = $1;
 "#;
 
 pub const UPDATE_CURRENT_STATE_NEW_TRIP: &str = r#"
-INSERT INTO trip_current_state (device_id, current_trip_id, ignition_on, last_updated_at, last_point_at, last_lat, last_lng, last_odometer_meters, last_correlation_id)
-VALUES ($1, $2, true, NOW(), $3, $4, $5, $7, $6)
+INSERT INTO trip_current_state (
+    device_id, current_trip_id, ignition_on, last_updated_at, last_point_at, last_lat, last_lng,
+    last_odometer_meters, last_correlation_id, current_trip_point_count,
+    current_trip_route_meters, current_trip_route_lat, current_trip_route_lng, current_driver_id
+)
+VALUES ($1, $2, true, $8, $3, $4, $5, $7, $6, 0, 0, $4, $5, $9)
 ON CONFLICT (device_id) DO UPDATE
 SET current_trip_id = $2,
     ignition_on = true,
-    last_updated_at = NOW(),
+    current_driver_id = $9,
+    stale_since = NULL,
+    current_trip_point_count = 0,
+    current_trip_route_meters = 0,
+    current_trip_route_lat = $4,
+    current_trip_route_lng = $5,
+    last_updated_at = $8,
     last_point_at = $3,
-    last_lat = $4,
-    last_lng = $5,
+    last_lat = COALESCE($4, trip_current_state.last_lat),
+    last_lng = COALESCE($5, trip_current_state.last_lng),
     last_odometer_meters = $7,
     last_correlation_id = $6;
 "#;
 
+// Upserts, like UPDATE_CURRENT_STATE_NEW_TRIP, so a device whose first
+// message is an ignition-off or a plain point still gets a state row
 pub const UPDATE_CURRENT_STATE_END_TRIP: &str = r#"
-UPDATE trip_current_state
+INSERT INTO trip_current_state (
+    device_id, current_trip_id, ignition_on, last_updated_at, last_point_at, last_lat, last_lng,
+    last_speed, last_correlation_id
+)
+VALUES ($1, NULL, false, $7, $3, $4, $5, $6, $2)
+ON CONFLICT (device_id) DO UPDATE
 SET current_trip_id = NULL,
     ignition_on = false,
-    last_updated_at = NOW(),
+    stale_since = NULL,
+    last_updated_at = $7,
     last_point_at = $3,
-    last_lat = $4,
-    last_lng = $5,
+    last_lat = COALESCE($4, trip_current_state.last_lat),
+    last_lng = COALESCE($5, trip_current_state.last_lng),
     last_speed = $6,
-    last_correlation_id = $2
-WHERE device_id = $1;
+    last_correlation_id = $2;
 "#;
 
 pub const UPDATE_CURRENT_STATE_POINT: &str = r#"
-UPDATE trip_current_state
+INSERT INTO trip_current_state (
+    device_id, ignition_on, last_point_at, last_lat, last_lng, last_speed, last_odometer_meters,
+    last_updated_at, last_correlation_id
+)
+VALUES ($1, false, $2, $3, $4, $5, $7, $8, $6)
+ON CONFLICT (device_id) DO UPDATE
 SET last_point_at = $2,
     last_lat = $3,
     last_lng = $4,
     last_speed = $5,
     last_odometer_meters = $7,
-    last_updated_at = NOW(),
-    last_correlation_id = $6
+    last_updated_at = $8,
+    last_correlation_id = $6;
+"#;
+
+// First driver identified on a trip opened without one
+pub const SET_CURRENT_TRIP_DRIVER: &str = r#"
+WITH state AS (
+    UPDATE trip_current_state SET current_driver_id = $2 WHERE device_id = $1
+)
+UPDATE trips SET driver_id = COALESCE(driver_id, $2) WHERE trip_id = $3;
+"#;
+
+pub const INCREMENT_CURRENT_TRIP_POINT_COUNT: &str = r#"
+UPDATE trip_current_state SET current_trip_point_count = current_trip_point_count + 1 WHERE device_id = $1;
+"#;
+
+pub const ADVANCE_CURRENT_TRIP_ROUTE: &str = r#"
+UPDATE trip_current_state
+SET current_trip_route_meters = current_trip_route_meters + $2,
+    current_trip_route_lat = $3,
+    current_trip_route_lng = $4
 WHERE device_id = $1;
 "#;
 
+pub const UPDATE_CURRENT_STATE_MSG_COUNTER: &str = r#"
+UPDATE trip_current_state SET last_msg_counter = $2 WHERE device_id = $1;
+"#;
+
+pub const UPDATE_CURRENT_STATE_IDLE_TIME: &str = r#"
+UPDATE trip_current_state SET last_idle_seconds = $2 WHERE device_id = $1;
+"#;
+
 pub const INSERT_TRIP_POINT: &str = r#"
-INSERT INTO trip_points (trip_id, device_id, timestamp, lat, lng, speed, heading, odometer_meters, correlation_id)
-VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9);
+INSERT INTO trip_points (
+    trip_id, device_id, timestamp, lat, lng, speed, heading, odometer_meters, correlation_id, altitude,
+    tenant_id, location_source
+) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12);
 "#;
 
 pub const INSERT_TRIP_ALERT: &str = r#"
 INSERT INTO trip_alerts (
-    alert_id, trip_id, timestamp, lat, lon, alert_type, raw_code, severity, device_id, correlation_id
-) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10);
+    alert_id, trip_id, timestamp, lat, lng, alert_type, raw_code, severity, device_id, correlation_id,
+    raw_alert_type, tenant_id
+) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12);
+"#;
+
+// ALERT_DEBOUNCE_SECONDS: an alert of the same raw type already stored for the
+// device within the window ($4 seconds up to $3)
+pub const RECENT_ALERT_EXISTS: &str = r#"
+SELECT EXISTS (
+    SELECT 1 FROM trip_alerts
+    WHERE device_id = $1
+      AND upper(btrim(raw_alert_type)) = $2
+      AND "timestamp" <= $3
+      AND "timestamp" > $3 - make_interval(secs => $4)
+);
+"#;
+
+// IDEMPOTENT_ALERTS: needs the unique index of migration_add_trip_alerts_natural_key.sql
+pub const INSERT_TRIP_ALERT_IDEMPOTENT: &str = r#"
+INSERT INTO trip_alerts (
+    alert_id, trip_id, timestamp, lat, lng, alert_type, raw_code, severity, device_id, correlation_id,
+    raw_alert_type, tenant_id
+) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12)
+ON CONFLICT (trip_id, "timestamp", alert_type, raw_alert_type) DO NOTHING;
 "#;
 
 pub const INSERT_DEVICE_IDLE_ACTIVITY: &str = r#"
@@ -77,11 +346,72 @@ INSERT INTO device_idle_activity (
     device_id,
     timestamp,
     lat,
-    lon,
+    lng,
     activity_type,
     raw_code,
     severity,
     metadata,
-    correlation_id
-) VALUES ($1,$2,$3,$4,$5,$6,$7,$8,$9,$10);
+    correlation_id,
+    tenant_id,
+    location_source,
+    speed,
+    heading
+) VALUES ($1,$2,$3,$4,$5,$6,$7,$8,$9,$10,$11,$12,$13,$14);
+"#;
+
+pub const NOTIFY_TRIP_EVENT: &str = r#"
+SELECT pg_notify($1, $2);
+"#;
+
+pub const SELECT_DEVICE_CONFIG: &str = r#"
+SELECT min_trip_distance_meters, state_update_min_interval_seconds,
+       idle_stop_threshold_seconds, point_sample_every_n
+FROM device_config WHERE device_id = $1;
+"#;
+
+pub const SELECT_CURRENT_STATE: &str = r#"
+SELECT device_id, current_trip_id, ignition_on, last_point_at, last_lat, last_lng, last_speed,
+       last_odometer_meters, last_correlation_id, last_updated_at
+FROM trip_current_state WHERE device_id = $1
+ORDER BY last_updated_at DESC
+LIMIT 1;
+"#;
+
+pub const SELECT_FLEET_HEALTH: &str = r#"
+SELECT DISTINCT ON (s.device_id)
+       s.device_id, s.ignition_on, s.last_point_at,
+       EXTRACT(EPOCH FROM ($3 - s.last_point_at))::float8 AS last_point_age_seconds,
+       t.trip_id AS active_trip_id, t.start_time AS active_trip_started_at,
+       s.last_lat, s.last_lng, s.last_speed
+FROM trip_current_state s
+LEFT JOIN trips t ON t.trip_id = s.current_trip_id AND t.end_time IS NULL
+WHERE s.device_id > $1
+ORDER BY s.device_id, s.last_updated_at DESC
+LIMIT $2;
+"#;
+
+pub const UPSERT_DEVICE_SEEN: &str = r#"
+INSERT INTO devices (device_id, first_seen_at, last_seen_at)
+VALUES ($1, $2, $2)
+ON CONFLICT (device_id) DO UPDATE
+SET last_seen_at = GREATEST(devices.last_seen_at, EXCLUDED.last_seen_at);
+"#;
+
+#[cfg(all(test, feature = "db-tests"))]
+pub const SELECT_DEVICE_SEEN: &str = r#"
+SELECT first_seen_at, last_seen_at FROM devices WHERE device_id = $1;
+"#;
+
+pub const INSERT_MESSAGE_ARCHIVE: &str = r#"
+INSERT INTO message_archive (message_uuid, device_id, "timestamp", raw, payload)
+VALUES ($1, $2, $3, $4, $5)
+ON CONFLICT (message_uuid, device_id) DO NOTHING;
+"#;
+
+pub const COUNT_ACTIVE_TRIPS: &str = r#"
+SELECT COUNT(*) FROM trips WHERE end_time IS NULL;
+"#;
+
+pub const COUNT_IDLE_DEVICES: &str = r#"
+SELECT COUNT(*) FROM trip_current_state WHERE ignition_on = false;
 "#;
@@ -0,0 +1,83 @@
+use crate::db::{init_pool, DbPool};
+use anyhow::Result;
+use std::time::Duration;
+
+/// One pool per Postgres shard. All rows of a device (trips, points, current
+/// state) live on the shard chosen by its id, so per-device transactions stay local.
+#[derive(Clone)]
+pub struct ShardedPool {
+    pools: Vec<DbPool>,
+}
+
+impl ShardedPool {
+    /// Connects to every shard, in the order given by `DATABASE_URLS`.
+    pub async fn connect(database_urls: &[String], connect_timeout: Duration) -> Result<Self> {
+        anyhow::ensure!(
+            !database_urls.is_empty(),
+            "at least one database URL is required"
+        );
+        let mut pools = Vec::with_capacity(database_urls.len());
+        for url in database_urls {
+            pools.push(init_pool(url, connect_timeout).await?);
+        }
+        Ok(Self { pools })
+    }
+
+    #[cfg(test)]
+    pub fn single(pool: DbPool) -> Self {
+        Self { pools: vec![pool] }
+    }
+
+    pub fn for_device(&self, device_id: &str) -> &DbPool {
+        &self.pools[shard_index(device_id, self.pools.len())]
+    }
+
+    pub fn all(&self) -> &[DbPool] {
+        &self.pools
+    }
+}
+
+/// Stable shard for a device: FNV-1a of the trimmed, upper-cased id. Unlike
+/// `DefaultHasher` this does not change between Rust releases, so devices never
+/// move shards on upgrade.
+pub fn shard_index(device_id: &str, shards: usize) -> usize {
+    if shards <= 1 {
+        return 0;
+    }
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in device_id.trim().bytes() {
+        hash ^= u64::from(byte.to_ascii_uppercase());
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    (hash % shards as u64) as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_single_shard_always_zero() {
+        assert_eq!(shard_index("0848086072", 1), 0);
+        assert_eq!(shard_index("", 1), 0);
+    }
+
+    #[test]
+    fn test_routing_is_deterministic_and_normalized() {
+        let shard = shard_index("862524060000001", 4);
+        assert_eq!(shard_index("862524060000001", 4), shard);
+        assert_eq!(shard_index(" 862524060000001 ", 4), shard);
+        assert_eq!(shard_index("abc-1", 4), shard_index("ABC-1", 4));
+    }
+
+    #[test]
+    fn test_routing_spreads_devices() {
+        let mut counts = [0usize; 4];
+        for i in 0..1000 {
+            counts[shard_index(&format!("86252406{:07}", i), 4)] += 1;
+        }
+        for count in counts {
+            assert!(count > 150, "unbalanced shards: {:?}", counts);
+        }
+    }
+}
@@ -0,0 +1,55 @@
+use crate::db::{queries, DbPool};
+
+/// Number of trips that are still open (`end_time IS NULL`).
+pub async fn count_active_trips(pool: &DbPool) -> anyhow::Result<i64> {
+    let count = sqlx::query_scalar(queries::COUNT_ACTIVE_TRIPS)
+        .fetch_one(pool)
+        .await?;
+    Ok(count)
+}
+
+/// Number of devices whose current state has the ignition off.
+pub async fn count_idle_devices(pool: &DbPool) -> anyhow::Result<i64> {
+    let count = sqlx::query_scalar(queries::COUNT_IDLE_DEVICES)
+        .fetch_one(pool)
+        .await?;
+    Ok(count)
+}
+
+#[cfg(all(test, feature = "db-tests"))]
+mod tests {
+    use super::*;
+    use crate::db::test_support::TestDb;
+    use uuid::Uuid;
+
+    #[tokio::test]
+    async fn test_count_active_trips_and_idle_devices() {
+        let db = TestDb::new().await;
+
+        for (device, ended) in [("DEV-1", false), ("DEV-2", false), ("DEV-3", true)] {
+            sqlx::query(
+                "INSERT INTO trips (trip_id, device_id, start_time, end_time) \
+                 VALUES ($1, $2, NOW(), CASE WHEN $3 THEN NOW() END)",
+            )
+            .bind(Uuid::new_v4())
+            .bind(device)
+            .bind(ended)
+            .execute(&db.pool)
+            .await
+            .unwrap();
+        }
+        for (device, ignition_on) in [("DEV-1", true), ("DEV-2", true), ("DEV-3", false)] {
+            sqlx::query("INSERT INTO trip_current_state (device_id, ignition_on) VALUES ($1, $2)")
+                .bind(device)
+                .bind(ignition_on)
+                .execute(&db.pool)
+                .await
+                .unwrap();
+        }
+
+        assert_eq!(count_active_trips(&db.pool).await.unwrap(), 2);
+        assert_eq!(count_idle_devices(&db.pool).await.unwrap(), 1);
+
+        db.cleanup().await;
+    }
+}
@@ -0,0 +1,100 @@
+//! Helpers for tests that need a real Postgres (`--features db-tests`).
+//!
+//! Each `TestDb` creates a throwaway schema on the server pointed to by
+//! `DATABASE_URL`, applies `schema.sql` inside it and drops it on `cleanup`.
+
+use crate::db::sharding::ShardedPool;
+use crate::db::DbPool;
+use crate::models::siscom::v1::KafkaMessage;
+use prost::Message;
+use sqlx::postgres::PgPoolOptions;
+use sqlx::Executor;
+use uuid::Uuid;
+
+const SCHEMA_SQL: &str = include_str!("../../schema.sql");
+
+pub struct TestDb {
+    pub pool: DbPool,
+    database_url: String,
+    schema: String,
+}
+
+impl TestDb {
+    /// The test schema as a single-shard pool, as `process_message` expects.
+    pub fn sharded(&self) -> ShardedPool {
+        ShardedPool::single(self.pool.clone())
+    }
+
+    pub async fn new() -> Self {
+        let database_url =
+            std::env::var("DATABASE_URL").expect("DATABASE_URL must be set to run db-tests");
+        let schema = format!("siscom_test_{}", Uuid::new_v4().simple());
+
+        let admin = PgPoolOptions::new()
+            .max_connections(1)
+            .connect(&database_url)
+            .await
+            .expect("failed to connect to DATABASE_URL");
+        admin
+            .execute(format!("CREATE SCHEMA {}", schema).as_str())
+            .await
+            .expect("failed to create test schema");
+        admin.close().await;
+
+        let session_setup = format!("SET search_path TO {}; SET TIME ZONE 'UTC';", schema);
+        let pool = PgPoolOptions::new()
+            .max_connections(5)
+            .after_connect(move |conn, _meta| {
+                let session_setup = session_setup.clone();
+                Box::pin(async move {
+                    conn.execute(session_setup.as_str()).await?;
+                    Ok(())
+                })
+            })
+            .connect(&database_url)
+            .await
+            .expect("failed to connect test pool");
+        pool.execute(SCHEMA_SQL)
+            .await
+            .expect("failed to apply schema.sql");
+
+        Self {
+            pool,
+            database_url,
+            schema,
+        }
+    }
+
+    pub async fn cleanup(self) {
+        self.pool.close().await;
+        let admin = PgPoolOptions::new()
+            .max_connections(1)
+            .connect(&self.database_url)
+            .await
+            .expect("failed to connect to DATABASE_URL");
+        admin
+            .execute(format!("DROP SCHEMA {} CASCADE", self.schema).as_str())
+            .await
+            .expect("failed to drop test schema");
+        admin.close().await;
+    }
+}
+
+/// Builds an encoded `KafkaMessage` for `device_id` at `gps_epoch` with extra data fields.
+pub fn payload(device_id: &str, gps_epoch: i64, fields: &[(&str, &str)]) -> Vec<u8> {
+    let mut data: std::collections::HashMap<String, String> = fields
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+    data.insert("DEVICE_ID".to_string(), device_id.to_string());
+    data.insert("GPS_EPOCH".to_string(), gps_epoch.to_string());
+
+    KafkaMessage {
+        uuid: Uuid::new_v4().to_string(),
+        decoded: None,
+        data,
+        metadata: None,
+        raw: String::new(),
+    }
+    .encode_to_vec()
+}
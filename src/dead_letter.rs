@@ -0,0 +1,72 @@
+use crate::config::AppConfig;
+use crate::kafka::base_client_config;
+use rdkafka::message::{Header, OwnedHeaders};
+use rdkafka::producer::{FutureProducer, FutureRecord};
+use std::sync::Arc;
+use tracing::{debug, error};
+
+/// Why a message could not be processed and was sent to the dead-letter sink.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeadLetterReason {
+    DecodeError,
+    MissingData,
+    SchemaTooOld,
+    SchemaInvalid,
+    Oversized,
+    MaxAttempts,
+}
+
+impl DeadLetterReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DeadLetterReason::DecodeError => "decode_error",
+            DeadLetterReason::MissingData => "missing_data",
+            DeadLetterReason::SchemaTooOld => "schema_too_old",
+            DeadLetterReason::SchemaInvalid => "schema_invalid",
+            DeadLetterReason::Oversized => "oversized",
+            DeadLetterReason::MaxAttempts => "max_attempts",
+        }
+    }
+}
+
+/// Destination for messages that cannot be processed, so they are kept instead of dropped.
+pub trait DeadLetterSink: Send + Sync {
+    fn send(&self, reason: DeadLetterReason, payload: &[u8]);
+}
+
+/// Forwards the original payload to a Kafka topic with the reason in a `reason` header.
+pub struct KafkaDeadLetterSink {
+    producer: FutureProducer,
+    topic: String,
+}
+
+impl KafkaDeadLetterSink {
+    /// Builds the sink when `KAFKA_DEAD_LETTER_TOPIC` is configured.
+    pub fn from_config(config: &AppConfig) -> anyhow::Result<Option<Arc<dyn DeadLetterSink>>> {
+        if config.kafka_dead_letter_topic.is_empty() {
+            return Ok(None);
+        }
+        let producer: FutureProducer = base_client_config(config).create()?;
+        Ok(Some(Arc::new(Self {
+            producer,
+            topic: config.kafka_dead_letter_topic.clone(),
+        })))
+    }
+}
+
+impl DeadLetterSink for KafkaDeadLetterSink {
+    fn send(&self, reason: DeadLetterReason, payload: &[u8]) {
+        let headers = OwnedHeaders::new().insert(Header {
+            key: "reason",
+            value: Some(reason.as_str()),
+        });
+        let record: FutureRecord<'_, (), [u8]> = FutureRecord::to(&self.topic)
+            .payload(payload)
+            .headers(headers);
+        // Enqueue without waiting for delivery; librdkafka retries in the background
+        match self.producer.send_result(record) {
+            Ok(_) => debug!("Sent message to dead-letter topic {}", self.topic),
+            Err((e, _)) => error!("Failed to enqueue dead-letter message: {}", e),
+        }
+    }
+}
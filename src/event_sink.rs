@@ -0,0 +1,277 @@
+use crate::config::AppConfig;
+use crate::db::queries;
+use crate::db::sharding::ShardedPool;
+use crate::kafka::base_client_config;
+use crate::publisher::TripPublisher;
+use rdkafka::message::{Header, OwnedHeaders};
+use rdkafka::producer::{FutureProducer, FutureRecord};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use tracing::{debug, error, warn};
+use uuid::Uuid;
+
+/// An event leaving the service, independent of the transport that carries it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutboundEvent {
+    /// Event name, e.g. `trip_started` or `trip_completed`.
+    pub name: &'static str,
+    /// Partitioning key; the device id, so a device's events stay ordered.
+    pub key: String,
+    pub trip_id: Uuid,
+    pub payload: Vec<u8>,
+}
+
+impl OutboundEvent {
+    /// A trip lifecycle event with its JSON body.
+    pub fn trip(name: &'static str, trip_id: Uuid, device_id: &str) -> Self {
+        Self {
+            name,
+            key: device_id.to_string(),
+            trip_id,
+            payload: trip_event_json(name, trip_id, device_id).into_bytes(),
+        }
+    }
+}
+
+/// JSON body of a trip lifecycle event.
+pub fn trip_event_json(name: &str, trip_id: Uuid, device_id: &str) -> String {
+    serde_json::json!({
+        "event": name,
+        "trip_id": trip_id,
+        "device_id": device_id,
+    })
+    .to_string()
+}
+
+/// Future returned by [`EventSink::emit`].
+pub type EmitFuture<'a> = Pin<Box<dyn Future<Output = ()> + Send + 'a>>;
+
+/// Destination for outbound events. Emitting never fails the caller: events
+/// are published after the data is committed, so a lost event must not roll
+/// anything back. Sinks log their own errors.
+pub trait EventSink: Send + Sync {
+    fn emit<'a>(&'a self, event: &'a OutboundEvent) -> EmitFuture<'a>;
+}
+
+/// Drops every event; the default when no destination is configured.
+pub struct NoopEventSink;
+
+impl EventSink for NoopEventSink {
+    fn emit<'a>(&'a self, _event: &'a OutboundEvent) -> EmitFuture<'a> {
+        Box::pin(std::future::ready(()))
+    }
+}
+
+/// Emits every event to each sink in turn, in configuration order.
+pub struct FanOutEventSink {
+    sinks: Vec<Arc<dyn EventSink>>,
+}
+
+impl EventSink for FanOutEventSink {
+    fn emit<'a>(&'a self, event: &'a OutboundEvent) -> EmitFuture<'a> {
+        Box::pin(async move {
+            for sink in &self.sinks {
+                sink.emit(event).await;
+            }
+        })
+    }
+}
+
+/// Publishes events to `EVENT_SINK_TOPIC`, keyed by device, with the event name
+/// in an `event` header.
+pub struct KafkaEventSink {
+    producer: FutureProducer,
+    topic: String,
+}
+
+impl EventSink for KafkaEventSink {
+    fn emit<'a>(&'a self, event: &'a OutboundEvent) -> EmitFuture<'a> {
+        let headers = OwnedHeaders::new().insert(Header {
+            key: "event",
+            value: Some(event.name),
+        });
+        let record = FutureRecord::to(&self.topic)
+            .key(&event.key)
+            .payload(&event.payload)
+            .headers(headers);
+        // Enqueue without waiting for delivery; librdkafka retries in the background
+        match self.producer.send_result(record) {
+            Ok(_) => debug!("Emitted {} to {}", event.name, self.topic),
+            Err((e, _)) => error!("Failed to enqueue {} event: {}", event.name, e),
+        }
+        Box::pin(std::future::ready(()))
+    }
+}
+
+/// Sends the JSON body of each event with `pg_notify` on `PG_NOTIFY_CHANNEL`,
+/// through the device's shard, so other services can `LISTEN` without Kafka.
+pub struct PgNotifyEventSink {
+    pools: ShardedPool,
+    channel: String,
+}
+
+impl EventSink for PgNotifyEventSink {
+    fn emit<'a>(&'a self, event: &'a OutboundEvent) -> EmitFuture<'a> {
+        Box::pin(async move {
+            let payload = String::from_utf8_lossy(&event.payload);
+            let result = sqlx::query(queries::NOTIFY_TRIP_EVENT)
+                .bind(&self.channel)
+                .bind(payload.as_ref())
+                .execute(self.pools.for_device(&event.key))
+                .await;
+            match result {
+                Ok(_) => debug!("Notified {} on {}", event.name, self.channel),
+                Err(e) => error!("Failed to notify {} event: {}", event.name, e),
+            }
+        })
+    }
+}
+
+/// Builds the sink for every configured destination: `EVENT_SINK=kafka` (JSON
+/// to `EVENT_SINK_TOPIC`), the `siscom.v1.Trip` topics of [`TripPublisher`] and
+/// `PG_NOTIFY_CHANNEL`. Unknown `EVENT_SINK` values are ignored with a warning.
+/// Dead letters are not events and keep their own topic.
+pub fn from_config(config: &AppConfig, pools: &ShardedPool) -> anyhow::Result<Arc<dyn EventSink>> {
+    let mut sinks: Vec<Arc<dyn EventSink>> = Vec::new();
+    match config.event_sink.to_ascii_lowercase().as_str() {
+        "kafka" => {
+            if config.event_sink_topic.is_empty() {
+                anyhow::bail!("EVENT_SINK=kafka requires EVENT_SINK_TOPIC");
+            }
+            let producer: FutureProducer = base_client_config(config).create()?;
+            sinks.push(Arc::new(KafkaEventSink {
+                producer,
+                topic: config.event_sink_topic.clone(),
+            }));
+        }
+        "" | "none" | "noop" => {}
+        other => warn!("Unknown EVENT_SINK '{}', ignored", other),
+    }
+    if let Some(publisher) = TripPublisher::from_config(config, pools)? {
+        sinks.push(Arc::new(publisher));
+    }
+    if !config.pg_notify_channel.is_empty() {
+        sinks.push(Arc::new(PgNotifyEventSink {
+            pools: pools.clone(),
+            channel: config.pg_notify_channel.clone(),
+        }));
+    }
+
+    Ok(match sinks.len() {
+        0 => Arc::new(NoopEventSink),
+        1 => sinks.remove(0),
+        _ => Arc::new(FanOutEventSink { sinks }),
+    })
+}
+
+/// Keeps emitted events in memory, for tests.
+#[cfg(test)]
+#[derive(Default)]
+pub struct RecordingEventSink {
+    events: std::sync::Mutex<Vec<OutboundEvent>>,
+}
+
+#[cfg(test)]
+impl RecordingEventSink {
+    pub fn names(&self) -> Vec<&'static str> {
+        self.events.lock().unwrap().iter().map(|e| e.name).collect()
+    }
+
+    pub fn events(&self) -> Vec<OutboundEvent> {
+        self.events.lock().unwrap().clone()
+    }
+}
+
+#[cfg(test)]
+impl EventSink for RecordingEventSink {
+    fn emit<'a>(&'a self, event: &'a OutboundEvent) -> EmitFuture<'a> {
+        self.events.lock().unwrap().push(event.clone());
+        Box::pin(std::future::ready(()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_trip_event_body() {
+        let trip_id = Uuid::new_v4();
+        let event = OutboundEvent::trip("trip_started", trip_id, "DEV-1");
+        let body: serde_json::Value = serde_json::from_slice(&event.payload).unwrap();
+
+        assert_eq!(event.key, "DEV-1");
+        assert_eq!(body["event"], "trip_started");
+        assert_eq!(body["trip_id"], trip_id.to_string());
+        assert_eq!(body["device_id"], "DEV-1");
+    }
+
+    /// Lazy pool, so sinks can be built without a database.
+    fn unconnected_pools() -> ShardedPool {
+        ShardedPool::single(
+            sqlx::postgres::PgPoolOptions::new()
+                .connect_lazy("postgres://localhost:1/unreachable")
+                .unwrap(),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_recording_sink_keeps_events_in_order() {
+        let sink = RecordingEventSink::default();
+        let trip_id = Uuid::new_v4();
+        sink.emit(&OutboundEvent::trip("trip_started", trip_id, "DEV-1"))
+            .await;
+        sink.emit(&OutboundEvent::trip("trip_completed", trip_id, "DEV-1"))
+            .await;
+
+        assert_eq!(sink.names(), vec!["trip_started", "trip_completed"]);
+        assert!(sink.events().iter().all(|event| event.key == "DEV-1"));
+    }
+
+    #[tokio::test]
+    async fn test_fan_out_emits_to_every_sink() {
+        let first = Arc::new(RecordingEventSink::default());
+        let second = Arc::new(RecordingEventSink::default());
+        let sink = FanOutEventSink {
+            sinks: vec![first.clone() as Arc<dyn EventSink>, second.clone()],
+        };
+
+        sink.emit(&OutboundEvent::trip(
+            "trip_started",
+            Uuid::new_v4(),
+            "DEV-1",
+        ))
+        .await;
+
+        assert_eq!(first.names(), vec!["trip_started"]);
+        assert_eq!(second.names(), vec!["trip_started"]);
+    }
+
+    #[tokio::test]
+    async fn test_from_config_defaults_to_noop() {
+        let pools = unconnected_pools();
+        let config = AppConfig::development();
+        let sink = from_config(&config, &pools).unwrap();
+        // The no-op sink accepts events without a broker or database
+        sink.emit(&OutboundEvent::trip(
+            "trip_started",
+            Uuid::new_v4(),
+            "DEV-1",
+        ))
+        .await;
+
+        let mut config = AppConfig::development();
+        config.event_sink = "bogus".to_string();
+        assert!(from_config(&config, &pools).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_from_config_kafka_requires_topic() {
+        let mut config = AppConfig::development();
+        config.event_sink = "kafka".to_string();
+        config.event_sink_topic = String::new();
+
+        assert!(from_config(&config, &unconnected_pools()).is_err());
+    }
+}
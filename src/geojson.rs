@@ -0,0 +1,247 @@
+use crate::db::sharding::ShardedPool;
+use crate::db::{alerts, points, queries, DbPool};
+use crate::models::trip::Trip;
+use crate::models::trip_alerts::TripAlert;
+use crate::models::trip_points::TripPoint;
+use serde_json::{json, Value};
+use uuid::Uuid;
+
+/// GeoJSON `FeatureCollection` of a trip: the route as the first feature,
+/// with the trip metadata as properties, followed by one `Point` feature per
+/// alert. Positions are `[lng, lat]`. The route is a `LineString`, a `Point`
+/// with a single stored point, or a null geometry without points; alerts
+/// without position also get a null geometry.
+pub fn trip_feature_collection(trip: &Trip, points: &[TripPoint], alerts: &[TripAlert]) -> Value {
+    let coordinates: Vec<Value> = points.iter().map(|p| json!([p.lng, p.lat])).collect();
+    let route = match coordinates.len() {
+        0 => Value::Null,
+        1 => json!({ "type": "Point", "coordinates": coordinates[0] }),
+        _ => json!({ "type": "LineString", "coordinates": coordinates }),
+    };
+
+    let mut features = vec![json!({
+        "type": "Feature",
+        "geometry": route,
+        "properties": {
+            "kind": "trip",
+            "trip_id": trip.trip_id,
+            "device_id": trip.device_id,
+            "asset_id": trip.asset_id,
+            "tenant_id": trip.tenant_id,
+            "start_time": trip.start_time,
+            "end_time": trip.end_time,
+            "distance_meters": trip.distance_meters,
+            "start_odometer_meters": trip.start_odometer_meters,
+            "end_odometer_meters": trip.end_odometer_meters,
+            "point_count": points.len(),
+        },
+    })];
+    features.extend(alerts.iter().map(|alert| {
+        let geometry = match (alert.lat, alert.lng) {
+            (Some(lat), Some(lng)) => json!({ "type": "Point", "coordinates": [lng, lat] }),
+            _ => Value::Null,
+        };
+        json!({
+            "type": "Feature",
+            "geometry": geometry,
+            "properties": {
+                "kind": "alert",
+                "alert_id": alert.alert_id,
+                "alert_type": alert.alert_type,
+                "timestamp": alert.timestamp,
+                "severity": alert.severity,
+                "raw_code": alert.raw_code,
+                "metadata": alert.metadata.as_ref().map(|m| &m.0),
+            },
+        })
+    }));
+
+    json!({ "type": "FeatureCollection", "features": features })
+}
+
+/// Loads a trip with its points and alerts and builds its GeoJSON. Returns
+/// `None` if the trip is not in this database.
+pub async fn export_trip(pool: &DbPool, trip_id: Uuid) -> anyhow::Result<Option<Value>> {
+    let trip: Option<Trip> = sqlx::query_as(queries::SELECT_TRIP_BY_ID)
+        .bind(trip_id)
+        .fetch_optional(pool)
+        .await?;
+    let Some(trip) = trip else {
+        return Ok(None);
+    };
+    let points = points::select_trip_points(pool, trip_id).await?;
+    let alerts = alerts::select_trip_alerts(pool, trip_id).await?;
+    Ok(Some(trip_feature_collection(&trip, &points, &alerts)))
+}
+
+/// [`export_trip`] on the first shard that has the trip.
+pub async fn export_trip_all_shards(
+    pools: &ShardedPool,
+    trip_id: Uuid,
+) -> anyhow::Result<Option<Value>> {
+    for pool in pools.all() {
+        if let Some(geojson) = export_trip(pool, trip_id).await? {
+            return Ok(Some(geojson));
+        }
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{TimeZone, Utc};
+
+    fn trip() -> Trip {
+        Trip {
+            trip_id: Uuid::new_v4(),
+            device_id: "GEO-1".to_string(),
+            start_time: Utc.timestamp_opt(1_700_000_000, 0).single().unwrap(),
+            start_lat: Some(20.65),
+            start_lng: Some(-100.39),
+            end_time: None,
+            end_lat: None,
+            end_lng: None,
+            distance_meters: None,
+            start_odometer_meters: None,
+            end_odometer_meters: None,
+            point_count: None,
+            tenant_id: None,
+            asset_id: None,
+        }
+    }
+
+    fn point(trip: &Trip, lat: f64, lng: f64) -> TripPoint {
+        TripPoint {
+            point_id: 1,
+            trip_id: trip.trip_id,
+            device_id: trip.device_id.clone(),
+            timestamp: trip.start_time,
+            lat,
+            lng,
+            speed: None,
+            heading: None,
+            odometer_meters: None,
+            correlation_id: Uuid::new_v4(),
+            altitude: None,
+            tenant_id: None,
+            location_source: None,
+        }
+    }
+
+    #[test]
+    fn test_route_geometry_by_point_count() {
+        let trip = trip();
+
+        let empty = trip_feature_collection(&trip, &[], &[]);
+        assert_eq!(empty["features"][0]["geometry"], Value::Null);
+
+        let single = trip_feature_collection(&trip, &[point(&trip, 20.65, -100.39)], &[]);
+        assert_eq!(single["features"][0]["geometry"]["type"], "Point");
+        assert_eq!(
+            single["features"][0]["geometry"]["coordinates"],
+            json!([-100.39, 20.65])
+        );
+
+        let route = trip_feature_collection(
+            &trip,
+            &[point(&trip, 20.65, -100.39), point(&trip, 20.66, -100.40)],
+            &[],
+        );
+        assert_eq!(route["type"], "FeatureCollection");
+        assert_eq!(route["features"][0]["geometry"]["type"], "LineString");
+        assert_eq!(route["features"][0]["properties"]["point_count"], 2);
+    }
+}
+
+#[cfg(all(test, feature = "db-tests"))]
+mod db_tests {
+    use super::*;
+    use crate::db::test_support::TestDb;
+    use chrono::{Duration, TimeZone, Utc};
+
+    #[tokio::test]
+    async fn test_export_seeded_trip() {
+        let db = TestDb::new().await;
+        let t0 = Utc.timestamp_opt(1_700_000_000, 0).single().unwrap();
+        let trip_id = Uuid::new_v4();
+
+        sqlx::query(
+            "INSERT INTO trips (trip_id, device_id, start_time, start_lat, start_lng, end_time, distance_meters) \
+             VALUES ($1, 'GEOJSON-001', $2, 20.65, -100.39, $3, 1500.0)",
+        )
+        .bind(trip_id)
+        .bind(t0)
+        .bind(t0 + Duration::minutes(10))
+        .execute(&db.pool)
+        .await
+        .unwrap();
+        for (offset, lat, lng) in [(60, 20.651, -100.391), (0, 20.650, -100.390)] {
+            sqlx::query(
+                "INSERT INTO trip_points (trip_id, device_id, \"timestamp\", lat, lng, correlation_id) \
+                 VALUES ($1, 'GEOJSON-001', $2, $3, $4, $5)",
+            )
+            .bind(trip_id)
+            .bind(t0 + Duration::seconds(offset))
+            .bind(lat)
+            .bind(lng)
+            .bind(Uuid::new_v4())
+            .execute(&db.pool)
+            .await
+            .unwrap();
+        }
+        sqlx::query(queries::INSERT_TRIP_ALERT)
+            .bind(Uuid::new_v4())
+            .bind(trip_id)
+            .bind(t0 + Duration::seconds(30))
+            .bind(Some(20.6505))
+            .bind(Some(-100.3905))
+            .bind("speeding")
+            .bind(None::<i32>)
+            .bind(2i16)
+            .bind("GEOJSON-001")
+            .bind(Uuid::new_v4())
+            .bind("SPEEDING")
+            .bind(None::<&str>)
+            .execute(&db.pool)
+            .await
+            .unwrap();
+
+        let geojson = export_trip_all_shards(&db.sharded(), trip_id)
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(geojson["type"], "FeatureCollection");
+        let features = geojson["features"].as_array().unwrap();
+        assert_eq!(features.len(), 2);
+
+        // Route ordered by time, as [lng, lat]
+        let route = &features[0];
+        assert_eq!(route["type"], "Feature");
+        assert_eq!(route["geometry"]["type"], "LineString");
+        assert_eq!(
+            route["geometry"]["coordinates"],
+            json!([[-100.390, 20.650], [-100.391, 20.651]])
+        );
+        assert_eq!(route["properties"]["trip_id"], trip_id.to_string());
+        assert_eq!(route["properties"]["device_id"], "GEOJSON-001");
+        assert_eq!(route["properties"]["distance_meters"], 1500.0);
+
+        let alert = &features[1];
+        assert_eq!(alert["geometry"]["type"], "Point");
+        assert_eq!(
+            alert["geometry"]["coordinates"],
+            json!([-100.3905, 20.6505])
+        );
+        assert_eq!(alert["properties"]["kind"], "alert");
+        assert_eq!(alert["properties"]["alert_type"], "speeding");
+
+        assert!(export_trip_all_shards(&db.sharded(), Uuid::new_v4())
+            .await
+            .unwrap()
+            .is_none());
+
+        db.cleanup().await;
+    }
+}
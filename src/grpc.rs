@@ -0,0 +1,182 @@
+use crate::db::devices;
+use crate::db::sharding::ShardedPool;
+use crate::models::proto::current_state_to_proto;
+use crate::models::siscom::v1::device_state_service_server::{
+    DeviceStateService, DeviceStateServiceServer,
+};
+use crate::models::siscom::v1::{CurrentState, DeviceStateRequest};
+use futures::Stream;
+use std::pin::Pin;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tonic::{Request, Response, Status};
+use tracing::{error, info};
+
+/// `siscom.v1.DeviceStateService`: live `trip_current_state` of a device.
+#[derive(Clone)]
+pub struct DeviceStateGrpc {
+    pools: ShardedPool,
+    /// How often `StreamPositions` re-reads the state (`GRPC_STREAM_POLL_MS`).
+    poll_interval: Duration,
+}
+
+impl DeviceStateGrpc {
+    pub fn new(pools: ShardedPool, poll_interval: Duration) -> Self {
+        Self {
+            pools,
+            poll_interval,
+        }
+    }
+
+    async fn current_state(&self, device_id: &str) -> Result<Option<CurrentState>, Status> {
+        devices::fetch_current_state(self.pools.for_device(device_id), device_id)
+            .await
+            .map(|state| state.as_ref().map(current_state_to_proto))
+            .map_err(|e| {
+                error!("Current state query failed for device {}: {}", device_id, e);
+                Status::internal("query failed")
+            })
+    }
+}
+
+#[allow(clippy::result_large_err)]
+fn device_id(request: Request<DeviceStateRequest>) -> Result<String, Status> {
+    let device_id = request.into_inner().device_id.trim().to_string();
+    if device_id.is_empty() {
+        return Err(Status::invalid_argument("device_id is required"));
+    }
+    Ok(device_id)
+}
+
+#[tonic::async_trait]
+impl DeviceStateService for DeviceStateGrpc {
+    async fn get_current_state(
+        &self,
+        request: Request<DeviceStateRequest>,
+    ) -> Result<Response<CurrentState>, Status> {
+        let device_id = device_id(request)?;
+        match self.current_state(&device_id).await? {
+            Some(state) => Ok(Response::new(state)),
+            None => Err(Status::not_found(format!(
+                "no current state for device {}",
+                device_id
+            ))),
+        }
+    }
+
+    type StreamPositionsStream = Pin<Box<dyn Stream<Item = Result<CurrentState, Status>> + Send>>;
+
+    /// Polls the state every `poll_interval` and sends it whenever it changes,
+    /// until the client disconnects or a query fails.
+    async fn stream_positions(
+        &self,
+        request: Request<DeviceStateRequest>,
+    ) -> Result<Response<Self::StreamPositionsStream>, Status> {
+        let device_id = device_id(request)?;
+        let service = self.clone();
+        let (sender, receiver) = mpsc::channel(16);
+
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(service.poll_interval);
+            let mut last_sent = None;
+            loop {
+                interval.tick().await;
+                match service.current_state(&device_id).await {
+                    Ok(Some(state)) if last_sent.as_ref() != Some(&state) => {
+                        if sender.send(Ok(state.clone())).await.is_err() {
+                            break;
+                        }
+                        last_sent = Some(state);
+                    }
+                    Ok(_) => {}
+                    Err(status) => {
+                        let _ = sender.send(Err(status)).await;
+                        break;
+                    }
+                }
+            }
+        });
+
+        let stream = futures::stream::unfold(receiver, |mut receiver| async move {
+            receiver.recv().await.map(|item| (item, receiver))
+        });
+        Ok(Response::new(Box::pin(stream)))
+    }
+}
+
+/// Serves `DeviceStateService` on `port`.
+pub async fn serve(port: u16, service: DeviceStateGrpc) -> anyhow::Result<()> {
+    let addr = ([0, 0, 0, 0], port).into();
+    info!("gRPC server listening on port {}", port);
+    tonic::transport::Server::builder()
+        .add_service(DeviceStateServiceServer::new(service))
+        .serve(addr)
+        .await?;
+    Ok(())
+}
+
+#[cfg(all(test, feature = "db-tests"))]
+mod tests {
+    use super::*;
+    use crate::db::test_support::TestDb;
+    use crate::models::siscom::v1::device_state_service_client::DeviceStateServiceClient;
+    use chrono::{TimeZone, Utc};
+    use tonic::transport::server::TcpIncoming;
+    use uuid::Uuid;
+
+    #[tokio::test]
+    async fn test_get_current_state_returns_seeded_row() {
+        let db = TestDb::new().await;
+        let t0 = Utc.timestamp_opt(1_700_000_000, 0).single().unwrap();
+        let trip_id = Uuid::new_v4();
+        sqlx::query(
+            "INSERT INTO trip_current_state (device_id, current_trip_id, ignition_on, last_point_at, last_lat, last_lng, last_speed, last_odometer_meters, last_updated_at) \
+             VALUES ('GRPC-1', $1, true, $2, 20.65, -100.39, 42.5, 1500, $2)",
+        )
+        .bind(trip_id)
+        .bind(t0)
+        .execute(&db.pool)
+        .await
+        .unwrap();
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let incoming = TcpIncoming::from_listener(listener, true, None).unwrap();
+        let service = DeviceStateGrpc::new(db.sharded(), Duration::from_millis(100));
+        tokio::spawn(
+            tonic::transport::Server::builder()
+                .add_service(DeviceStateServiceServer::new(service))
+                .serve_with_incoming(incoming),
+        );
+
+        let mut client = DeviceStateServiceClient::connect(format!("http://{}", addr))
+            .await
+            .unwrap();
+        let state = client
+            .get_current_state(DeviceStateRequest {
+                device_id: "GRPC-1".to_string(),
+            })
+            .await
+            .unwrap()
+            .into_inner();
+
+        assert_eq!(state.device_id, "GRPC-1");
+        assert!(state.ignition_on);
+        assert_eq!(state.current_trip_id, Some(trip_id.to_string()));
+        assert_eq!(state.last_point_epoch, Some(1_700_000_000));
+        assert_eq!(state.lat, Some(20.65));
+        assert_eq!(state.lng, Some(-100.39));
+        assert_eq!(state.speed, Some(42.5));
+        assert_eq!(state.odometer_meters, Some(1500));
+
+        let missing = client
+            .get_current_state(DeviceStateRequest {
+                device_id: "GRPC-UNKNOWN".to_string(),
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(missing.code(), tonic::Code::NotFound);
+
+        db.cleanup().await;
+    }
+}
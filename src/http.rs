@@ -0,0 +1,293 @@
+use crate::broker_failover::BrokerRotation;
+use crate::circuit_breaker::{BreakerState, CircuitBreaker};
+use crate::db::devices;
+use crate::db::points::{self, BoundingBox};
+use crate::db::sharding::ShardedPool;
+use crate::metrics;
+use crate::models::current_state::{CurrentState, DeviceHealth};
+use crate::models::trip_points::TripPoint;
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use axum::routing::get;
+use axum::{Json, Router};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::Instant;
+use tracing::{error, info};
+
+/// Shared state of the HTTP handlers.
+#[derive(Clone)]
+pub struct HttpState {
+    pub db_breaker: Arc<CircuitBreaker>,
+    /// Kafka cluster the consumer is connected to, reported by `/health`.
+    pub brokers: Arc<BrokerRotation>,
+    pub pools: ShardedPool,
+    /// Cap on the points returned by `/points` (`BBOX_MAX_POINTS`).
+    pub max_bbox_points: i64,
+}
+
+/// Page size of `/devices/health` when `limit` is not given, and its maximum.
+const DEFAULT_HEALTH_PAGE: i64 = 100;
+const MAX_HEALTH_PAGE: i64 = 1000;
+
+/// Serves the HTTP endpoints (`/metrics`, `/health`, `/points`,
+/// `/devices/health`, `/devices/{id}/state`).
+pub async fn serve(port: u16, state: HttpState) -> anyhow::Result<()> {
+    let app = Router::new()
+        .route("/metrics", get(metrics_handler))
+        .route("/health", get(health_handler))
+        .route("/points", get(points_handler))
+        .route("/devices/health", get(fleet_health_handler))
+        .route("/devices/:device_id/state", get(device_state_handler))
+        .with_state(state);
+
+    let listener = tokio::net::TcpListener::bind(("0.0.0.0", port)).await?;
+    info!("HTTP server listening on port {}", port);
+    axum::serve(listener, app).await?;
+    Ok(())
+}
+
+async fn metrics_handler() -> String {
+    metrics::render()
+}
+
+/// Reports 503 while the database circuit breaker is open, along with the
+/// active Kafka cluster.
+async fn health_handler(State(state): State<HttpState>) -> (StatusCode, Json<serde_json::Value>) {
+    let breaker_state = state.db_breaker.state(Instant::now());
+    let (status, label) = match breaker_state {
+        BreakerState::Closed => (StatusCode::OK, "ok"),
+        BreakerState::Open => (StatusCode::SERVICE_UNAVAILABLE, "degraded"),
+    };
+    (
+        status,
+        Json(serde_json::json!({
+            "status": label,
+            "db_circuit_breaker": breaker_state.as_str(),
+            "kafka_bootstrap_servers": state.brokers.active(),
+        })),
+    )
+}
+
+/// `GET /points?min_lat=..&min_lon=..&max_lat=..&max_lon=..&from=..&to=..`
+/// (`from`/`to` in RFC 3339).
+#[derive(Debug, Deserialize)]
+struct PointsQuery {
+    min_lat: f64,
+    min_lon: f64,
+    max_lat: f64,
+    max_lon: f64,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+    /// Only points of this tenant; all tenants when absent.
+    tenant: Option<String>,
+}
+
+/// Trip points inside a map viewport, oldest first, capped at `BBOX_MAX_POINTS`.
+async fn points_handler(
+    State(state): State<HttpState>,
+    Query(query): Query<PointsQuery>,
+) -> Result<Json<Vec<TripPoint>>, (StatusCode, String)> {
+    let bbox = BoundingBox {
+        min_lat: query.min_lat,
+        min_lon: query.min_lon,
+        max_lat: query.max_lat,
+        max_lon: query.max_lon,
+    };
+    if !bbox.is_valid() || query.from > query.to {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "invalid bounding box or time range".to_string(),
+        ));
+    }
+    points::select_points_in_bbox_all_shards(
+        &state.pools,
+        &bbox,
+        query.from,
+        query.to,
+        state.max_bbox_points,
+        query.tenant.as_deref(),
+    )
+    .await
+    .map(Json)
+    .map_err(|e| {
+        error!("Bounding box query failed: {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "query failed".to_string(),
+        )
+    })
+}
+
+/// `trip_current_state` row of a device, for support; 404 if it never reported.
+async fn device_state_handler(
+    State(state): State<HttpState>,
+    Path(device_id): Path<String>,
+) -> Result<Json<CurrentState>, (StatusCode, String)> {
+    match devices::fetch_current_state(state.pools.for_device(&device_id), &device_id).await {
+        Ok(Some(current)) => Ok(Json(current)),
+        Ok(None) => Err((
+            StatusCode::NOT_FOUND,
+            format!("no current state for device {}", device_id),
+        )),
+        Err(e) => {
+            error!("Current state query failed for device {}: {}", device_id, e);
+            Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "query failed".to_string(),
+            ))
+        }
+    }
+}
+
+/// `GET /devices/health?limit=..&after=..`: `after` is the `next_after` of
+/// the previous page.
+#[derive(Debug, Deserialize)]
+struct FleetHealthQuery {
+    limit: Option<i64>,
+    after: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct FleetHealthPage {
+    devices: Vec<DeviceHealth>,
+    /// Cursor of the next page; absent on the last one.
+    next_after: Option<String>,
+}
+
+/// Health report of every device (ignition, age of the last point, open trip
+/// and last position), paged by device id.
+async fn fleet_health_handler(
+    State(state): State<HttpState>,
+    Query(query): Query<FleetHealthQuery>,
+) -> Result<Json<FleetHealthPage>, (StatusCode, String)> {
+    let limit = query.limit.unwrap_or(DEFAULT_HEALTH_PAGE);
+    if !(1..=MAX_HEALTH_PAGE).contains(&limit) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("limit must be between 1 and {}", MAX_HEALTH_PAGE),
+        ));
+    }
+    let devices = devices::select_fleet_health_all_shards(
+        &state.pools,
+        query.after.as_deref().unwrap_or(""),
+        limit,
+        Utc::now(),
+    )
+    .await
+    .map_err(|e| {
+        error!("Fleet health query failed: {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "query failed".to_string(),
+        )
+    })?;
+    let next_after = (devices.len() as i64 == limit)
+        .then(|| devices.last().map(|d| d.device_id.clone()))
+        .flatten();
+    Ok(Json(FleetHealthPage {
+        devices,
+        next_after,
+    }))
+}
+
+#[cfg(all(test, feature = "db-tests"))]
+mod tests {
+    use super::*;
+    use crate::db::test_support::TestDb;
+    use chrono::TimeZone;
+    use std::time::Duration;
+    use uuid::Uuid;
+
+    fn http_state(db: &TestDb) -> HttpState {
+        HttpState {
+            db_breaker: Arc::new(CircuitBreaker::new(3, Duration::from_secs(30))),
+            brokers: Arc::new(BrokerRotation::parse("localhost:9092")),
+            pools: db.sharded(),
+            max_bbox_points: 100,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_device_state_found_and_not_found() {
+        let db = TestDb::new().await;
+        let t0 = Utc.timestamp_opt(1_700_000_000, 0).single().unwrap();
+        let trip_id = Uuid::new_v4();
+        let correlation_id = Uuid::new_v4();
+        sqlx::query(
+            "INSERT INTO trip_current_state (device_id, current_trip_id, ignition_on, last_point_at, last_lat, last_lng, last_correlation_id, last_updated_at) \
+             VALUES ('HTTP-1', $1, true, $2, 20.65, -100.39, $3, $2)",
+        )
+        .bind(trip_id)
+        .bind(t0)
+        .bind(correlation_id)
+        .execute(&db.pool)
+        .await
+        .unwrap();
+
+        let Json(found) = device_state_handler(State(http_state(&db)), Path("HTTP-1".to_string()))
+            .await
+            .unwrap();
+        assert!(found.ignition_on);
+        assert_eq!(found.current_trip_id, Some(trip_id));
+        assert_eq!(found.last_point_at, Some(t0));
+        assert_eq!(found.last_lat, Some(20.65));
+        assert_eq!(found.last_correlation_id, Some(correlation_id));
+
+        let json = serde_json::to_value(&found).unwrap();
+        assert_eq!(json["device_id"], "HTTP-1");
+        assert_eq!(json["current_trip_id"], trip_id.to_string());
+
+        let (status, _) =
+            device_state_handler(State(http_state(&db)), Path("HTTP-UNKNOWN".to_string()))
+                .await
+                .unwrap_err();
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        db.cleanup().await;
+    }
+
+    #[tokio::test]
+    async fn test_fleet_health_pages_with_cursor() {
+        let db = TestDb::new().await;
+        for device_id in ["HEALTH-1", "HEALTH-2", "HEALTH-3"] {
+            sqlx::query(
+                "INSERT INTO trip_current_state (device_id, ignition_on, last_point_at, last_lat, last_lng, last_updated_at) \
+                 VALUES ($1, false, now(), 20.65, -100.39, now())",
+            )
+            .bind(device_id)
+            .execute(&db.pool)
+            .await
+            .unwrap();
+        }
+        let query = |limit, after: Option<&str>| FleetHealthQuery {
+            limit: Some(limit),
+            after: after.map(str::to_string),
+        };
+
+        let Json(first) = fleet_health_handler(State(http_state(&db)), Query(query(2, None)))
+            .await
+            .unwrap();
+        assert_eq!(first.devices.len(), 2);
+        assert_eq!(first.next_after.as_deref(), Some("HEALTH-2"));
+
+        let Json(last) =
+            fleet_health_handler(State(http_state(&db)), Query(query(2, Some("HEALTH-2"))))
+                .await
+                .unwrap();
+        assert_eq!(last.devices[0].device_id, "HEALTH-3");
+        assert_eq!(last.next_after, None);
+
+        let json = serde_json::to_value(&last).unwrap();
+        assert_eq!(json["devices"][0]["ignition_on"], false);
+        assert!(json["devices"][0]["active_trip_id"].is_null());
+
+        let (status, _) = fleet_health_handler(State(http_state(&db)), Query(query(0, None)))
+            .await
+            .unwrap_err();
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        db.cleanup().await;
+    }
+}
@@ -1,58 +1,321 @@
+use crate::backoff::Backoff;
+use crate::broker_failover::BrokerRotation;
 use crate::config::AppConfig;
-use crate::db::DbPool;
+use crate::consumer_lag;
+use crate::db::sharding::ShardedPool;
+use crate::metrics;
+use crate::models::siscom::v1::KafkaMessage;
+use crate::processor::context::ProcessorContext;
 use crate::processor::message_processor;
+use crate::watchdog::Watchdog;
+use crate::worker_pool::WorkerPool;
 use rdkafka::config::ClientConfig;
 use rdkafka::consumer::{Consumer, StreamConsumer};
-use rdkafka::message::Message;
+use rdkafka::message::{Headers, Message};
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use tracing::{error, info, warn};
 
-/// Starts the Kafka consumer with SASL/SCRAM authentication and a circuit breaker mechanism.
-pub async fn start_kafka_consumer(config: &AppConfig, pool: DbPool) -> anyhow::Result<()> {
-    info!(
-        "Initializing Kafka consumer for topic: {}",
-        config.kafka_topic
-    );
-
+/// Client configuration shared by the consumer and producers (brokers + SASL).
+/// Producers use the first cluster of `KAFKA_BOOTSTRAP_SERVERS`; the consumer
+/// overrides it with the active one.
+pub fn base_client_config(config: &AppConfig) -> ClientConfig {
+    let brokers = BrokerRotation::parse(&config.kafka_bootstrap_servers);
     let mut client_config = ClientConfig::new();
     client_config
-        .set("bootstrap.servers", &config.kafka_bootstrap_servers)
-        .set("group.id", &config.kafka_group_id)
-        .set("auto.offset.reset", &config.kafka_auto_offset_reset)
+        .set("bootstrap.servers", brokers.active())
         // SASL Configuration
         .set("security.protocol", &config.kafka_security_protocol)
         .set("sasl.mechanism", &config.kafka_sasl_mechanism)
         .set("sasl.username", &config.kafka_username)
         .set("sasl.password", &config.kafka_password);
+    client_config
+}
+
+/// A consumed payload and the header properties to merge into its data map.
+/// `message` is set when the consumer loop already decoded it for routing.
+struct Inbound {
+    payload: Vec<u8>,
+    properties: Vec<(String, String)>,
+    message: Option<KafkaMessage>,
+    position: MessagePosition,
+}
+
+/// Where a consumed message came from, for the per-partition metrics.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MessagePosition {
+    pub topic: String,
+    pub partition: i32,
+    pub offset: i64,
+}
+
+impl MessagePosition {
+    pub fn of<M: Message>(message: &M) -> Self {
+        Self {
+            topic: message.topic().to_string(),
+            partition: message.partition(),
+            offset: message.offset(),
+        }
+    }
+}
+
+/// Values of the headers listed in `KAFKA_HEADER_PROPERTIES` (e.g. `vendor`,
+/// `tenant`), matched case-insensitively and keyed by the upper-cased name as
+/// in the data map. Headers without a value or not valid UTF-8 are skipped.
+pub fn header_properties<H: Headers>(
+    headers: Option<&H>,
+    wanted: &[String],
+) -> Vec<(String, String)> {
+    let Some(headers) = headers else {
+        return Vec::new();
+    };
+    headers
+        .iter()
+        .filter(|header| {
+            wanted
+                .iter()
+                .any(|name| name.eq_ignore_ascii_case(header.key))
+        })
+        .filter_map(|header| {
+            let value = std::str::from_utf8(header.value?).ok()?;
+            Some((header.key.to_uppercase(), value.to_string()))
+        })
+        .collect()
+}
+
+/// Worker key for a consumed message. The Kafka message key (the device id
+/// for our producers) is used as is, leaving the decoding to the worker;
+/// without one the payload is decoded here, header properties included, and
+/// the parsed message is returned so the worker doesn't decode it again.
+/// `None` if the payload was dropped while decoding.
+fn route_message(
+    ctx: &ProcessorContext,
+    key: Option<&[u8]>,
+    payload: &[u8],
+    properties: &[(String, String)],
+) -> Option<(String, Option<KafkaMessage>)> {
+    if let Some(key) = key.filter(|key| !key.is_empty()) {
+        return Some((String::from_utf8_lossy(key).trim().to_string(), None));
+    }
+    let message = message_processor::decode_message(ctx, payload, properties)?;
+    Some((message_processor::routing_key(&message), Some(message)))
+}
+
+/// Topic, or regex with `KAFKA_TOPIC_PATTERN`, to subscribe to. librdkafka
+/// treats names starting with `^` as a pattern and also assigns topics created
+/// later (on the next metadata refresh), so new per-tenant topics are consumed
+/// without a restart.
+pub fn topic_subscription(config: &AppConfig) -> anyhow::Result<String> {
+    let Some(pattern) = config.kafka_topic_pattern.as_deref() else {
+        return Ok(config.kafka_topic.clone());
+    };
+    validate_topic_pattern(pattern)
+        .map_err(|e| anyhow::anyhow!("invalid KAFKA_TOPIC_PATTERN '{}': {}", pattern, e))?;
+    if pattern.starts_with('^') {
+        Ok(pattern.to_string())
+    } else {
+        Ok(format!("^{}", pattern))
+    }
+}
+
+/// Catches the usual mistakes (unbalanced groups or classes, a dangling
+/// escape, a leading quantifier) at startup; librdkafka compiles the
+/// expression itself when subscribing.
+fn validate_topic_pattern(pattern: &str) -> anyhow::Result<()> {
+    let body = pattern.strip_prefix('^').unwrap_or(pattern);
+    anyhow::ensure!(!body.is_empty(), "empty pattern");
+    anyhow::ensure!(
+        !body.starts_with(['*', '+', '?', '{']),
+        "nothing to repeat at the start"
+    );
+    let mut groups = 0usize;
+    let mut in_class = false;
+    let mut chars = body.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => anyhow::ensure!(chars.next().is_some(), "dangling escape at the end"),
+            ']' if in_class => in_class = false,
+            _ if in_class => {}
+            '[' => in_class = true,
+            '(' => groups += 1,
+            ')' => {
+                groups = groups
+                    .checked_sub(1)
+                    .ok_or_else(|| anyhow::anyhow!("unmatched ')'"))?
+            }
+            _ => {}
+        }
+    }
+    anyhow::ensure!(!in_class, "unclosed '['");
+    anyhow::ensure!(groups == 0, "unclosed '('");
+    Ok(())
+}
 
-    // Create the consumer
-    let consumer: StreamConsumer = client_config.create()?;
+/// Creates a consumer on `bootstrap_servers` and subscribes it to `topic`.
+fn subscribe_consumer(
+    config: &AppConfig,
+    topic: &str,
+    bootstrap_servers: &str,
+) -> anyhow::Result<Arc<StreamConsumer>> {
+    let mut client_config = base_client_config(config);
+    client_config
+        .set("bootstrap.servers", bootstrap_servers)
+        .set("group.id", &config.kafka_group_id)
+        .set("auto.offset.reset", &config.kafka_auto_offset_reset);
 
-    consumer.subscribe(&[&config.kafka_topic])?;
-    info!("Subscribed to topic: {}", config.kafka_topic);
+    let consumer: Arc<StreamConsumer> = Arc::new(client_config.create()?);
+    consumer.subscribe(&[topic])?;
+    info!("Subscribed to topic {} on {}", topic, bootstrap_servers);
+    Ok(consumer)
+}
 
-    let pool = Arc::new(pool);
+/// Starts the Kafka consumer with SASL/SCRAM authentication and a circuit breaker mechanism.
+/// With several clusters in `KAFKA_BOOTSTRAP_SERVERS`, a tripped breaker
+/// fails over to the next one; the cooldown applies once every cluster failed.
+pub async fn start_kafka_consumer(
+    config: &AppConfig,
+    pools: ShardedPool,
+    ctx: Arc<ProcessorContext>,
+    brokers: Arc<BrokerRotation>,
+) -> anyhow::Result<()> {
+    let topic = topic_subscription(config)?;
+    info!("Initializing Kafka consumer for topic: {}", topic);
+
+    let mut consumer = subscribe_consumer(config, &topic, brokers.active())?;
+    let lag_refresh = Duration::from_secs(config.metrics_refresh_seconds);
+    let mut lag_task = tokio::spawn(consumer_lag::refresh_consumer_lag(
+        consumer.clone(),
+        lag_refresh,
+    ));
+
+    let pools = Arc::new(pools);
+    let handler = {
+        let pools = pools.clone();
+        let ctx = ctx.clone();
+        move |inbound: Inbound| {
+            let pools = pools.clone();
+            let ctx = ctx.clone();
+            async move {
+                let message = inbound.message.or_else(|| {
+                    message_processor::decode_message(&ctx, &inbound.payload, &inbound.properties)
+                });
+                if let Some(message) = message {
+                    if let Err(e) = message_processor::process_message_with_attempts(
+                        &pools,
+                        &ctx,
+                        &inbound.payload,
+                        message,
+                    )
+                    .await
+                    {
+                        error!("Error processing message: {}", e);
+                    }
+                }
+                metrics::record_partition_processed(
+                    &inbound.position.topic,
+                    inbound.position.partition,
+                    inbound.position.offset,
+                );
+            }
+        }
+    };
+    let workers = if config.process_inline {
+        info!("PROCESS_INLINE enabled: processing messages in the consumer loop");
+        WorkerPool::inline(handler)
+    } else {
+        info!(
+            "Started {} workers (queue capacity {})",
+            config.worker_count, config.worker_queue_capacity
+        );
+        WorkerPool::spawn(config.worker_count, config.worker_queue_capacity, handler)
+    };
     let mut consecutive_failures = 0;
     let max_retries = config.kafka_max_retries;
     let cooldown_duration = Duration::from_secs(config.kafka_circuit_breaker_cooldown);
+    let mut backoff = Backoff::new(
+        Duration::from_millis(500),
+        Duration::from_millis(config.kafka_backoff_max_ms),
+    );
+
+    // Watchdog (opt-in): resubscribe if no message arrives within the timeout
+    let mut watchdog = (config.watchdog_timeout_seconds > 0).then(|| {
+        Watchdog::new(
+            Duration::from_secs(config.watchdog_timeout_seconds),
+            Instant::now(),
+        )
+    });
 
     loop {
         // Circuit Breaker Check
         if consecutive_failures >= max_retries {
-            warn!(
-                "Circuit breaker tripped ({} consecutive failures)! Sleeping for {} seconds...",
-                consecutive_failures, config.kafka_circuit_breaker_cooldown
-            );
-            tokio::time::sleep(cooldown_duration).await;
+            let failed_over = brokers.cluster_count() > 1 && brokers.rotate() != 0;
+            if !failed_over {
+                warn!(
+                    "Circuit breaker tripped ({} consecutive failures)! Sleeping for {} seconds...",
+                    consecutive_failures, config.kafka_circuit_breaker_cooldown
+                );
+                tokio::time::sleep(cooldown_duration).await;
+            }
+            if brokers.cluster_count() > 1 {
+                warn!("Failing over to Kafka cluster {}", brokers.active());
+                match subscribe_consumer(config, &topic, brokers.active()) {
+                    Ok(next) => {
+                        consumer = next;
+                        lag_task.abort();
+                        lag_task = tokio::spawn(consumer_lag::refresh_consumer_lag(
+                            consumer.clone(),
+                            lag_refresh,
+                        ));
+                    }
+                    Err(e) => error!("Failed to create consumer for {}: {}", brokers.active(), e),
+                }
+            }
             consecutive_failures = 0;
+            backoff.reset();
             info!("Circuit breaker reset. Resuming consumption.");
         }
 
-        match consumer.recv().await {
+        // Database circuit breaker: stop pulling messages while the DB is down
+        if let Some(remaining) = ctx.db_breaker.remaining_open(Instant::now()) {
+            warn!(
+                "Database circuit breaker open, pausing consumption for {} seconds...",
+                remaining.as_secs()
+            );
+            tokio::time::sleep(remaining).await;
+            info!("Database circuit breaker reset. Resuming consumption.");
+        }
+
+        let received = match watchdog.as_mut() {
+            Some(watchdog) => {
+                match tokio::time::timeout(watchdog.remaining(Instant::now()), consumer.recv())
+                    .await
+                {
+                    Ok(received) => received,
+                    Err(_) => {
+                        warn!(
+                            "Watchdog: no messages received in {} seconds, resubscribing to {}",
+                            config.watchdog_timeout_seconds, topic
+                        );
+                        consumer.unsubscribe();
+                        if let Err(e) = consumer.subscribe(&[&topic]) {
+                            error!("Watchdog resubscribe failed: {}", e);
+                        }
+                        watchdog.touch(Instant::now());
+                        continue;
+                    }
+                }
+            }
+            None => consumer.recv().await,
+        };
+
+        match received {
             Ok(m) => {
                 // Success: Reset failure counter
                 consecutive_failures = 0;
+                backoff.reset();
+                if let Some(watchdog) = watchdog.as_mut() {
+                    watchdog.touch(Instant::now());
+                }
 
                 let payload = match m.payload() {
                     None => {
@@ -61,18 +324,34 @@ pub async fn start_kafka_consumer(config: &AppConfig, pool: DbPool) -> anyhow::R
                     }
                     Some(p) => p,
                 };
+                // Before copying it for the worker
+                if message_processor::reject_oversized(&ctx, payload) {
+                    continue;
+                }
 
-                let pool_clone = pool.clone();
-                let payload_vec = payload.to_vec();
-
-                // Process the message in a background task to not block the consumer loop
-                tokio::spawn(async move {
-                    if let Err(e) =
-                        message_processor::process_message(&pool_clone, &payload_vec).await
-                    {
-                        error!("Error processing message: {}", e);
-                    }
-                });
+                // Same device -> same worker, so trip state is applied in order.
+                // Waits (backpressure) when that worker's queue is full; with
+                // PROCESS_INLINE it waits for the message to be processed.
+                let properties = header_properties(m.headers(), &config.kafka_header_properties);
+                let position = MessagePosition::of(&m);
+                let Some((key, message)) = route_message(&ctx, m.key(), payload, &properties)
+                else {
+                    metrics::record_partition_processed(
+                        &position.topic,
+                        position.partition,
+                        position.offset,
+                    );
+                    continue;
+                };
+                let inbound = Inbound {
+                    payload: payload.to_vec(),
+                    properties,
+                    message,
+                    position,
+                };
+                if let Err(e) = workers.dispatch(&key, inbound).await {
+                    error!("Failed to dispatch message to worker: {}", e);
+                }
             }
             Err(e) => {
                 error!(
@@ -83,9 +362,175 @@ pub async fn start_kafka_consumer(config: &AppConfig, pool: DbPool) -> anyhow::R
                 );
                 consecutive_failures += 1;
 
-                // Small delay to prevent tight loop in case of minor network glitches
-                tokio::time::sleep(Duration::from_millis(500)).await;
+                // Jittered backoff so consumers don't reconnect in lockstep after a broker restart
+                let delay = backoff.next_delay();
+                warn!("Retrying Kafka in {} ms", delay.as_millis());
+                tokio::time::sleep(delay).await;
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rdkafka::message::{Header, OwnedHeaders, OwnedMessage, Timestamp};
+
+    #[test]
+    fn test_topic_subscription_literal_or_pattern() {
+        let mut config = AppConfig::development();
+        assert_eq!(topic_subscription(&config).unwrap(), "siscom-minimal");
+
+        config.kafka_topic_pattern = Some("^siscom\\..*".to_string());
+        assert_eq!(topic_subscription(&config).unwrap(), "^siscom\\..*");
+
+        // Sin `^` librdkafka lo tomaría como nombre literal
+        config.kafka_topic_pattern = Some("siscom-(acme|beta)".to_string());
+        assert_eq!(topic_subscription(&config).unwrap(), "^siscom-(acme|beta)");
+    }
+
+    #[test]
+    fn test_invalid_topic_pattern_is_rejected() {
+        let mut config = AppConfig::development();
+        for pattern in [
+            "^",
+            "*siscom",
+            "siscom-(acme",
+            "siscom)",
+            "siscom-[a-z",
+            "siscom\\",
+        ] {
+            config.kafka_topic_pattern = Some(pattern.to_string());
+            let err = topic_subscription(&config).unwrap_err();
+            assert!(
+                err.to_string().contains("KAFKA_TOPIC_PATTERN"),
+                "{}",
+                pattern
+            );
+        }
+        assert!(validate_topic_pattern("siscom-[()]+").is_ok());
+    }
+
+    #[test]
+    fn test_header_properties_extracts_wanted_headers() {
+        let headers = OwnedHeaders::new()
+            .insert(Header {
+                key: "Vendor",
+                value: Some("queclink"),
+            })
+            .insert(Header {
+                key: "tenant",
+                value: Some("acme"),
+            })
+            .insert(Header {
+                key: "trace-id",
+                value: Some("abc"),
+            })
+            .insert(Header::<&[u8]> {
+                key: "region",
+                value: None,
+            });
+        let wanted = vec![
+            "vendor".to_string(),
+            "TENANT".to_string(),
+            "region".to_string(),
+        ];
+
+        assert_eq!(
+            header_properties(Some(&headers), &wanted),
+            vec![
+                ("VENDOR".to_string(), "queclink".to_string()),
+                ("TENANT".to_string(), "acme".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_header_properties_without_headers_or_config() {
+        let headers = OwnedHeaders::new().insert(Header {
+            key: "vendor",
+            value: Some("queclink"),
+        });
+
+        assert!(header_properties(Some(&headers), &[]).is_empty());
+        assert!(header_properties::<OwnedHeaders>(None, &["vendor".to_string()]).is_empty());
+    }
+
+    #[test]
+    fn test_header_properties_skips_non_utf8_values() {
+        let headers = OwnedHeaders::new().insert(Header {
+            key: "vendor",
+            value: Some(&[0xff, 0xfe][..]),
+        });
+
+        assert!(header_properties(Some(&headers), &["vendor".to_string()]).is_empty());
+    }
+
+    fn payload_without_device_id() -> Vec<u8> {
+        use prost::Message as _;
+        KafkaMessage {
+            uuid: "msg-1".to_string(),
+            data: std::collections::HashMap::from([(
+                "GPS_EPOCH".to_string(),
+                "1700000000".to_string(),
+            )]),
+            ..Default::default()
+        }
+        .encode_to_vec()
+    }
+
+    #[test]
+    fn test_route_message_prefers_the_kafka_key() {
+        let ctx = ProcessorContext::new(AppConfig::development());
+
+        // Con clave no se decodifica: ni siquiera un payload ilegible
+        let (key, message) = route_message(&ctx, Some(b"DEV-1"), b"not protobuf", &[]).unwrap();
+        assert_eq!(key, "DEV-1");
+        assert!(message.is_none());
+    }
+
+    #[test]
+    fn test_route_message_uses_device_id_header_without_key() {
+        let ctx = ProcessorContext::new(AppConfig::development());
+        let properties = vec![("DEVICE_ID".to_string(), "DEV-2".to_string())];
+
+        for key in [None, Some(&b""[..])] {
+            let (key, message) =
+                route_message(&ctx, key, &payload_without_device_id(), &properties).unwrap();
+            assert_eq!(key, "DEV-2");
+            // El worker recibe el mensaje ya decodificado
+            let message = message.unwrap();
+            assert_eq!(message.uuid, "msg-1");
+            assert_eq!(message.data["DEVICE_ID"], "DEV-2");
+        }
+    }
+
+    #[test]
+    fn test_route_message_drops_undecodable_payload_without_key() {
+        let ctx = ProcessorContext::new(AppConfig::development());
+
+        assert!(route_message(&ctx, None, &[0x1f, 0x8b, 0x00], &[]).is_none());
+    }
+
+    #[test]
+    fn test_message_position_from_message() {
+        let message = OwnedMessage::new(
+            Some(b"payload".to_vec()),
+            Some(b"DEV-1".to_vec()),
+            "siscom-minimal".to_string(),
+            Timestamp::NotAvailable,
+            4,
+            1_234,
+            None,
+        );
+
+        assert_eq!(
+            MessagePosition::of(&message),
+            MessagePosition {
+                topic: "siscom-minimal".to_string(),
+                partition: 4,
+                offset: 1_234,
+            }
+        );
+    }
+}
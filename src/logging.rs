@@ -0,0 +1,124 @@
+use std::io;
+use tracing::warn;
+use tracing_subscriber::fmt::MakeWriter;
+
+/// Log output format, from `LOG_FORMAT`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LogFormat {
+    /// Human-readable multi-field lines (the `tracing_subscriber` default).
+    #[default]
+    Pretty,
+    /// Abbreviated single-line output.
+    Compact,
+    /// One JSON object per line; event and span fields become top-level keys.
+    Json,
+}
+
+impl LogFormat {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_lowercase().as_str() {
+            "" | "pretty" => Some(LogFormat::Pretty),
+            "compact" => Some(LogFormat::Compact),
+            "json" => Some(LogFormat::Json),
+            _ => None,
+        }
+    }
+}
+
+/// Installs the global subscriber. Unknown formats fall back to `pretty`.
+pub fn init(level: &str, format: &str) {
+    init_with(level, format, io::stdout);
+}
+
+/// Same as [`init`], but logs to stderr so stdout carries only command output.
+pub fn init_stderr(level: &str, format: &str) {
+    init_with(level, format, io::stderr);
+}
+
+fn init_with<W>(level: &str, format: &str, writer: W)
+where
+    W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+{
+    let parsed = LogFormat::parse(format);
+    install(level, parsed.unwrap_or_default(), writer);
+    if parsed.is_none() {
+        warn!("Unknown LOG_FORMAT '{}', using pretty", format);
+    }
+}
+
+fn install<W>(level: &str, format: LogFormat, writer: W)
+where
+    W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+{
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(level)
+        .with_writer(writer);
+    match format {
+        LogFormat::Pretty => builder.init(),
+        LogFormat::Compact => builder.compact().init(),
+        LogFormat::Json => builder
+            .json()
+            .flatten_event(true)
+            .with_current_span(true)
+            .init(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use tracing::{info, info_span};
+
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl io::Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl<'w> MakeWriter<'w> for Buffer {
+        type Writer = Buffer;
+
+        fn make_writer(&'w self) -> Self::Writer {
+            self.clone()
+        }
+    }
+
+    #[test]
+    fn test_parse_log_format() {
+        assert_eq!(LogFormat::parse(""), Some(LogFormat::Pretty));
+        assert_eq!(LogFormat::parse("pretty"), Some(LogFormat::Pretty));
+        assert_eq!(LogFormat::parse(" Compact "), Some(LogFormat::Compact));
+        assert_eq!(LogFormat::parse("JSON"), Some(LogFormat::Json));
+        assert_eq!(LogFormat::parse("logfmt"), None);
+    }
+
+    #[test]
+    fn test_json_format_emits_parseable_lines() {
+        let buffer = Buffer::default();
+        // Global subscriber: this is the only test in the crate that installs one
+        install("info", LogFormat::Json, buffer.clone());
+
+        info_span!("message", message_uuid = "3f1e2d4c").in_scope(|| {
+            info!(device_id = "0848086072", "Message destination");
+        });
+
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let line = output
+            .lines()
+            .find(|line| line.contains("Message destination"))
+            .expect("event was not logged");
+        let json: serde_json::Value = serde_json::from_str(line).unwrap();
+        assert_eq!(json["device_id"], "0848086072");
+        assert_eq!(json["message"], "Message destination");
+        assert_eq!(json["span"]["message_uuid"], "3f1e2d4c");
+    }
+}
@@ -1,30 +1,183 @@
+mod backoff;
+mod broker_failover;
+mod circuit_breaker;
+mod clock;
 mod config;
+mod consumer_lag;
 mod db;
+mod dead_letter;
+mod event_sink;
+mod geojson;
+mod grpc;
+mod http;
 mod kafka;
+mod logging;
+mod metrics;
 mod models;
+mod passthrough;
 mod processor;
+mod publisher;
+mod watchdog;
+mod worker_pool;
 
+use anyhow::Context;
+use broker_failover::BrokerRotation;
 use config::AppConfig;
-use tracing::info;
+use dead_letter::KafkaDeadLetterSink;
+use passthrough::KafkaPassthrough;
+use processor::context::ProcessorContext;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{error, info};
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    // Load config
-    let config = AppConfig::load()?;
+    // Load config (`--dev` uses the local development profile instead of the environment)
+    let config = if std::env::args().any(|arg| arg == "--dev") {
+        AppConfig::development()
+    } else {
+        AppConfig::load()?
+    };
 
-    // Init logging
-    tracing_subscriber::fmt()
-        .with_env_filter(&config.log_level)
-        .init();
+    // Init logging; an export to stdout logs to stderr so the output stays valid
+    let args: Vec<String> = std::env::args().collect();
+    let export_to_stdout =
+        args.iter().any(|arg| arg == "--export-trip") && !args.iter().any(|arg| arg == "--output");
+    if export_to_stdout {
+        logging::init_stderr(&config.log_level, &config.log_format);
+    } else {
+        logging::init(&config.log_level, &config.log_format);
+    }
 
     info!("Starting Siscom Trips Service (Kafka Edition)...");
+    info!("Configuration: {:?}", config);
 
     // Init DB
-    let pool = db::init_pool(&config.database_url).await?;
-    info!("Connected to database");
+    let pools = db::sharding::ShardedPool::connect(
+        &config.database_urls,
+        Duration::from_secs(config.db_connect_timeout_seconds),
+    )
+    .await?;
+    info!("Connected to {} database shard(s)", pools.all().len());
+
+    let events = event_sink::from_config(&config, &pools)?;
+
+    // Maintenance: close every open trip at its last known point and exit
+    if std::env::args().any(|arg| arg == "--close-open-trips") {
+        let ctx = ProcessorContext::new(config.clone()).with_event_sink(events);
+        let closed =
+            db::maintenance::close_open_trips_all_shards(&pools, &ctx, chrono::Utc::now()).await?;
+        info!("Closed {} open trip(s)", closed);
+        return Ok(());
+    }
+
+    // Maintenance: merge overlapping open trips of the same device and exit
+    if std::env::args().any(|arg| arg == "--reconcile-open-trips") {
+        let reconciled = db::maintenance::reconcile_all_open_trips_all_shards(&pools).await?;
+        info!(
+            "Reconciled overlapping open trips of {} device(s)",
+            reconciled
+        );
+        return Ok(());
+    }
+
+    // Maintenance: recompute distance and stats of one trip from its stored points and exit
+    if let Some(index) = args.iter().position(|arg| arg == "--recompute-trip") {
+        let trip_id: uuid::Uuid = args
+            .get(index + 1)
+            .context("--recompute-trip requires a trip uuid")?
+            .parse()
+            .context("--recompute-trip requires a valid trip uuid")?;
+        let source = processor::distance::DistanceSource::parse(&config.distance_source);
+        match db::maintenance::recompute_trip_all_shards(&pools, trip_id, source).await? {
+            Some(recomputed) => info!("Recomputed trip {}: {:?}", trip_id, recomputed),
+            None => anyhow::bail!("Trip {} not found", trip_id),
+        }
+        return Ok(());
+    }
+
+    // Export one trip (route and alerts) as GeoJSON to stdout or `--output <path>` and exit
+    if let Some(index) = args.iter().position(|arg| arg == "--export-trip") {
+        let trip_id: uuid::Uuid = args
+            .get(index + 1)
+            .context("--export-trip requires a trip uuid")?
+            .parse()
+            .context("--export-trip requires a valid trip uuid")?;
+        let option = |name: &str| {
+            args.iter()
+                .position(|arg| arg == name)
+                .and_then(|i| args.get(i + 1))
+        };
+        let format = option("--format").map_or("geojson", String::as_str);
+        if format != "geojson" {
+            anyhow::bail!("Unsupported --format '{}', expected geojson", format);
+        }
+        let Some(geojson) = geojson::export_trip_all_shards(&pools, trip_id).await? else {
+            anyhow::bail!("Trip {} not found", trip_id);
+        };
+        let body = serde_json::to_string_pretty(&geojson)?;
+        match option("--output") {
+            Some(path) => {
+                std::fs::write(path, body).with_context(|| format!("Failed to write {}", path))?;
+                info!("Exported trip {} to {}", trip_id, path);
+            }
+            None => println!("{}", body),
+        }
+        return Ok(());
+    }
+
+    let ctx = Arc::new(
+        ProcessorContext::new(config.clone())
+            .with_dead_letter(KafkaDeadLetterSink::from_config(&config)?)
+            .with_passthrough(KafkaPassthrough::from_config(&config)?)
+            .with_event_sink(events),
+    );
+
+    // Metrics
+    tokio::spawn(metrics::refresh_gauges(
+        pools.clone(),
+        Duration::from_secs(config.metrics_refresh_seconds),
+    ));
+    if config.stale_trip_seconds > 0 {
+        tokio::spawn(db::maintenance::close_stale_trips_periodically(
+            pools.clone(),
+            ctx.clone(),
+            chrono::Duration::seconds(config.stale_trip_seconds as i64),
+            chrono::Duration::seconds(config.stale_trip_resume_seconds as i64),
+            Duration::from_secs(config.stale_trip_scan_seconds),
+        ));
+    }
+    if ctx.tx_batcher.is_some() {
+        tokio::spawn(processor::message_processor::flush_expired_batches(
+            ctx.clone(),
+        ));
+    }
+    let http_port = config.http_port;
+    let brokers = Arc::new(BrokerRotation::parse(&config.kafka_bootstrap_servers));
+    let http_state = http::HttpState {
+        db_breaker: ctx.db_breaker.clone(),
+        brokers: brokers.clone(),
+        pools: pools.clone(),
+        max_bbox_points: config.bbox_max_points,
+    };
+    tokio::spawn(async move {
+        if let Err(e) = http::serve(http_port, http_state).await {
+            error!("HTTP server failed: {}", e);
+        }
+    });
+    let grpc_port = config.grpc_port;
+    let grpc_service = grpc::DeviceStateGrpc::new(
+        pools.clone(),
+        Duration::from_millis(config.grpc_stream_poll_ms),
+    );
+    tokio::spawn(async move {
+        if let Err(e) = grpc::serve(grpc_port, grpc_service).await {
+            error!("gRPC server failed: {}", e);
+        }
+    });
 
     // Start Kafka
-    kafka::start_kafka_consumer(&config, pool).await?;
+    kafka::start_kafka_consumer(&config, pools, ctx, brokers).await?;
 
     Ok(())
 }
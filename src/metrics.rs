@@ -0,0 +1,343 @@
+use crate::consumer_lag::PartitionLag;
+use crate::db::sharding::ShardedPool;
+use crate::db::{stats, DbPool};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+use tracing::{error, warn};
+
+/// Categories of message processing failures, used to label the failure counters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FailureKind {
+    Parse,
+    MissingData,
+    MissingDeviceId,
+    InvalidTimestamp,
+    InvalidGps,
+    Db,
+    Timeout,
+}
+
+impl FailureKind {
+    pub const ALL: [FailureKind; 7] = [
+        FailureKind::Parse,
+        FailureKind::MissingData,
+        FailureKind::MissingDeviceId,
+        FailureKind::InvalidTimestamp,
+        FailureKind::InvalidGps,
+        FailureKind::Db,
+        FailureKind::Timeout,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FailureKind::Parse => "parse",
+            FailureKind::MissingData => "missing_data",
+            FailureKind::MissingDeviceId => "missing_device_id",
+            FailureKind::InvalidTimestamp => "invalid_timestamp",
+            FailureKind::InvalidGps => "invalid_gps",
+            FailureKind::Db => "db",
+            FailureKind::Timeout => "timeout",
+        }
+    }
+}
+
+static FAILURES: [AtomicU64; 7] = [
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+];
+
+static DEVICES_FILTERED: AtomicU64 = AtomicU64::new(0);
+static RATE_LIMITED: AtomicU64 = AtomicU64::new(0);
+static DISTANCE_MISMATCHES: AtomicU64 = AtomicU64::new(0);
+static OVERSIZED: AtomicU64 = AtomicU64::new(0);
+static POISONED: AtomicU64 = AtomicU64::new(0);
+static NOOP_SKIPPED: AtomicU64 = AtomicU64::new(0);
+static DUPLICATE_ALERTS: AtomicU64 = AtomicU64::new(0);
+static ACTIVE_TRIPS: AtomicI64 = AtomicI64::new(0);
+static IDLE_DEVICES: AtomicI64 = AtomicI64::new(0);
+static CONSUMER_LAG: Mutex<Vec<PartitionLag>> = Mutex::new(Vec::new());
+/// (topic, partition) -> (messages processed, last processed offset)
+static PARTITIONS: Mutex<BTreeMap<(String, i32), (u64, i64)>> = Mutex::new(BTreeMap::new());
+
+/// Increments the counter for `kind` and emits a structured event with a `failure_kind` field.
+pub fn record_failure(kind: FailureKind, detail: &str) {
+    FAILURES[kind as usize].fetch_add(1, Ordering::Relaxed);
+    warn!(failure_kind = kind.as_str(), "{}", detail);
+}
+
+pub fn failure_count(kind: FailureKind) -> u64 {
+    FAILURES[kind as usize].load(Ordering::Relaxed)
+}
+
+/// Counts a message dropped because its device is not permitted by the device filter.
+pub fn record_device_filtered() {
+    DEVICES_FILTERED.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Counts a message dropped by the per-device rate limiter.
+pub fn record_rate_limited() {
+    RATE_LIMITED.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Counts a message skipped before opening a transaction (`SKIP_NOOP_TRANSACTIONS`).
+pub fn record_noop_skipped() {
+    NOOP_SKIPPED.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Counts an alert already stored for its trip, skipped by `IDEMPOTENT_ALERTS`.
+pub fn record_duplicate_alert() {
+    DUPLICATE_ALERTS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Counts a payload rejected for exceeding `MAX_PAYLOAD_BYTES`.
+pub fn record_oversized() {
+    OVERSIZED.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Counts a message dead-lettered after failing `MAX_MESSAGE_ATTEMPTS` times.
+pub fn record_poisoned() {
+    POISONED.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Counts a closed trip whose route and odometer distances disagree beyond `DISTANCE_MISMATCH_PERCENT`.
+pub fn record_distance_mismatch() {
+    DISTANCE_MISMATCHES.fetch_add(1, Ordering::Relaxed);
+}
+
+#[cfg(all(test, feature = "db-tests"))]
+pub fn distance_mismatch_count() -> u64 {
+    DISTANCE_MISMATCHES.load(Ordering::Relaxed)
+}
+
+/// Replaces the `kafka_consumer_lag` series with the latest per-partition lag.
+pub fn set_consumer_lag(mut lags: Vec<PartitionLag>) {
+    lags.sort_by(|a, b| (&a.topic, a.partition).cmp(&(&b.topic, b.partition)));
+    *CONSUMER_LAG.lock().unwrap() = lags;
+}
+
+/// Counts a processed message of `topic`/`partition` and records its offset.
+pub fn record_partition_processed(topic: &str, partition: i32, offset: i64) {
+    let mut partitions = PARTITIONS.lock().unwrap();
+    let entry = partitions
+        .entry((topic.to_string(), partition))
+        .or_insert((0, offset));
+    entry.0 += 1;
+    entry.1 = entry.1.max(offset);
+}
+
+/// Periodically refreshes the `active_trips` and `idle_devices` gauges, summed across shards.
+pub async fn refresh_gauges(pools: ShardedPool, interval: Duration) {
+    loop {
+        match sum_shards(&pools, stats::count_active_trips).await {
+            Ok(count) => ACTIVE_TRIPS.store(count, Ordering::Relaxed),
+            Err(e) => error!("Failed to refresh active_trips gauge: {}", e),
+        }
+        match sum_shards(&pools, stats::count_idle_devices).await {
+            Ok(count) => IDLE_DEVICES.store(count, Ordering::Relaxed),
+            Err(e) => error!("Failed to refresh idle_devices gauge: {}", e),
+        }
+        tokio::time::sleep(interval).await;
+    }
+}
+
+async fn sum_shards<'a, F, Fut>(pools: &'a ShardedPool, count: F) -> anyhow::Result<i64>
+where
+    F: Fn(&'a DbPool) -> Fut,
+    Fut: std::future::Future<Output = anyhow::Result<i64>>,
+{
+    let mut total = 0;
+    for pool in pools.all() {
+        total += count(pool).await?;
+    }
+    Ok(total)
+}
+
+/// Renders all metrics in the Prometheus text exposition format.
+pub fn render() -> String {
+    let mut out = String::new();
+
+    let _ = writeln!(out, "# TYPE processing_failures_total counter");
+    for kind in FailureKind::ALL {
+        let _ = writeln!(
+            out,
+            "processing_failures_total{{failure_kind=\"{}\"}} {}",
+            kind.as_str(),
+            failure_count(kind)
+        );
+    }
+
+    let _ = writeln!(out, "# TYPE devices_filtered_total counter");
+    let _ = writeln!(
+        out,
+        "devices_filtered_total {}",
+        DEVICES_FILTERED.load(Ordering::Relaxed)
+    );
+
+    let _ = writeln!(out, "# TYPE messages_rate_limited_total counter");
+    let _ = writeln!(
+        out,
+        "messages_rate_limited_total {}",
+        RATE_LIMITED.load(Ordering::Relaxed)
+    );
+
+    let _ = writeln!(out, "# TYPE messages_oversized_total counter");
+    let _ = writeln!(
+        out,
+        "messages_oversized_total {}",
+        OVERSIZED.load(Ordering::Relaxed)
+    );
+
+    let _ = writeln!(out, "# TYPE messages_noop_skipped_total counter");
+    let _ = writeln!(
+        out,
+        "messages_noop_skipped_total {}",
+        NOOP_SKIPPED.load(Ordering::Relaxed)
+    );
+
+    let _ = writeln!(out, "# TYPE trip_alerts_duplicate_total counter");
+    let _ = writeln!(
+        out,
+        "trip_alerts_duplicate_total {}",
+        DUPLICATE_ALERTS.load(Ordering::Relaxed)
+    );
+
+    let _ = writeln!(out, "# TYPE messages_poisoned_total counter");
+    let _ = writeln!(
+        out,
+        "messages_poisoned_total {}",
+        POISONED.load(Ordering::Relaxed)
+    );
+
+    let _ = writeln!(out, "# TYPE trip_distance_mismatch_total counter");
+    let _ = writeln!(
+        out,
+        "trip_distance_mismatch_total {}",
+        DISTANCE_MISMATCHES.load(Ordering::Relaxed)
+    );
+
+    let _ = writeln!(out, "# TYPE active_trips gauge");
+    let _ = writeln!(out, "active_trips {}", ACTIVE_TRIPS.load(Ordering::Relaxed));
+
+    let _ = writeln!(out, "# TYPE idle_devices gauge");
+    let _ = writeln!(out, "idle_devices {}", IDLE_DEVICES.load(Ordering::Relaxed));
+
+    let _ = writeln!(out, "# TYPE kafka_consumer_lag gauge");
+    for lag in CONSUMER_LAG.lock().unwrap().iter() {
+        let _ = writeln!(
+            out,
+            "kafka_consumer_lag{{topic=\"{}\",partition=\"{}\"}} {}",
+            lag.topic, lag.partition, lag.lag
+        );
+    }
+
+    let partitions = PARTITIONS.lock().unwrap();
+    let _ = writeln!(out, "# TYPE messages_processed_by_partition counter");
+    for ((topic, partition), (processed, _)) in partitions.iter() {
+        let _ = writeln!(
+            out,
+            "messages_processed_by_partition{{topic=\"{}\",partition=\"{}\"}} {}",
+            topic, partition, processed
+        );
+    }
+    let _ = writeln!(out, "# TYPE last_processed_offset_by_partition gauge");
+    for ((topic, partition), (_, offset)) in partitions.iter() {
+        let _ = writeln!(
+            out,
+            "last_processed_offset_by_partition{{topic=\"{}\",partition=\"{}\"}} {}",
+            topic, partition, offset
+        );
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_includes_all_series() {
+        let output = render();
+
+        for kind in FailureKind::ALL {
+            assert!(output.contains(&format!(
+                "processing_failures_total{{failure_kind=\"{}\"}}",
+                kind.as_str()
+            )));
+        }
+        assert!(output.contains("devices_filtered_total "));
+        assert!(output.contains("messages_rate_limited_total "));
+        assert!(output.contains("trip_distance_mismatch_total "));
+        assert!(output.contains("messages_oversized_total "));
+        assert!(output.contains("messages_poisoned_total "));
+        assert!(output.contains("messages_noop_skipped_total "));
+        assert!(output.contains("trip_alerts_duplicate_total "));
+        assert!(output.contains("active_trips "));
+        assert!(output.contains("idle_devices "));
+        assert!(output.contains("# TYPE kafka_consumer_lag gauge"));
+        assert!(output.contains("# TYPE messages_processed_by_partition counter"));
+        assert!(output.contains("# TYPE last_processed_offset_by_partition gauge"));
+    }
+
+    #[test]
+    fn test_render_processed_by_partition() {
+        record_partition_processed("partition-metrics-test", 3, 100);
+        record_partition_processed("partition-metrics-test", 3, 101);
+        // Out of order (another worker): the offset does not go back
+        record_partition_processed("partition-metrics-test", 3, 99);
+        record_partition_processed("partition-metrics-test", 0, 7);
+
+        let output = render();
+        assert!(output.contains(
+            "messages_processed_by_partition{topic=\"partition-metrics-test\",partition=\"3\"} 3"
+        ));
+        assert!(output.contains(
+            "last_processed_offset_by_partition{topic=\"partition-metrics-test\",partition=\"3\"} 101"
+        ));
+        assert!(output.contains(
+            "messages_processed_by_partition{topic=\"partition-metrics-test\",partition=\"0\"} 1"
+        ));
+    }
+
+    #[test]
+    fn test_render_consumer_lag_per_partition() {
+        set_consumer_lag(vec![
+            PartitionLag {
+                topic: "siscom-minimal".to_string(),
+                partition: 1,
+                lag: 7,
+            },
+            PartitionLag {
+                topic: "siscom-minimal".to_string(),
+                partition: 0,
+                lag: 42,
+            },
+        ]);
+
+        let output = render();
+        let first = output
+            .find("kafka_consumer_lag{topic=\"siscom-minimal\",partition=\"0\"} 42")
+            .unwrap();
+        let second = output
+            .find("kafka_consumer_lag{topic=\"siscom-minimal\",partition=\"1\"} 7")
+            .unwrap();
+        assert!(first < second);
+    }
+
+    #[test]
+    fn test_render_reflects_recorded_failures() {
+        record_failure(FailureKind::Db, "test failure");
+        let count = failure_count(FailureKind::Db);
+
+        assert!(count >= 1);
+        assert!(render().contains("processing_failures_total{failure_kind=\"db\"}"));
+    }
+}
@@ -0,0 +1,34 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::FromRow;
+use uuid::Uuid;
+
+/// Row of `trip_current_state`: last known position and trip of a device.
+#[derive(Debug, Clone, PartialEq, FromRow, Serialize)]
+pub struct CurrentState {
+    pub device_id: String,
+    pub current_trip_id: Option<Uuid>,
+    pub ignition_on: bool,
+    pub last_point_at: Option<DateTime<Utc>>,
+    pub last_lat: Option<f64>,
+    pub last_lng: Option<f64>,
+    pub last_speed: Option<f64>,
+    pub last_odometer_meters: Option<i32>,
+    pub last_correlation_id: Option<Uuid>,
+    pub last_updated_at: DateTime<Utc>,
+}
+
+/// Health summary of a device: `trip_current_state` joined with its open trip.
+#[derive(Debug, Clone, PartialEq, FromRow, Serialize)]
+pub struct DeviceHealth {
+    pub device_id: String,
+    pub ignition_on: bool,
+    pub last_point_at: Option<DateTime<Utc>>,
+    /// Seconds since `last_point_at` at query time.
+    pub last_point_age_seconds: Option<f64>,
+    pub active_trip_id: Option<Uuid>,
+    pub active_trip_started_at: Option<DateTime<Utc>>,
+    pub last_lat: Option<f64>,
+    pub last_lng: Option<f64>,
+    pub last_speed: Option<f64>,
+}
@@ -1,3 +1,5 @@
+pub mod current_state;
+pub mod proto;
 pub mod trip;
 pub mod trip_alerts;
 pub mod trip_points;
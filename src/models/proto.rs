@@ -0,0 +1,140 @@
+use crate::models::current_state::CurrentState;
+use crate::models::siscom::v1;
+use crate::models::trip::Trip;
+use crate::models::trip_points::TripPoint;
+
+/// Converts a stored trip into its `siscom.v1.Trip` protobuf representation.
+pub fn trip_to_proto(trip: &Trip) -> v1::Trip {
+    v1::Trip {
+        trip_id: trip.trip_id.to_string(),
+        device_id: trip.device_id.clone(),
+        start_epoch: trip.start_time.timestamp(),
+        end_epoch: trip.end_time.map(|t| t.timestamp()),
+        start_lat: trip.start_lat,
+        start_lng: trip.start_lng,
+        end_lat: trip.end_lat,
+        end_lng: trip.end_lng,
+        distance_meters: trip.distance_meters,
+        start_odometer_meters: trip.start_odometer_meters,
+        end_odometer_meters: trip.end_odometer_meters,
+        point_count: trip.point_count,
+    }
+}
+
+/// Converts a `trip_current_state` row into its `siscom.v1.CurrentState` protobuf representation.
+pub fn current_state_to_proto(state: &CurrentState) -> v1::CurrentState {
+    v1::CurrentState {
+        device_id: state.device_id.clone(),
+        ignition_on: state.ignition_on,
+        current_trip_id: state.current_trip_id.map(|id| id.to_string()),
+        last_point_epoch: state.last_point_at.map(|t| t.timestamp()),
+        lat: state.last_lat,
+        lng: state.last_lng,
+        speed: state.last_speed,
+        odometer_meters: state.last_odometer_meters,
+        last_updated_epoch: state.last_updated_at.timestamp(),
+    }
+}
+
+/// Converts a stored trip point into its `siscom.v1.TripPoint` protobuf representation.
+#[allow(dead_code)]
+pub fn trip_point_to_proto(point: &TripPoint) -> v1::TripPoint {
+    v1::TripPoint {
+        trip_id: point.trip_id.to_string(),
+        device_id: point.device_id.clone(),
+        epoch: point.timestamp.timestamp(),
+        lat: point.lat,
+        lng: point.lng,
+        speed: point.speed,
+        heading: point.heading,
+        odometer_meters: point.odometer_meters,
+        correlation_id: point.correlation_id.to_string(),
+        altitude: point.altitude,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{TimeZone, Utc};
+    use prost::Message;
+    use uuid::Uuid;
+
+    fn sample_trip() -> Trip {
+        Trip {
+            trip_id: Uuid::new_v4(),
+            device_id: "0848086072".to_string(),
+            start_time: Utc.timestamp_opt(1_700_000_000, 0).single().unwrap(),
+            start_lat: Some(20.652494),
+            start_lng: Some(-100.391404),
+            end_time: Some(Utc.timestamp_opt(1_700_001_800, 0).single().unwrap()),
+            end_lat: Some(20.700000),
+            end_lng: Some(-100.450000),
+            distance_meters: Some(8250.0),
+            start_odometer_meters: Some(120_000),
+            end_odometer_meters: Some(128_250),
+            point_count: Some(42),
+            tenant_id: None,
+            asset_id: None,
+        }
+    }
+
+    #[test]
+    fn test_trip_proto_round_trip() {
+        let trip = sample_trip();
+
+        let encoded = trip_to_proto(&trip).encode_to_vec();
+        let decoded = v1::Trip::decode(encoded.as_slice()).unwrap();
+
+        assert_eq!(decoded.trip_id, trip.trip_id.to_string());
+        assert_eq!(decoded.device_id, "0848086072");
+        assert_eq!(decoded.start_epoch, 1_700_000_000);
+        assert_eq!(decoded.end_epoch, Some(1_700_001_800));
+        assert_eq!(decoded.start_lat, Some(20.652494));
+        assert_eq!(decoded.end_lng, Some(-100.450000));
+        assert_eq!(decoded.distance_meters, Some(8250.0));
+        assert_eq!(decoded.end_odometer_meters, Some(128_250));
+        assert_eq!(decoded.point_count, Some(42));
+    }
+
+    #[test]
+    fn test_open_trip_has_no_end() {
+        let mut trip = sample_trip();
+        trip.end_time = None;
+        trip.end_lat = None;
+        trip.end_lng = None;
+
+        let decoded = v1::Trip::decode(trip_to_proto(&trip).encode_to_vec().as_slice()).unwrap();
+
+        assert_eq!(decoded.end_epoch, None);
+        assert_eq!(decoded.end_lat, None);
+    }
+
+    #[test]
+    fn test_trip_point_proto_round_trip() {
+        let point = TripPoint {
+            point_id: 1,
+            trip_id: Uuid::new_v4(),
+            device_id: "0848086072".to_string(),
+            timestamp: Utc.timestamp_opt(1_700_000_030, 0).single().unwrap(),
+            lat: 20.653,
+            lng: -100.392,
+            speed: Some(45.0),
+            heading: None,
+            odometer_meters: Some(120_300),
+            correlation_id: Uuid::new_v4(),
+            altitude: Some(1820.7),
+            tenant_id: None,
+            location_source: None,
+        };
+
+        let encoded = trip_point_to_proto(&point).encode_to_vec();
+        let decoded = v1::TripPoint::decode(encoded.as_slice()).unwrap();
+
+        assert_eq!(decoded, trip_point_to_proto(&point));
+        assert_eq!(decoded.epoch, 1_700_000_030);
+        assert_eq!(decoded.speed, Some(45.0));
+        assert_eq!(decoded.heading, None);
+        assert_eq!(decoded.altitude, Some(1820.7));
+    }
+}
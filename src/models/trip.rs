@@ -1,4 +1,4 @@
-use chrono::NaiveDateTime;
+use chrono::{DateTime, Utc};
 use sqlx::FromRow;
 use uuid::Uuid;
 
@@ -6,14 +6,20 @@ use uuid::Uuid;
 #[allow(dead_code)]
 pub struct Trip {
     pub trip_id: Uuid,
-    pub device_id: Uuid, // DDL says uuid
-    pub start_time: NaiveDateTime,
+    pub device_id: String,
+    pub start_time: DateTime<Utc>,
     pub start_lat: Option<f64>, // DDL says float8 NULL
     pub start_lng: Option<f64>, // DDL says float8 NULL
-    pub end_time: Option<NaiveDateTime>,
+    pub end_time: Option<DateTime<Utc>>,
     pub end_lat: Option<f64>,
     pub end_lng: Option<f64>,
     pub distance_meters: Option<f64>,
     pub start_odometer_meters: Option<i32>,
     pub end_odometer_meters: Option<i32>,
+    /// Points stored while the trip was open (`current_trip_point_count` at close)
+    pub point_count: Option<i32>,
+    pub tenant_id: Option<String>,
+    /// Vehicle/asset carrying the device when the trip started (`device_assets`),
+    /// the device_id itself when unmapped
+    pub asset_id: Option<String>,
 }
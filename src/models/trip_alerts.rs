@@ -1,4 +1,4 @@
-use chrono::NaiveDateTime;
+use chrono::{DateTime, Utc};
 use sqlx::FromRow;
 use uuid::Uuid;
 
@@ -10,13 +10,71 @@ use sqlx::types::Json;
 pub struct TripAlert {
     pub alert_id: Uuid,
     pub trip_id: Uuid, // DDL says NOT NULL
-    pub timestamp: NaiveDateTime,
+    pub timestamp: DateTime<Utc>,
     pub lat: Option<f64>,
-    pub lon: Option<f64>,
+    pub lng: Option<f64>,      // `lng` as in trip_points (was `lon`)
     pub alert_type: String,    // Enum in DB, map to String
     pub raw_code: Option<i32>, // DDL says int4
     pub severity: Option<i16>, // DDL says int2
     pub device_id: String,
     pub correlation_id: Option<Uuid>,
     pub metadata: Option<Json<Value>>,
+    pub tenant_id: Option<String>,
+}
+
+#[cfg(all(test, feature = "db-tests"))]
+mod tests {
+    use super::*;
+    use crate::db::queries;
+    use crate::db::test_support::TestDb;
+    use chrono::TimeZone;
+
+    #[tokio::test]
+    async fn test_trip_alert_round_trip() {
+        let db = TestDb::new().await;
+        let alert_id = Uuid::new_v4();
+        let trip_id = Uuid::new_v4();
+        let correlation_id = Uuid::new_v4();
+        let timestamp = Utc.timestamp_opt(1_700_000_000, 0).single().unwrap();
+
+        sqlx::query(queries::INSERT_TRIP_ALERT)
+            .bind(alert_id)
+            .bind(trip_id)
+            .bind(timestamp)
+            .bind(Some(20.65))
+            .bind(Some(-100.39))
+            .bind("speeding")
+            .bind(Some(42))
+            .bind(2i16)
+            .bind("ALERT-RT-1")
+            .bind(correlation_id)
+            .bind("OVER SPEED")
+            .bind(Some("acme"))
+            .execute(&db.pool)
+            .await
+            .unwrap();
+
+        let alert: TripAlert = sqlx::query_as(
+            "SELECT alert_id, trip_id, \"timestamp\", lat, lng, alert_type, raw_code, severity, \
+                    device_id, correlation_id, metadata, tenant_id \
+             FROM trip_alerts WHERE alert_id = $1",
+        )
+        .bind(alert_id)
+        .fetch_one(&db.pool)
+        .await
+        .unwrap();
+
+        assert_eq!(alert.trip_id, trip_id);
+        assert_eq!(alert.timestamp, timestamp);
+        assert_eq!(alert.lat, Some(20.65));
+        assert_eq!(alert.lng, Some(-100.39));
+        assert_eq!(alert.alert_type, "speeding");
+        assert_eq!(alert.raw_code, Some(42));
+        assert_eq!(alert.severity, Some(2));
+        assert_eq!(alert.device_id, "ALERT-RT-1");
+        assert_eq!(alert.correlation_id, Some(correlation_id));
+        assert_eq!(alert.tenant_id.as_deref(), Some("acme"));
+
+        db.cleanup().await;
+    }
 }
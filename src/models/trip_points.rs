@@ -1,18 +1,80 @@
-use chrono::NaiveDateTime;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
 use sqlx::FromRow;
 use uuid::Uuid;
 
-#[derive(Debug, FromRow)]
+#[derive(Debug, FromRow, Serialize)]
 #[allow(dead_code)]
 pub struct TripPoint {
     pub point_id: i64, // bigserial
     pub trip_id: Uuid,
     pub device_id: String,
-    pub timestamp: NaiveDateTime,
+    pub timestamp: DateTime<Utc>,
     pub lat: f64,
     pub lng: f64, // DDL says lng
     pub speed: Option<f64>,
     pub heading: Option<f64>,
     pub odometer_meters: Option<i32>,
     pub correlation_id: Uuid,
+    pub altitude: Option<f64>,
+    pub tenant_id: Option<String>,
+    /// `gps`, or `cell` when approximated from the cell tower (NULL on old rows)
+    pub location_source: Option<String>,
+}
+
+#[cfg(all(test, feature = "db-tests"))]
+mod tests {
+    use super::*;
+    use crate::db::queries;
+    use crate::db::test_support::TestDb;
+    use chrono::TimeZone;
+
+    #[tokio::test]
+    async fn test_trip_point_round_trip() {
+        let db = TestDb::new().await;
+        let trip_id = Uuid::new_v4();
+        let correlation_id = Uuid::new_v4();
+        let timestamp = Utc.timestamp_opt(1_700_000_030, 0).single().unwrap();
+
+        sqlx::query(queries::INSERT_TRIP_POINT)
+            .bind(trip_id)
+            .bind("POINT-RT-1")
+            .bind(timestamp)
+            .bind(20.653)
+            .bind(-100.392)
+            .bind(45.0)
+            .bind(180.0)
+            .bind(120_300.0)
+            .bind(correlation_id)
+            .bind(Some(1820.7))
+            .bind(None::<&str>)
+            .bind("gps")
+            .execute(&db.pool)
+            .await
+            .unwrap();
+
+        let point: TripPoint = sqlx::query_as(
+            "SELECT point_id, trip_id, device_id, \"timestamp\", lat, lng, speed, heading, \
+                    odometer_meters, correlation_id, altitude, tenant_id, location_source \
+             FROM trip_points WHERE trip_id = $1",
+        )
+        .bind(trip_id)
+        .fetch_one(&db.pool)
+        .await
+        .unwrap();
+
+        assert_eq!(point.device_id, "POINT-RT-1");
+        assert_eq!(point.timestamp, timestamp);
+        assert_eq!(point.lat, 20.653);
+        assert_eq!(point.lng, -100.392);
+        assert_eq!(point.speed, Some(45.0));
+        assert_eq!(point.heading, Some(180.0));
+        assert_eq!(point.odometer_meters, Some(120_300));
+        assert_eq!(point.correlation_id, correlation_id);
+        assert_eq!(point.altitude, Some(1820.7));
+        assert_eq!(point.tenant_id, None);
+        assert_eq!(point.location_source.as_deref(), Some("gps"));
+
+        db.cleanup().await;
+    }
 }
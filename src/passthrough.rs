@@ -0,0 +1,45 @@
+use crate::config::AppConfig;
+use crate::kafka::base_client_config;
+use rdkafka::producer::{FutureProducer, FutureRecord};
+use std::sync::Arc;
+use tracing::{debug, warn};
+
+/// Receives a copy of every incoming payload, before any decoding.
+pub trait PayloadTap: Send + Sync {
+    fn tap(&self, payload: &[u8]);
+}
+
+/// Republishes each payload verbatim to `KAFKA_DEBUG_TOPIC`, so engineers can
+/// inspect the exact bytes the service sees without broker-level access.
+pub struct KafkaPassthrough {
+    producer: FutureProducer,
+    topic: String,
+}
+
+impl KafkaPassthrough {
+    /// Builds the passthrough when `ENABLE_DEBUG_PASSTHROUGH` is set and a topic is configured.
+    pub fn from_config(config: &AppConfig) -> anyhow::Result<Option<Arc<dyn PayloadTap>>> {
+        if !config.enable_debug_passthrough {
+            return Ok(None);
+        }
+        if config.kafka_debug_topic.is_empty() {
+            warn!("ENABLE_DEBUG_PASSTHROUGH is set but KAFKA_DEBUG_TOPIC is empty, passthrough disabled");
+            return Ok(None);
+        }
+        let producer: FutureProducer = base_client_config(config).create()?;
+        Ok(Some(Arc::new(Self {
+            producer,
+            topic: config.kafka_debug_topic.clone(),
+        })))
+    }
+}
+
+impl PayloadTap for KafkaPassthrough {
+    fn tap(&self, payload: &[u8]) {
+        let record: FutureRecord<'_, (), [u8]> = FutureRecord::to(&self.topic).payload(payload);
+        // Fire and forget: a slow or failing debug topic must never hold up processing
+        if let Err((e, _)) = self.producer.send_result(record) {
+            debug!("Dropped debug passthrough payload: {}", e);
+        }
+    }
+}
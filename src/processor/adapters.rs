@@ -0,0 +1,385 @@
+use crate::models::siscom::v1::kafka_message::Decoded;
+use crate::models::siscom::v1::{KafkaMessage, Vendor};
+use crate::processor::json_input::{decode_json, decode_json_lenient};
+use prost::Message;
+use std::collections::HashMap;
+use tracing::warn;
+
+/// Formato de los payloads de entrada (`INPUT_FORMAT`)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum InputFormat {
+    Protobuf,
+    Json,
+    /// Intenta protobuf y, si falla, JSON
+    Auto,
+}
+
+impl InputFormat {
+    pub fn parse(value: &str) -> Self {
+        match value.trim().to_lowercase().as_str() {
+            "" | "protobuf" => InputFormat::Protobuf,
+            "json" => InputFormat::Json,
+            "auto" => InputFormat::Auto,
+            other => {
+                warn!("Unknown INPUT_FORMAT '{}', using protobuf", other);
+                InputFormat::Protobuf
+            }
+        }
+    }
+}
+
+/// Adaptador de payload por fabricante.
+///
+/// El sobre `KafkaMessage` es común a todos los fabricantes, pero cada uno
+/// tiene sus particularidades (campos que sólo vienen en el bloque `decoded`,
+/// nombres distintos, etc). Cada adaptador normaliza esas diferencias para que
+/// `process_message` trabaje siempre con el mapa `data` homogéneo.
+pub trait PayloadAdapter: Send + Sync {
+    fn adapt(&self, message: KafkaMessage) -> KafkaMessage;
+}
+
+/// Adaptador para Queclink: completa los campos de `data` que falten (o
+/// vengan vacíos) con los valores del bloque `decoded.queclink`.
+pub struct QueclinkAdapter;
+
+impl PayloadAdapter for QueclinkAdapter {
+    fn adapt(&self, mut message: KafkaMessage) -> KafkaMessage {
+        if let Some(Decoded::Queclink(decoded)) = message.decoded.as_ref() {
+            for (key, value) in &decoded.fields {
+                if value.trim().is_empty() {
+                    continue;
+                }
+                let missing = message
+                    .data
+                    .get(key)
+                    .map(|v| v.trim().is_empty())
+                    .unwrap_or(true);
+                if missing {
+                    message.data.insert(key.clone(), value.clone());
+                }
+            }
+        }
+        message
+    }
+}
+
+/// Identifica el fabricante a partir del bloque `decoded` del mensaje
+pub fn vendor_of(message: &KafkaMessage) -> Vendor {
+    match message.decoded {
+        Some(Decoded::Suntech(_)) => Vendor::Suntech,
+        Some(Decoded::Queclink(_)) => Vendor::Queclink,
+        None => Vendor::Unknown,
+    }
+}
+
+/// Registro de adaptadores indexado por fabricante.
+///
+/// Los mensajes cuyo fabricante no tiene adaptador registrado se procesan
+/// con el parseo genérico (el `KafkaMessage` tal cual se decodificó).
+pub struct AdapterRegistry {
+    adapters: HashMap<Vendor, Box<dyn PayloadAdapter>>,
+    input_format: InputFormat,
+    device_id_fields: Vec<String>,
+    lenient_json: bool,
+}
+
+impl AdapterRegistry {
+    pub fn empty() -> Self {
+        Self {
+            adapters: HashMap::new(),
+            input_format: InputFormat::Protobuf,
+            device_id_fields: vec!["DEVICE_ID".to_string()],
+            lenient_json: false,
+        }
+    }
+
+    pub fn with_input_format(mut self, input_format: InputFormat) -> Self {
+        self.input_format = input_format;
+        self
+    }
+
+    /// Con `LENIENT_PARSING`, un JSON que no decodifica completo se recupera
+    /// campo por campo en vez de descartarse
+    pub fn with_lenient_json(mut self, lenient_json: bool) -> Self {
+        self.lenient_json = lenient_json;
+        self
+    }
+
+    /// Campos candidatos a identidad del equipo (`DEVICE_ID_FIELDS`), en orden
+    pub fn with_device_id_fields(mut self, fields: &[String]) -> Self {
+        if !fields.is_empty() {
+            self.device_id_fields = fields.to_vec();
+        }
+        self
+    }
+
+    pub fn register(&mut self, vendor: Vendor, adapter: Box<dyn PayloadAdapter>) {
+        self.adapters.insert(vendor, adapter);
+    }
+
+    /// Decodifica el payload y aplica el adaptador del fabricante, si existe
+    pub fn parse(&self, payload: &[u8]) -> anyhow::Result<KafkaMessage> {
+        let message = match self.input_format {
+            InputFormat::Protobuf => KafkaMessage::decode(payload)?,
+            InputFormat::Json => self.decode_json(payload)?,
+            InputFormat::Auto => match KafkaMessage::decode(payload) {
+                Ok(message) => message,
+                Err(_) => self.decode_json(payload)?,
+            },
+        };
+        let mut message = match self.adapters.get(&vendor_of(&message)) {
+            Some(adapter) => adapter.adapt(message),
+            None => message,
+        };
+        resolve_device_id(&mut message.data, &self.device_id_fields);
+        Ok(message)
+    }
+}
+
+impl AdapterRegistry {
+    fn decode_json(&self, payload: &[u8]) -> serde_json::Result<KafkaMessage> {
+        match decode_json(payload) {
+            Ok(message) => Ok(message),
+            Err(e) if self.lenient_json => {
+                let (message, failed) = decode_json_lenient(payload)?;
+                warn!(
+                    "Lenient JSON parse recovered message {} ({}); missing or malformed fields: {}",
+                    message.uuid,
+                    e,
+                    failed.join(", ")
+                );
+                Ok(message)
+            }
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// Copia a DEVICE_ID el primer campo candidato con valor (p. ej. IMEI, SN o
+/// TERMINAL_ID según el fabricante). Sin candidatos el mapa queda igual.
+pub fn resolve_device_id(data: &mut HashMap<String, String>, fields: &[String]) {
+    let device_id = fields
+        .iter()
+        .filter_map(|field| data.get(field))
+        .map(|value| value.trim())
+        .find(|value| !value.is_empty())
+        .map(str::to_string);
+    if let Some(device_id) = device_id {
+        data.insert("DEVICE_ID".to_string(), device_id);
+    }
+}
+
+impl Default for AdapterRegistry {
+    fn default() -> Self {
+        let mut registry = Self::empty();
+        registry.register(Vendor::Queclink, Box::new(QueclinkAdapter));
+        registry
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::siscom::v1::{QueclinkDecoded, SuntechDecoded};
+    use uuid::Uuid;
+
+    fn queclink_message(data: &[(&str, &str)], fields: &[(&str, &str)]) -> KafkaMessage {
+        KafkaMessage {
+            uuid: Uuid::new_v4().to_string(),
+            decoded: Some(Decoded::Queclink(QueclinkDecoded {
+                fields: fields
+                    .iter()
+                    .map(|(k, v)| (k.to_string(), v.to_string()))
+                    .collect(),
+            })),
+            data: data
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+            metadata: None,
+            raw: String::new(),
+        }
+    }
+
+    #[test]
+    fn test_queclink_adapter_fills_missing_fields() {
+        let message = queclink_message(
+            &[("DEVICE_ID", "862524060000001")],
+            &[("LATITUD", "+20.652494"), ("ALERT", "Turn On")],
+        );
+
+        let adapted = QueclinkAdapter.adapt(message);
+
+        assert_eq!(adapted.data.get("LATITUD").unwrap(), "+20.652494");
+        assert_eq!(adapted.data.get("ALERT").unwrap(), "Turn On");
+    }
+
+    #[test]
+    fn test_queclink_adapter_keeps_top_level_fields() {
+        let message = queclink_message(
+            &[("DEVICE_ID", "862524060000001"), ("SPEED", "45.0")],
+            &[("DEVICE_ID", "otro"), ("SPEED", "0.0")],
+        );
+
+        let adapted = QueclinkAdapter.adapt(message);
+
+        assert_eq!(adapted.data.get("DEVICE_ID").unwrap(), "862524060000001");
+        assert_eq!(adapted.data.get("SPEED").unwrap(), "45.0");
+    }
+
+    #[test]
+    fn test_queclink_adapter_replaces_empty_top_level_fields() {
+        let message = queclink_message(&[("COURSE", "  ")], &[("COURSE", "180")]);
+
+        let adapted = QueclinkAdapter.adapt(message);
+
+        assert_eq!(adapted.data.get("COURSE").unwrap(), "180");
+    }
+
+    #[test]
+    fn test_registry_applies_vendor_adapter() {
+        let message = queclink_message(&[], &[("DEVICE_ID", "862524060000001")]);
+        let payload = message.encode_to_vec();
+
+        let parsed = AdapterRegistry::default().parse(&payload).unwrap();
+
+        assert_eq!(parsed.data.get("DEVICE_ID").unwrap(), "862524060000001");
+    }
+
+    #[test]
+    fn test_registry_falls_back_to_generic_parse() {
+        let message = KafkaMessage {
+            uuid: Uuid::new_v4().to_string(),
+            decoded: Some(Decoded::Suntech(SuntechDecoded {
+                fields: [("DEVICE_ID".to_string(), "0001".to_string())]
+                    .into_iter()
+                    .collect(),
+            })),
+            data: Default::default(),
+            metadata: None,
+            raw: String::new(),
+        };
+        let payload = message.encode_to_vec();
+
+        let parsed = AdapterRegistry::default().parse(&payload).unwrap();
+
+        assert_eq!(vendor_of(&parsed), Vendor::Suntech);
+        assert!(parsed.data.is_empty());
+    }
+
+    fn fields(names: &[&str]) -> Vec<String> {
+        names.iter().map(|n| n.to_string()).collect()
+    }
+
+    #[test]
+    fn test_device_id_from_imei() {
+        let payload = queclink_message(&[("IMEI", "862524060000001")], &[]).encode_to_vec();
+        let registry = AdapterRegistry::default().with_device_id_fields(&fields(&[
+            "DEVICE_ID",
+            "IMEI",
+            "SN",
+            "TERMINAL_ID",
+        ]));
+
+        let parsed = registry.parse(&payload).unwrap();
+
+        assert_eq!(parsed.data.get("DEVICE_ID").unwrap(), "862524060000001");
+    }
+
+    #[test]
+    fn test_device_id_from_sn_follows_field_order() {
+        let mut data: HashMap<String, String> = [
+            ("DEVICE_ID", " "),
+            ("SN", "SN-0042"),
+            ("TERMINAL_ID", "T-9"),
+        ]
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+
+        resolve_device_id(
+            &mut data,
+            &fields(&["DEVICE_ID", "IMEI", "SN", "TERMINAL_ID"]),
+        );
+        assert_eq!(data.get("DEVICE_ID").unwrap(), "SN-0042");
+
+        // El orden configurado manda sobre un DEVICE_ID presente
+        resolve_device_id(&mut data, &fields(&["TERMINAL_ID", "DEVICE_ID"]));
+        assert_eq!(data.get("DEVICE_ID").unwrap(), "T-9");
+    }
+
+    #[test]
+    fn test_default_device_id_fields_ignore_alternates() {
+        let payload = queclink_message(&[("IMEI", "862524060000001")], &[]).encode_to_vec();
+
+        let parsed = AdapterRegistry::default().parse(&payload).unwrap();
+
+        assert!(!parsed.data.contains_key("DEVICE_ID"));
+    }
+
+    #[test]
+    fn test_parse_input_format() {
+        assert_eq!(InputFormat::parse("protobuf"), InputFormat::Protobuf);
+        assert_eq!(InputFormat::parse("JSON"), InputFormat::Json);
+        assert_eq!(InputFormat::parse("auto"), InputFormat::Auto);
+        assert_eq!(InputFormat::parse("xml"), InputFormat::Protobuf);
+    }
+
+    #[test]
+    fn test_registry_json_input() {
+        let registry = AdapterRegistry::default().with_input_format(InputFormat::Json);
+        let payload = br#"{"uuid": "abc", "data": {"DEVICE_ID": "0848086072"}}"#;
+
+        let parsed = registry.parse(payload).unwrap();
+
+        assert_eq!(parsed.data.get("DEVICE_ID").unwrap(), "0848086072");
+        assert!(AdapterRegistry::default()
+            .with_input_format(InputFormat::Json)
+            .parse(&queclink_message(&[], &[]).encode_to_vec())
+            .is_err());
+    }
+
+    #[test]
+    fn test_registry_lenient_json_input() {
+        let payload =
+            br#"{"uuid": "abc", "data": {"DEVICE_ID": "0848086072"}, "metadata": {"bytes": -1}}"#;
+
+        let strict = AdapterRegistry::default().with_input_format(InputFormat::Json);
+        assert!(strict.parse(payload).is_err());
+
+        let lenient = AdapterRegistry::default()
+            .with_input_format(InputFormat::Json)
+            .with_lenient_json(true);
+        let parsed = lenient.parse(payload).unwrap();
+        assert_eq!(parsed.data.get("DEVICE_ID").unwrap(), "0848086072");
+        assert_eq!(parsed.metadata.unwrap().bytes, 0);
+    }
+
+    #[test]
+    fn test_registry_auto_input_accepts_both() {
+        let registry = AdapterRegistry::default().with_input_format(InputFormat::Auto);
+        let protobuf = queclink_message(&[("DEVICE_ID", "862524060000001")], &[]).encode_to_vec();
+        let json = br#"{"data": {"DEVICE_ID": "0848086072"}}"#;
+
+        assert_eq!(
+            registry
+                .parse(&protobuf)
+                .unwrap()
+                .data
+                .get("DEVICE_ID")
+                .unwrap(),
+            "862524060000001"
+        );
+        assert_eq!(
+            registry.parse(json).unwrap().data.get("DEVICE_ID").unwrap(),
+            "0848086072"
+        );
+    }
+
+    #[test]
+    fn test_registry_rejects_invalid_payload() {
+        assert!(AdapterRegistry::default()
+            .parse(&[0xff, 0xff, 0xff])
+            .is_err());
+    }
+}
@@ -0,0 +1,77 @@
+use std::collections::HashMap;
+use tracing::warn;
+
+/// Nombre de alerta por RAW_CODE del fabricante (`ALERT_CODE_MAP`, p. ej.
+/// `27:Turn On,28:Turn Off,30:SPEEDING`), para mensajes que traen el código
+/// pero no ALERT. El nombre se trata igual que un ALERT recibido (ignición,
+/// normalización de `alert_type`).
+#[derive(Debug, Default)]
+pub struct AlertCodeMap {
+    names: HashMap<i32, String>,
+}
+
+impl AlertCodeMap {
+    /// Carga las entradas `código:nombre`; entradas inválidas se ignoran con un aviso
+    pub fn parse(entries: &[String]) -> Self {
+        let names = entries
+            .iter()
+            .filter_map(|entry| {
+                let parsed = entry.split_once(':').and_then(|(code, name)| {
+                    let name = name.trim();
+                    if name.is_empty() {
+                        return None;
+                    }
+                    Some((code.trim().parse().ok()?, name.to_string()))
+                });
+                if parsed.is_none() {
+                    warn!("Ignoring invalid alert code mapping '{}'", entry);
+                }
+                parsed
+            })
+            .collect();
+        Self { names }
+    }
+
+    /// Alerta derivada del código cuando el mensaje no trae una propia
+    pub fn resolve(&self, alert: Option<&str>, raw_code: Option<i32>) -> Option<&str> {
+        if alert.is_some_and(|alert| !alert.trim().is_empty()) {
+            return None;
+        }
+        self.names.get(&raw_code?).map(String::as_str)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn codes() -> AlertCodeMap {
+        AlertCodeMap::parse(&["27:Turn On".to_string(), " 30 : SPEEDING ".to_string()])
+    }
+
+    #[test]
+    fn test_code_only_message_gets_alert_name() {
+        let codes = codes();
+
+        assert_eq!(codes.resolve(None, Some(27)), Some("Turn On"));
+        assert_eq!(codes.resolve(Some("  "), Some(30)), Some("SPEEDING"));
+    }
+
+    #[test]
+    fn test_explicit_alert_or_unknown_code_is_kept() {
+        let codes = codes();
+
+        assert_eq!(codes.resolve(Some("SOS"), Some(30)), None);
+        assert_eq!(codes.resolve(None, Some(99)), None);
+        assert_eq!(codes.resolve(None, None), None);
+    }
+
+    #[test]
+    fn test_invalid_entries_are_ignored() {
+        let codes =
+            AlertCodeMap::parse(&["abc:SOS".to_string(), "31:".to_string(), "32".to_string()]);
+
+        assert_eq!(codes.resolve(None, Some(31)), None);
+        assert_eq!(codes.resolve(None, Some(32)), None);
+    }
+}
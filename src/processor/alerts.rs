@@ -0,0 +1,99 @@
+/// Taxonomía canónica de alertas almacenada en `trip_alerts.alert_type`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AlertKind {
+    Speeding,
+    HarshBraking,
+    HarshAcceleration,
+    LowBattery,
+    PowerCut,
+    Sos,
+    /// Alerta no reconocida; el texto original se conserva en `raw_alert_type`
+    Other,
+}
+
+impl AlertKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AlertKind::Speeding => "speeding",
+            AlertKind::HarshBraking => "harsh_braking",
+            AlertKind::HarshAcceleration => "harsh_acceleration",
+            AlertKind::LowBattery => "low_battery",
+            AlertKind::PowerCut => "power_cut",
+            AlertKind::Sos => "sos",
+            AlertKind::Other => "other",
+        }
+    }
+}
+
+/// Severidad de las alertas cuando el mensaje no trae SEVERITY/PRIORITY
+pub const DEFAULT_ALERT_SEVERITY: i16 = 1;
+
+/// Severidad a guardar: la explícita del mensaje o la de por defecto
+pub fn alert_severity(explicit: Option<i16>) -> i16 {
+    explicit.unwrap_or(DEFAULT_ALERT_SEVERITY)
+}
+
+/// Normaliza el texto de alerta del fabricante ("SPEEDING", "Over Speed",
+/// "OVERSPEED", ...) ignorando mayúsculas, espacios y separadores
+pub fn normalize_alert(raw: &str) -> AlertKind {
+    let key: String = raw
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .collect::<String>()
+        .to_uppercase();
+
+    match key.as_str() {
+        "SPEEDING" | "OVERSPEED" | "OVERSPEEDING" | "SPEEDALERT" => AlertKind::Speeding,
+        "HARSHBRAKING" | "HARSHBRAKE" | "HARDBRAKING" | "HARDBRAKE" => AlertKind::HarshBraking,
+        "HARSHACCELERATION" | "HARDACCELERATION" | "RAPIDACCELERATION" => {
+            AlertKind::HarshAcceleration
+        }
+        "LOWBATTERY" | "BATTERYLOW" | "LOWBAT" | "LOWBATT" => AlertKind::LowBattery,
+        "POWERCUT" | "MAINPOWERCUT" | "EXTERNALPOWERCUT" | "POWERDISCONNECTED" => {
+            AlertKind::PowerCut
+        }
+        "SOS" | "PANIC" | "PANICBUTTON" | "EMERGENCY" => AlertKind::Sos,
+        _ => AlertKind::Other,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_speeding_spellings() {
+        for raw in [
+            "SPEEDING",
+            "Over Speed",
+            "OVERSPEED",
+            "over_speed",
+            "Speeding ",
+        ] {
+            assert_eq!(normalize_alert(raw), AlertKind::Speeding, "{}", raw);
+        }
+    }
+
+    #[test]
+    fn test_low_battery_and_sos_spellings() {
+        for raw in ["LOW BATTERY", "Battery Low", "LowBat"] {
+            assert_eq!(normalize_alert(raw), AlertKind::LowBattery, "{}", raw);
+        }
+        for raw in ["SOS", "Panic Button", "PANIC"] {
+            assert_eq!(normalize_alert(raw), AlertKind::Sos, "{}", raw);
+        }
+    }
+
+    #[test]
+    fn test_unknown_alert_is_other() {
+        assert_eq!(normalize_alert("DOOR OPEN"), AlertKind::Other);
+        assert_eq!(normalize_alert(""), AlertKind::Other);
+        assert_eq!(AlertKind::Other.as_str(), "other");
+    }
+
+    #[test]
+    fn test_explicit_severity_overrides_default() {
+        assert_eq!(alert_severity(Some(3)), 3);
+        assert_eq!(alert_severity(None), DEFAULT_ALERT_SEVERITY);
+    }
+}
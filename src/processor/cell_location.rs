@@ -0,0 +1,110 @@
+use std::collections::HashMap;
+
+/// Celda a la que estaba conectado el equipo (MCC/MNC/LAC/CID del mensaje)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CellInfo {
+    pub mcc: u16,
+    pub mnc: u16,
+    pub lac: u32,
+    pub cid: u32,
+}
+
+impl CellInfo {
+    /// Lee la celda del mapa `data`; requiere los cuatro campos. LAC y CID
+    /// aceptan decimal o hexadecimal con prefijo `0x`, como los reportan
+    /// algunos fabricantes.
+    pub fn from_data(data: &HashMap<String, String>) -> Option<Self> {
+        let field = |key: &str| data.get(key).map(|s| s.trim()).filter(|s| !s.is_empty());
+        Some(Self {
+            mcc: field("MCC")?.parse().ok()?,
+            mnc: field("MNC")?.parse().ok()?,
+            lac: parse_cell_number(field("LAC")?)?,
+            cid: parse_cell_number(field("CID")?)?,
+        })
+    }
+}
+
+fn parse_cell_number(value: &str) -> Option<u32> {
+    match value
+        .strip_prefix("0x")
+        .or_else(|| value.strip_prefix("0X"))
+    {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => value.parse().ok(),
+    }
+}
+
+/// Resuelve la posición aproximada de una celda (base de datos propia,
+/// servicio externo, ...). Se consulta solo para mensajes sin LATITUD/LONGITUD;
+/// sin resolvedor configurado esos mensajes quedan sin posición.
+pub trait CellLocator: Send + Sync {
+    /// `(lat, lon)` de la celda, o None si no se conoce
+    fn locate(&self, cell: &CellInfo) -> Option<(f64, f64)>;
+}
+
+/// Origen de la posición guardada en `location_source`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LocationSource {
+    #[default]
+    Gps,
+    /// Aproximada por la celda (`CellLocator`)
+    Cell,
+}
+
+impl LocationSource {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LocationSource::Gps => "gps",
+            LocationSource::Cell => "cell",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn data(fields: &[(&str, &str)]) -> HashMap<String, String> {
+        fields
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_cell_from_decimal_and_hex_fields() {
+        let cell = CellInfo::from_data(&data(&[
+            ("MCC", "334"),
+            ("MNC", "20"),
+            ("LAC", "0x1A2B"),
+            ("CID", "40961"),
+        ]));
+
+        assert_eq!(
+            cell,
+            Some(CellInfo {
+                mcc: 334,
+                mnc: 20,
+                lac: 0x1A2B,
+                cid: 40961,
+            })
+        );
+    }
+
+    #[test]
+    fn test_incomplete_or_invalid_cell_is_none() {
+        assert_eq!(
+            CellInfo::from_data(&data(&[("MCC", "334"), ("MNC", "20"), ("LAC", "100")])),
+            None
+        );
+        assert_eq!(
+            CellInfo::from_data(&data(&[
+                ("MCC", "334"),
+                ("MNC", "20"),
+                ("LAC", "0xZZ"),
+                ("CID", "1"),
+            ])),
+            None
+        );
+    }
+}
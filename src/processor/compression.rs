@@ -0,0 +1,144 @@
+use flate2::read::GzDecoder;
+use std::borrow::Cow;
+use std::fmt;
+use std::io::Read;
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+#[derive(Debug)]
+pub enum DecompressError {
+    /// El payload inflado supera `MAX_PAYLOAD_BYTES`
+    TooLarge,
+    Io(std::io::Error),
+}
+
+impl fmt::Display for DecompressError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecompressError::TooLarge => write!(f, "inflated payload exceeds the size limit"),
+            DecompressError::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+/// Descomprime el payload si viene en gzip (bytes mágicos 0x1f 0x8b); en otro
+/// caso lo devuelve tal cual. Un payload protobuf nunca empieza con 0x1f
+/// (tipo de cable 7 no existe), así que la detección no es ambigua.
+///
+/// Se infla como mucho `max_bytes` + 1 bytes (0 = sin límite): un gzip
+/// pequeño puede expandirse a gigabytes, así que pasarse del límite es
+/// `TooLarge` sin terminar de leerlo.
+pub fn decompress_payload(
+    payload: &[u8],
+    max_bytes: usize,
+) -> Result<Cow<'_, [u8]>, DecompressError> {
+    if !payload.starts_with(&GZIP_MAGIC) {
+        return Ok(Cow::Borrowed(payload));
+    }
+
+    let limit = match max_bytes {
+        0 => u64::MAX,
+        max => max as u64 + 1,
+    };
+    let mut decoded = Vec::with_capacity((payload.len() * 4).min(limit as usize));
+    GzDecoder::new(payload)
+        .take(limit)
+        .read_to_end(&mut decoded)
+        .map_err(DecompressError::Io)?;
+    if max_bytes > 0 && decoded.len() > max_bytes {
+        return Err(DecompressError::TooLarge);
+    }
+    Ok(Cow::Owned(decoded))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::siscom::v1::KafkaMessage;
+    use crate::processor::adapters::AdapterRegistry;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use prost::Message;
+    use std::io::Write;
+
+    fn sample_payload() -> Vec<u8> {
+        KafkaMessage {
+            uuid: "3f1e2d4c-5b6a-4798-8a9b-0c1d2e3f4a5b".to_string(),
+            decoded: None,
+            data: [
+                ("DEVICE_ID", "0848086072"),
+                ("ALERT", "Turn On"),
+                ("LATITUD", "+20.652494"),
+                ("LONGITUD", "-100.391404"),
+            ]
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect(),
+            metadata: None,
+            raw: String::new(),
+        }
+        .encode_to_vec()
+    }
+
+    fn gzip(bytes: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(bytes).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn test_plain_payload_is_borrowed() {
+        let payload = sample_payload();
+
+        let result = decompress_payload(&payload, 0).unwrap();
+
+        assert!(matches!(result, Cow::Borrowed(_)));
+        assert_eq!(result.as_ref(), payload.as_slice());
+    }
+
+    #[test]
+    fn test_gzipped_payload_is_inflated() {
+        let payload = sample_payload();
+        let compressed = gzip(&payload);
+
+        let result = decompress_payload(&compressed, 0).unwrap();
+
+        assert_eq!(result.as_ref(), payload.as_slice());
+    }
+
+    #[test]
+    fn test_gzipped_payload_decodes_like_plain() {
+        let registry = AdapterRegistry::default();
+        let plain = registry.parse(&sample_payload()).unwrap();
+
+        let compressed = gzip(&sample_payload());
+        let inflated = decompress_payload(&compressed, 0).unwrap();
+        let parsed = registry.parse(&inflated).unwrap();
+
+        assert_eq!(parsed, plain);
+    }
+
+    #[test]
+    fn test_truncated_gzip_is_an_error() {
+        let compressed = gzip(&sample_payload());
+
+        assert!(matches!(
+            decompress_payload(&compressed[..compressed.len() / 2], 0),
+            Err(DecompressError::Io(_))
+        ));
+    }
+
+    #[test]
+    fn test_inflation_is_capped() {
+        // 1 MiB de ceros se comprime a ~1 KiB
+        let bomb = gzip(&vec![0; 1 << 20]);
+        assert!(bomb.len() < 4096);
+
+        assert!(matches!(
+            decompress_payload(&bomb, 64 * 1024),
+            Err(DecompressError::TooLarge)
+        ));
+        assert_eq!(decompress_payload(&bomb, 1 << 20).unwrap().len(), 1 << 20);
+        assert_eq!(decompress_payload(&bomb, 0).unwrap().len(), 1 << 20);
+    }
+}
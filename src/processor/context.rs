@@ -0,0 +1,161 @@
+use crate::circuit_breaker::CircuitBreaker;
+use crate::clock::{Clock, SystemClock};
+use crate::config::AppConfig;
+use crate::dead_letter::{DeadLetterReason, DeadLetterSink};
+use crate::event_sink::{EventSink, NoopEventSink};
+use crate::passthrough::PayloadTap;
+use crate::processor::adapters::{AdapterRegistry, InputFormat};
+use crate::processor::alert_codes::AlertCodeMap;
+use crate::processor::cell_location::CellLocator;
+use crate::processor::device_config::DeviceConfigCache;
+use crate::processor::device_filter::{DeviceFilter, DeviceFilterMode};
+use crate::processor::distance::DistanceSource;
+use crate::processor::geofence::{parse_geofences, Geofence};
+use crate::processor::message_processor::DuplicateIgnitionOn;
+use crate::processor::msg_class::MsgClassRoutes;
+use crate::processor::payload_schema::PayloadSchema;
+use crate::processor::point_sampler::PointSampler;
+use crate::processor::rate_limit::DeviceRateLimiter;
+use crate::processor::state::LastUpdatedAtSource;
+use crate::processor::tenant::TenantResolver;
+use crate::processor::timezone::DeviceTimezones;
+use crate::processor::trip_id::TripIdStrategy;
+use crate::processor::tx_batch::TxBatcher;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Estado compartido por todas las tareas que procesan mensajes
+pub struct ProcessorContext {
+    pub config: AppConfig,
+    pub adapters: AdapterRegistry,
+    pub device_filter: DeviceFilter,
+    pub trip_id_strategy: TripIdStrategy,
+    pub distance_source: DistanceSource,
+    pub duplicate_ignition_on: DuplicateIgnitionOn,
+    pub last_updated_at_source: LastUpdatedAtSource,
+    pub geofences: Vec<Geofence>,
+    pub timezones: DeviceTimezones,
+    pub msg_class_routes: MsgClassRoutes,
+    pub alert_codes: AlertCodeMap,
+    /// None salvo con ENABLE_SCHEMA_VALIDATION
+    pub payload_schema: Option<PayloadSchema>,
+    pub tenants: TenantResolver,
+    pub rate_limiter: DeviceRateLimiter,
+    pub point_sampler: PointSampler,
+    pub device_config: DeviceConfigCache,
+    pub tx_batcher: Option<TxBatcher>,
+    pub db_breaker: Arc<CircuitBreaker>,
+    pub clock: Arc<dyn Clock>,
+    pub dead_letter: Option<Arc<dyn DeadLetterSink>>,
+    pub passthrough: Option<Arc<dyn PayloadTap>>,
+    /// Destino único de los eventos de viaje (EVENT_SINK, tópicos de viajes y
+    /// PG_NOTIFY_CHANNEL); no-op por defecto
+    pub event_sink: Arc<dyn EventSink>,
+    pub cell_locator: Option<Arc<dyn CellLocator>>,
+}
+
+impl ProcessorContext {
+    pub fn new(config: AppConfig) -> Self {
+        let device_filter = DeviceFilter::new(
+            DeviceFilterMode::parse(&config.device_filter_mode),
+            &config.device_filter_list,
+        );
+        let adapters = AdapterRegistry::default()
+            .with_input_format(InputFormat::parse(&config.input_format))
+            .with_device_id_fields(&config.device_id_fields)
+            .with_lenient_json(config.lenient_parsing);
+        let trip_id_strategy = TripIdStrategy::parse(&config.trip_id_strategy);
+        let distance_source = DistanceSource::parse(&config.distance_source);
+        let duplicate_ignition_on = DuplicateIgnitionOn::parse(&config.duplicate_ignition_on);
+        let last_updated_at_source = LastUpdatedAtSource::parse(&config.last_updated_at_source);
+        let geofences = parse_geofences(&config.geofences);
+        let timezones = DeviceTimezones::parse(&config.device_timezone, &config.device_timezones);
+        let msg_class_routes = MsgClassRoutes::parse(&config.msg_class_routes);
+        let alert_codes = AlertCodeMap::parse(&config.alert_code_map);
+        let payload_schema = config
+            .enable_schema_validation
+            .then(|| PayloadSchema::parse(&config.payload_schema));
+        let tenants = TenantResolver::parse(&config.tenant_default, &config.tenant_map);
+        let rate_limiter =
+            DeviceRateLimiter::new(config.per_device_rate_limit, config.per_device_rate_burst);
+        let device_config = DeviceConfigCache::new(
+            config.device_config_overrides,
+            Duration::from_secs(config.device_config_cache_seconds),
+        );
+        let tx_batcher = TxBatcher::from_config(&config);
+        let db_breaker = Arc::new(CircuitBreaker::new(
+            config.db_max_retries,
+            Duration::from_secs(config.db_circuit_breaker_cooldown),
+        ));
+        Self {
+            config,
+            adapters,
+            device_filter,
+            trip_id_strategy,
+            distance_source,
+            duplicate_ignition_on,
+            last_updated_at_source,
+            geofences,
+            timezones,
+            msg_class_routes,
+            alert_codes,
+            payload_schema,
+            tenants,
+            rate_limiter,
+            point_sampler: PointSampler::default(),
+            device_config,
+            tx_batcher,
+            db_breaker,
+            clock: Arc::new(SystemClock),
+            dead_letter: None,
+            passthrough: None,
+            event_sink: Arc::new(NoopEventSink),
+            cell_locator: None,
+        }
+    }
+
+    /// `last_updated_at` para un mensaje con hora GPS `gps_time`, según
+    /// LAST_UPDATED_AT_SOURCE
+    pub fn last_updated_at(&self, gps_time: NaiveDateTime) -> DateTime<Utc> {
+        self.last_updated_at_source
+            .resolve(self.clock.now(), gps_time)
+    }
+
+    /// Reloj usado para `last_updated_at`; por defecto el del sistema
+    #[cfg(all(test, feature = "db-tests"))]
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    pub fn with_dead_letter(mut self, dead_letter: Option<Arc<dyn DeadLetterSink>>) -> Self {
+        self.dead_letter = dead_letter;
+        self
+    }
+
+    pub fn with_passthrough(mut self, passthrough: Option<Arc<dyn PayloadTap>>) -> Self {
+        self.passthrough = passthrough;
+        self
+    }
+
+    pub fn with_event_sink(mut self, event_sink: Arc<dyn EventSink>) -> Self {
+        self.event_sink = event_sink;
+        self
+    }
+
+    /// Resolvedor de posición por celda para mensajes sin GPS; sin él (por
+    /// defecto) esos mensajes quedan sin posición
+    #[cfg(test)]
+    pub fn with_cell_locator(mut self, cell_locator: Option<Arc<dyn CellLocator>>) -> Self {
+        self.cell_locator = cell_locator;
+        self
+    }
+
+    /// Envía el payload original al dead-letter, si está configurado
+    pub fn send_dead_letter(&self, reason: DeadLetterReason, payload: &[u8]) {
+        if let Some(sink) = self.dead_letter.as_ref() {
+            sink.send(reason, payload);
+        }
+    }
+}
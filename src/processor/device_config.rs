@@ -0,0 +1,194 @@
+use crate::config::AppConfig;
+use crate::db::queries;
+use sqlx::PgConnection;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Fila de `device_config`: cada columna NULL hereda el valor global
+#[derive(Debug, Clone, Default, PartialEq, sqlx::FromRow)]
+pub struct DeviceOverrides {
+    pub min_trip_distance_meters: Option<f64>,
+    pub state_update_min_interval_seconds: Option<i32>,
+    pub idle_stop_threshold_seconds: Option<f64>,
+    pub point_sample_every_n: Option<i32>,
+}
+
+/// Umbrales efectivos para un dispositivo
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DeviceSettings {
+    pub min_trip_distance_meters: f64,
+    pub state_update_min_interval_seconds: u64,
+    pub idle_stop_threshold_seconds: f64,
+    pub point_sample_every_n: u64,
+}
+
+impl DeviceSettings {
+    /// Valores globales de la configuración
+    pub fn global(config: &AppConfig) -> Self {
+        Self {
+            min_trip_distance_meters: config.min_trip_distance_meters,
+            state_update_min_interval_seconds: config.state_update_min_interval_seconds,
+            idle_stop_threshold_seconds: config.idle_stop_threshold_seconds,
+            point_sample_every_n: config.point_sample_every_n,
+        }
+    }
+}
+
+impl DeviceOverrides {
+    /// Aplica los overrides sobre los valores globales (negativos se ignoran)
+    pub fn resolve(&self, global: DeviceSettings) -> DeviceSettings {
+        DeviceSettings {
+            min_trip_distance_meters: self
+                .min_trip_distance_meters
+                .unwrap_or(global.min_trip_distance_meters),
+            state_update_min_interval_seconds: self
+                .state_update_min_interval_seconds
+                .and_then(|v| u64::try_from(v).ok())
+                .unwrap_or(global.state_update_min_interval_seconds),
+            idle_stop_threshold_seconds: self
+                .idle_stop_threshold_seconds
+                .unwrap_or(global.idle_stop_threshold_seconds),
+            point_sample_every_n: self
+                .point_sample_every_n
+                .and_then(|v| u64::try_from(v).ok())
+                .unwrap_or(global.point_sample_every_n),
+        }
+    }
+}
+
+/// Overrides por dispositivo (`DEVICE_CONFIG_OVERRIDES`) con caché en memoria
+/// de `DEVICE_CONFIG_CACHE_SECONDS`, para no consultar `device_config` en
+/// cada mensaje. Los dispositivos sin fila también se cachean.
+pub struct DeviceConfigCache {
+    enabled: bool,
+    ttl: Duration,
+    entries: Mutex<HashMap<String, (Instant, DeviceOverrides)>>,
+}
+
+impl DeviceConfigCache {
+    pub fn new(enabled: bool, ttl: Duration) -> Self {
+        Self {
+            enabled,
+            ttl,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Overrides cacheados del dispositivo, si no expiraron
+    pub fn cached(&self, device_id: &str, now: Instant) -> Option<DeviceOverrides> {
+        let entries = self.entries.lock().unwrap();
+        entries
+            .get(device_id)
+            .filter(|(loaded_at, _)| now.saturating_duration_since(*loaded_at) < self.ttl)
+            .map(|(_, overrides)| overrides.clone())
+    }
+
+    pub fn store(&self, device_id: &str, overrides: DeviceOverrides, now: Instant) {
+        self.entries
+            .lock()
+            .unwrap()
+            .insert(device_id.to_string(), (now, overrides));
+    }
+
+    /// Umbrales efectivos del dispositivo: del caché o de `device_config`,
+    /// con los globales como respaldo
+    pub async fn settings(
+        &self,
+        conn: &mut PgConnection,
+        config: &AppConfig,
+        device_id: &str,
+    ) -> sqlx::Result<DeviceSettings> {
+        let global = DeviceSettings::global(config);
+        if !self.enabled {
+            return Ok(global);
+        }
+        let now = Instant::now();
+        let overrides = match self.cached(device_id, now) {
+            Some(overrides) => overrides,
+            None => {
+                let overrides: DeviceOverrides = sqlx::query_as(queries::SELECT_DEVICE_CONFIG)
+                    .bind(device_id)
+                    .fetch_optional(conn)
+                    .await?
+                    .unwrap_or_default();
+                self.store(device_id, overrides.clone(), now);
+                overrides
+            }
+        };
+        Ok(overrides.resolve(global))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn global() -> DeviceSettings {
+        DeviceSettings {
+            min_trip_distance_meters: 500.0,
+            state_update_min_interval_seconds: 0,
+            idle_stop_threshold_seconds: 300.0,
+            point_sample_every_n: 1,
+        }
+    }
+
+    #[test]
+    fn test_overrides_take_precedence_over_global() {
+        let overrides = DeviceOverrides {
+            min_trip_distance_meters: Some(50.0),
+            point_sample_every_n: Some(5),
+            ..Default::default()
+        };
+
+        let settings = overrides.resolve(global());
+
+        assert_eq!(settings.min_trip_distance_meters, 50.0);
+        assert_eq!(settings.point_sample_every_n, 5);
+        // Sin override: el valor global
+        assert_eq!(settings.idle_stop_threshold_seconds, 300.0);
+        assert_eq!(settings.state_update_min_interval_seconds, 0);
+    }
+
+    #[test]
+    fn test_missing_row_and_negative_values_use_global() {
+        assert_eq!(DeviceOverrides::default().resolve(global()), global());
+
+        let overrides = DeviceOverrides {
+            state_update_min_interval_seconds: Some(-1),
+            point_sample_every_n: Some(-3),
+            ..Default::default()
+        };
+        assert_eq!(overrides.resolve(global()), global());
+    }
+
+    #[test]
+    fn test_cache_hits_until_ttl() {
+        let cache = DeviceConfigCache::new(true, Duration::from_secs(60));
+        let now = Instant::now();
+        let overrides = DeviceOverrides {
+            min_trip_distance_meters: Some(50.0),
+            ..Default::default()
+        };
+
+        assert_eq!(cache.cached("DEV-1", now), None);
+        cache.store("DEV-1", overrides.clone(), now);
+
+        assert_eq!(
+            cache.cached("DEV-1", now + Duration::from_secs(59)),
+            Some(overrides)
+        );
+        assert_eq!(cache.cached("DEV-1", now + Duration::from_secs(60)), None);
+        assert_eq!(cache.cached("DEV-2", now), None);
+    }
+
+    #[test]
+    fn test_cache_keeps_devices_without_row() {
+        let cache = DeviceConfigCache::new(true, Duration::from_secs(60));
+        let now = Instant::now();
+
+        cache.store("DEV-1", DeviceOverrides::default(), now);
+
+        assert_eq!(cache.cached("DEV-1", now), Some(DeviceOverrides::default()));
+    }
+}
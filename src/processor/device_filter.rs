@@ -0,0 +1,104 @@
+use std::collections::HashSet;
+use tracing::warn;
+
+/// Modo de filtrado de dispositivos (`DEVICE_FILTER_MODE`)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DeviceFilterMode {
+    /// Procesar todos los dispositivos
+    Off,
+    /// Procesar sólo los dispositivos de la lista
+    Allow,
+    /// Procesar todos excepto los de la lista
+    Deny,
+    /// Procesar todos. Se conserva por compatibilidad: todos los dispositivos
+    /// se registran en la tabla `devices` sin importar el modo.
+    AutoRegister,
+}
+
+impl DeviceFilterMode {
+    pub fn parse(value: &str) -> Self {
+        match value.trim().to_lowercase().as_str() {
+            "" | "off" => DeviceFilterMode::Off,
+            "allow" => DeviceFilterMode::Allow,
+            "deny" => DeviceFilterMode::Deny,
+            "auto_register" => DeviceFilterMode::AutoRegister,
+            other => {
+                warn!("Unknown DEVICE_FILTER_MODE '{}', filtering disabled", other);
+                DeviceFilterMode::Off
+            }
+        }
+    }
+}
+
+/// Lista de dispositivos permitidos/denegados cargada al arrancar
+pub struct DeviceFilter {
+    mode: DeviceFilterMode,
+    devices: HashSet<String>,
+}
+
+impl DeviceFilter {
+    pub fn new(mode: DeviceFilterMode, devices: &[String]) -> Self {
+        Self {
+            mode,
+            devices: devices.iter().cloned().collect(),
+        }
+    }
+
+    /// Indica si los mensajes del dispositivo deben procesarse
+    pub fn is_allowed(&self, device_id: &str) -> bool {
+        match self.mode {
+            DeviceFilterMode::Off | DeviceFilterMode::AutoRegister => true,
+            DeviceFilterMode::Allow => self.devices.contains(device_id),
+            DeviceFilterMode::Deny => !self.devices.contains(device_id),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn devices() -> Vec<String> {
+        vec!["0848086072".to_string(), "862524060000001".to_string()]
+    }
+
+    #[test]
+    fn test_parse_modes() {
+        assert_eq!(DeviceFilterMode::parse("off"), DeviceFilterMode::Off);
+        assert_eq!(DeviceFilterMode::parse(""), DeviceFilterMode::Off);
+        assert_eq!(DeviceFilterMode::parse("ALLOW"), DeviceFilterMode::Allow);
+        assert_eq!(DeviceFilterMode::parse("deny"), DeviceFilterMode::Deny);
+        assert_eq!(
+            DeviceFilterMode::parse("auto_register"),
+            DeviceFilterMode::AutoRegister
+        );
+        assert_eq!(DeviceFilterMode::parse("whatever"), DeviceFilterMode::Off);
+    }
+
+    #[test]
+    fn test_allow_mode() {
+        let filter = DeviceFilter::new(DeviceFilterMode::Allow, &devices());
+        assert!(filter.is_allowed("0848086072"));
+        assert!(!filter.is_allowed("unknown-device"));
+    }
+
+    #[test]
+    fn test_deny_mode() {
+        let filter = DeviceFilter::new(DeviceFilterMode::Deny, &devices());
+        assert!(!filter.is_allowed("0848086072"));
+        assert!(filter.is_allowed("unknown-device"));
+    }
+
+    #[test]
+    fn test_auto_register_mode() {
+        let filter = DeviceFilter::new(DeviceFilterMode::AutoRegister, &[]);
+        assert!(filter.is_allowed("unknown-device"));
+    }
+
+    #[test]
+    fn test_off_mode_allows_everything() {
+        let filter = DeviceFilter::new(DeviceFilterMode::Off, &devices());
+        assert!(filter.is_allowed("0848086072"));
+        assert!(filter.is_allowed("unknown-device"));
+    }
+}
@@ -0,0 +1,159 @@
+use crate::processor::geo::haversine_meters;
+use tracing::warn;
+
+/// De dónde sale el `distance_meters` de un viaje cerrado (`DISTANCE_SOURCE`).
+///
+/// - `odometer`: diferencia de ODOMETER entre el apagado y el encendido.
+/// - `haversine`: suma de distancias entre todos los puntos recibidos del
+///   viaje (aunque no se guarden, ver `POINT_SAMPLE_EVERY_N`); con pocos
+///   puntos subestima la ruta real.
+/// - `max`: el mayor de los dos.
+///
+/// Si el odómetro se reinició (fin menor que inicio) o falta, se usa haversine.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DistanceSource {
+    Haversine,
+    #[default]
+    Odometer,
+    Max,
+}
+
+impl DistanceSource {
+    pub fn parse(value: &str) -> Self {
+        match value.trim().to_lowercase().as_str() {
+            "" | "odometer" => DistanceSource::Odometer,
+            "haversine" => DistanceSource::Haversine,
+            "max" => DistanceSource::Max,
+            other => {
+                warn!("Unknown DISTANCE_SOURCE '{}', using odometer", other);
+                DistanceSource::Odometer
+            }
+        }
+    }
+
+    /// true si la distancia de odómetro basta y no hace falta leer la ruta
+    pub fn odometer_only(&self, odometer_delta: Option<f64>) -> bool {
+        *self == DistanceSource::Odometer && valid_odometer_delta(odometer_delta).is_some()
+    }
+
+    /// Distancia final del viaje a partir de la ruta y la diferencia de odómetro
+    pub fn trip_distance(&self, haversine: f64, odometer_delta: Option<f64>) -> f64 {
+        let Some(odometer) = valid_odometer_delta(odometer_delta) else {
+            return haversine;
+        };
+        match self {
+            DistanceSource::Haversine => haversine,
+            DistanceSource::Odometer => odometer,
+            DistanceSource::Max => haversine.max(odometer),
+        }
+    }
+}
+
+/// Diferencia de odómetro utilizable: un valor negativo indica un reinicio
+fn valid_odometer_delta(odometer_delta: Option<f64>) -> Option<f64> {
+    odometer_delta.filter(|delta| delta.is_finite() && *delta >= 0.0)
+}
+
+/// Diferencia porcentual entre la ruta haversine y el odómetro de un viaje
+/// cerrado, si supera `max_percent` (relativa a la mayor de las dos). Sin
+/// odómetro válido o sin ruta no hay con qué comparar. 0 = deshabilitado.
+pub fn distance_mismatch(
+    haversine: f64,
+    odometer_delta: Option<f64>,
+    max_percent: f64,
+) -> Option<f64> {
+    if max_percent <= 0.0 || haversine <= 0.0 {
+        return None;
+    }
+    let odometer = valid_odometer_delta(odometer_delta)?;
+    let percent = (haversine - odometer).abs() / haversine.max(odometer) * 100.0;
+    (percent > max_percent).then_some(percent)
+}
+
+/// Tramo en metros desde la última posición de la ruta hasta `to`; sin
+/// posición previa (viaje abierto sin coordenadas) el tramo es 0
+pub fn route_step_meters(from: Option<(f64, f64)>, to: (f64, f64)) -> f64 {
+    from.map(|(lat, lon)| haversine_meters(lat, lon, to.0, to.1))
+        .unwrap_or(0.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(DistanceSource::parse(""), DistanceSource::Odometer);
+        assert_eq!(DistanceSource::parse("odometer"), DistanceSource::Odometer);
+        assert_eq!(
+            DistanceSource::parse(" Haversine "),
+            DistanceSource::Haversine
+        );
+        assert_eq!(DistanceSource::parse("MAX"), DistanceSource::Max);
+        assert_eq!(DistanceSource::parse("gps"), DistanceSource::Odometer);
+    }
+
+    #[test]
+    fn test_each_source() {
+        assert_eq!(
+            DistanceSource::Haversine.trip_distance(1200.0, Some(1500.0)),
+            1200.0
+        );
+        assert_eq!(
+            DistanceSource::Odometer.trip_distance(1200.0, Some(1500.0)),
+            1500.0
+        );
+        assert_eq!(
+            DistanceSource::Max.trip_distance(1200.0, Some(1500.0)),
+            1500.0
+        );
+        assert_eq!(
+            DistanceSource::Max.trip_distance(1800.0, Some(1500.0)),
+            1800.0
+        );
+    }
+
+    #[test]
+    fn test_odometer_reset_falls_back_to_haversine() {
+        for source in [
+            DistanceSource::Haversine,
+            DistanceSource::Odometer,
+            DistanceSource::Max,
+        ] {
+            assert_eq!(source.trip_distance(1200.0, Some(-98_000.0)), 1200.0);
+            assert_eq!(source.trip_distance(1200.0, None), 1200.0);
+        }
+        assert!(!DistanceSource::Odometer.odometer_only(Some(-98_000.0)));
+        assert!(DistanceSource::Odometer.odometer_only(Some(0.0)));
+        assert!(!DistanceSource::Max.odometer_only(Some(1500.0)));
+    }
+
+    #[test]
+    fn test_distances_in_agreement_are_not_a_mismatch() {
+        // 10 % de diferencia con un máximo de 20 %
+        assert_eq!(distance_mismatch(900.0, Some(1000.0), 20.0), None);
+        assert_eq!(distance_mismatch(1000.0, Some(1000.0), 20.0), None);
+    }
+
+    #[test]
+    fn test_disagreeing_distances_are_a_mismatch() {
+        assert_eq!(distance_mismatch(500.0, Some(1000.0), 20.0), Some(50.0));
+        assert_eq!(distance_mismatch(4000.0, Some(1000.0), 20.0), Some(75.0));
+    }
+
+    #[test]
+    fn test_mismatch_skipped_without_both_sources() {
+        assert_eq!(distance_mismatch(500.0, None, 20.0), None);
+        assert_eq!(distance_mismatch(500.0, Some(-98_000.0), 20.0), None);
+        assert_eq!(distance_mismatch(0.0, Some(1000.0), 20.0), None);
+        assert_eq!(distance_mismatch(500.0, Some(1000.0), 0.0), None);
+    }
+
+    #[test]
+    fn test_route_step() {
+        assert_eq!(route_step_meters(None, (20.0, -100.0)), 0.0);
+
+        let d = route_step_meters(Some((0.0, 0.0)), (1.0, 0.0));
+        assert!((d - 111_195.0).abs() < 10.0, "{}", d);
+    }
+}
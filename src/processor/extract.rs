@@ -0,0 +1,800 @@
+use crate::clock::Clock;
+use crate::metrics::{self, FailureKind};
+use crate::models::siscom::v1::{KafkaMessage, Metadata};
+use crate::processor::cell_location::{CellInfo, LocationSource};
+use crate::processor::sequence::MsgCounter;
+use chrono::{NaiveDateTime, TimeZone, Utc};
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+use tracing::warn;
+use uuid::Uuid;
+
+/// Campos normalizados de un mensaje, listos para aplicar las reglas de viaje
+#[derive(Debug, Clone)]
+pub struct MessageData {
+    pub device_id: String,
+    pub message_uuid: Uuid,
+    /// CORRELATION_ID del mensaje si es un uuid válido; si no, `message_uuid`
+    pub correlation_id: Uuid,
+    pub timestamp: NaiveDateTime,
+    /// true si `timestamp` viene de GPS_EPOCH (reloj del equipo) y no de un respaldo
+    pub device_time: bool,
+    pub lat: f64,
+    pub lon: f64,
+    /// false si al mensaje le falta LATITUD o LONGITUD (p. ej. un reporte de
+    /// batería); `lat`/`lon` quedan en 0 y no deben usarse como posición
+    pub has_position: bool,
+    /// Origen de `lat`/`lon`: GPS, o la celda si se resolvió con `CellLocator`
+    pub location_source: LocationSource,
+    /// MCC/MNC/LAC/CID, si el mensaje los trae
+    pub cell: Option<CellInfo>,
+    pub speed: f64,
+    pub heading: f64,
+    pub odometer_meters: f64,
+    pub altitude: Option<f64>,
+    pub alert: Option<String>,
+    pub raw_code: Option<i32>,
+    /// SEVERITY o PRIORITY explícita del fabricante
+    pub severity: Option<i16>,
+    pub delivery_type: DeliveryType,
+    /// MSG_CLASS tal como viene (STATUS, ALERT, GPS, ... o el número del enum)
+    pub msg_class: Option<String>,
+    pub msg_counter: Option<MsgCounter>,
+    pub idle_time_seconds: Option<f64>,
+    pub metadata: Option<Metadata>,
+    pub raw: String,
+    /// Cuenta dueña del equipo; viene de TENANT/ACCOUNT_ID y se resuelve con
+    /// `TenantResolver` antes de persistir
+    pub tenant_id: Option<String>,
+    /// Conductor identificado por DRIVER_ID o IBUTTON
+    pub driver_id: Option<String>,
+}
+
+impl MessageData {
+    /// Coordenadas del mensaje, o None si no las trae
+    pub fn position(&self) -> Option<(f64, f64)> {
+        self.has_position.then_some((self.lat, self.lon))
+    }
+}
+
+/// Modo de entrega reportado en DELIVERY_TYPE
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DeliveryType {
+    /// Reporte en tiempo real (o sin DELIVERY_TYPE)
+    #[default]
+    RealTime,
+    /// Reporte almacenado en el equipo y reenviado (puede llegar horas tarde)
+    Stored,
+}
+
+impl DeliveryType {
+    pub fn parse(value: &str) -> Self {
+        let value = value.trim().to_uppercase();
+        if value.contains("STORE") || value.contains("BUFFER") {
+            DeliveryType::Stored
+        } else {
+            DeliveryType::RealTime
+        }
+    }
+}
+
+/// Convierte un GPS_EPOCH (segundos) en timestamp UTC
+pub fn parse_gps_epoch(value: &str) -> Option<NaiveDateTime> {
+    value
+        .parse::<i64>()
+        .ok()
+        .and_then(|epoch| Utc.timestamp_opt(epoch, 0).single())
+        .map(|t| t.naive_utc())
+}
+
+/// Coordenada en grados con signo ("+20.652494", "-100.391404") o con sufijo
+/// de hemisferio ("20.652494N", "100.391404 W"); `negative` (S u O) la vuelve
+/// negativa. Con sufijo el número no lleva signo. No valida el rango.
+fn parse_coordinate(value: &str, positive: char, negative: char) -> Option<f64> {
+    let value = value.trim();
+    let finite = |number: &str| number.parse::<f64>().ok().filter(|n| n.is_finite());
+    let hemisphere = value.chars().last()?.to_ascii_uppercase();
+    let sign = if hemisphere == positive {
+        1.0
+    } else if hemisphere == negative {
+        -1.0
+    } else {
+        return finite(value);
+    };
+    let number = value[..value.len() - 1].trim_end();
+    if number.starts_with(['+', '-']) {
+        return None;
+    }
+    finite(number).map(|n| n * sign)
+}
+
+/// Latitud con signo o sufijo N/S
+pub fn parse_latitude(value: &str) -> Option<f64> {
+    parse_coordinate(value, 'N', 'S')
+}
+
+/// Longitud con signo o sufijo E/W
+pub fn parse_longitude(value: &str) -> Option<f64> {
+    parse_coordinate(value, 'E', 'W')
+}
+
+/// Indica si las coordenadas presentes en el mensaje no son válidas
+/// (no numéricas o fuera de rango). La ausencia de coordenadas no es un error.
+pub fn is_invalid_gps(lat: Option<&str>, lon: Option<&str>) -> bool {
+    let invalid = |value: Option<&str>, parse: fn(&str) -> Option<f64>, limit: f64| match value {
+        Some(v) => !matches!(parse(v), Some(n) if n.abs() <= limit),
+        None => false,
+    };
+    invalid(lat, parse_latitude, 90.0) || invalid(lon, parse_longitude, 180.0)
+}
+
+/// Severidad numérica acotada al rango de `int2`; texto no numérico es None
+pub fn parse_severity(value: &str) -> Option<i16> {
+    let severity = value.trim().parse::<i64>().ok()?;
+    Some(severity.clamp(i16::MIN as i64, i16::MAX as i64) as i16)
+}
+
+/// Versión de esquema declarada por el productor (SCHEMA_VERSION,
+/// schema_version o PROTOCOL_VERSION); None si no la trae o no es numérica
+pub fn schema_version(data: &HashMap<String, String>) -> Option<u32> {
+    ["SCHEMA_VERSION", "schema_version", "PROTOCOL_VERSION"]
+        .iter()
+        .find_map(|key| data.get(*key).and_then(|v| v.trim().parse().ok()))
+}
+
+/// Campo de `data` cuyo valor no se pudo interpretar con el tipo esperado
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FieldError {
+    pub field: String,
+    pub value: String,
+    pub expected: &'static str,
+}
+
+impl fmt::Display for FieldError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "failed to parse {} value '{}' as {}",
+            self.field, self.value, self.expected
+        )
+    }
+}
+
+impl std::error::Error for FieldError {}
+
+/// Lee y convierte un campo del mapa `data`. Ausente o vacío es `Ok(None)`;
+/// un valor que no convierte devuelve el campo y el valor ofensivo.
+pub fn parse_data_field<T: FromStr>(
+    data: &HashMap<String, String>,
+    key: &str,
+) -> Result<Option<T>, FieldError> {
+    let Some(value) = data.get(key) else {
+        return Ok(None);
+    };
+    let trimmed = value.trim();
+    if trimmed.is_empty() {
+        return Ok(None);
+    }
+    trimmed.parse::<T>().map(Some).map_err(|_| FieldError {
+        field: key.to_string(),
+        value: value.clone(),
+        expected: std::any::type_name::<T>(),
+    })
+}
+
+/// Como `parse_data_field`, pero un valor inválido se registra y se trata como ausente
+fn data_field<T: FromStr>(data: &HashMap<String, String>, key: &str) -> Option<T> {
+    parse_data_field(data, key).unwrap_or_else(|e| {
+        warn!(field = %e.field, "{}", e);
+        None
+    })
+}
+
+/// Lee un campo numérico del mapa `data`. Acepta enteros ("45") y decimales
+/// ("45.5") con espacios alrededor; vacío o no numérico se trata como ausente,
+/// igual que "NaN", "inf" o "-inf", que `f64::from_str` sí acepta y romperían
+/// los cálculos de distancia.
+pub fn data_f64(data: &HashMap<String, String>, key: &str) -> Option<f64> {
+    data_field::<f64>(data, key).filter(|value| {
+        let finite = value.is_finite();
+        if !finite {
+            warn!(field = %key, "non-finite {} value '{}', ignoring", key, value);
+        }
+        finite
+    })
+}
+
+/// Como `data_f64` para LATITUD/LONGITUD, que además aceptan sufijo de hemisferio
+fn data_coordinate(
+    data: &HashMap<String, String>,
+    key: &str,
+    parse: fn(&str) -> Option<f64>,
+) -> Option<f64> {
+    let value = data.get(key)?.trim();
+    if value.is_empty() {
+        return None;
+    }
+    let parsed = parse(value);
+    if parsed.is_none() {
+        warn!(field = %key, "failed to parse {} value '{}' as a coordinate", key, value);
+    }
+    parsed
+}
+
+/// Extrae los campos del mapa `data`. Los mensajes sin DEVICE_ID se descartan;
+/// timestamps o coordenadas inválidas se registran y se usa el valor por defecto
+/// (para el timestamp, la hora actual de `clock`).
+pub fn extract_message(
+    message: KafkaMessage,
+    clock: &dyn Clock,
+) -> Result<MessageData, FailureKind> {
+    let device_id = message.data.get("DEVICE_ID").cloned().unwrap_or_default();
+    if device_id.is_empty() {
+        metrics::record_failure(
+            FailureKind::MissingDeviceId,
+            &format!(
+                "Message missing DEVICE_ID in data map, skipping. uuid={} data={:?} metadata={:?}",
+                message.uuid, message.data, message.metadata
+            ),
+        );
+        return Err(FailureKind::MissingDeviceId);
+    }
+
+    let message_uuid = Uuid::parse_str(&message.uuid).unwrap_or_else(|_| Uuid::new_v4());
+    let correlation_id = message
+        .data
+        .get("CORRELATION_ID")
+        .or_else(|| message.data.get("correlation_id"))
+        .and_then(|s| Uuid::parse_str(s.trim()).ok())
+        .unwrap_or(message_uuid);
+
+    // Use GPS_EPOCH if available, otherwise fallback to decoded_epoch or current time
+    let gps_timestamp = match message.data.get("GPS_EPOCH") {
+        Some(epoch_str) => {
+            let parsed = parse_gps_epoch(epoch_str);
+            if parsed.is_none() {
+                metrics::record_failure(
+                    FailureKind::InvalidTimestamp,
+                    &format!(
+                        "Invalid GPS_EPOCH '{}' for device {}, using fallback time",
+                        epoch_str, device_id
+                    ),
+                );
+            }
+            parsed
+        }
+        None => None,
+    };
+    let timestamp = gps_timestamp.unwrap_or_else(|| {
+        if let Some(metadata) = message.metadata.as_ref() {
+            if metadata.decoded_epoch > 0 {
+                return Utc
+                    .timestamp_millis_opt(metadata.decoded_epoch as i64)
+                    .single()
+                    .map(|t| t.naive_utc())
+                    .unwrap_or_else(|| clock.now().naive_utc());
+            }
+        }
+        clock.now().naive_utc()
+    });
+
+    let lat_raw = message.data.get("LATITUD").map(|s| s.as_str());
+    let lon_raw = message.data.get("LONGITUD").map(|s| s.as_str());
+    if is_invalid_gps(lat_raw, lon_raw) {
+        metrics::record_failure(
+            FailureKind::InvalidGps,
+            &format!(
+                "Invalid GPS coordinates lat={:?} lon={:?} for device {}",
+                lat_raw, lon_raw, device_id
+            ),
+        );
+    }
+
+    let lat = data_coordinate(&message.data, "LATITUD", parse_latitude);
+    let lon = data_coordinate(&message.data, "LONGITUD", parse_longitude);
+
+    Ok(MessageData {
+        has_position: lat.is_some() && lon.is_some(),
+        location_source: LocationSource::Gps,
+        cell: CellInfo::from_data(&message.data),
+        lat: lat.unwrap_or(0.0),
+        lon: lon.unwrap_or(0.0),
+        speed: data_f64(&message.data, "SPEED").unwrap_or(0.0),
+        heading: data_f64(&message.data, "COURSE").unwrap_or(0.0),
+        odometer_meters: data_f64(&message.data, "ODOMETER").unwrap_or(0.0),
+        altitude: data_f64(&message.data, "ALTITUDE"),
+        alert: message.data.get("ALERT").cloned(),
+        raw_code: data_field(&message.data, "RAW_CODE"),
+        severity: ["SEVERITY", "PRIORITY"]
+            .iter()
+            .find_map(|key| message.data.get(*key).and_then(|s| parse_severity(s))),
+        delivery_type: message
+            .data
+            .get("DELIVERY_TYPE")
+            .map(|s| DeliveryType::parse(s))
+            .unwrap_or_default(),
+        msg_class: message
+            .data
+            .get("MSG_CLASS")
+            .map(|s| s.trim().to_string())
+            .filter(|s| !s.is_empty()),
+        msg_counter: message
+            .data
+            .get("MSG_COUNTER")
+            .or_else(|| message.data.get("MSG_NUM"))
+            .and_then(|s| MsgCounter::parse(s)),
+        idle_time_seconds: data_f64(&message.data, "IDLE_TIME"),
+        tenant_id: ["TENANT", "ACCOUNT_ID"].iter().find_map(|key| {
+            message
+                .data
+                .get(*key)
+                .map(|s| s.trim())
+                .filter(|s| !s.is_empty())
+                .map(str::to_string)
+        }),
+        driver_id: ["DRIVER_ID", "IBUTTON"].iter().find_map(|key| {
+            message
+                .data
+                .get(*key)
+                .map(|s| s.trim())
+                .filter(|s| !s.is_empty())
+                .map(str::to_string)
+        }),
+        metadata: message.metadata,
+        raw: message.raw,
+        device_id,
+        message_uuid,
+        correlation_id,
+        timestamp,
+        device_time: gps_timestamp.is_some(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::{MockClock, SystemClock};
+
+    fn message(data: &[(&str, &str)]) -> KafkaMessage {
+        KafkaMessage {
+            uuid: Uuid::new_v4().to_string(),
+            decoded: None,
+            data: data
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+            metadata: None,
+            raw: String::new(),
+        }
+    }
+
+    #[test]
+    fn test_extract_valid_message() {
+        let data = extract_message(
+            message(&[
+                ("DEVICE_ID", "0848086072"),
+                ("GPS_EPOCH", "1700000000"),
+                ("LATITUD", "+20.652494"),
+                ("LONGITUD", "-100.391404"),
+                ("SPEED", "45.5"),
+                ("ALERT", "Turn On"),
+                ("RAW_CODE", "27"),
+            ]),
+            &SystemClock,
+        )
+        .unwrap();
+
+        assert_eq!(data.device_id, "0848086072");
+        assert_eq!(data.timestamp, parse_gps_epoch("1700000000").unwrap());
+        assert_eq!(data.lat, 20.652494);
+        assert_eq!(data.lon, -100.391404);
+        assert_eq!(data.speed, 45.5);
+        assert_eq!(data.alert.as_deref(), Some("Turn On"));
+        assert_eq!(data.raw_code, Some(27));
+    }
+
+    #[test]
+    fn test_missing_device_id_is_counted() {
+        let before = metrics::failure_count(FailureKind::MissingDeviceId);
+
+        let result = extract_message(message(&[("LATITUD", "20.0")]), &SystemClock);
+
+        assert_eq!(result.unwrap_err(), FailureKind::MissingDeviceId);
+        assert!(metrics::failure_count(FailureKind::MissingDeviceId) > before);
+    }
+
+    #[test]
+    fn test_invalid_timestamp_is_counted() {
+        let before = metrics::failure_count(FailureKind::InvalidTimestamp);
+
+        let result = extract_message(
+            message(&[("DEVICE_ID", "0848086072"), ("GPS_EPOCH", "not-a-number")]),
+            &SystemClock,
+        );
+
+        // El mensaje se procesa con el timestamp de respaldo
+        assert!(result.is_ok());
+        assert!(metrics::failure_count(FailureKind::InvalidTimestamp) > before);
+    }
+
+    #[test]
+    fn test_invalid_gps_is_counted() {
+        let before = metrics::failure_count(FailureKind::InvalidGps);
+
+        let result = extract_message(
+            message(&[
+                ("DEVICE_ID", "0848086072"),
+                ("LATITUD", "abc"),
+                ("LONGITUD", "-100.391404"),
+            ]),
+            &SystemClock,
+        );
+
+        assert!(result.is_ok());
+        assert!(metrics::failure_count(FailureKind::InvalidGps) > before);
+    }
+
+    #[test]
+    fn test_integer_speed_and_course() {
+        let data = extract_message(
+            message(&[
+                ("DEVICE_ID", "0848086072"),
+                ("SPEED", "45"),
+                ("COURSE", "270"),
+            ]),
+            &SystemClock,
+        )
+        .unwrap();
+
+        assert_eq!(data.speed, 45.0);
+        assert_eq!(data.heading, 270.0);
+    }
+
+    #[test]
+    fn test_mixed_integer_and_decimal_fields() {
+        let data = extract_message(
+            message(&[
+                ("DEVICE_ID", "0848086072"),
+                ("SPEED", " 62 "),
+                ("COURSE", "180.5"),
+                ("ODOMETER", "123456"),
+            ]),
+            &SystemClock,
+        )
+        .unwrap();
+
+        assert_eq!(data.speed, 62.0);
+        assert_eq!(data.heading, 180.5);
+        assert_eq!(data.odometer_meters, 123456.0);
+    }
+
+    #[test]
+    fn test_data_f64_empty_is_none() {
+        let data: HashMap<String, String> = [
+            ("SPEED".to_string(), "".to_string()),
+            ("COURSE".to_string(), "  ".to_string()),
+        ]
+        .into_iter()
+        .collect();
+
+        assert_eq!(data_f64(&data, "SPEED"), None);
+        assert_eq!(data_f64(&data, "COURSE"), None);
+        assert_eq!(data_f64(&data, "ODOMETER"), None);
+    }
+
+    #[test]
+    fn test_data_f64_rejects_non_finite_values() {
+        for value in ["NaN", "nan", "inf", "-inf", "Infinity"] {
+            let data: HashMap<String, String> =
+                ["LATITUD", "LONGITUD", "SPEED", "COURSE", "ODOMETER"]
+                    .iter()
+                    .map(|key| (key.to_string(), value.to_string()))
+                    .collect();
+
+            for key in ["LATITUD", "LONGITUD", "SPEED", "COURSE", "ODOMETER"] {
+                assert_eq!(data_f64(&data, key), None, "{} = {}", key, value);
+            }
+        }
+    }
+
+    #[test]
+    fn test_non_finite_coordinates_leave_message_without_position() {
+        let message = message(&[
+            ("DEVICE_ID", "DEV-NAN"),
+            ("LATITUD", "NaN"),
+            ("LONGITUD", "inf"),
+            ("SPEED", "-inf"),
+        ]);
+
+        let data = extract_message(message, &SystemClock).unwrap();
+
+        assert_eq!(data.position(), None);
+        assert_eq!(data.speed, 0.0);
+    }
+
+    #[test]
+    fn test_field_error_names_field_and_value() {
+        let data: HashMap<String, String> = [
+            ("LATITUD".to_string(), "abc".to_string()),
+            ("RAW_CODE".to_string(), "12x".to_string()),
+            ("SPEED".to_string(), " 45 ".to_string()),
+        ]
+        .into_iter()
+        .collect();
+
+        let err = parse_data_field::<f64>(&data, "LATITUD").unwrap_err();
+        assert_eq!(err.field, "LATITUD");
+        assert_eq!(err.value, "abc");
+        assert_eq!(
+            err.to_string(),
+            "failed to parse LATITUD value 'abc' as f64"
+        );
+
+        let err = parse_data_field::<i32>(&data, "RAW_CODE").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "failed to parse RAW_CODE value '12x' as i32"
+        );
+
+        assert_eq!(parse_data_field::<f64>(&data, "SPEED"), Ok(Some(45.0)));
+        assert_eq!(parse_data_field::<f64>(&data, "COURSE"), Ok(None));
+    }
+
+    #[test]
+    fn test_delivery_type_parse() {
+        assert_eq!(DeliveryType::parse("REAL TIME"), DeliveryType::RealTime);
+        assert_eq!(DeliveryType::parse("STORED"), DeliveryType::Stored);
+        assert_eq!(DeliveryType::parse(" Buffered "), DeliveryType::Stored);
+        assert_eq!(DeliveryType::parse(""), DeliveryType::RealTime);
+    }
+
+    #[test]
+    fn test_delivery_type_defaults_to_real_time() {
+        let data = extract_message(message(&[("DEVICE_ID", "0848086072")]), &SystemClock).unwrap();
+        assert_eq!(data.delivery_type, DeliveryType::RealTime);
+
+        let data = extract_message(
+            message(&[("DEVICE_ID", "0848086072"), ("DELIVERY_TYPE", "STORED")]),
+            &SystemClock,
+        )
+        .unwrap();
+        assert_eq!(data.delivery_type, DeliveryType::Stored);
+    }
+
+    #[test]
+    fn test_msg_counter_from_either_field() {
+        let data = extract_message(
+            message(&[("DEVICE_ID", "0848086072"), ("MSG_COUNTER", "06C5")]),
+            &SystemClock,
+        )
+        .unwrap();
+        assert_eq!(data.msg_counter.map(|c| c.value), Some(0x06C5));
+
+        let data = extract_message(
+            message(&[("DEVICE_ID", "0848086072"), ("MSG_NUM", "0010")]),
+            &SystemClock,
+        )
+        .unwrap();
+        assert_eq!(data.msg_counter.map(|c| c.value), Some(0x10));
+    }
+
+    #[test]
+    fn test_altitude_is_optional() {
+        let data = extract_message(
+            message(&[("DEVICE_ID", "862524060000001"), ("ALTITUDE", "1820.7")]),
+            &SystemClock,
+        )
+        .unwrap();
+        assert_eq!(data.altitude, Some(1820.7));
+
+        let data =
+            extract_message(message(&[("DEVICE_ID", "862524060000001")]), &SystemClock).unwrap();
+        assert_eq!(data.altitude, None);
+    }
+
+    #[test]
+    fn test_driver_id_from_either_field() {
+        let driver = |fields: &[(&str, &str)]| {
+            let mut fields = fields.to_vec();
+            fields.push(("DEVICE_ID", "0848086072"));
+            extract_message(message(&fields), &SystemClock)
+                .unwrap()
+                .driver_id
+        };
+
+        assert_eq!(driver(&[("DRIVER_ID", " D-17 ")]).as_deref(), Some("D-17"));
+        assert_eq!(
+            driver(&[("IBUTTON", "01A2B3C4")]).as_deref(),
+            Some("01A2B3C4")
+        );
+        assert_eq!(driver(&[("DRIVER_ID", "")]), None);
+        assert_eq!(driver(&[]), None);
+    }
+
+    #[test]
+    fn test_is_invalid_gps() {
+        assert!(!is_invalid_gps(Some("+20.652494"), Some("-100.391404")));
+        assert!(!is_invalid_gps(None, None));
+        assert!(is_invalid_gps(Some("abc"), Some("-100.391404")));
+        assert!(is_invalid_gps(Some("91.0"), Some("0.0")));
+        assert!(is_invalid_gps(Some("0.0"), Some("-180.5")));
+        assert!(!is_invalid_gps(Some("20.6N"), Some("100.3W")));
+        assert!(is_invalid_gps(Some("91.0S"), Some("100.3W")));
+        assert!(is_invalid_gps(Some("20.6E"), None));
+    }
+
+    #[test]
+    fn test_coordinates_with_sign_or_hemisphere() {
+        assert_eq!(parse_latitude("+20.652494"), Some(20.652494));
+        assert_eq!(parse_longitude("-100.391404"), Some(-100.391404));
+        assert_eq!(parse_latitude("20.6N"), Some(20.6));
+        assert_eq!(parse_latitude("20.6s"), Some(-20.6));
+        assert_eq!(parse_longitude("100.3W"), Some(-100.3));
+        assert_eq!(parse_longitude(" 100.3 E "), Some(100.3));
+    }
+
+    #[test]
+    fn test_invalid_hemisphere_coordinates() {
+        // Hemisferio del otro eje, signo y sufijo a la vez, sufijo sin número
+        assert_eq!(parse_latitude("20.6W"), None);
+        assert_eq!(parse_longitude("100.3N"), None);
+        assert_eq!(parse_latitude("-20.6S"), None);
+        assert_eq!(parse_latitude("+20.6N"), None);
+        assert_eq!(parse_latitude("N"), None);
+        assert_eq!(parse_latitude(""), None);
+        assert_eq!(parse_latitude("infN"), None);
+    }
+
+    #[test]
+    fn test_hemisphere_coordinates_are_extracted() {
+        let data = extract_message(
+            message(&[
+                ("DEVICE_ID", "0848086072"),
+                ("LATITUD", "20.652494N"),
+                ("LONGITUD", "100.391404W"),
+            ]),
+            &SystemClock,
+        )
+        .unwrap();
+
+        assert_eq!(data.position(), Some((20.652494, -100.391404)));
+    }
+
+    #[test]
+    fn test_missing_timestamp_falls_back_to_clock() {
+        let now = Utc.timestamp_opt(1_700_000_123, 0).single().unwrap();
+        let clock = MockClock::new(now);
+
+        let data = extract_message(message(&[("DEVICE_ID", "0848086072")]), &clock).unwrap();
+        assert_eq!(data.timestamp, now.naive_utc());
+    }
+
+    #[test]
+    fn test_correlation_id_from_data_when_valid() {
+        let correlation_id = Uuid::new_v4();
+        let data = extract_message(
+            message(&[
+                ("DEVICE_ID", "0848086072"),
+                ("CORRELATION_ID", &correlation_id.to_string()),
+            ]),
+            &SystemClock,
+        )
+        .unwrap();
+
+        assert_eq!(data.correlation_id, correlation_id);
+        assert_ne!(data.message_uuid, correlation_id);
+    }
+
+    #[test]
+    fn test_correlation_id_falls_back_to_message_uuid() {
+        let data = extract_message(message(&[("DEVICE_ID", "0848086072")]), &SystemClock).unwrap();
+        assert_eq!(data.correlation_id, data.message_uuid);
+
+        let data = extract_message(
+            message(&[
+                ("DEVICE_ID", "0848086072"),
+                ("CORRELATION_ID", "not-a-uuid"),
+            ]),
+            &SystemClock,
+        )
+        .unwrap();
+        assert_eq!(data.correlation_id, data.message_uuid);
+    }
+
+    #[test]
+    fn test_schema_version_from_any_field() {
+        let data = |pairs: &[(&str, &str)]| -> HashMap<String, String> {
+            pairs
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect()
+        };
+        assert_eq!(schema_version(&data(&[("SCHEMA_VERSION", "3")])), Some(3));
+        assert_eq!(schema_version(&data(&[("schema_version", " 2 ")])), Some(2));
+        assert_eq!(schema_version(&data(&[("PROTOCOL_VERSION", "1")])), Some(1));
+        assert_eq!(schema_version(&data(&[("PROTOCOL_VERSION", "v1")])), None);
+        assert_eq!(schema_version(&data(&[])), None);
+    }
+
+    #[test]
+    fn test_parse_severity() {
+        assert_eq!(parse_severity(" 3 "), Some(3));
+        assert_eq!(parse_severity("-2"), Some(-2));
+        assert_eq!(parse_severity("99999"), Some(i16::MAX));
+        assert_eq!(parse_severity("-99999"), Some(i16::MIN));
+        assert_eq!(parse_severity("high"), None);
+        assert_eq!(parse_severity(""), None);
+    }
+
+    #[test]
+    fn test_severity_from_either_field() {
+        let data = extract_message(
+            message(&[("DEVICE_ID", "0848086072"), ("PRIORITY", "2")]),
+            &SystemClock,
+        )
+        .unwrap();
+        assert_eq!(data.severity, Some(2));
+
+        // Un SEVERITY inválido cae a PRIORITY y, sin ninguno, queda None
+        let data = extract_message(
+            message(&[
+                ("DEVICE_ID", "0848086072"),
+                ("SEVERITY", "urgent"),
+                ("PRIORITY", "4"),
+            ]),
+            &SystemClock,
+        )
+        .unwrap();
+        assert_eq!(data.severity, Some(4));
+
+        let data = extract_message(
+            message(&[("DEVICE_ID", "0848086072"), ("SEVERITY", "urgent")]),
+            &SystemClock,
+        )
+        .unwrap();
+        assert_eq!(data.severity, None);
+    }
+
+    #[test]
+    fn test_tenant_from_either_field() {
+        let tenant = |data: &[(&str, &str)]| {
+            extract_message(message(data), &SystemClock)
+                .unwrap()
+                .tenant_id
+        };
+
+        assert_eq!(
+            tenant(&[("DEVICE_ID", "DEV-1"), ("TENANT", " acme ")]),
+            Some("acme".to_string())
+        );
+        assert_eq!(
+            tenant(&[("DEVICE_ID", "DEV-1"), ("ACCOUNT_ID", "beta")]),
+            Some("beta".to_string())
+        );
+        assert_eq!(tenant(&[("DEVICE_ID", "DEV-1"), ("TENANT", "")]), None);
+        assert_eq!(tenant(&[("DEVICE_ID", "DEV-1")]), None);
+    }
+
+    #[test]
+    fn test_message_without_coordinates_has_no_position() {
+        let data = extract_message(
+            message(&[("DEVICE_ID", "0848086072"), ("BATTERY", "12.4")]),
+            &SystemClock,
+        )
+        .unwrap();
+        assert_eq!(data.position(), None);
+
+        let data = extract_message(
+            message(&[
+                ("DEVICE_ID", "0848086072"),
+                ("LATITUD", "20.5"),
+                ("LONGITUD", ""),
+            ]),
+            &SystemClock,
+        )
+        .unwrap();
+        // Una sola coordenada no es una posición: nada de rellenar con 0.0
+        assert_eq!(data.position(), None);
+    }
+}
@@ -0,0 +1,72 @@
+/// Radio medio de la Tierra en metros
+const EARTH_RADIUS_METERS: f64 = 6_371_000.0;
+
+/// Distancia en metros entre dos coordenadas (fórmula de haversine)
+pub fn haversine_meters(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
+    let d_lat = (lat2 - lat1).to_radians();
+    let d_lon = (lon2 - lon1).to_radians();
+    let a = (d_lat / 2.0).sin().powi(2)
+        + lat1.to_radians().cos() * lat2.to_radians().cos() * (d_lon / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS_METERS * a.sqrt().asin()
+}
+
+/// Rumbo inicial en grados (0 = norte, 90 = este, [0, 360)) de la primera
+/// coordenada a la segunda. None si son el mismo punto: el rumbo no está definido.
+pub fn bearing(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> Option<f64> {
+    if lat1 == lat2 && lon1 == lon2 {
+        return None;
+    }
+    let (phi1, phi2) = (lat1.to_radians(), lat2.to_radians());
+    let d_lon = (lon2 - lon1).to_radians();
+    let y = d_lon.sin() * phi2.cos();
+    let x = phi1.cos() * phi2.sin() - phi1.sin() * phi2.cos() * d_lon.cos();
+    Some(y.atan2(x).to_degrees().rem_euclid(360.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_same_point_is_zero() {
+        assert_eq!(haversine_meters(20.65, -100.39, 20.65, -100.39), 0.0);
+    }
+
+    #[test]
+    fn test_known_distance() {
+        // Querétaro centro -> Ciudad de México centro, ~ 185 km en línea recta
+        let d = haversine_meters(20.5888, -100.3899, 19.4326, -99.1332);
+        assert!((d - 185_000.0).abs() < 5_000.0, "{}", d);
+    }
+
+    #[test]
+    fn test_bearing_cardinal_directions() {
+        let north = bearing(20.0, -100.0, 21.0, -100.0).unwrap();
+        let east = bearing(0.0, -100.0, 0.0, -99.0).unwrap();
+        let south = bearing(21.0, -100.0, 20.0, -100.0).unwrap();
+        let west = bearing(0.0, -99.0, 0.0, -100.0).unwrap();
+
+        assert!(north.abs() < 1e-9, "{}", north);
+        assert!((east - 90.0).abs() < 1e-9, "{}", east);
+        assert!((south - 180.0).abs() < 1e-9, "{}", south);
+        assert!((west - 270.0).abs() < 1e-9, "{}", west);
+    }
+
+    #[test]
+    fn test_bearing_known_route() {
+        // Querétaro centro -> Ciudad de México centro, hacia el sureste
+        let b = bearing(20.5888, -100.3899, 19.4326, -99.1332).unwrap();
+        assert!((b - 135.0).abs() < 2.0, "{}", b);
+    }
+
+    #[test]
+    fn test_bearing_same_point_is_undefined() {
+        assert_eq!(bearing(20.65, -100.39, 20.65, -100.39), None);
+    }
+
+    #[test]
+    fn test_one_degree_of_latitude() {
+        let d = haversine_meters(0.0, 0.0, 1.0, 0.0);
+        assert!((d - 111_195.0).abs() < 10.0, "{}", d);
+    }
+}
@@ -0,0 +1,144 @@
+use crate::processor::geo::haversine_meters;
+use tracing::warn;
+
+/// Geocerca circular configurada en `GEOFENCES`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Geofence {
+    pub name: String,
+    pub lat: f64,
+    pub lon: f64,
+    pub radius_meters: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GeofenceEvent {
+    Enter,
+    Exit,
+}
+
+impl GeofenceEvent {
+    pub fn alert_type(&self) -> &'static str {
+        match self {
+            GeofenceEvent::Enter => "geofence_enter",
+            GeofenceEvent::Exit => "geofence_exit",
+        }
+    }
+}
+
+impl Geofence {
+    /// Lee una geocerca con formato `nombre:lat:lon:radio_metros`
+    pub fn parse(value: &str) -> Option<Self> {
+        let parts: Vec<&str> = value.split(':').map(str::trim).collect();
+        let [name, lat, lon, radius] = parts.as_slice() else {
+            return None;
+        };
+        let fence = Self {
+            name: name.to_string(),
+            lat: lat.parse().ok()?,
+            lon: lon.parse().ok()?,
+            radius_meters: radius.parse().ok()?,
+        };
+        (!fence.name.is_empty() && fence.radius_meters > 0.0).then_some(fence)
+    }
+
+    pub fn contains(&self, lat: f64, lon: f64) -> bool {
+        haversine_meters(self.lat, self.lon, lat, lon) <= self.radius_meters
+    }
+}
+
+/// Carga la lista de geocercas; las entradas inválidas se ignoran con un aviso
+pub fn parse_geofences(entries: &[String]) -> Vec<Geofence> {
+    entries
+        .iter()
+        .filter_map(|entry| {
+            let fence = Geofence::parse(entry);
+            if fence.is_none() {
+                warn!("Ignoring invalid geofence '{}'", entry);
+            }
+            fence
+        })
+        .collect()
+}
+
+/// Entradas y salidas entre la posición anterior y la actual. Sin posición
+/// anterior no hay transición que reportar.
+pub fn detect_transitions(
+    geofences: &[Geofence],
+    previous: Option<(f64, f64)>,
+    current: (f64, f64),
+) -> Vec<(&Geofence, GeofenceEvent)> {
+    let Some((prev_lat, prev_lon)) = previous else {
+        return Vec::new();
+    };
+    geofences
+        .iter()
+        .filter_map(|fence| {
+            let was_inside = fence.contains(prev_lat, prev_lon);
+            let is_inside = fence.contains(current.0, current.1);
+            match (was_inside, is_inside) {
+                (false, true) => Some((fence, GeofenceEvent::Enter)),
+                (true, false) => Some((fence, GeofenceEvent::Exit)),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn depot() -> Geofence {
+        Geofence::parse("depot:20.650000:-100.390000:500").unwrap()
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            depot(),
+            Geofence {
+                name: "depot".to_string(),
+                lat: 20.65,
+                lon: -100.39,
+                radius_meters: 500.0,
+            }
+        );
+        assert_eq!(Geofence::parse("depot:20.65:-100.39"), None);
+        assert_eq!(Geofence::parse("depot:abc:-100.39:500"), None);
+        assert_eq!(Geofence::parse("depot:20.65:-100.39:0"), None);
+    }
+
+    #[test]
+    fn test_path_crossing_the_boundary() {
+        let fences = vec![depot()];
+        // ~0.001° de latitud son ~111 m
+        let path = [
+            (20.640000, -100.390000), // ~1.1 km al sur, fuera
+            (20.647000, -100.390000), // ~330 m, dentro
+            (20.650000, -100.390000), // centro
+            (20.656000, -100.390000), // ~670 m al norte, fuera
+        ];
+
+        let mut events = Vec::new();
+        let mut previous = None;
+        for point in path {
+            for (fence, event) in detect_transitions(&fences, previous, point) {
+                events.push((fence.name.clone(), event));
+            }
+            previous = Some(point);
+        }
+
+        assert_eq!(
+            events,
+            vec![
+                ("depot".to_string(), GeofenceEvent::Enter),
+                ("depot".to_string(), GeofenceEvent::Exit),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_no_transition_without_previous_position() {
+        assert!(detect_transitions(&[depot()], None, (20.65, -100.39)).is_empty());
+    }
+}
@@ -0,0 +1,54 @@
+/// Indica si IDLE_TIME acaba de cruzar el umbral de parada con motor encendido.
+///
+/// Solo se dispara en el cruce: mientras la parada continúe (IDLE_TIME sigue
+/// sobre el umbral) no se repite; al moverse el equipo reinicia IDLE_TIME y
+/// una nueva parada vuelve a dispararlo. Umbral 0 = deshabilitado.
+pub fn crosses_idle_threshold(previous: Option<f64>, current: Option<f64>, threshold: f64) -> bool {
+    if threshold <= 0.0 {
+        return false;
+    }
+    match current {
+        Some(current) if current >= threshold => !matches!(previous, Some(p) if p >= threshold),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crossing_fires() {
+        assert!(crosses_idle_threshold(Some(240.0), Some(300.0), 300.0));
+        assert!(crosses_idle_threshold(None, Some(420.0), 300.0));
+    }
+
+    #[test]
+    fn test_below_threshold_does_not_fire() {
+        assert!(!crosses_idle_threshold(Some(60.0), Some(120.0), 300.0));
+        assert!(!crosses_idle_threshold(Some(60.0), None, 300.0));
+    }
+
+    #[test]
+    fn test_same_stop_is_debounced() {
+        let threshold = 300.0;
+        let readings = [0.0, 120.0, 310.0, 420.0, 600.0, 0.0, 150.0, 330.0];
+
+        let mut previous = None;
+        let mut fired = Vec::new();
+        for reading in readings {
+            if crosses_idle_threshold(previous, Some(reading), threshold) {
+                fired.push(reading);
+            }
+            previous = Some(reading);
+        }
+
+        // Una alerta por parada
+        assert_eq!(fired, vec![310.0, 330.0]);
+    }
+
+    #[test]
+    fn test_disabled_with_zero_threshold() {
+        assert!(!crosses_idle_threshold(None, Some(10_000.0), 0.0));
+    }
+}
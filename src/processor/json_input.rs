@@ -0,0 +1,259 @@
+use crate::models::siscom::v1::{KafkaMessage, Metadata};
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::HashMap;
+
+/// Sobre JSON equivalente a `siscom.v1.KafkaMessage`
+#[derive(Debug, Deserialize)]
+struct JsonMessage {
+    #[serde(default)]
+    uuid: String,
+    #[serde(default)]
+    data: HashMap<String, Value>,
+    #[serde(default)]
+    metadata: Option<JsonMetadata>,
+    #[serde(default)]
+    raw: String,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct JsonMetadata {
+    worker_id: u32,
+    received_epoch: u64,
+    decoded_epoch: u64,
+    bytes: u32,
+    client_ip: String,
+    client_port: u32,
+}
+
+/// Convierte un valor JSON del mapa `data` a texto, como llega en protobuf.
+/// Los nulos se omiten para que cuenten como campo ausente.
+fn value_to_string(value: Value) -> Option<String> {
+    match value {
+        Value::Null => None,
+        Value::String(s) => Some(s),
+        other => Some(other.to_string()),
+    }
+}
+
+/// Decodifica un mensaje JSON al mismo `KafkaMessage` que produce la ruta protobuf
+pub fn decode_json(payload: &[u8]) -> serde_json::Result<KafkaMessage> {
+    let message: JsonMessage = serde_json::from_slice(payload)?;
+
+    Ok(KafkaMessage {
+        uuid: message.uuid,
+        decoded: None,
+        data: message
+            .data
+            .into_iter()
+            .filter_map(|(k, v)| value_to_string(v).map(|v| (k, v)))
+            .collect(),
+        metadata: message.metadata.map(|m| Metadata {
+            worker_id: m.worker_id,
+            received_epoch: m.received_epoch,
+            decoded_epoch: m.decoded_epoch,
+            bytes: m.bytes,
+            client_ip: m.client_ip,
+            client_port: m.client_port,
+        }),
+        raw: message.raw,
+    })
+}
+
+/// Campos de `data` que se revisan tras una decodificación tolerante
+const KEY_DATA_FIELDS: [&str; 4] = ["DEVICE_ID", "LATITUD", "LONGITUD", "GPS_EPOCH"];
+
+/// Decodificación tolerante (`LENIENT_PARSING`) para cuando `decode_json`
+/// falla: toma lo que pueda leerse del JSON y devuelve también la lista de
+/// campos malformados o ausentes, para registrarla. Solo falla si el payload
+/// no es JSON o no es un objeto.
+pub fn decode_json_lenient(payload: &[u8]) -> serde_json::Result<(KafkaMessage, Vec<String>)> {
+    let value: Value = serde_json::from_slice(payload)?;
+    let Value::Object(mut object) = value else {
+        return Err(serde::de::Error::custom("JSON payload is not an object"));
+    };
+    let mut failed = Vec::new();
+
+    let uuid = match object.remove("uuid") {
+        None | Some(Value::Null) => String::new(),
+        Some(Value::String(uuid)) => uuid,
+        Some(_) => {
+            failed.push("uuid".to_string());
+            String::new()
+        }
+    };
+
+    let data: HashMap<String, String> = match object.remove("data") {
+        None | Some(Value::Null) => HashMap::new(),
+        Some(Value::Object(data)) => data
+            .into_iter()
+            .filter_map(|(k, v)| value_to_string(v).map(|v| (k, v)))
+            .collect(),
+        Some(_) => {
+            failed.push("data".to_string());
+            HashMap::new()
+        }
+    };
+
+    let metadata = match object.remove("metadata") {
+        None | Some(Value::Null) => None,
+        Some(Value::Object(fields)) => {
+            let mut metadata = Metadata::default();
+            for (key, value) in fields {
+                let ok = match key.as_str() {
+                    "worker_id" => lenient_u32(&value).map(|v| metadata.worker_id = v),
+                    "received_epoch" => value.as_u64().map(|v| metadata.received_epoch = v),
+                    "decoded_epoch" => value.as_u64().map(|v| metadata.decoded_epoch = v),
+                    "bytes" => lenient_u32(&value).map(|v| metadata.bytes = v),
+                    "client_ip" => value.as_str().map(|v| metadata.client_ip = v.to_string()),
+                    "client_port" => lenient_u32(&value).map(|v| metadata.client_port = v),
+                    _ => Some(()),
+                };
+                if ok.is_none() && !value.is_null() {
+                    failed.push(format!("metadata.{}", key));
+                }
+            }
+            Some(metadata)
+        }
+        Some(_) => {
+            failed.push("metadata".to_string());
+            None
+        }
+    };
+
+    let raw = match object.remove("raw") {
+        None | Some(Value::Null) => String::new(),
+        Some(Value::String(raw)) => raw,
+        Some(_) => {
+            failed.push("raw".to_string());
+            String::new()
+        }
+    };
+
+    for key in KEY_DATA_FIELDS {
+        if !data.contains_key(key) {
+            failed.push(format!("data.{}", key));
+        }
+    }
+
+    let message = KafkaMessage {
+        uuid,
+        decoded: None,
+        data,
+        metadata,
+        raw,
+    };
+    Ok((message, failed))
+}
+
+fn lenient_u32(value: &Value) -> Option<u32> {
+    value.as_u64().and_then(|v| u32::try_from(v).ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::SystemClock;
+    use crate::processor::extract::extract_message;
+    use prost::Message;
+
+    const SAMPLE_JSON: &str = r#"{
+        "uuid": "3f1e2d4c-5b6a-4798-8a9b-0c1d2e3f4a5b",
+        "data": {
+            "DEVICE_ID": "0848086072",
+            "ALERT": "Turn On",
+            "GPS_EPOCH": "1700000000",
+            "LATITUD": "+20.652494",
+            "LONGITUD": "-100.391404",
+            "SPEED": 45,
+            "COURSE": 180.5,
+            "RAW_CODE": null
+        },
+        "metadata": {
+            "worker_id": 3,
+            "decoded_epoch": 1700000000123,
+            "client_ip": "10.0.0.5"
+        },
+        "raw": "+RESP:GTIGN,..."
+    }"#;
+
+    #[test]
+    fn test_decode_json_envelope() {
+        let message = decode_json(SAMPLE_JSON.as_bytes()).unwrap();
+
+        assert_eq!(message.uuid, "3f1e2d4c-5b6a-4798-8a9b-0c1d2e3f4a5b");
+        assert_eq!(message.data.get("DEVICE_ID").unwrap(), "0848086072");
+        assert_eq!(message.data.get("SPEED").unwrap(), "45");
+        assert_eq!(message.data.get("COURSE").unwrap(), "180.5");
+        assert!(!message.data.contains_key("RAW_CODE"));
+        let metadata = message.metadata.unwrap();
+        assert_eq!(metadata.worker_id, 3);
+        assert_eq!(metadata.client_ip, "10.0.0.5");
+        assert_eq!(metadata.bytes, 0);
+        assert_eq!(message.raw, "+RESP:GTIGN,...");
+    }
+
+    #[test]
+    fn test_json_and_protobuf_extract_same_fields() {
+        let from_json = decode_json(SAMPLE_JSON.as_bytes()).unwrap();
+        let encoded = from_json.encode_to_vec();
+        let from_protobuf = KafkaMessage::decode(encoded.as_slice()).unwrap();
+
+        let json_data = extract_message(from_json, &SystemClock).unwrap();
+        let protobuf_data = extract_message(from_protobuf, &SystemClock).unwrap();
+
+        assert_eq!(json_data.device_id, protobuf_data.device_id);
+        assert_eq!(json_data.message_uuid, protobuf_data.message_uuid);
+        assert_eq!(json_data.timestamp, protobuf_data.timestamp);
+        assert_eq!(json_data.lat, protobuf_data.lat);
+        assert_eq!(json_data.lon, protobuf_data.lon);
+        assert_eq!(json_data.speed, protobuf_data.speed);
+        assert_eq!(json_data.heading, protobuf_data.heading);
+        assert_eq!(json_data.alert, protobuf_data.alert);
+    }
+
+    #[test]
+    fn test_decode_json_rejects_invalid() {
+        assert!(decode_json(b"not json").is_err());
+    }
+
+    #[test]
+    fn test_lenient_decode_recovers_message_with_one_bad_field() {
+        let payload = SAMPLE_JSON.replace("\"worker_id\": 3", "\"worker_id\": \"three\"");
+        assert!(decode_json(payload.as_bytes()).is_err());
+
+        let (message, failed) = decode_json_lenient(payload.as_bytes()).unwrap();
+
+        assert_eq!(failed, vec!["metadata.worker_id".to_string()]);
+        let metadata = message.metadata.clone().unwrap();
+        assert_eq!(metadata.worker_id, 0);
+        assert_eq!(metadata.client_ip, "10.0.0.5");
+
+        let data = extract_message(message, &SystemClock).unwrap();
+        assert_eq!(data.device_id, "0848086072");
+        assert_eq!(data.alert.as_deref(), Some("Turn On"));
+        assert_eq!(data.position(), Some((20.652494, -100.391404)));
+        assert_eq!(data.timestamp.and_utc().timestamp(), 1_700_000_000);
+    }
+
+    #[test]
+    fn test_lenient_decode_reports_missing_key_fields() {
+        let payload = br#"{"uuid": 42, "data": {"DEVICE_ID": "0848086072", "ALERT": "Turn Off"}}"#;
+
+        let (message, failed) = decode_json_lenient(payload).unwrap();
+
+        assert_eq!(message.uuid, "");
+        assert_eq!(message.data.get("ALERT").unwrap(), "Turn Off");
+        assert_eq!(
+            failed,
+            vec!["uuid", "data.LATITUD", "data.LONGITUD", "data.GPS_EPOCH"]
+        );
+    }
+
+    #[test]
+    fn test_lenient_decode_rejects_non_objects() {
+        assert!(decode_json_lenient(b"not json").is_err());
+        assert!(decode_json_lenient(b"[1, 2, 3]").is_err());
+    }
+}
@@ -0,0 +1,2526 @@
+//! End-to-end trip lifecycle against a real database (`--features db-tests`).
+
+use crate::clock::{MockClock, SystemClock};
+use crate::config::AppConfig;
+use crate::db::maintenance;
+use crate::db::test_support::{payload, TestDb};
+use crate::event_sink::{self, RecordingEventSink};
+use crate::metrics;
+use crate::models::siscom::v1::KafkaMessage;
+use crate::processor::context::ProcessorContext;
+use crate::processor::extract::extract_message;
+use crate::processor::geo::haversine_meters;
+use crate::processor::message_processor::{
+    decode_message, persist_message, process_message, process_message_with_attempts,
+    MessageDestination, TripEvent,
+};
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use prost::Message;
+use sqlx::Row;
+use std::sync::Arc;
+
+const T0: i64 = 1_700_000_000;
+
+fn at(epoch: i64) -> DateTime<Utc> {
+    Utc.timestamp_opt(epoch, 0).single().unwrap()
+}
+
+#[tokio::test]
+async fn test_full_trip_lifecycle() {
+    let db = TestDb::new().await;
+    let ctx = ProcessorContext::new(AppConfig::development());
+    let device = "LIFECYCLE-001";
+
+    let sequence = vec![
+        payload(
+            device,
+            T0,
+            &[
+                ("ALERT", "ENGINE ON"),
+                ("LATITUD", "20.650000"),
+                ("LONGITUD", "-100.390000"),
+                ("ODOMETER", "1000"),
+            ],
+        ),
+        payload(
+            device,
+            T0 + 30,
+            &[
+                ("LATITUD", "20.651000"),
+                ("LONGITUD", "-100.391000"),
+                ("ODOMETER", "1200"),
+            ],
+        ),
+        payload(
+            device,
+            T0 + 60,
+            &[
+                ("LATITUD", "20.652000"),
+                ("LONGITUD", "-100.392000"),
+                ("ODOMETER", "1500"),
+            ],
+        ),
+        payload(
+            device,
+            T0 + 90,
+            &[
+                ("LATITUD", "20.653000"),
+                ("LONGITUD", "-100.393000"),
+                ("ODOMETER", "1900"),
+            ],
+        ),
+        payload(
+            device,
+            T0 + 120,
+            &[
+                ("ALERT", "SPEEDING"),
+                ("LATITUD", "20.654000"),
+                ("LONGITUD", "-100.394000"),
+                ("ODOMETER", "2400"),
+            ],
+        ),
+        payload(
+            device,
+            T0 + 150,
+            &[
+                ("ALERT", "ENGINE OFF"),
+                ("LATITUD", "20.655000"),
+                ("LONGITUD", "-100.395000"),
+                ("ODOMETER", "3000"),
+            ],
+        ),
+    ];
+
+    let mut destinations = Vec::new();
+    for message in &sequence {
+        destinations.push(process_message(&db.sharded(), &ctx, message).await.unwrap());
+    }
+
+    // El destino registrado por mensaje corresponde a lo que se escribió
+    assert_eq!(
+        destinations,
+        vec![
+            Some(MessageDestination::NewTrip),
+            Some(MessageDestination::TripPoint),
+            Some(MessageDestination::TripPoint),
+            Some(MessageDestination::TripPoint),
+            Some(MessageDestination::TripAlert),
+            Some(MessageDestination::EndTrip),
+        ]
+    );
+
+    let trips = sqlx::query(
+        "SELECT trip_id, start_time, end_time, distance_meters, speed_p50, speed_p95 FROM trips WHERE device_id = $1",
+    )
+    .bind(device)
+    .fetch_all(&db.pool)
+    .await
+    .unwrap();
+    assert_eq!(trips.len(), 1);
+
+    let trip = &trips[0];
+    let trip_id: uuid::Uuid = trip.get("trip_id");
+    assert_eq!(trip.get::<DateTime<Utc>, _>("start_time"), at(T0));
+    assert_eq!(
+        trip.get::<Option<DateTime<Utc>>, _>("end_time"),
+        Some(at(T0 + 150))
+    );
+    assert_eq!(trip.get::<Option<f64>, _>("distance_meters"), Some(2000.0));
+    // 3 puntos sin SPEED (0 km/h): solo alcanza para p50
+    assert_eq!(trip.get::<Option<f64>, _>("speed_p50"), Some(0.0));
+    assert_eq!(trip.get::<Option<f64>, _>("speed_p95"), None);
+
+    let points: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM trip_points WHERE trip_id = $1")
+        .bind(trip_id)
+        .fetch_one(&db.pool)
+        .await
+        .unwrap();
+    assert_eq!(points, 3);
+
+    let ignition_alerts: i64 = sqlx::query_scalar(
+        "SELECT COUNT(*) FROM trip_alerts WHERE trip_id = $1 AND alert_type IN ('ignition_on', 'ignition_off')",
+    )
+    .bind(trip_id)
+    .fetch_one(&db.pool)
+    .await
+    .unwrap();
+    assert_eq!(ignition_alerts, 2);
+
+    let state = sqlx::query(
+        "SELECT ignition_on, current_trip_id FROM trip_current_state WHERE device_id = $1",
+    )
+    .bind(device)
+    .fetch_one(&db.pool)
+    .await
+    .unwrap();
+    assert!(!state.get::<bool, _>("ignition_on"));
+    assert_eq!(state.get::<Option<uuid::Uuid>, _>("current_trip_id"), None);
+
+    db.cleanup().await;
+}
+
+#[tokio::test]
+async fn test_raw_payload_is_archived_when_enabled() {
+    let db = TestDb::new().await;
+    let mut config = AppConfig::development();
+    config.raw_payload_retention = true;
+    let ctx = ProcessorContext::new(config);
+    let device = "ARCHIVE-001";
+
+    let message = payload(device, T0, &[("LATITUD", "20.65"), ("LONGITUD", "-100.39")]);
+    process_message(&db.sharded(), &ctx, &message)
+        .await
+        .unwrap();
+
+    let archived: Vec<u8> =
+        sqlx::query_scalar("SELECT payload FROM message_archive WHERE device_id = $1")
+            .bind(device)
+            .fetch_one(&db.pool)
+            .await
+            .unwrap();
+    assert_eq!(archived, message);
+
+    db.cleanup().await;
+}
+
+#[tokio::test]
+async fn test_dry_run_commits_nothing() {
+    let db = TestDb::new().await;
+    let mut config = AppConfig::development();
+    config.dry_run = true;
+    config.raw_payload_retention = true;
+    let ctx = ProcessorContext::new(config);
+    let device = "DRYRUN-001";
+
+    for message in [
+        payload(device, T0, &[("ALERT", "ENGINE ON"), ("ODOMETER", "1000")]),
+        payload(
+            device,
+            T0 + 30,
+            &[("LATITUD", "20.65"), ("LONGITUD", "-100.39")],
+        ),
+    ] {
+        process_message(&db.sharded(), &ctx, &message)
+            .await
+            .unwrap();
+    }
+
+    for table in [
+        "trips",
+        "trip_points",
+        "trip_alerts",
+        "trip_current_state",
+        "message_archive",
+    ] {
+        let count: i64 = sqlx::query_scalar(&format!(
+            "SELECT COUNT(*) FROM {} WHERE device_id = $1",
+            table
+        ))
+        .bind(device)
+        .fetch_one(&db.pool)
+        .await
+        .unwrap();
+        assert_eq!(count, 0, "{} has rows after dry run", table);
+    }
+
+    db.cleanup().await;
+}
+
+#[tokio::test]
+async fn test_trip_point_altitude_is_stored() {
+    let db = TestDb::new().await;
+    let ctx = ProcessorContext::new(AppConfig::development());
+    let device = "ALTITUDE-001";
+
+    for message in [
+        payload(device, T0, &[("ALERT", "Turn On")]),
+        payload(
+            device,
+            T0 + 30,
+            &[
+                ("LATITUD", "20.65"),
+                ("LONGITUD", "-100.39"),
+                ("ALTITUDE", "1820.7"),
+            ],
+        ),
+        payload(
+            device,
+            T0 + 60,
+            &[("LATITUD", "20.66"), ("LONGITUD", "-100.39")],
+        ),
+    ] {
+        process_message(&db.sharded(), &ctx, &message)
+            .await
+            .unwrap();
+    }
+
+    let altitudes: Vec<Option<f64>> = sqlx::query_scalar(
+        "SELECT altitude FROM trip_points WHERE device_id = $1 ORDER BY \"timestamp\"",
+    )
+    .bind(device)
+    .fetch_all(&db.pool)
+    .await
+    .unwrap();
+    assert_eq!(altitudes, vec![Some(1820.7), None]);
+
+    db.cleanup().await;
+}
+
+#[tokio::test]
+async fn test_current_state_uses_injected_clock() {
+    let db = TestDb::new().await;
+    let clock = Arc::new(MockClock::new(at(T0 + 3600)));
+    let ctx = ProcessorContext::new(AppConfig::development()).with_clock(clock.clone());
+    let device = "CLOCK-001";
+
+    let last_updated_at = || async {
+        sqlx::query_scalar::<_, DateTime<Utc>>(
+            "SELECT last_updated_at FROM trip_current_state WHERE device_id = $1",
+        )
+        .bind(device)
+        .fetch_one(&db.pool)
+        .await
+        .unwrap()
+    };
+
+    let start = payload(device, T0, &[("ALERT", "ENGINE ON")]);
+    process_message(&db.sharded(), &ctx, &start).await.unwrap();
+    assert_eq!(last_updated_at().await, at(T0 + 3600));
+
+    // Solo el reloj avanza: el timestamp del mensaje no cambia last_updated_at
+    clock.advance(Duration::minutes(10));
+    let point = payload(
+        device,
+        T0 + 30,
+        &[("LATITUD", "20.65"), ("LONGITUD", "-100.39")],
+    );
+    process_message(&db.sharded(), &ctx, &point).await.unwrap();
+    assert_eq!(last_updated_at().await, at(T0 + 3600 + 600));
+
+    db.cleanup().await;
+}
+
+#[tokio::test]
+async fn test_trip_started_event_once_per_real_trip_start() {
+    let db = TestDb::new().await;
+    let ctx = ProcessorContext::new(AppConfig::development());
+    let device = "STARTED-001";
+
+    let sequence = [
+        payload(device, T0, &[("ALERT", "ENGINE ON")]),
+        // Ignition-on repetido con viaje activo: no abre viaje ni emite evento
+        payload(device, T0 + 30, &[("ALERT", "ENGINE ON")]),
+        payload(
+            device,
+            T0 + 60,
+            &[("LATITUD", "20.65"), ("LONGITUD", "-100.39")],
+        ),
+        payload(device, T0 + 90, &[("ALERT", "ENGINE OFF")]),
+        payload(device, T0 + 120, &[("ALERT", "ENGINE ON")]),
+    ];
+
+    let mut started = Vec::new();
+    for message in &sequence {
+        let decoded = KafkaMessage::decode(message.as_slice()).unwrap();
+        let data = extract_message(decoded, &SystemClock).unwrap();
+        let (_, events) = persist_message(&db.pool, &ctx, &data, message)
+            .await
+            .unwrap();
+        started.extend(events.into_iter().filter_map(|event| match event {
+            TripEvent::Started { trip_id } => Some(trip_id),
+            TripEvent::Completed { .. } => None,
+        }));
+    }
+
+    let trip_ids: Vec<uuid::Uuid> =
+        sqlx::query_scalar("SELECT trip_id FROM trips WHERE device_id = $1 ORDER BY start_time")
+            .bind(device)
+            .fetch_all(&db.pool)
+            .await
+            .unwrap();
+    assert_eq!(trip_ids.len(), 2);
+    assert_eq!(started, trip_ids);
+
+    db.cleanup().await;
+}
+
+#[tokio::test]
+async fn test_alert_without_coordinates_during_trip() {
+    let db = TestDb::new().await;
+    let ctx = ProcessorContext::new(AppConfig::development());
+    let device = "NOCOORDS-001";
+
+    for message in [
+        payload(
+            device,
+            T0,
+            &[
+                ("ALERT", "ENGINE ON"),
+                ("LATITUD", "20.65"),
+                ("LONGITUD", "-100.39"),
+            ],
+        ),
+        // Reporte de batería: alerta sin LATITUD/LONGITUD
+        payload(device, T0 + 30, &[("ALERT", "LOW BATTERY")]),
+        // Reporte de estado sin coordenadas: no es un punto
+        payload(device, T0 + 60, &[("BATTERY", "11.9")]),
+    ] {
+        process_message(&db.sharded(), &ctx, &message)
+            .await
+            .unwrap();
+    }
+
+    let alert = sqlx::query(
+        "SELECT lat, lng FROM trip_alerts WHERE device_id = $1 AND alert_type = 'low_battery'",
+    )
+    .bind(device)
+    .fetch_one(&db.pool)
+    .await
+    .unwrap();
+    assert_eq!(alert.get::<Option<f64>, _>("lat"), None);
+    assert_eq!(alert.get::<Option<f64>, _>("lng"), None);
+
+    let points: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM trip_points WHERE device_id = $1")
+        .bind(device)
+        .fetch_one(&db.pool)
+        .await
+        .unwrap();
+    assert_eq!(points, 0);
+
+    // La última posición conocida se conserva
+    let state =
+        sqlx::query("SELECT last_lat, last_lng FROM trip_current_state WHERE device_id = $1")
+            .bind(device)
+            .fetch_one(&db.pool)
+            .await
+            .unwrap();
+    assert_eq!(state.get::<Option<f64>, _>("last_lat"), Some(20.65));
+    assert_eq!(state.get::<Option<f64>, _>("last_lng"), Some(-100.39));
+
+    db.cleanup().await;
+}
+
+#[tokio::test]
+async fn test_tx_batch_commits_on_ignition() {
+    let db = TestDb::new().await;
+    let mut config = AppConfig::development();
+    config.tx_batch_size = 100;
+    config.tx_batch_ms = 60_000;
+    let ctx = ProcessorContext::new(config);
+    let device = "BATCH-001";
+
+    let committed_points = || async {
+        sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM trip_points WHERE device_id = $1")
+            .bind(device)
+            .fetch_one(&db.pool)
+            .await
+            .unwrap()
+    };
+    let trips = || async {
+        sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM trips WHERE device_id = $1")
+            .bind(device)
+            .fetch_one(&db.pool)
+            .await
+            .unwrap()
+    };
+
+    let start = payload(device, T0, &[("ALERT", "ENGINE ON")]);
+    process_message(&db.sharded(), &ctx, &start).await.unwrap();
+    // El encendido se confirma de inmediato
+    assert_eq!(trips().await, 1);
+
+    for offset in [30, 60] {
+        let point = payload(
+            device,
+            T0 + offset,
+            &[("LATITUD", "20.65"), ("LONGITUD", "-100.39")],
+        );
+        process_message(&db.sharded(), &ctx, &point).await.unwrap();
+    }
+    // Los puntos siguen en el lote abierto
+    assert_eq!(committed_points().await, 0);
+
+    let end = payload(device, T0 + 90, &[("ALERT", "ENGINE OFF")]);
+    process_message(&db.sharded(), &ctx, &end).await.unwrap();
+    // El apagado confirma el lote completo junto con el cierre del viaje
+    assert_eq!(committed_points().await, 2);
+    let open: i64 =
+        sqlx::query_scalar("SELECT COUNT(*) FROM trips WHERE device_id = $1 AND end_time IS NULL")
+            .bind(device)
+            .fetch_one(&db.pool)
+            .await
+            .unwrap();
+    assert_eq!(open, 0);
+
+    db.cleanup().await;
+}
+
+#[tokio::test]
+async fn test_overlapping_open_trips_are_merged_while_processing() {
+    let db = TestDb::new().await;
+    let ctx = ProcessorContext::new(AppConfig::development());
+    let device = "OVERLAP-001";
+
+    // Dos viajes abiertos y un estado activo sin current_trip_id
+    let first = uuid::Uuid::new_v4();
+    for (trip_id, start) in [(first, T0), (uuid::Uuid::new_v4(), T0 + 600)] {
+        sqlx::query("INSERT INTO trips (trip_id, device_id, start_time) VALUES ($1, $2, $3)")
+            .bind(trip_id)
+            .bind(device)
+            .bind(at(start))
+            .execute(&db.pool)
+            .await
+            .unwrap();
+    }
+    sqlx::query("INSERT INTO trip_current_state (device_id, ignition_on) VALUES ($1, true)")
+        .bind(device)
+        .execute(&db.pool)
+        .await
+        .unwrap();
+
+    let point = payload(
+        device,
+        T0 + 900,
+        &[("LATITUD", "20.65"), ("LONGITUD", "-100.39")],
+    );
+    process_message(&db.sharded(), &ctx, &point).await.unwrap();
+
+    let trips: Vec<uuid::Uuid> =
+        sqlx::query_scalar("SELECT trip_id FROM trips WHERE device_id = $1")
+            .bind(device)
+            .fetch_all(&db.pool)
+            .await
+            .unwrap();
+    assert_eq!(trips, vec![first]);
+
+    let point_trip: uuid::Uuid =
+        sqlx::query_scalar("SELECT trip_id FROM trip_points WHERE device_id = $1")
+            .bind(device)
+            .fetch_one(&db.pool)
+            .await
+            .unwrap();
+    assert_eq!(point_trip, first);
+
+    let current: Option<uuid::Uuid> =
+        sqlx::query_scalar("SELECT current_trip_id FROM trip_current_state WHERE device_id = $1")
+            .bind(device)
+            .fetch_one(&db.pool)
+            .await
+            .unwrap();
+    assert_eq!(current, Some(first));
+
+    db.cleanup().await;
+}
+
+#[tokio::test]
+async fn test_odometer_reset_falls_back_to_haversine_distance() {
+    let db = TestDb::new().await;
+    let ctx = ProcessorContext::new(AppConfig::development());
+    let device = "ODO-RESET-001";
+
+    // El odómetro se reinicia a mitad del viaje: fin < inicio
+    let sequence = vec![
+        payload(
+            device,
+            T0,
+            &[
+                ("ALERT", "ENGINE ON"),
+                ("LATITUD", "0.0"),
+                ("LONGITUD", "0.0"),
+                ("ODOMETER", "99000"),
+            ],
+        ),
+        payload(
+            device,
+            T0 + 60,
+            &[
+                ("LATITUD", "0.01"),
+                ("LONGITUD", "0.0"),
+                ("ODOMETER", "200"),
+            ],
+        ),
+        payload(
+            device,
+            T0 + 120,
+            &[
+                ("ALERT", "ENGINE OFF"),
+                ("LATITUD", "0.02"),
+                ("LONGITUD", "0.0"),
+                ("ODOMETER", "1000"),
+            ],
+        ),
+    ];
+    for message in &sequence {
+        process_message(&db.sharded(), &ctx, message).await.unwrap();
+    }
+
+    let distance: Option<f64> =
+        sqlx::query_scalar("SELECT distance_meters FROM trips WHERE device_id = $1")
+            .bind(device)
+            .fetch_one(&db.pool)
+            .await
+            .unwrap();
+    // 0.02° de latitud ~ 2224 m
+    let distance = distance.unwrap();
+    assert!((distance - 2224.0).abs() < 5.0, "{}", distance);
+
+    db.cleanup().await;
+}
+
+async fn current_trip_point_count(pool: &crate::db::DbPool, device: &str) -> i32 {
+    sqlx::query_scalar(
+        "SELECT current_trip_point_count FROM trip_current_state WHERE device_id = $1",
+    )
+    .bind(device)
+    .fetch_one(pool)
+    .await
+    .unwrap()
+}
+
+#[tokio::test]
+async fn test_current_trip_point_count_matches_inserted_points() {
+    let db = TestDb::new().await;
+    let ctx = ProcessorContext::new(AppConfig::development());
+    let device = "COUNT-001";
+
+    let point = |offset: i64| {
+        payload(
+            device,
+            T0 + offset,
+            &[("LATITUD", "20.65"), ("LONGITUD", "-100.39")],
+        )
+    };
+    let ignition = |offset: i64, alert: &str| {
+        payload(
+            device,
+            T0 + offset,
+            &[
+                ("ALERT", alert),
+                ("LATITUD", "20.65"),
+                ("LONGITUD", "-100.39"),
+            ],
+        )
+    };
+
+    process_message(&db.sharded(), &ctx, &ignition(0, "ENGINE ON"))
+        .await
+        .unwrap();
+    for offset in [30, 60, 90] {
+        process_message(&db.sharded(), &ctx, &point(offset))
+            .await
+            .unwrap();
+    }
+    let inserted: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM trip_points WHERE device_id = $1")
+        .bind(device)
+        .fetch_one(&db.pool)
+        .await
+        .unwrap();
+    assert_eq!(inserted, 3);
+    assert_eq!(current_trip_point_count(&db.pool, device).await, 3);
+
+    process_message(&db.sharded(), &ctx, &ignition(120, "ENGINE OFF"))
+        .await
+        .unwrap();
+    let point_count: Option<i32> =
+        sqlx::query_scalar("SELECT point_count FROM trips WHERE device_id = $1")
+            .bind(device)
+            .fetch_one(&db.pool)
+            .await
+            .unwrap();
+    assert_eq!(point_count, Some(3));
+
+    // Un viaje nuevo arranca el contador en cero
+    process_message(&db.sharded(), &ctx, &ignition(200, "ENGINE ON"))
+        .await
+        .unwrap();
+    assert_eq!(current_trip_point_count(&db.pool, device).await, 0);
+
+    db.cleanup().await;
+}
+
+#[tokio::test]
+async fn test_force_trip_end_closes_active_trip() {
+    let db = TestDb::new().await;
+    let ctx = ProcessorContext::new(AppConfig::development());
+    let device = "FORCE-001";
+    let message = |offset: i64, alert: &str| {
+        payload(
+            device,
+            T0 + offset,
+            &[
+                ("ALERT", alert),
+                ("LATITUD", "20.65"),
+                ("LONGITUD", "-100.39"),
+            ],
+        )
+    };
+
+    process_message(&db.sharded(), &ctx, &message(0, "ENGINE ON"))
+        .await
+        .unwrap();
+    let destination = process_message(&db.sharded(), &ctx, &message(600, "FORCE_TRIP_END"))
+        .await
+        .unwrap();
+    assert_eq!(destination, Some(MessageDestination::ForcedEndTrip));
+
+    let end_time: Option<DateTime<Utc>> =
+        sqlx::query_scalar("SELECT end_time FROM trips WHERE device_id = $1")
+            .bind(device)
+            .fetch_one(&db.pool)
+            .await
+            .unwrap();
+    assert_eq!(end_time, Some(at(T0 + 600)));
+
+    let forced: i64 = sqlx::query_scalar(
+        "SELECT COUNT(*) FROM trip_alerts WHERE device_id = $1 AND alert_type = 'forced_end'",
+    )
+    .bind(device)
+    .fetch_one(&db.pool)
+    .await
+    .unwrap();
+    assert_eq!(forced, 1);
+
+    let state = sqlx::query(
+        "SELECT ignition_on, current_trip_id FROM trip_current_state WHERE device_id = $1",
+    )
+    .bind(device)
+    .fetch_one(&db.pool)
+    .await
+    .unwrap();
+    assert!(!state.get::<bool, _>("ignition_on"));
+    assert_eq!(state.get::<Option<uuid::Uuid>, _>("current_trip_id"), None);
+
+    db.cleanup().await;
+}
+
+#[tokio::test]
+async fn test_force_trip_end_without_active_trip_is_a_no_op() {
+    let db = TestDb::new().await;
+    let ctx = ProcessorContext::new(AppConfig::development());
+    let device = "FORCE-002";
+
+    let destination = process_message(
+        &db.sharded(),
+        &ctx,
+        &payload(
+            device,
+            T0,
+            &[
+                ("ALERT", "FORCE_TRIP_END"),
+                ("LATITUD", "20.65"),
+                ("LONGITUD", "-100.39"),
+            ],
+        ),
+    )
+    .await
+    .unwrap();
+    assert_eq!(destination, Some(MessageDestination::ForcedEndTrip));
+
+    let trips: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM trips WHERE device_id = $1")
+        .bind(device)
+        .fetch_one(&db.pool)
+        .await
+        .unwrap();
+    assert_eq!(trips, 0);
+    let alerts: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM trip_alerts WHERE device_id = $1")
+        .bind(device)
+        .fetch_one(&db.pool)
+        .await
+        .unwrap();
+    assert_eq!(alerts, 0);
+
+    db.cleanup().await;
+}
+
+#[tokio::test]
+async fn test_point_sampling_keeps_every_nth_point_and_full_distance() {
+    let db = TestDb::new().await;
+    let mut config = AppConfig::development();
+    config.point_sample_every_n = 3;
+    config.distance_source = "haversine".to_string();
+    let ctx = ProcessorContext::new(config);
+    let device = "SAMPLE-001";
+
+    // Zigzag: la ruta de los puntos guardados es más corta que la real
+    let route: Vec<(f64, f64)> = (0..=7)
+        .map(|i| (i as f64 * 0.001, if i % 2 == 1 { 0.001 } else { 0.0 }))
+        .collect();
+    let coords = |i: usize| (route[i].0.to_string(), route[i].1.to_string());
+
+    let (lat, lon) = coords(0);
+    process_message(
+        &db.sharded(),
+        &ctx,
+        &payload(
+            device,
+            T0,
+            &[
+                ("ALERT", "ENGINE ON"),
+                ("LATITUD", &lat),
+                ("LONGITUD", &lon),
+            ],
+        ),
+    )
+    .await
+    .unwrap();
+    for i in 1..=6 {
+        let (lat, lon) = coords(i);
+        process_message(
+            &db.sharded(),
+            &ctx,
+            &payload(
+                device,
+                T0 + i as i64 * 10,
+                &[("LATITUD", &lat), ("LONGITUD", &lon)],
+            ),
+        )
+        .await
+        .unwrap();
+    }
+    let (lat, lon) = coords(7);
+    process_message(
+        &db.sharded(),
+        &ctx,
+        &payload(
+            device,
+            T0 + 70,
+            &[
+                ("ALERT", "ENGINE OFF"),
+                ("LATITUD", &lat),
+                ("LONGITUD", &lon),
+            ],
+        ),
+    )
+    .await
+    .unwrap();
+
+    // Puntos 3 y 6 de 6
+    let stored: Vec<DateTime<Utc>> = sqlx::query_scalar(
+        "SELECT \"timestamp\" FROM trip_points WHERE device_id = $1 ORDER BY \"timestamp\"",
+    )
+    .bind(device)
+    .fetch_all(&db.pool)
+    .await
+    .unwrap();
+    assert_eq!(stored, vec![at(T0 + 30), at(T0 + 60)]);
+
+    let expected: f64 = route
+        .windows(2)
+        .map(|p| haversine_meters(p[0].0, p[0].1, p[1].0, p[1].1))
+        .sum();
+    let distance: Option<f64> =
+        sqlx::query_scalar("SELECT distance_meters FROM trips WHERE device_id = $1")
+            .bind(device)
+            .fetch_one(&db.pool)
+            .await
+            .unwrap();
+    let distance = distance.unwrap();
+    assert!(
+        (distance - expected).abs() < 0.01,
+        "{} != {}",
+        distance,
+        expected
+    );
+
+    db.cleanup().await;
+}
+
+#[tokio::test]
+async fn test_movement_without_trip_is_flagged_as_tow() {
+    let db = TestDb::new().await;
+    let mut config = AppConfig::development();
+    config.tow_detection_meters = 100.0;
+    let ctx = ProcessorContext::new(config);
+    let device = "TOW-001";
+
+    // Estacionado, deriva de GPS (~11 m) y luego arrastrado ~111 m
+    for (offset, lat) in [(0, "20.6500"), (60, "20.6501"), (120, "20.6511")] {
+        let destination = process_message(
+            &db.sharded(),
+            &ctx,
+            &payload(
+                device,
+                T0 + offset,
+                &[("LATITUD", lat), ("LONGITUD", "-100.390000")],
+            ),
+        )
+        .await
+        .unwrap();
+        assert_eq!(destination, Some(MessageDestination::IdleActivity));
+    }
+
+    let activity: Vec<String> = sqlx::query_scalar(
+        "SELECT activity_type FROM device_idle_activity WHERE device_id = $1 ORDER BY \"timestamp\"",
+    )
+    .bind(device)
+    .fetch_all(&db.pool)
+    .await
+    .unwrap();
+    assert_eq!(
+        activity,
+        vec!["gps_idle_point", "gps_idle_point", "movement_while_off"]
+    );
+    let trips: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM trips WHERE device_id = $1")
+        .bind(device)
+        .fetch_one(&db.pool)
+        .await
+        .unwrap();
+    assert_eq!(trips, 0);
+
+    db.cleanup().await;
+}
+
+async fn engine_on_then_point(db: &TestDb, ctx: &ProcessorContext, device: &str) {
+    for message in [
+        payload(
+            device,
+            T0,
+            &[
+                ("ALERT", "ENGINE ON"),
+                ("LATITUD", "20.650000"),
+                ("LONGITUD", "-100.390000"),
+            ],
+        ),
+        payload(
+            device,
+            T0 + 30,
+            &[("LATITUD", "20.651000"), ("LONGITUD", "-100.391000")],
+        ),
+    ] {
+        process_message(&db.sharded(), ctx, &message).await.unwrap();
+    }
+}
+
+#[tokio::test]
+async fn test_stale_trip_resumes_before_close() {
+    let db = TestDb::new().await;
+    let ctx = ProcessorContext::new(AppConfig::development());
+    let device = "STALE-001";
+    let stale_after = Duration::minutes(10);
+    let resume_window = Duration::minutes(5);
+
+    engine_on_then_point(&db, &ctx, device).await;
+    let trip_id: uuid::Uuid =
+        sqlx::query_scalar("SELECT current_trip_id FROM trip_current_state WHERE device_id = $1")
+            .bind(device)
+            .fetch_one(&db.pool)
+            .await
+            .unwrap();
+
+    // Sin reportes durante 11 minutos: se marca pero aún no se cierra
+    let flagged_at = at(T0 + 30) + Duration::minutes(11);
+    let (flagged, closed) = maintenance::flag_and_close_stale_trips(
+        &db.pool,
+        &ctx,
+        flagged_at,
+        stale_after,
+        resume_window,
+    )
+    .await
+    .unwrap();
+    assert_eq!((flagged, closed), (1, 0));
+
+    // El equipo vuelve a reportar dentro de la ventana y continúa el mismo viaje
+    let resumed_at = T0 + 30 + 12 * 60;
+    let destination = process_message(
+        &db.sharded(),
+        &ctx,
+        &payload(
+            device,
+            resumed_at,
+            &[("LATITUD", "20.652000"), ("LONGITUD", "-100.392000")],
+        ),
+    )
+    .await
+    .unwrap();
+    assert_eq!(destination, Some(MessageDestination::TripPoint));
+    let stale_since: Option<DateTime<Utc>> =
+        sqlx::query_scalar("SELECT stale_since FROM trip_current_state WHERE device_id = $1")
+            .bind(device)
+            .fetch_one(&db.pool)
+            .await
+            .unwrap();
+    assert_eq!(stale_since, None);
+
+    // Pasada la ventana del marcado original ya no hay nada que cerrar
+    let (flagged, closed) = maintenance::flag_and_close_stale_trips(
+        &db.pool,
+        &ctx,
+        flagged_at + resume_window,
+        stale_after,
+        resume_window,
+    )
+    .await
+    .unwrap();
+    assert_eq!((flagged, closed), (0, 0));
+
+    let trips: Vec<(uuid::Uuid, Option<DateTime<Utc>>)> =
+        sqlx::query_as("SELECT trip_id, end_time FROM trips WHERE device_id = $1")
+            .bind(device)
+            .fetch_all(&db.pool)
+            .await
+            .unwrap();
+    assert_eq!(trips, vec![(trip_id, None)]);
+
+    db.cleanup().await;
+}
+
+#[tokio::test]
+async fn test_stale_trip_closed_then_new_trip() {
+    let db = TestDb::new().await;
+    let events = Arc::new(RecordingEventSink::default());
+    let ctx = ProcessorContext::new(AppConfig::development()).with_event_sink(events.clone());
+    let device = "STALE-002";
+    let stale_after = Duration::minutes(10);
+    let resume_window = Duration::minutes(5);
+
+    engine_on_then_point(&db, &ctx, device).await;
+
+    let flagged_at = at(T0 + 30) + Duration::minutes(11);
+    maintenance::flag_and_close_stale_trips(&db.pool, &ctx, flagged_at, stale_after, resume_window)
+        .await
+        .unwrap();
+    assert_eq!(events.names(), vec!["trip_started"]);
+    let (flagged, closed) = maintenance::flag_and_close_stale_trips(
+        &db.pool,
+        &ctx,
+        flagged_at + resume_window,
+        stale_after,
+        resume_window,
+    )
+    .await
+    .unwrap();
+    assert_eq!((flagged, closed), (0, 1));
+    assert_eq!(events.names(), vec!["trip_started", "trip_completed"]);
+
+    // El viaje inactivo quedó cerrado en su último punto conocido, con el
+    // mismo resumen que deja un apagado
+    let trip = sqlx::query(
+        "SELECT end_time, speed_p50, bbox_max_lat, bearing_degrees FROM trips WHERE device_id = $1",
+    )
+    .bind(device)
+    .fetch_one(&db.pool)
+    .await
+    .unwrap();
+    assert_eq!(
+        trip.get::<Option<DateTime<Utc>>, _>("end_time"),
+        Some(at(T0 + 30))
+    );
+    assert_eq!(trip.get::<Option<f64>, _>("speed_p50"), Some(0.0));
+    assert_eq!(trip.get::<Option<f64>, _>("bbox_max_lat"), Some(20.651));
+    assert!(trip.get::<Option<f64>, _>("bearing_degrees").is_some());
+
+    // Un encendido posterior abre un viaje nuevo
+    let destination = process_message(
+        &db.sharded(),
+        &ctx,
+        &payload(
+            device,
+            T0 + 3600,
+            &[
+                ("ALERT", "ENGINE ON"),
+                ("LATITUD", "20.660000"),
+                ("LONGITUD", "-100.400000"),
+            ],
+        ),
+    )
+    .await
+    .unwrap();
+    assert_eq!(destination, Some(MessageDestination::NewTrip));
+    let open: i64 =
+        sqlx::query_scalar("SELECT COUNT(*) FROM trips WHERE device_id = $1 AND end_time IS NULL")
+            .bind(device)
+            .fetch_one(&db.pool)
+            .await
+            .unwrap();
+    let total: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM trips WHERE device_id = $1")
+        .bind(device)
+        .fetch_one(&db.pool)
+        .await
+        .unwrap();
+    assert_eq!((open, total), (1, 2));
+
+    db.cleanup().await;
+}
+
+#[tokio::test]
+async fn test_stale_close_applies_distance_source_and_minimum() {
+    let db = TestDb::new().await;
+    let stale_after = Duration::minutes(10);
+    let resume_window = Duration::minutes(5);
+    let flagged_at = at(T0 + 30) + Duration::minutes(11);
+    // Sin ODOMETER la diferencia es 0; con haversine cuenta la ruta (~145 m)
+    let route = haversine_meters(20.65, -100.39, 20.651, -100.391);
+
+    for (device, min_distance, kept) in [("STALE-KEEP", 50.0, true), ("STALE-DROP", 500.0, false)] {
+        let events = Arc::new(RecordingEventSink::default());
+        let mut config = AppConfig::development();
+        config.distance_source = "haversine".to_string();
+        config.min_trip_distance_meters = min_distance;
+        let ctx = ProcessorContext::new(config).with_event_sink(events.clone());
+
+        engine_on_then_point(&db, &ctx, device).await;
+        for now in [flagged_at, flagged_at + resume_window] {
+            maintenance::flag_and_close_stale_trips(
+                &db.pool,
+                &ctx,
+                now,
+                stale_after,
+                resume_window,
+            )
+            .await
+            .unwrap();
+        }
+
+        let distance: Option<Option<f64>> =
+            sqlx::query_scalar("SELECT distance_meters FROM trips WHERE device_id = $1")
+                .bind(device)
+                .fetch_optional(&db.pool)
+                .await
+                .unwrap();
+        if kept {
+            assert!(
+                (distance.flatten().unwrap() - route).abs() < 1.0,
+                "{}",
+                device
+            );
+            assert_eq!(events.names(), vec!["trip_started", "trip_completed"]);
+        } else {
+            // Bajo MIN_TRIP_DISTANCE_METERS se descarta y no se publica
+            assert_eq!(distance, None, "{}", device);
+            assert_eq!(events.names(), vec!["trip_started"]);
+        }
+    }
+
+    db.cleanup().await;
+}
+
+#[tokio::test]
+async fn test_distance_mismatch_counted_on_trip_close() {
+    let db = TestDb::new().await;
+    let mut config = AppConfig::development();
+    config.distance_mismatch_percent = 20.0;
+    let ctx = ProcessorContext::new(config);
+    // Ningún otro test activa DISTANCE_MISMATCH_PERCENT, el contador es solo de este
+    let before = metrics::distance_mismatch_count();
+
+    // Ruta de ~2224 m (0.02° de latitud); el odómetro coincide o no
+    for (device, end_odometer) in [("MISMATCH-OK", "3200"), ("MISMATCH-BAD", "1500")] {
+        let sequence = vec![
+            payload(
+                device,
+                T0,
+                &[
+                    ("ALERT", "ENGINE ON"),
+                    ("LATITUD", "0.0"),
+                    ("LONGITUD", "0.0"),
+                    ("ODOMETER", "1000"),
+                ],
+            ),
+            payload(device, T0 + 60, &[("LATITUD", "0.01"), ("LONGITUD", "0.0")]),
+            payload(
+                device,
+                T0 + 120,
+                &[
+                    ("ALERT", "ENGINE OFF"),
+                    ("LATITUD", "0.02"),
+                    ("LONGITUD", "0.0"),
+                    ("ODOMETER", end_odometer),
+                ],
+            ),
+        ];
+        for message in &sequence {
+            process_message(&db.sharded(), &ctx, message).await.unwrap();
+        }
+    }
+
+    assert_eq!(metrics::distance_mismatch_count() - before, 1);
+    // La discrepancia solo se registra: ambos viajes cierran con su alerta de apagado
+    for device in ["MISMATCH-OK", "MISMATCH-BAD"] {
+        let end_alerts: Vec<String> = sqlx::query_scalar(
+            "SELECT alert_type FROM trip_alerts WHERE device_id = $1 AND \"timestamp\" = $2",
+        )
+        .bind(device)
+        .bind(at(T0 + 120))
+        .fetch_all(&db.pool)
+        .await
+        .unwrap();
+        assert_eq!(end_alerts, vec!["ignition_off".to_string()], "{}", device);
+    }
+
+    db.cleanup().await;
+}
+
+#[tokio::test]
+async fn test_records_carry_resolved_tenant() {
+    let db = TestDb::new().await;
+    let mut config = AppConfig::development();
+    config.tenant_default = "shared".to_string();
+    config.tenant_map = vec!["TENANT-MAPPED:acme".to_string()];
+    let ctx = ProcessorContext::new(config);
+
+    // Un equipo del mapa (ignora el TENANT del mensaje), uno con TENANT propio
+    // y otro sin tenant que toma TENANT_DEFAULT
+    for (device, fields) in [
+        ("TENANT-MAPPED", vec![("TENANT", "other")]),
+        ("TENANT-FIELD", vec![("TENANT", "beta")]),
+        ("TENANT-NONE", vec![]),
+    ] {
+        let with = |extra: &[(&'static str, &'static str)]| {
+            let mut all = fields.clone();
+            all.extend_from_slice(extra);
+            all
+        };
+        let sequence = [
+            payload(
+                device,
+                T0 - 60,
+                &with(&[("LATITUD", "20.65"), ("LONGITUD", "-100.39")]),
+            ),
+            payload(
+                device,
+                T0,
+                &with(&[
+                    ("ALERT", "ENGINE ON"),
+                    ("LATITUD", "20.65"),
+                    ("LONGITUD", "-100.39"),
+                ]),
+            ),
+            payload(
+                device,
+                T0 + 60,
+                &with(&[("LATITUD", "20.66"), ("LONGITUD", "-100.39")]),
+            ),
+            payload(
+                device,
+                T0 + 120,
+                &with(&[
+                    ("ALERT", "ENGINE OFF"),
+                    ("LATITUD", "20.67"),
+                    ("LONGITUD", "-100.39"),
+                ]),
+            ),
+        ];
+        for message in &sequence {
+            process_message(&db.sharded(), &ctx, message).await.unwrap();
+        }
+    }
+
+    for (device, expected) in [
+        ("TENANT-MAPPED", "acme"),
+        ("TENANT-FIELD", "beta"),
+        ("TENANT-NONE", "shared"),
+    ] {
+        for table in [
+            "trips",
+            "trip_points",
+            "trip_alerts",
+            "device_idle_activity",
+        ] {
+            let tenants: Vec<Option<String>> = sqlx::query_scalar(&format!(
+                "SELECT DISTINCT tenant_id FROM {} WHERE device_id = $1",
+                table
+            ))
+            .bind(device)
+            .fetch_all(&db.pool)
+            .await
+            .unwrap();
+            assert_eq!(
+                tenants,
+                vec![Some(expected.to_string())],
+                "{} {}",
+                table,
+                device
+            );
+        }
+    }
+
+    db.cleanup().await;
+}
+
+#[tokio::test]
+async fn test_messages_before_replay_cutoff_skip_current_state() {
+    let db = TestDb::new().await;
+    let mut config = AppConfig::development();
+    config.replay_cutoff = Some(at(T0));
+    let ctx = ProcessorContext::new(config);
+    let device = "REPLAY-001";
+
+    // Estado en vivo: viaje abierto en T0
+    for message in [
+        payload(
+            device,
+            T0,
+            &[
+                ("ALERT", "ENGINE ON"),
+                ("LATITUD", "20.650000"),
+                ("LONGITUD", "-100.390000"),
+            ],
+        ),
+        payload(
+            device,
+            T0 + 60,
+            &[("LATITUD", "20.651000"), ("LONGITUD", "-100.391000")],
+        ),
+    ] {
+        process_message(&db.sharded(), &ctx, &message)
+            .await
+            .unwrap();
+    }
+
+    // Backfill de un día antes: un apagado y un punto viejos
+    for message in [
+        payload(
+            device,
+            T0 - 86_400,
+            &[
+                ("ALERT", "ENGINE OFF"),
+                ("LATITUD", "19.400000"),
+                ("LONGITUD", "-99.100000"),
+            ],
+        ),
+        payload(
+            device,
+            T0 - 86_340,
+            &[("LATITUD", "19.401000"), ("LONGITUD", "-99.101000")],
+        ),
+    ] {
+        let destination = process_message(&db.sharded(), &ctx, &message)
+            .await
+            .unwrap();
+        assert_eq!(destination, Some(MessageDestination::Replayed));
+    }
+
+    let row = sqlx::query(
+        "SELECT ignition_on, last_point_at, last_lat FROM trip_current_state WHERE device_id = $1",
+    )
+    .bind(device)
+    .fetch_one(&db.pool)
+    .await
+    .unwrap();
+    assert!(row.get::<bool, _>("ignition_on"));
+    assert_eq!(row.get::<DateTime<Utc>, _>("last_point_at"), at(T0 + 60));
+    assert_eq!(row.get::<f64, _>("last_lat"), 20.651);
+
+    let open_trips: i64 =
+        sqlx::query_scalar("SELECT COUNT(*) FROM trips WHERE device_id = $1 AND end_time IS NULL")
+            .bind(device)
+            .fetch_one(&db.pool)
+            .await
+            .unwrap();
+    assert_eq!(open_trips, 1);
+
+    let history: Vec<(String, DateTime<Utc>)> = sqlx::query_as(
+        "SELECT activity_type, \"timestamp\" FROM device_idle_activity WHERE device_id = $1 ORDER BY \"timestamp\"",
+    )
+    .bind(device)
+    .fetch_all(&db.pool)
+    .await
+    .unwrap();
+    assert_eq!(
+        history,
+        vec![
+            ("ENGINE OFF".to_string(), at(T0 - 86_400)),
+            ("gps_idle_point".to_string(), at(T0 - 86_340)),
+        ]
+    );
+
+    db.cleanup().await;
+}
+
+#[tokio::test]
+async fn test_status_message_updates_state_without_point() {
+    let db = TestDb::new().await;
+    let mut config = AppConfig::development();
+    config.msg_class_routes = vec!["STATUS:state".to_string(), "GPS:point".to_string()];
+    let ctx = ProcessorContext::new(config);
+    let device = "MSGCLASS-001";
+
+    let sequence = [
+        (
+            payload(
+                device,
+                T0,
+                &[
+                    ("ALERT", "ENGINE ON"),
+                    ("MSG_CLASS", "STATUS"),
+                    ("LATITUD", "20.650000"),
+                    ("LONGITUD", "-100.390000"),
+                ],
+            ),
+            MessageDestination::NewTrip,
+        ),
+        (
+            payload(
+                device,
+                T0 + 30,
+                &[
+                    ("MSG_CLASS", "GPS"),
+                    ("LATITUD", "20.651000"),
+                    ("LONGITUD", "-100.391000"),
+                ],
+            ),
+            MessageDestination::TripPoint,
+        ),
+        (
+            payload(
+                device,
+                T0 + 60,
+                &[
+                    ("MSG_CLASS", "STATUS"),
+                    ("LATITUD", "20.652000"),
+                    ("LONGITUD", "-100.392000"),
+                ],
+            ),
+            MessageDestination::StateUpdate,
+        ),
+    ];
+    for (message, expected) in &sequence {
+        let destination = process_message(&db.sharded(), &ctx, message).await.unwrap();
+        assert_eq!(destination, Some(*expected));
+    }
+
+    let points: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM trip_points WHERE device_id = $1")
+        .bind(device)
+        .fetch_one(&db.pool)
+        .await
+        .unwrap();
+    assert_eq!(points, 1);
+
+    let row = sqlx::query(
+        "SELECT ignition_on, last_point_at, last_lat FROM trip_current_state WHERE device_id = $1",
+    )
+    .bind(device)
+    .fetch_one(&db.pool)
+    .await
+    .unwrap();
+    assert!(row.get::<bool, _>("ignition_on"));
+    assert_eq!(row.get::<DateTime<Utc>, _>("last_point_at"), at(T0 + 60));
+    assert_eq!(row.get::<f64, _>("last_lat"), 20.652);
+
+    db.cleanup().await;
+}
+
+#[tokio::test]
+async fn test_first_message_creates_current_state() {
+    let db = TestDb::new().await;
+    let ctx = ProcessorContext::new(AppConfig::development());
+
+    for (device, alert, expected) in [
+        (
+            "FIRST-OFF",
+            Some("ENGINE OFF"),
+            MessageDestination::IgnoredIgnitionOff,
+        ),
+        ("FIRST-POINT", None, MessageDestination::IdleActivity),
+    ] {
+        let mut fields = vec![("LATITUD", "20.650000"), ("LONGITUD", "-100.390000")];
+        fields.extend(alert.map(|alert| ("ALERT", alert)));
+        let destination = process_message(&db.sharded(), &ctx, &payload(device, T0, &fields))
+            .await
+            .unwrap();
+        assert_eq!(destination, Some(expected));
+
+        let row = sqlx::query(
+            "SELECT ignition_on, current_trip_id, last_point_at, last_lat, last_lng \
+             FROM trip_current_state WHERE device_id = $1",
+        )
+        .bind(device)
+        .fetch_optional(&db.pool)
+        .await
+        .unwrap()
+        .unwrap_or_else(|| panic!("no current state for {}", device));
+        assert!(!row.get::<bool, _>("ignition_on"));
+        assert_eq!(row.get::<Option<uuid::Uuid>, _>("current_trip_id"), None);
+        assert_eq!(row.get::<DateTime<Utc>, _>("last_point_at"), at(T0));
+        assert_eq!(row.get::<f64, _>("last_lat"), 20.65);
+        assert_eq!(row.get::<f64, _>("last_lng"), -100.39);
+    }
+
+    db.cleanup().await;
+}
+
+async fn duplicate_ignition_on(db: &TestDb, mode: &str, device: &str) -> Vec<MessageDestination> {
+    let mut config = AppConfig::development();
+    config.duplicate_ignition_on = mode.to_string();
+    let ctx = ProcessorContext::new(config);
+
+    let sequence = [
+        payload(
+            device,
+            T0,
+            &[
+                ("ALERT", "ENGINE ON"),
+                ("LATITUD", "20.650000"),
+                ("LONGITUD", "-100.390000"),
+                ("ODOMETER", "1000"),
+            ],
+        ),
+        payload(
+            device,
+            T0 + 60,
+            &[
+                ("LATITUD", "20.660000"),
+                ("LONGITUD", "-100.390000"),
+                ("ODOMETER", "2100"),
+            ],
+        ),
+        // Se perdió el apagado: llega otro encendido con el viaje abierto
+        payload(
+            device,
+            T0 + 3600,
+            &[
+                ("ALERT", "ENGINE ON"),
+                ("LATITUD", "20.700000"),
+                ("LONGITUD", "-100.390000"),
+                ("ODOMETER", "6500"),
+            ],
+        ),
+    ];
+    let mut destinations = Vec::new();
+    for message in &sequence {
+        let destination = process_message(&db.sharded(), &ctx, message).await.unwrap();
+        destinations.push(destination.unwrap());
+    }
+    destinations
+}
+
+#[tokio::test]
+async fn test_duplicate_ignition_on_is_ignored_by_default() {
+    let db = TestDb::new().await;
+    let device = "DUP-ON-IGNORE";
+
+    let destinations = duplicate_ignition_on(&db, "ignore", device).await;
+    assert_eq!(destinations[2], MessageDestination::IgnoredIgnitionOn);
+
+    let trips: Vec<(uuid::Uuid, Option<DateTime<Utc>>)> =
+        sqlx::query_as("SELECT trip_id, end_time FROM trips WHERE device_id = $1")
+            .bind(device)
+            .fetch_all(&db.pool)
+            .await
+            .unwrap();
+    assert_eq!(trips.len(), 1);
+    assert_eq!(trips[0].1, None);
+
+    let last_point_at: DateTime<Utc> =
+        sqlx::query_scalar("SELECT last_point_at FROM trip_current_state WHERE device_id = $1")
+            .bind(device)
+            .fetch_one(&db.pool)
+            .await
+            .unwrap();
+    assert_eq!(last_point_at, at(T0 + 3600));
+
+    db.cleanup().await;
+}
+
+#[tokio::test]
+async fn test_duplicate_ignition_on_splits_trip() {
+    let db = TestDb::new().await;
+    let device = "DUP-ON-SPLIT";
+
+    let destinations = duplicate_ignition_on(&db, "split", device).await;
+    assert_eq!(destinations[2], MessageDestination::SplitTrip);
+
+    let trips: Vec<(uuid::Uuid, DateTime<Utc>, Option<DateTime<Utc>>)> = sqlx::query_as(
+        "SELECT trip_id, start_time, end_time FROM trips WHERE device_id = $1 ORDER BY start_time",
+    )
+    .bind(device)
+    .fetch_all(&db.pool)
+    .await
+    .unwrap();
+    assert_eq!(trips.len(), 2);
+    let (first, second) = (&trips[0], &trips[1]);
+    // El viaje anterior se cierra en su último punto, no en el nuevo encendido
+    assert_eq!(first.1, at(T0));
+    assert_eq!(first.2, Some(at(T0 + 60)));
+    assert_eq!(second.1, at(T0 + 3600));
+    assert_eq!(second.2, None);
+
+    let end_alert: String = sqlx::query_scalar(
+        "SELECT alert_type FROM trip_alerts WHERE trip_id = $1 ORDER BY \"timestamp\" DESC LIMIT 1",
+    )
+    .bind(first.0)
+    .fetch_one(&db.pool)
+    .await
+    .unwrap();
+    assert_eq!(end_alert, "missed_ignition_off");
+
+    let current: Option<uuid::Uuid> =
+        sqlx::query_scalar("SELECT current_trip_id FROM trip_current_state WHERE device_id = $1")
+            .bind(device)
+            .fetch_one(&db.pool)
+            .await
+            .unwrap();
+    assert_eq!(current, Some(second.0));
+
+    db.cleanup().await;
+}
+
+#[tokio::test]
+async fn test_point_gap_splits_trip() {
+    let db = TestDb::new().await;
+    let mut config = AppConfig::development();
+    config.max_point_gap_seconds = 1800;
+    let ctx = ProcessorContext::new(config);
+    let device = "GAP-001";
+
+    let sequence = [
+        (
+            payload(
+                device,
+                T0,
+                &[
+                    ("ALERT", "ENGINE ON"),
+                    ("LATITUD", "20.650000"),
+                    ("LONGITUD", "-100.390000"),
+                    ("ODOMETER", "1000"),
+                ],
+            ),
+            MessageDestination::NewTrip,
+        ),
+        (
+            payload(
+                device,
+                T0 + 600,
+                &[
+                    ("LATITUD", "20.660000"),
+                    ("LONGITUD", "-100.390000"),
+                    ("ODOMETER", "2100"),
+                ],
+            ),
+            MessageDestination::TripPoint,
+        ),
+        // Tres horas sin reportar y sin apagado/encendido
+        (
+            payload(
+                device,
+                T0 + 600 + 3 * 3600,
+                &[
+                    ("LATITUD", "20.800000"),
+                    ("LONGITUD", "-100.390000"),
+                    ("ODOMETER", "40000"),
+                ],
+            ),
+            MessageDestination::GapSplit,
+        ),
+        (
+            payload(
+                device,
+                T0 + 660 + 3 * 3600,
+                &[
+                    ("LATITUD", "20.801000"),
+                    ("LONGITUD", "-100.390000"),
+                    ("ODOMETER", "40100"),
+                ],
+            ),
+            MessageDestination::TripPoint,
+        ),
+    ];
+    for (message, expected) in &sequence {
+        let destination = process_message(&db.sharded(), &ctx, message).await.unwrap();
+        assert_eq!(destination, Some(*expected));
+    }
+
+    type TripRow = (
+        uuid::Uuid,
+        DateTime<Utc>,
+        Option<DateTime<Utc>>,
+        Option<f64>,
+    );
+    let trips: Vec<TripRow> =
+        sqlx::query_as(
+            "SELECT trip_id, start_time, end_time, end_lat FROM trips WHERE device_id = $1 ORDER BY start_time",
+        )
+        .bind(device)
+        .fetch_all(&db.pool)
+        .await
+        .unwrap();
+    assert_eq!(trips.len(), 2);
+    // El primero cierra en el último punto antes del hueco
+    assert_eq!(trips[0].2, Some(at(T0 + 600)));
+    assert_eq!(trips[0].3, Some(20.66));
+    // El segundo empieza en el primer punto después del hueco y sigue abierto
+    assert_eq!(trips[1].1, at(T0 + 600 + 3 * 3600));
+    assert_eq!(trips[1].2, None);
+
+    for (trip_id, _, _, _) in &trips {
+        let markers: i64 = sqlx::query_scalar(
+            "SELECT COUNT(*) FROM trip_alerts WHERE trip_id = $1 AND alert_type = 'gap_split'",
+        )
+        .bind(trip_id)
+        .fetch_one(&db.pool)
+        .await
+        .unwrap();
+        assert_eq!(markers, 1);
+    }
+
+    let points: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM trip_points WHERE trip_id = $1")
+        .bind(trips[1].0)
+        .fetch_one(&db.pool)
+        .await
+        .unwrap();
+    assert_eq!(points, 1);
+
+    db.cleanup().await;
+}
+
+#[tokio::test]
+async fn test_trip_events_are_notified_after_commit() {
+    let db = TestDb::new().await;
+    let channel = format!("trip_events_{}", uuid::Uuid::new_v4().simple());
+    let mut config = AppConfig::development();
+    config.pg_notify_channel = channel.clone();
+    let events = event_sink::from_config(&config, &db.sharded()).unwrap();
+    let ctx = ProcessorContext::new(config).with_event_sink(events);
+    let device = "NOTIFY-001";
+
+    let mut listener = sqlx::postgres::PgListener::connect_with(&db.pool)
+        .await
+        .unwrap();
+    listener.listen(&channel).await.unwrap();
+
+    process_message(
+        &db.sharded(),
+        &ctx,
+        &payload(
+            device,
+            T0,
+            &[
+                ("ALERT", "ENGINE ON"),
+                ("LATITUD", "20.650000"),
+                ("LONGITUD", "-100.390000"),
+            ],
+        ),
+    )
+    .await
+    .unwrap();
+
+    let notification = tokio::time::timeout(std::time::Duration::from_secs(5), listener.recv())
+        .await
+        .expect("no notification received")
+        .unwrap();
+    assert_eq!(notification.channel(), channel);
+
+    let trip_id: uuid::Uuid = sqlx::query_scalar("SELECT trip_id FROM trips WHERE device_id = $1")
+        .bind(device)
+        .fetch_one(&db.pool)
+        .await
+        .unwrap();
+    let payload: serde_json::Value = serde_json::from_str(notification.payload()).unwrap();
+    assert_eq!(payload["event"], "trip_started");
+    assert_eq!(payload["trip_id"], trip_id.to_string());
+    assert_eq!(payload["device_id"], device);
+
+    db.cleanup().await;
+}
+
+#[tokio::test]
+async fn test_closed_trip_stores_bearing_and_bounding_box() {
+    let db = TestDb::new().await;
+    let ctx = ProcessorContext::new(AppConfig::development());
+    let device = "BEARING-001";
+
+    // Hacia el norte con un desvío al este a mitad del viaje
+    for message in [
+        payload(
+            device,
+            T0,
+            &[
+                ("ALERT", "ENGINE ON"),
+                ("LATITUD", "20.600000"),
+                ("LONGITUD", "-100.400000"),
+                ("ODOMETER", "1000"),
+            ],
+        ),
+        payload(
+            device,
+            T0 + 60,
+            &[("LATITUD", "20.650000"), ("LONGITUD", "-100.350000")],
+        ),
+        payload(
+            device,
+            T0 + 120,
+            &[
+                ("ALERT", "ENGINE OFF"),
+                ("LATITUD", "20.700000"),
+                ("LONGITUD", "-100.400000"),
+                ("ODOMETER", "13000"),
+            ],
+        ),
+    ] {
+        process_message(&db.sharded(), &ctx, &message)
+            .await
+            .unwrap();
+    }
+
+    let trip = sqlx::query(
+        "SELECT bearing_degrees, bbox_min_lat, bbox_min_lng, bbox_max_lat, bbox_max_lng \
+         FROM trips WHERE device_id = $1",
+    )
+    .bind(device)
+    .fetch_one(&db.pool)
+    .await
+    .unwrap();
+    let bearing: f64 = trip.get::<Option<f64>, _>("bearing_degrees").unwrap();
+    assert!(bearing.abs() < 1e-6, "{}", bearing);
+    assert_eq!(trip.get::<Option<f64>, _>("bbox_min_lat"), Some(20.6));
+    assert_eq!(trip.get::<Option<f64>, _>("bbox_min_lng"), Some(-100.4));
+    assert_eq!(trip.get::<Option<f64>, _>("bbox_max_lat"), Some(20.7));
+    assert_eq!(trip.get::<Option<f64>, _>("bbox_max_lng"), Some(-100.35));
+
+    db.cleanup().await;
+}
+
+#[tokio::test]
+async fn test_code_only_messages_use_alert_code_map() {
+    let db = TestDb::new().await;
+    let mut config = AppConfig::development();
+    config.alert_code_map = vec!["27:Turn On".to_string(), "30:SPEEDING".to_string()];
+    let ctx = ProcessorContext::new(config);
+    let device = "RAWCODE-001";
+
+    let mut destinations = Vec::new();
+    for message in [
+        payload(
+            device,
+            T0,
+            &[
+                ("RAW_CODE", "27"),
+                ("LATITUD", "20.65"),
+                ("LONGITUD", "-100.39"),
+            ],
+        ),
+        payload(
+            device,
+            T0 + 30,
+            &[
+                ("RAW_CODE", "30"),
+                ("LATITUD", "20.66"),
+                ("LONGITUD", "-100.39"),
+            ],
+        ),
+        // Código sin mapeo: sigue siendo un punto
+        payload(
+            device,
+            T0 + 60,
+            &[
+                ("RAW_CODE", "99"),
+                ("LATITUD", "20.67"),
+                ("LONGITUD", "-100.39"),
+            ],
+        ),
+    ] {
+        destinations.push(
+            process_message(&db.sharded(), &ctx, &message)
+                .await
+                .unwrap(),
+        );
+    }
+    assert_eq!(
+        destinations,
+        vec![
+            Some(MessageDestination::NewTrip),
+            Some(MessageDestination::TripAlert),
+            Some(MessageDestination::TripPoint),
+        ]
+    );
+
+    let alert = sqlx::query(
+        "SELECT alert_type, raw_code FROM trip_alerts WHERE device_id = $1 AND \"timestamp\" = $2",
+    )
+    .bind(device)
+    .bind(at(T0 + 30))
+    .fetch_one(&db.pool)
+    .await
+    .unwrap();
+    assert_eq!(alert.get::<String, _>("alert_type"), "speeding");
+    assert_eq!(alert.get::<Option<i32>, _>("raw_code"), Some(30));
+
+    db.cleanup().await;
+}
+
+#[tokio::test]
+async fn test_idle_activity_stores_speed_and_heading() {
+    let db = TestDb::new().await;
+    let ctx = ProcessorContext::new(AppConfig::development());
+    let device = "IDLE-SPEED-001";
+
+    // Sin viaje abierto pero moviéndose: posible grúa o robo
+    let destination = process_message(
+        &db.sharded(),
+        &ctx,
+        &payload(
+            device,
+            T0,
+            &[
+                ("LATITUD", "20.650000"),
+                ("LONGITUD", "-100.390000"),
+                ("SPEED", "23.5"),
+                ("COURSE", "270"),
+            ],
+        ),
+    )
+    .await
+    .unwrap();
+    assert_eq!(destination, Some(MessageDestination::IdleActivity));
+
+    let row = sqlx::query("SELECT speed, heading FROM device_idle_activity WHERE device_id = $1")
+        .bind(device)
+        .fetch_one(&db.pool)
+        .await
+        .unwrap();
+    assert_eq!(row.get::<Option<f64>, _>("speed"), Some(23.5));
+    assert_eq!(row.get::<Option<f64>, _>("heading"), Some(270.0));
+
+    db.cleanup().await;
+}
+
+#[tokio::test]
+async fn test_last_updated_at_from_gps_time() {
+    let db = TestDb::new().await;
+    let mut config = AppConfig::development();
+    config.last_updated_at_source = "gps".to_string();
+    let clock = Arc::new(MockClock::new(at(T0 + 3600)));
+    let ctx = ProcessorContext::new(config).with_clock(clock);
+    let device = "GPS-UPDATED-001";
+
+    let last_updated_at = || async {
+        sqlx::query_scalar::<_, DateTime<Utc>>(
+            "SELECT last_updated_at FROM trip_current_state WHERE device_id = $1",
+        )
+        .bind(device)
+        .fetch_one(&db.pool)
+        .await
+        .unwrap()
+    };
+
+    let start = payload(device, T0, &[("ALERT", "ENGINE ON")]);
+    process_message(&db.sharded(), &ctx, &start).await.unwrap();
+    assert_eq!(last_updated_at().await, at(T0));
+
+    let point = payload(
+        device,
+        T0 + 30,
+        &[("LATITUD", "20.65"), ("LONGITUD", "-100.39")],
+    );
+    process_message(&db.sharded(), &ctx, &point).await.unwrap();
+    assert_eq!(last_updated_at().await, at(T0 + 30));
+
+    db.cleanup().await;
+}
+
+#[tokio::test]
+async fn test_trip_asset_id_from_device_assets() {
+    let db = TestDb::new().await;
+    let ctx = ProcessorContext::new(AppConfig::development());
+    sqlx::query("INSERT INTO device_assets (device_id, asset_id) VALUES ($1, $2)")
+        .bind("ASSET-DEV-1")
+        .bind("TRUCK-42")
+        .execute(&db.pool)
+        .await
+        .unwrap();
+
+    for device in ["ASSET-DEV-1", "ASSET-DEV-2"] {
+        process_message(
+            &db.sharded(),
+            &ctx,
+            &payload(device, T0, &[("ALERT", "ENGINE ON")]),
+        )
+        .await
+        .unwrap();
+    }
+
+    let asset_of = |device: &'static str| {
+        let pool = db.pool.clone();
+        async move {
+            sqlx::query_scalar::<_, Option<String>>(
+                "SELECT asset_id FROM trips WHERE device_id = $1",
+            )
+            .bind(device)
+            .fetch_one(&pool)
+            .await
+            .unwrap()
+        }
+    };
+    // Mapeado: el vehículo; sin mapeo: el propio device_id
+    assert_eq!(asset_of("ASSET-DEV-1").await.as_deref(), Some("TRUCK-42"));
+    assert_eq!(
+        asset_of("ASSET-DEV-2").await.as_deref(),
+        Some("ASSET-DEV-2")
+    );
+
+    db.cleanup().await;
+}
+
+#[tokio::test]
+async fn test_skipped_duplicate_point_opens_no_transaction() {
+    let db = TestDb::new().await;
+    let mut config = AppConfig::development();
+    config.skip_noop_transactions = true;
+    let ctx = ProcessorContext::new(config);
+    let device = "NOOP-001";
+
+    let start = payload(
+        device,
+        T0,
+        &[("ALERT", "ENGINE ON"), ("MSG_COUNTER", "0001")],
+    );
+    process_message(&db.sharded(), &ctx, &start).await.unwrap();
+    let point = payload(
+        device,
+        T0 + 30,
+        &[
+            ("LATITUD", "20.65"),
+            ("LONGITUD", "-100.39"),
+            ("MSG_COUNTER", "0002"),
+        ],
+    );
+    let destination = process_message(&db.sharded(), &ctx, &point).await.unwrap();
+    assert_eq!(destination, Some(MessageDestination::TripPoint));
+
+    // Mismo contador, hora posterior: sin transacción no avanza ni last_seen_at
+    let duplicate = payload(
+        device,
+        T0 + 60,
+        &[
+            ("LATITUD", "20.66"),
+            ("LONGITUD", "-100.40"),
+            ("MSG_COUNTER", "0002"),
+        ],
+    );
+    let destination = process_message(&db.sharded(), &ctx, &duplicate)
+        .await
+        .unwrap();
+    assert_eq!(destination, None);
+
+    let last_seen_at: DateTime<Utc> =
+        sqlx::query_scalar("SELECT last_seen_at FROM devices WHERE device_id = $1")
+            .bind(device)
+            .fetch_one(&db.pool)
+            .await
+            .unwrap();
+    assert_eq!(last_seen_at, at(T0 + 30));
+    let points: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM trip_points WHERE device_id = $1")
+        .bind(device)
+        .fetch_one(&db.pool)
+        .await
+        .unwrap();
+    assert_eq!(points, 1);
+    let last_point_at: DateTime<Utc> =
+        sqlx::query_scalar("SELECT last_point_at FROM trip_current_state WHERE device_id = $1")
+            .bind(device)
+            .fetch_one(&db.pool)
+            .await
+            .unwrap();
+    assert_eq!(last_point_at, at(T0 + 30));
+
+    db.cleanup().await;
+}
+
+#[tokio::test]
+async fn test_ignition_off_without_trip_recorded_only_when_enabled() {
+    let db = TestDb::new().await;
+    let mut config = AppConfig::development();
+    config.record_ignition_off_no_trip = true;
+    let recording = ProcessorContext::new(config);
+    let default = ProcessorContext::new(AppConfig::development());
+
+    for (device, ctx) in [("OFF-NO-TRIP-1", &recording), ("OFF-NO-TRIP-2", &default)] {
+        let off = payload(
+            device,
+            T0,
+            &[
+                ("ALERT", "ENGINE OFF"),
+                ("LATITUD", "20.65"),
+                ("LONGITUD", "-100.39"),
+            ],
+        );
+        let destination = process_message(&db.sharded(), ctx, &off).await.unwrap();
+        assert_eq!(destination, Some(MessageDestination::IgnoredIgnitionOff));
+    }
+
+    let activity_of = |device: &'static str| {
+        let pool = db.pool.clone();
+        async move {
+            sqlx::query_scalar::<_, String>(
+                "SELECT activity_type FROM device_idle_activity WHERE device_id = $1",
+            )
+            .bind(device)
+            .fetch_all(&pool)
+            .await
+            .unwrap()
+        }
+    };
+    assert_eq!(
+        activity_of("OFF-NO-TRIP-1").await,
+        vec!["ignition_off_no_trip"]
+    );
+    assert!(activity_of("OFF-NO-TRIP-2").await.is_empty());
+
+    // En ambos casos se actualiza la última posición y no se abre viaje
+    for device in ["OFF-NO-TRIP-1", "OFF-NO-TRIP-2"] {
+        let row = sqlx::query(
+            "SELECT ignition_on, last_point_at FROM trip_current_state WHERE device_id = $1",
+        )
+        .bind(device)
+        .fetch_one(&db.pool)
+        .await
+        .unwrap();
+        assert!(!row.get::<bool, _>("ignition_on"));
+        assert_eq!(row.get::<DateTime<Utc>, _>("last_point_at"), at(T0));
+    }
+
+    db.cleanup().await;
+}
+
+#[tokio::test]
+async fn test_driver_change_splits_trip() {
+    let db = TestDb::new().await;
+    let mut config = AppConfig::development();
+    config.split_trip_on_driver_change = true;
+    let ctx = ProcessorContext::new(config);
+    let device = "DRIVER-001";
+
+    let sequence = [
+        (
+            payload(
+                device,
+                T0,
+                &[
+                    ("ALERT", "ENGINE ON"),
+                    ("LATITUD", "20.650000"),
+                    ("LONGITUD", "-100.390000"),
+                    ("ODOMETER", "1000"),
+                ],
+            ),
+            MessageDestination::NewTrip,
+        ),
+        // El conductor se identifica después del encendido
+        (
+            payload(
+                device,
+                T0 + 30,
+                &[
+                    ("LATITUD", "20.651000"),
+                    ("LONGITUD", "-100.390000"),
+                    ("ODOMETER", "1100"),
+                    ("IBUTTON", "01A2B3C4"),
+                ],
+            ),
+            MessageDestination::TripPoint,
+        ),
+        (
+            payload(
+                device,
+                T0 + 60,
+                &[
+                    ("LATITUD", "20.652000"),
+                    ("LONGITUD", "-100.390000"),
+                    ("ODOMETER", "1200"),
+                    ("DRIVER_ID", "01A2B3C4"),
+                ],
+            ),
+            MessageDestination::TripPoint,
+        ),
+        (
+            payload(
+                device,
+                T0 + 90,
+                &[
+                    ("LATITUD", "20.653000"),
+                    ("LONGITUD", "-100.390000"),
+                    ("ODOMETER", "1300"),
+                    ("DRIVER_ID", "05F6E7D8"),
+                ],
+            ),
+            MessageDestination::DriverChange,
+        ),
+        (
+            payload(
+                device,
+                T0 + 120,
+                &[
+                    ("LATITUD", "20.654000"),
+                    ("LONGITUD", "-100.390000"),
+                    ("ODOMETER", "1400"),
+                    ("DRIVER_ID", "05F6E7D8"),
+                ],
+            ),
+            MessageDestination::TripPoint,
+        ),
+    ];
+    for (message, expected) in &sequence {
+        let destination = process_message(&db.sharded(), &ctx, message).await.unwrap();
+        assert_eq!(destination, Some(*expected));
+    }
+
+    let trips = sqlx::query(
+        "SELECT trip_id, start_time, end_time, driver_id FROM trips WHERE device_id = $1 ORDER BY start_time",
+    )
+    .bind(device)
+    .fetch_all(&db.pool)
+    .await
+    .unwrap();
+    assert_eq!(trips.len(), 2);
+    // El primer viaje cierra en el último punto del conductor anterior
+    assert_eq!(
+        trips[0].get::<Option<String>, _>("driver_id").as_deref(),
+        Some("01A2B3C4")
+    );
+    assert_eq!(
+        trips[0].get::<Option<DateTime<Utc>>, _>("end_time"),
+        Some(at(T0 + 60))
+    );
+    assert_eq!(
+        trips[1].get::<Option<String>, _>("driver_id").as_deref(),
+        Some("05F6E7D8")
+    );
+    assert_eq!(trips[1].get::<DateTime<Utc>, _>("start_time"), at(T0 + 90));
+    assert_eq!(trips[1].get::<Option<DateTime<Utc>>, _>("end_time"), None);
+
+    for trip in &trips {
+        let markers: i64 = sqlx::query_scalar(
+            "SELECT COUNT(*) FROM trip_alerts WHERE trip_id = $1 AND alert_type = 'driver_change'",
+        )
+        .bind(trip.get::<uuid::Uuid, _>("trip_id"))
+        .fetch_one(&db.pool)
+        .await
+        .unwrap();
+        assert_eq!(markers, 1);
+    }
+
+    let current_driver: Option<String> =
+        sqlx::query_scalar("SELECT current_driver_id FROM trip_current_state WHERE device_id = $1")
+            .bind(device)
+            .fetch_one(&db.pool)
+            .await
+            .unwrap();
+    assert_eq!(current_driver.as_deref(), Some("05F6E7D8"));
+
+    db.cleanup().await;
+}
+
+#[tokio::test]
+async fn test_idempotent_alerts_skip_redelivered_alert() {
+    use sqlx::Executor;
+
+    let db = TestDb::new().await;
+    db.pool
+        .execute(include_str!(
+            "../../migration_add_trip_alerts_natural_key.sql"
+        ))
+        .await
+        .unwrap();
+    let mut config = AppConfig::development();
+    config.idempotent_alerts = true;
+    let ctx = ProcessorContext::new(config);
+    let device = "IDEMPOTENT-001";
+
+    let start = payload(device, T0, &[("ALERT", "ENGINE ON")]);
+    process_message(&db.sharded(), &ctx, &start).await.unwrap();
+    let alert = payload(
+        device,
+        T0 + 30,
+        &[
+            ("ALERT", "SPEEDING"),
+            ("LATITUD", "20.65"),
+            ("LONGITUD", "-100.39"),
+        ],
+    );
+    // El mismo payload reentregado y el mismo evento reenviado con otro uuid
+    let resent = payload(
+        device,
+        T0 + 30,
+        &[
+            ("ALERT", "SPEEDING"),
+            ("LATITUD", "20.65"),
+            ("LONGITUD", "-100.39"),
+        ],
+    );
+    for message in [&alert, &alert, &resent] {
+        let destination = process_message(&db.sharded(), &ctx, message).await.unwrap();
+        assert_eq!(destination, Some(MessageDestination::TripAlert));
+    }
+
+    let alerts: i64 = sqlx::query_scalar(
+        "SELECT COUNT(*) FROM trip_alerts WHERE device_id = $1 AND raw_alert_type = 'SPEEDING'",
+    )
+    .bind(device)
+    .fetch_one(&db.pool)
+    .await
+    .unwrap();
+    assert_eq!(alerts, 1);
+
+    // Otra hora es otra alerta
+    let later = payload(device, T0 + 90, &[("ALERT", "SPEEDING")]);
+    process_message(&db.sharded(), &ctx, &later).await.unwrap();
+    let alerts: i64 = sqlx::query_scalar(
+        "SELECT COUNT(*) FROM trip_alerts WHERE device_id = $1 AND raw_alert_type = 'SPEEDING'",
+    )
+    .bind(device)
+    .fetch_one(&db.pool)
+    .await
+    .unwrap();
+    assert_eq!(alerts, 2);
+
+    db.cleanup().await;
+}
+
+async fn count_raw_alerts(db: &TestDb, device: &str, raw_alert_type: &str) -> i64 {
+    sqlx::query_scalar(
+        "SELECT COUNT(*) FROM trip_alerts WHERE device_id = $1 AND raw_alert_type = $2",
+    )
+    .bind(device)
+    .bind(raw_alert_type)
+    .fetch_one(&db.pool)
+    .await
+    .unwrap()
+}
+
+#[tokio::test]
+async fn test_alert_debounce_window() {
+    let db = TestDb::new().await;
+    let mut config = AppConfig::development();
+    config.alert_debounce_seconds = 60;
+    let ctx = ProcessorContext::new(config);
+    let device = "DEBOUNCE-001";
+
+    let start = payload(device, T0, &[("ALERT", "ENGINE ON")]);
+    process_message(&db.sharded(), &ctx, &start).await.unwrap();
+    for offset in [10, 30, 69, 70, 100] {
+        let sos = payload(device, T0 + offset, &[("ALERT", "SOS")]);
+        process_message(&db.sharded(), &ctx, &sos).await.unwrap();
+    }
+    // Otro tipo no comparte la ventana
+    let speeding = payload(device, T0 + 20, &[("ALERT", "SPEEDING")]);
+    process_message(&db.sharded(), &ctx, &speeding)
+        .await
+        .unwrap();
+
+    // T0+10 abre la ventana y T0+70 la siguiente
+    assert_eq!(count_raw_alerts(&db, device, "SOS").await, 2);
+    assert_eq!(count_raw_alerts(&db, device, "SPEEDING").await, 1);
+
+    db.cleanup().await;
+}
+
+#[tokio::test]
+async fn test_rolled_back_alert_does_not_debounce_its_retry() {
+    use sqlx::Executor;
+
+    let db = TestDb::new().await;
+    // El primer SOS insertado aborta la transacción (la secuencia no se revierte)
+    db.pool
+        .execute(
+            r#"
+            CREATE SEQUENCE alert_failures;
+            CREATE FUNCTION fail_first_sos() RETURNS trigger AS $$
+            BEGIN
+                IF NEW.raw_alert_type = 'SOS' AND nextval('alert_failures') = 1 THEN
+                    RAISE EXCEPTION 'injected failure';
+                END IF;
+                RETURN NEW;
+            END $$ LANGUAGE plpgsql;
+            CREATE TRIGGER fail_first_sos AFTER INSERT ON trip_alerts
+                FOR EACH ROW EXECUTE FUNCTION fail_first_sos();
+            "#,
+        )
+        .await
+        .unwrap();
+    let mut config = AppConfig::development();
+    config.alert_debounce_seconds = 60;
+    config.max_message_attempts = 3;
+    config.kafka_backoff_max_ms = 10;
+    let ctx = ProcessorContext::new(config);
+    let device = "DEBOUNCE-002";
+
+    let start = payload(device, T0, &[("ALERT", "ENGINE ON")]);
+    process_message(&db.sharded(), &ctx, &start).await.unwrap();
+    let sos = payload(device, T0 + 10, &[("ALERT", "SOS")]);
+    let message = decode_message(&ctx, &sos, &[]).unwrap();
+    let destination = process_message_with_attempts(&db.sharded(), &ctx, &sos, message)
+        .await
+        .unwrap();
+
+    assert_eq!(destination, Some(MessageDestination::TripAlert));
+    assert_eq!(count_raw_alerts(&db, device, "SOS").await, 1);
+
+    db.cleanup().await;
+}
+
+#[tokio::test]
+async fn test_without_idempotent_alerts_redelivery_duplicates() {
+    let db = TestDb::new().await;
+    let ctx = ProcessorContext::new(AppConfig::development());
+    let device = "IDEMPOTENT-002";
+
+    let start = payload(device, T0, &[("ALERT", "ENGINE ON")]);
+    process_message(&db.sharded(), &ctx, &start).await.unwrap();
+    for _ in 0..2 {
+        let alert = payload(device, T0 + 30, &[("ALERT", "SPEEDING")]);
+        process_message(&db.sharded(), &ctx, &alert).await.unwrap();
+    }
+
+    let alerts: i64 = sqlx::query_scalar(
+        "SELECT COUNT(*) FROM trip_alerts WHERE device_id = $1 AND raw_alert_type = 'SPEEDING'",
+    )
+    .bind(device)
+    .fetch_one(&db.pool)
+    .await
+    .unwrap();
+    assert_eq!(alerts, 2);
+
+    db.cleanup().await;
+}
+
+#[tokio::test]
+async fn test_geofence_transitions_in_one_message_are_all_stored() {
+    use sqlx::Executor;
+
+    let db = TestDb::new().await;
+    db.pool
+        .execute(include_str!(
+            "../../migration_add_trip_alerts_natural_key.sql"
+        ))
+        .await
+        .unwrap();
+
+    for (device, idempotent_alerts) in [("FENCES-001", false), ("FENCES-002", true)] {
+        let mut config = AppConfig::development();
+        config.idempotent_alerts = idempotent_alerts;
+        // yard_a y yard_b son contiguas (~670 m entre centros); annex se
+        // superpone a yard_b
+        config.geofences = vec![
+            "yard_a:20.650000:-100.390000:300".to_string(),
+            "yard_b:20.656000:-100.390000:300".to_string(),
+            "annex:20.656000:-100.392000:300".to_string(),
+        ];
+        let ctx = ProcessorContext::new(config);
+
+        let start = payload(
+            device,
+            T0,
+            &[
+                ("ALERT", "ENGINE ON"),
+                ("LATITUD", "20.650000"),
+                ("LONGITUD", "-100.390000"),
+            ],
+        );
+        process_message(&db.sharded(), &ctx, &start).await.unwrap();
+        // Un solo punto sale de yard_a y entra a yard_b y annex
+        let crossing = payload(
+            device,
+            T0 + 60,
+            &[("LATITUD", "20.656000"), ("LONGITUD", "-100.390000")],
+        );
+        let destination = process_message(&db.sharded(), &ctx, &crossing)
+            .await
+            .unwrap();
+        assert_eq!(destination, Some(MessageDestination::TripPoint));
+
+        let alerts: Vec<(String, String)> = sqlx::query_as(
+            "SELECT alert_type, raw_alert_type FROM trip_alerts \
+             WHERE device_id = $1 AND alert_type LIKE 'geofence_%' ORDER BY alert_type, raw_alert_type",
+        )
+        .bind(device)
+        .fetch_all(&db.pool)
+        .await
+        .unwrap();
+        assert_eq!(
+            alerts,
+            vec![
+                ("geofence_enter".to_string(), "annex".to_string()),
+                ("geofence_enter".to_string(), "yard_b".to_string()),
+                ("geofence_exit".to_string(), "yard_a".to_string()),
+            ]
+        );
+
+        // El punto del cruce se guardó: la transacción no se revirtió
+        let points: i64 =
+            sqlx::query_scalar("SELECT COUNT(*) FROM trip_points WHERE device_id = $1")
+                .bind(device)
+                .fetch_one(&db.pool)
+                .await
+                .unwrap();
+        assert_eq!(points, 1);
+    }
+
+    db.cleanup().await;
+}
+
+#[tokio::test]
+async fn test_idle_stop_and_geofence_in_one_message() {
+    let db = TestDb::new().await;
+    let mut config = AppConfig::development();
+    config.idle_stop_threshold_seconds = 300.0;
+    config.geofences = vec!["yard:20.656000:-100.390000:300".to_string()];
+    let ctx = ProcessorContext::new(config);
+    let device = "IDLE-FENCE-001";
+
+    let start = payload(
+        device,
+        T0,
+        &[
+            ("ALERT", "ENGINE ON"),
+            ("LATITUD", "20.650000"),
+            ("LONGITUD", "-100.390000"),
+        ],
+    );
+    process_message(&db.sharded(), &ctx, &start).await.unwrap();
+    // El mismo punto entra a la geocerca y cruza el umbral de parada
+    let stop = payload(
+        device,
+        T0 + 600,
+        &[
+            ("LATITUD", "20.656000"),
+            ("LONGITUD", "-100.390000"),
+            ("IDLE_TIME", "300"),
+        ],
+    );
+    let destination = process_message(&db.sharded(), &ctx, &stop).await.unwrap();
+    assert_eq!(destination, Some(MessageDestination::TripPoint));
+
+    let alerts: Vec<String> = sqlx::query_scalar(
+        "SELECT alert_type FROM trip_alerts WHERE device_id = $1 AND \"timestamp\" = $2 ORDER BY alert_type",
+    )
+    .bind(device)
+    .bind(at(T0 + 600))
+    .fetch_all(&db.pool)
+    .await
+    .unwrap();
+    assert_eq!(alerts, vec!["geofence_enter", "idle_stop"]);
+
+    db.cleanup().await;
+}
+
+#[tokio::test]
+async fn test_trip_events_emitted_through_event_sink() {
+    let db = TestDb::new().await;
+    let events = Arc::new(RecordingEventSink::default());
+    let ctx = ProcessorContext::new(AppConfig::development()).with_event_sink(events.clone());
+    let device = "EVENTS-001";
+
+    for message in [
+        payload(device, T0, &[("ALERT", "ENGINE ON")]),
+        payload(
+            device,
+            T0 + 30,
+            &[("LATITUD", "20.65"), ("LONGITUD", "-100.39")],
+        ),
+        payload(device, T0 + 60, &[("ALERT", "ENGINE OFF")]),
+    ] {
+        process_message(&db.sharded(), &ctx, &message)
+            .await
+            .unwrap();
+    }
+
+    // Solo el inicio y el fin del viaje generan eventos, con la clave del equipo
+    assert_eq!(events.names(), vec!["trip_started", "trip_completed"]);
+    let emitted = events.events();
+    assert!(emitted.iter().all(|event| event.key == device));
+    let started: serde_json::Value = serde_json::from_slice(&emitted[0].payload).unwrap();
+    let completed: serde_json::Value = serde_json::from_slice(&emitted[1].payload).unwrap();
+    assert_eq!(started["trip_id"], completed["trip_id"]);
+
+    db.cleanup().await;
+}
@@ -1,8 +1,34 @@
-use crate::db::queries;
-use crate::models::siscom::v1::KafkaMessage;
-use chrono::{TimeZone, Utc};
-use prost::Message;
-use sqlx::{Postgres, Row};
+use crate::backoff::Backoff;
+use crate::db::sharding::ShardedPool;
+use crate::db::{devices, maintenance, queries};
+use crate::dead_letter::DeadLetterReason;
+use crate::event_sink::OutboundEvent;
+use crate::metrics::{self, FailureKind};
+use crate::models::siscom::v1::{KafkaMessage, Metadata};
+use crate::processor::alerts::{alert_severity, normalize_alert, DEFAULT_ALERT_SEVERITY};
+use crate::processor::cell_location::LocationSource;
+use crate::processor::compression::{decompress_payload, DecompressError};
+use crate::processor::context::ProcessorContext;
+use crate::processor::device_config::DeviceSettings;
+use crate::processor::distance::{distance_mismatch, route_step_meters};
+use crate::processor::extract::{extract_message, schema_version, MessageData};
+use crate::processor::geo::bearing;
+use crate::processor::geofence::detect_transitions;
+use crate::processor::idle::crosses_idle_threshold;
+use crate::processor::msg_class::ClassRoute;
+use crate::processor::sequence::is_duplicate_counter;
+use crate::processor::state::{
+    is_before_replay_cutoff, is_driver_change, is_late_stored, is_point_gap, should_write_state,
+    DeviceState,
+};
+use crate::processor::tow::{is_moving_while_off, MOVEMENT_WHILE_OFF};
+use crate::processor::trip_stats::speed_percentiles;
+use crate::processor::tx_batch::{OpenBatch, TxBatcher};
+use sqlx::postgres::PgRow;
+use sqlx::{Acquire, PgConnection, Postgres, Row};
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tracing::{debug, error, info, warn};
 use uuid::Uuid;
 
@@ -30,8 +56,17 @@ pub fn is_ignition_off(alert: Option<&str>) -> bool {
     }
 }
 
+/// Detecta el comando de control FORCE_TRIP_END, enviado por un operador u
+/// otro sistema para cerrar el viaje del equipo (p. ej. vehículo recuperado)
+pub fn is_force_trip_end(alert: Option<&str>) -> bool {
+    match alert.map(|s| s.trim().to_uppercase().replace(' ', "_")) {
+        Some(ref s) => s == "FORCE_TRIP_END",
+        None => false,
+    }
+}
+
 /// Determina el destino de un mensaje basado en el estado del viaje y el tipo de alerta
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum MessageDestination {
     /// Crear nuevo viaje (ignition on sin viaje activo)
     NewTrip,
@@ -47,6 +82,57 @@ pub enum MessageDestination {
     IgnoredIgnitionOn,
     /// Ignition off ignorado (no hay viaje activo)
     IgnoredIgnitionOff,
+    /// Cierre forzado (FORCE_TRIP_END) del viaje abierto, sin importar la ignición
+    ForcedEndTrip,
+    /// Anterior a REPLAY_CUTOFF: solo historial, sin tocar el estado actual
+    Replayed,
+    /// Solo actualiza trip_current_state, sin punto ni actividad (MSG_CLASS_ROUTES)
+    StateUpdate,
+    /// Ignition on con viaje activo y DUPLICATE_IGNITION_ON=split: se cierra el
+    /// viaje (se perdió el apagado) y se abre uno nuevo
+    SplitTrip,
+    /// Punto tras más de MAX_POINT_GAP_SECONDS sin reportar: se cierra el
+    /// viaje en el último punto previo y se abre uno nuevo en este
+    GapSplit,
+    /// Punto con otro conductor (SPLIT_TRIP_ON_DRIVER_CHANGE): se cierra el
+    /// viaje en el último punto del conductor anterior y se abre uno nuevo
+    DriverChange,
+}
+
+/// Qué hacer con un ignition on cuando ya hay viaje activo (`DUPLICATE_IGNITION_ON`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DuplicateIgnitionOn {
+    /// Se registra como ignorado y solo actualiza la última posición
+    #[default]
+    Ignore,
+    /// Se asume un apagado perdido: cierra el viaje y abre otro
+    Split,
+}
+
+impl DuplicateIgnitionOn {
+    pub fn parse(value: &str) -> Self {
+        match value.trim().to_lowercase().as_str() {
+            "" | "ignore" => DuplicateIgnitionOn::Ignore,
+            "split" => DuplicateIgnitionOn::Split,
+            other => {
+                warn!("Unknown DUPLICATE_IGNITION_ON '{}', using ignore", other);
+                DuplicateIgnitionOn::Ignore
+            }
+        }
+    }
+}
+
+/// Con `Split`, un ignition on con viaje activo parte el viaje en vez de ignorarse
+pub fn apply_duplicate_ignition_on(
+    destination: MessageDestination,
+    mode: DuplicateIgnitionOn,
+) -> MessageDestination {
+    match (destination, mode) {
+        (MessageDestination::IgnoredIgnitionOn, DuplicateIgnitionOn::Split) => {
+            MessageDestination::SplitTrip
+        }
+        (destination, _) => destination,
+    }
 }
 
 /// Determina a dónde debe ir un mensaje basado en el estado actual
@@ -54,7 +140,9 @@ pub fn determine_destination(alert: Option<&str>, is_trip_active: bool) -> Messa
     let engine_on = is_ignition_on(alert);
     let engine_off = is_ignition_off(alert);
 
-    if engine_on {
+    if is_force_trip_end(alert) {
+        MessageDestination::ForcedEndTrip
+    } else if engine_on {
         if !is_trip_active {
             MessageDestination::NewTrip
         } else {
@@ -76,212 +164,1268 @@ pub fn determine_destination(alert: Option<&str>, is_trip_active: bool) -> Messa
     }
 }
 
-pub async fn process_message(pool: &sqlx::Pool<Postgres>, payload: &[u8]) -> anyhow::Result<()> {
-    // 1. Parse Protobuf
-    let message = match KafkaMessage::decode(payload) {
+/// Ajusta el destino según la ruta de MSG_CLASS. Solo cambia puntos y
+/// actividad idle: la ignición y las alertas con texto conservan su destino.
+pub fn route_by_class(
+    destination: MessageDestination,
+    route: Option<ClassRoute>,
+) -> MessageDestination {
+    match (destination, route) {
+        (
+            MessageDestination::TripPoint | MessageDestination::IdleActivity,
+            Some(ClassRoute::State),
+        ) => MessageDestination::StateUpdate,
+        (MessageDestination::TripPoint, Some(ClassRoute::Alert)) => MessageDestination::TripAlert,
+        (destination, _) => destination,
+    }
+}
+
+/// Indica si un viaje recién cerrado debe descartarse por no alcanzar la
+/// distancia mínima configurada. Un mínimo de 0 conserva todos los viajes, y
+/// los viajes sin distancia conocida (sin odómetro) nunca se descartan.
+pub fn should_discard_trip(distance_meters: Option<f64>, min_distance_meters: f64) -> bool {
+    match distance_meters {
+        Some(distance) => min_distance_meters > 0.0 && distance < min_distance_meters,
+        None => false,
+    }
+}
+
+/// Elimina un viaje junto con sus puntos y alertas dentro de la transacción
+async fn discard_trip(conn: &mut PgConnection, trip_id: Uuid) -> anyhow::Result<()> {
+    sqlx::query(queries::DELETE_TRIP_ALERTS_BY_TRIP)
+        .bind(trip_id)
+        .execute(&mut *conn)
+        .await?;
+    sqlx::query(queries::DELETE_TRIP_POINTS_BY_TRIP)
+        .bind(trip_id)
+        .execute(&mut *conn)
+        .await?;
+    sqlx::query(queries::DELETE_TRIP)
+        .bind(trip_id)
+        .execute(&mut *conn)
+        .await?;
+    Ok(())
+}
+
+/// Ajusta `distance_meters` de un viaje recién cerrado según DISTANCE_SOURCE.
+/// `odometer_delta` es la diferencia de odómetro que dejó UPDATE_TRIP_END; la
+/// ruta haversine es la acumulada en el estado más el tramo hasta el cierre.
+async fn resolve_trip_distance(
+    conn: &mut PgConnection,
+    ctx: &ProcessorContext,
+    trip_id: Uuid,
+    route_meters: f64,
+    odometer_delta: Option<f64>,
+) -> anyhow::Result<Option<f64>> {
+    if ctx.distance_source.odometer_only(odometer_delta) {
+        return Ok(odometer_delta);
+    }
+    let distance = ctx
+        .distance_source
+        .trip_distance(route_meters, odometer_delta);
+    sqlx::query(queries::UPDATE_TRIP_DISTANCE)
+        .bind(trip_id)
+        .bind(distance)
+        .execute(&mut *conn)
+        .await?;
+    Ok(Some(distance))
+}
+
+/// Guarda en el viaje los percentiles de velocidad de sus puntos
+async fn store_speed_percentiles(conn: &mut PgConnection, trip_id: Uuid) -> anyhow::Result<()> {
+    let speeds: Vec<f64> = sqlx::query_scalar(queries::SELECT_TRIP_POINT_SPEEDS)
+        .bind(trip_id)
+        .fetch_all(&mut *conn)
+        .await?;
+    let percentiles = speed_percentiles(&speeds);
+    sqlx::query(queries::UPDATE_TRIP_SPEED_PERCENTILES)
+        .bind(trip_id)
+        .bind(percentiles.p50)
+        .bind(percentiles.p85)
+        .bind(percentiles.p95)
+        .execute(&mut *conn)
+        .await?;
+    Ok(())
+}
+
+/// Un viaje recién cerrado con UPDATE_TRIP_END, por un apagado o por mantenimiento
+pub struct ClosedTrip {
+    pub trip_id: Uuid,
+    /// Ruta haversine acumulada hasta el punto de cierre
+    pub route_meters: f64,
+    /// `distance_meters` que dejó UPDATE_TRIP_END (diferencia de odómetro)
+    pub odometer_delta: Option<f64>,
+    pub start_position: Option<(f64, f64)>,
+    pub end_position: Option<(f64, f64)>,
+}
+
+impl ClosedTrip {
+    /// A partir de la fila RETURNING de UPDATE_TRIP_END
+    pub fn from_end_row(
+        trip_id: Uuid,
+        ended: Option<&PgRow>,
+        route_meters: f64,
+        end_position: Option<(f64, f64)>,
+    ) -> Self {
+        let odometer_delta = ended.and_then(|row| row.try_get("distance_meters").ok());
+        let start_position = ended.and_then(|row| {
+            Some((
+                row.try_get::<Option<f64>, _>("start_lat").ok()??,
+                row.try_get::<Option<f64>, _>("start_lng").ok()??,
+            ))
+        });
+        Self {
+            trip_id,
+            route_meters,
+            odometer_delta,
+            start_position,
+            end_position,
+        }
+    }
+}
+
+/// Completa el cierre de un viaje: distancia según DISTANCE_SOURCE (haversine
+/// si el odómetro falta o se reinició), descarte bajo MIN_TRIP_DISTANCE_METERS
+/// y, si se conserva, percentiles de velocidad, rumbo y bbox. Devuelve false si
+/// el viaje se descartó.
+pub async fn finish_closed_trip(
+    conn: &mut PgConnection,
+    ctx: &ProcessorContext,
+    settings: &DeviceSettings,
+    closed: &ClosedTrip,
+) -> anyhow::Result<bool> {
+    let trip_id = closed.trip_id;
+    let distance_meters = resolve_trip_distance(
+        conn,
+        ctx,
+        trip_id,
+        closed.route_meters,
+        closed.odometer_delta,
+    )
+    .await?;
+    if should_discard_trip(distance_meters, settings.min_trip_distance_meters) {
+        info!(
+            "Discarding trip {}: distance {:?} m below minimum {} m",
+            trip_id, distance_meters, settings.min_trip_distance_meters
+        );
+        discard_trip(conn, trip_id).await?;
+        return Ok(false);
+    }
+    store_speed_percentiles(conn, trip_id).await?;
+    let trip_bearing = closed
+        .start_position
+        .zip(closed.end_position)
+        .and_then(|((lat1, lon1), (lat2, lon2))| bearing(lat1, lon1, lat2, lon2));
+    sqlx::query(queries::UPDATE_TRIP_ROUTE_SUMMARY)
+        .bind(trip_id)
+        .bind(trip_bearing)
+        .execute(&mut *conn)
+        .await?;
+    Ok(true)
+}
+
+/// Un payload de más de `MAX_PAYLOAD_BYTES` (0 = sin límite) no se copia ni
+/// se intenta decodificar: va directo al dead-letter. Devuelve true si se rechazó.
+pub fn reject_oversized(ctx: &ProcessorContext, payload: &[u8]) -> bool {
+    let max = ctx.config.max_payload_bytes;
+    if max == 0 || payload.len() <= max {
+        return false;
+    }
+    warn!(
+        "Payload of {} bytes exceeds MAX_PAYLOAD_BYTES={}, sending to dead-letter",
+        payload.len(),
+        max
+    );
+    metrics::record_oversized();
+    ctx.send_dead_letter(DeadLetterReason::Oversized, payload);
+    true
+}
+
+/// DEVICE_ID del mensaje decodificado (incluido el de las cabeceras), usado
+/// para asignar el mensaje a un worker y conservar el orden por dispositivo
+pub fn routing_key(message: &KafkaMessage) -> String {
+    message
+        .data
+        .get("DEVICE_ID")
+        .map(|id| id.trim().to_string())
+        .unwrap_or_default()
+}
+
+/// Procesa un mensaje. Devuelve el destino aplicado, o `None` si el mensaje
+/// se descartó antes de llegar a la base de datos.
+#[cfg(test)]
+pub async fn process_message(
+    pools: &ShardedPool,
+    ctx: &ProcessorContext,
+    payload: &[u8],
+) -> anyhow::Result<Option<MessageDestination>> {
+    match decode_message(ctx, payload, &[]) {
+        Some(message) => process_decoded_message(pools, ctx, payload, message).await,
+        None => Ok(None),
+    }
+}
+
+/// Como `process_decoded_message`, reintentando los mensajes que
+/// fallan (errores de base de datos, timeouts) hasta `MAX_MESSAGE_ATTEMPTS`
+/// veces con backoff. Agotados los intentos el payload va al dead-letter con
+/// `reason: max_attempts` y se sigue con el siguiente, para que un mensaje
+/// envenenado no bloquee su partición. 0 = sin reintentos (el error se devuelve).
+pub async fn process_message_with_attempts(
+    pools: &ShardedPool,
+    ctx: &ProcessorContext,
+    payload: &[u8],
+    message: KafkaMessage,
+) -> anyhow::Result<Option<MessageDestination>> {
+    let max_attempts = ctx.config.max_message_attempts;
+    let mut backoff = Backoff::new(
+        Duration::from_millis(100),
+        Duration::from_millis(ctx.config.kafka_backoff_max_ms),
+    );
+    let mut attempt = 1;
+    loop {
+        let err = match process_decoded_message(pools, ctx, payload, message.clone()).await {
+            Ok(destination) => return Ok(destination),
+            Err(e) if max_attempts == 0 => return Err(e),
+            Err(e) => e,
+        };
+        if attempt >= max_attempts {
+            metrics::record_poisoned();
+            error!(
+                "Message failed {} attempts, sending to dead-letter: {}",
+                attempt, err
+            );
+            ctx.send_dead_letter(DeadLetterReason::MaxAttempts, payload);
+            return Ok(None);
+        }
+        let delay = backoff.next_delay();
+        warn!(
+            "Attempt {}/{} failed, retrying in {} ms: {}",
+            attempt,
+            max_attempts,
+            delay.as_millis(),
+            err
+        );
+        tokio::time::sleep(delay).await;
+        attempt += 1;
+    }
+}
+
+/// Descomprime y decodifica el payload, agregando al mapa `data` las
+/// propiedades del transporte (cabeceras de `KAFKA_HEADER_PROPERTIES`, p. ej.
+/// VENDOR, TENANT o DEVICE_ID) que el payload no traiga. `None` si el mensaje
+/// se descartó (ya enviado al dead-letter).
+pub fn decode_message(
+    ctx: &ProcessorContext,
+    payload: &[u8],
+    properties: &[(String, String)],
+) -> Option<KafkaMessage> {
+    if reject_oversized(ctx, payload) {
+        return None;
+    }
+
+    if let Some(passthrough) = ctx.passthrough.as_ref() {
+        passthrough.tap(payload);
+    }
+
+    // 1. Parse Protobuf (gzip-compressed payloads are inflated first)
+    let decoded_payload = match decompress_payload(payload, ctx.config.max_payload_bytes) {
+        Ok(p) => p,
+        Err(DecompressError::TooLarge) => {
+            warn!(
+                "Gzip payload of {} bytes inflates beyond MAX_PAYLOAD_BYTES={}, sending to dead-letter",
+                payload.len(),
+                ctx.config.max_payload_bytes
+            );
+            metrics::record_oversized();
+            ctx.send_dead_letter(DeadLetterReason::Oversized, payload);
+            return None;
+        }
+        Err(e) => {
+            metrics::record_failure(
+                FailureKind::Parse,
+                &format!("Failed to decompress gzip payload: {}", e),
+            );
+            ctx.send_dead_letter(DeadLetterReason::DecodeError, payload);
+            return None;
+        }
+    };
+    let mut message = match ctx.adapters.parse(&decoded_payload) {
         Ok(m) => m,
         Err(e) => {
-            warn!("Failed to decode Protobuf KafkaMessage: {}", e);
-            return Ok(());
+            metrics::record_failure(
+                FailureKind::Parse,
+                &format!("Failed to decode KafkaMessage: {}", e),
+            );
+            ctx.send_dead_letter(DeadLetterReason::DecodeError, payload);
+            return None;
         }
     };
 
-    // 2. Extract Data
-    let device_id_str = message.data.get("DEVICE_ID").cloned().unwrap_or_default();
-    if device_id_str.is_empty() {
+    // Un mensaje sin `data` (solo uuid/metadata) no puede procesarse, pero se conserva
+    if message.data.is_empty() {
+        metrics::record_failure(
+            FailureKind::MissingData,
+            &format!(
+                "Message without data map, sending to dead-letter. uuid={} metadata={:?}",
+                message.uuid, message.metadata
+            ),
+        );
+        ctx.send_dead_letter(DeadLetterReason::MissingData, payload);
+        return None;
+    }
+
+    for (key, value) in properties {
+        message
+            .data
+            .entry(key.clone())
+            .or_insert_with(|| value.clone());
+    }
+    Some(message)
+}
+
+/// Procesa un mensaje ya decodificado por `decode_message`. Devuelve el
+/// destino aplicado, o `None` si el mensaje se descartó antes de llegar a la
+/// base de datos. `payload` es el original, para el dead-letter.
+pub async fn process_decoded_message(
+    pools: &ShardedPool,
+    ctx: &ProcessorContext,
+    payload: &[u8],
+    message: KafkaMessage,
+) -> anyhow::Result<Option<MessageDestination>> {
+    // Mensajes de un formato anterior a MIN_SCHEMA_VERSION no se procesan
+    if is_schema_too_old(schema_version(&message.data), ctx.config.min_schema_version) {
         warn!(
-            "Message missing DEVICE_ID in data map, skipping. uuid={} data={:?} metadata={:?}",
-            message.uuid, message.data, message.metadata
+            "Message {} below MIN_SCHEMA_VERSION {}, sending to dead-letter. version={:?}",
+            message.uuid,
+            ctx.config.min_schema_version,
+            schema_version(&message.data)
         );
-        return Ok(());
+        ctx.send_dead_letter(DeadLetterReason::SchemaTooOld, payload);
+        return Ok(None);
+    }
+
+    // ENABLE_SCHEMA_VALIDATION: campos requeridos y tipos de PAYLOAD_SCHEMA
+    if let Some(schema) = ctx.payload_schema.as_ref() {
+        let errors = schema.validate(&message.data);
+        if !errors.is_empty() {
+            let errors: Vec<String> = errors.iter().map(ToString::to_string).collect();
+            metrics::record_failure(
+                FailureKind::Parse,
+                &format!(
+                    "Message {} failed schema validation, sending to dead-letter: {}",
+                    message.uuid,
+                    errors.join("; ")
+                ),
+            );
+            ctx.send_dead_letter(DeadLetterReason::SchemaInvalid, payload);
+            return Ok(None);
+        }
+    }
+
+    // 2. Extract Data
+    let mut data = match extract_message(message, ctx.clock.as_ref()) {
+        Ok(data) => data,
+        Err(_) => return Ok(None),
+    };
+    // Equipos con el reloj en hora local (DEVICE_TIMEZONE / DEVICE_TIMEZONES)
+    if data.device_time {
+        data.timestamp = ctx.timezones.to_utc(&data.device_id, data.timestamp);
+    }
+    locate_by_cell(ctx, &mut data);
+    // Mensajes con RAW_CODE conocido pero sin ALERT (ALERT_CODE_MAP)
+    if let Some(alert) = ctx
+        .alert_codes
+        .resolve(data.alert.as_deref(), data.raw_code)
+    {
+        debug!(
+            "Device {} alert '{}' derived from raw code {:?}",
+            data.device_id, alert, data.raw_code
+        );
+        data.alert = Some(alert.to_string());
+    }
+    data.tenant_id = ctx
+        .tenants
+        .resolve(&data.device_id, data.tenant_id.as_deref());
+
+    if !ctx.device_filter.is_allowed(&data.device_id) {
+        metrics::record_device_filtered();
+        debug!(
+            "Device {} not permitted by device filter, skipping uuid: {}",
+            data.device_id, data.message_uuid
+        );
+        return Ok(None);
+    }
+
+    if is_rate_limited(ctx, &data, Instant::now()) {
+        metrics::record_rate_limited();
+        debug!(
+            "Device {} exceeded PER_DEVICE_RATE_LIMIT, dropping uuid: {}",
+            data.device_id, data.message_uuid
+        );
+        return Ok(None);
     }
 
     info!(
         "Processing Protobuf message for device: {} uuid: {}\n",
-        device_id_str, message.uuid
+        data.device_id, data.message_uuid
     );
 
-    let message_uuid = Uuid::parse_str(&message.uuid).unwrap_or_else(|_| Uuid::new_v4());
+    let pool = pools.for_device(&data.device_id);
+    if ctx.config.skip_noop_transactions {
+        let state = devices::peek_device_state(pool, &data.device_id)
+            .await?
+            .unwrap_or_default();
+        if is_noop_message(ctx, &data, &state) {
+            metrics::record_noop_skipped();
+            info!(
+                "Skipping duplicate/out-of-order point for device {} without a transaction: MSG_COUNTER {:?} <= last {:?}",
+                data.device_id, data.msg_counter, state.last_msg_counter
+            );
+            return Ok(None);
+        }
+    }
 
-    // Use GPS_EPOCH if available, otherwise fallback to decoded_epoch or current time
-    let timestamp = if let Some(epoch_str) = message.data.get("GPS_EPOCH") {
-        if let Ok(epoch) = epoch_str.parse::<i64>() {
-            Utc.timestamp_opt(epoch, 0).single().map(|t| t.naive_utc())
-        } else {
-            None
+    let timeout = Duration::from_secs(ctx.config.process_timeout_seconds);
+    let Some(persisted) = with_process_timeout(timeout, data.message_uuid, async {
+        match ctx.tx_batcher.as_ref() {
+            Some(batcher) => persist_batched(pools, ctx, batcher, &data, payload).await,
+            None => persist_message(pool, ctx, &data, payload).await,
         }
-    } else {
-        None
-    }
-    .unwrap_or_else(|| {
-        if let Some(metadata) = message.metadata.as_ref() {
-            if metadata.decoded_epoch > 0 {
-                return Utc
-                    .timestamp_millis_opt(metadata.decoded_epoch as i64)
-                    .single()
-                    .map(|t| t.naive_utc())
-                    .unwrap_or_else(|| Utc::now().naive_utc());
+    })
+    .await
+    else {
+        anyhow::bail!(
+            "Processing timed out for device {} uuid {}",
+            data.device_id,
+            data.message_uuid
+        );
+    };
+
+    let (destination, events) = match persisted {
+        Ok(outcome) => {
+            ctx.db_breaker.record_success();
+            outcome
+        }
+        Err(e) => {
+            if ctx.db_breaker.record_failure(Instant::now()) {
+                warn!(
+                    "Database circuit breaker tripped after {} consecutive failures",
+                    ctx.config.db_max_retries
+                );
             }
+            metrics::record_failure(
+                FailureKind::Db,
+                &format!("Database error for device {}: {}", data.device_id, e),
+            );
+            return Err(e);
         }
-        Utc::now().naive_utc()
-    });
+    };
 
-    let lat = message
-        .data
-        .get("LATITUD")
-        .and_then(|s| s.parse::<f64>().ok())
-        .unwrap_or(0.0);
-    let lon = message
-        .data
-        .get("LONGITUD")
-        .and_then(|s| s.parse::<f64>().ok())
-        .unwrap_or(0.0);
-    let speed = message
-        .data
-        .get("SPEED")
-        .and_then(|s| s.parse::<f64>().ok())
-        .unwrap_or(0.0);
-    let odometer_meters = message
-        .data
-        .get("ODOMETER")
-        .and_then(|s| s.parse::<f64>().ok())
-        .unwrap_or(0.0);
-    let heading = message
-        .data
-        .get("COURSE")
-        .and_then(|s| s.parse::<f64>().ok())
-        .unwrap_or(0.0);
+    info!(
+        device_id = %data.device_id,
+        message_uuid = %data.message_uuid,
+        destination = ?destination,
+        dry_run = ctx.config.dry_run,
+        "Message destination"
+    );
+
+    publish_events(ctx, &data.device_id, &events).await;
+
+    Ok(Some(destination))
+}
+
+/// Sin LATITUD/LONGITUD, usa la posición aproximada de la celda (MCC/MNC/
+/// LAC/CID) si hay `CellLocator` configurado. Nunca reemplaza una posición GPS.
+fn locate_by_cell(ctx: &ProcessorContext, data: &mut MessageData) {
+    if data.has_position {
+        return;
+    }
+    let (Some(locator), Some(cell)) = (ctx.cell_locator.as_ref(), data.cell.as_ref()) else {
+        return;
+    };
+    if let Some((lat, lon)) = locator.locate(cell) {
+        debug!(
+            "Device {} without GPS located by cell {:?}",
+            data.device_id, cell
+        );
+        data.lat = lat;
+        data.lon = lon;
+        data.has_position = true;
+        data.location_source = LocationSource::Cell;
+    }
+}
+
+/// true si el mensaje declara una versión menor a `min_version` (0 = sin
+/// mínimo). Sin versión se trata como formato legado y se procesa.
+fn is_schema_too_old(version: Option<u32>, min_version: u32) -> bool {
+    version.is_some_and(|v| v < min_version)
+}
+
+/// Aplica el límite por dispositivo; los encendidos, apagados y cierres
+/// forzados nunca se descartan (ni consumen tokens) para no perder límites de viaje
+fn is_rate_limited(ctx: &ProcessorContext, data: &MessageData, now: Instant) -> bool {
+    let alert = data.alert.as_deref();
+    if is_ignition_on(alert) || is_ignition_off(alert) || is_force_trip_end(alert) {
+        return false;
+    }
+    !ctx.rate_limiter.allow(&data.device_id, now)
+}
+
+/// Limita la duración de `fut` (0 = sin límite). Al expirar el futuro se
+/// descarta junto con su transacción, que hace rollback al soltarse.
+async fn with_process_timeout<T>(
+    timeout: Duration,
+    message_uuid: Uuid,
+    fut: impl Future<Output = T>,
+) -> Option<T> {
+    if timeout.is_zero() {
+        return Some(fut.await);
+    }
+    match tokio::time::timeout(timeout, fut).await {
+        Ok(result) => Some(result),
+        Err(_) => {
+            metrics::record_failure(
+                FailureKind::Timeout,
+                &format!(
+                    "Processing of message {} exceeded {} seconds, transaction aborted",
+                    message_uuid,
+                    timeout.as_secs_f64()
+                ),
+            );
+            None
+        }
+    }
+}
+
+/// Metadata guardada con la actividad idle: la del mensaje más la instancia
+/// de este servicio que lo procesó
+fn idle_metadata(metadata: Option<&Metadata>, instance_id: &str) -> serde_json::Value {
+    match metadata {
+        Some(m) => serde_json::json!({
+            "worker_id": m.worker_id,
+            "received_epoch": m.received_epoch,
+            "decoded_epoch": m.decoded_epoch,
+            "bytes": m.bytes,
+            "client_ip": m.client_ip,
+            "client_port": m.client_port,
+            "instance_id": instance_id
+        }),
+        None => serde_json::json!({ "instance_id": instance_id }),
+    }
+}
+
+/// Evento de viaje que se publica una vez confirmada la transacción
+#[derive(Debug, Clone, PartialEq)]
+pub enum TripEvent {
+    Started { trip_id: Uuid },
+    Completed { trip_id: Uuid },
+}
+
+impl TripEvent {
+    fn name(&self) -> &'static str {
+        match self {
+            TripEvent::Started { .. } => "trip_started",
+            TripEvent::Completed { .. } => "trip_completed",
+        }
+    }
+
+    fn trip_id(&self) -> Uuid {
+        match self {
+            TripEvent::Started { trip_id } | TripEvent::Completed { trip_id } => *trip_id,
+        }
+    }
+}
+
+/// Publica los eventos de viaje por `ctx.event_sink`, ya confirmada la
+/// transacción; un fallo al publicar no invalida el mensaje
+async fn publish_events(ctx: &ProcessorContext, device_id: &str, events: &[TripEvent]) {
+    for event in events {
+        ctx.event_sink
+            .emit(&OutboundEvent::trip(
+                event.name(),
+                event.trip_id(),
+                device_id,
+            ))
+            .await;
+    }
+}
+
+/// Actualiza la última posición conocida, respetando STATE_UPDATE_MIN_INTERVAL_SECONDS;
+/// los reportes almacenados que llegan tarde no la sobrescriben
+/// Con `ALERT_DEBOUNCE_SECONDS` (0 = deshabilitado), indica si ya hay una
+/// alerta del mismo tipo para el dispositivo dentro de la ventana. Se consulta
+/// dentro de la transacción: una alerta revertida (reintento, lote abortado)
+/// no suprime a la siguiente.
+async fn is_debounced_alert(
+    tx: &mut sqlx::Transaction<'_, Postgres>,
+    ctx: &ProcessorContext,
+    device_id: &str,
+    alert_type: &str,
+    timestamp: chrono::NaiveDateTime,
+) -> sqlx::Result<bool> {
+    if ctx.config.alert_debounce_seconds == 0 {
+        return Ok(false);
+    }
+    sqlx::query_scalar(queries::RECENT_ALERT_EXISTS)
+        .bind(device_id)
+        .bind(alert_type.trim().to_uppercase())
+        .bind(timestamp)
+        .bind(ctx.config.alert_debounce_seconds as f64)
+        .fetch_one(&mut **tx)
+        .await
+}
+
+async fn update_state_point(
+    tx: &mut sqlx::Transaction<'_, Postgres>,
+    ctx: &ProcessorContext,
+    settings: &DeviceSettings,
+    state: &DeviceState,
+    data: &MessageData,
+) -> anyhow::Result<()> {
+    if !data.has_position {
+        debug!(
+            "Keeping last position of device {}: message {} has no coordinates",
+            data.device_id, data.message_uuid
+        );
+        return Ok(());
+    }
 
-    let alert_type = message.data.get("ALERT").map(|s| s.as_str());
+    if is_late_stored(data.delivery_type, state, data.timestamp) {
+        debug!(
+            "Skipping current-state write for stored message {} older than last point of device {}",
+            data.message_uuid, data.device_id
+        );
+        return Ok(());
+    }
+
+    if !should_write_state(
+        settings.state_update_min_interval_seconds,
+        state,
+        data.timestamp,
+        data.lat,
+        data.lon,
+    ) {
+        debug!(
+            "Skipping current-state write for device {} within deadband",
+            data.device_id
+        );
+        return Ok(());
+    }
 
+    sqlx::query(queries::UPDATE_CURRENT_STATE_POINT)
+        .bind(&data.device_id)
+        .bind(data.timestamp)
+        .bind(data.lat)
+        .bind(data.lon)
+        .bind(data.speed)
+        .bind(data.correlation_id)
+        .bind(data.odometer_meters)
+        .bind(ctx.last_updated_at(data.timestamp))
+        .execute(&mut **tx)
+        .await?;
+    Ok(())
+}
+
+/// Destino del mensaje según el estado del dispositivo (ignición, ruta de
+/// MSG_CLASS, DUPLICATE_IGNITION_ON, MAX_POINT_GAP_SECONDS y
+/// SPLIT_TRIP_ON_DRIVER_CHANGE)
+fn message_destination(
+    ctx: &ProcessorContext,
+    data: &MessageData,
+    state: &DeviceState,
+) -> MessageDestination {
+    let destination = apply_duplicate_ignition_on(
+        route_by_class(
+            determine_destination(data.alert.as_deref(), state.ignition_on),
+            ctx.msg_class_routes.route(data.msg_class.as_deref()),
+        ),
+        ctx.duplicate_ignition_on,
+    );
+    if destination != MessageDestination::TripPoint {
+        destination
+    } else if is_point_gap(state, data.timestamp, ctx.config.max_point_gap_seconds) {
+        MessageDestination::GapSplit
+    } else if ctx.config.split_trip_on_driver_change
+        && is_driver_change(
+            state.current_driver_id.as_deref(),
+            data.driver_id.as_deref(),
+        )
+    {
+        MessageDestination::DriverChange
+    } else {
+        destination
+    }
+}
+
+/// SKIP_NOOP_TRANSACTIONS: un punto de viaje con MSG_COUNTER repetido no
+/// escribe nada, así que se descarta con la lectura previa del estado sin
+/// abrir transacción. Con RAW_PAYLOAD_RETENTION o antes de REPLAY_CUTOFF el
+/// mensaje sí escribe y nunca se descarta aquí.
+fn is_noop_message(ctx: &ProcessorContext, data: &MessageData, state: &DeviceState) -> bool {
+    ctx.config.skip_noop_transactions
+        && !ctx.config.raw_payload_retention
+        && !is_before_replay_cutoff(
+            ctx.config.replay_cutoff.map(|t| t.naive_utc()),
+            data.timestamp,
+        )
+        && message_destination(ctx, data, state) == MessageDestination::TripPoint
+        && is_duplicate_counter(state.last_msg_counter, data.msg_counter)
+}
+
+/// Aplica las reglas de viaje a un mensaje en su propia transacción
+pub(super) async fn persist_message(
+    pool: &sqlx::Pool<Postgres>,
+    ctx: &ProcessorContext,
+    data: &MessageData,
+    payload: &[u8],
+) -> anyhow::Result<(MessageDestination, Vec<TripEvent>)> {
     // 3. Start Transaction
     let mut tx = pool.begin().await?;
+    let (destination, events) = apply_message(&mut tx, ctx, data, payload).await?;
 
-    // 4. Get Active Trip State (FOR UPDATE)
-    let active_trip_row = sqlx::query(queries::SELECT_ACTIVE_TRIP_ID)
-        .bind(&device_id_str)
-        .fetch_optional(&mut *tx)
+    if ctx.config.dry_run {
+        info!(
+            device_id = data.device_id.as_str(),
+            events = ?events,
+            "Dry run: rolling back transaction"
+        );
+        tx.rollback().await?;
+        return Ok((destination, Vec::new()));
+    }
+
+    tx.commit().await?;
+
+    Ok((destination, events))
+}
+
+/// Modo TX_BATCH_SIZE: aplica el mensaje dentro del lote abierto de su
+/// dispositivo, en un savepoint para que un error descarte solo ese mensaje.
+/// Los eventos devueltos son los del lote si este se confirmó ahora.
+async fn persist_batched(
+    pools: &ShardedPool,
+    ctx: &ProcessorContext,
+    batcher: &TxBatcher,
+    data: &MessageData,
+    payload: &[u8],
+) -> anyhow::Result<(MessageDestination, Vec<TripEvent>)> {
+    let mut slot = batcher.slot(&data.device_id).lock().await;
+
+    // Solo se agrupan mensajes consecutivos del mismo dispositivo
+    let batch = match slot.take() {
+        Some(batch) if batch.device_id == data.device_id => batch,
+        previous => {
+            if let Some(previous) = previous {
+                commit_batch(ctx, previous).await?;
+            }
+            let pool = pools.for_device(&data.device_id);
+            OpenBatch::begin(pool, &data.device_id, Instant::now()).await?
+        }
+    };
+    let batch = slot.insert(batch);
+
+    let mut savepoint = batch.tx.begin().await?;
+    match apply_message(&mut savepoint, ctx, data, payload).await {
+        Ok((destination, events)) => {
+            savepoint.commit().await?;
+            batch.messages += 1;
+            batch.events.extend(events);
+            if !batcher.should_commit(destination, batch, Instant::now()) {
+                return Ok((destination, Vec::new()));
+            }
+            let events = match slot.take() {
+                Some(batch) => batch.commit().await?,
+                None => Vec::new(),
+            };
+            Ok((destination, events))
+        }
+        Err(e) => {
+            // Se descarta este mensaje y se confirma lo acumulado hasta ahora
+            if let Err(rollback_err) = savepoint.rollback().await {
+                warn!("Failed to roll back savepoint: {}", rollback_err);
+            }
+            if let Some(batch) = slot.take() {
+                if let Err(commit_err) = commit_batch(ctx, batch).await {
+                    error!("Failed to commit transaction batch: {}", commit_err);
+                }
+            }
+            Err(e)
+        }
+    }
+}
+
+/// Confirma un lote y publica sus eventos
+async fn commit_batch(ctx: &ProcessorContext, batch: OpenBatch) -> anyhow::Result<()> {
+    let device_id = batch.device_id.clone();
+    let events = batch.commit().await?;
+    publish_events(ctx, &device_id, &events).await;
+    Ok(())
+}
+
+/// Confirma los lotes que superaron TX_BATCH_MS sin recibir más mensajes
+pub async fn flush_expired_batches(ctx: Arc<ProcessorContext>) {
+    let Some(batcher) = ctx.tx_batcher.as_ref() else {
+        return;
+    };
+    let interval = (batcher.max_age() / 2).max(Duration::from_millis(10));
+    loop {
+        tokio::time::sleep(interval).await;
+        for slot in batcher.slots() {
+            let mut slot = slot.lock().await;
+            if !slot
+                .as_ref()
+                .is_some_and(|batch| batcher.is_expired(batch, Instant::now()))
+            {
+                continue;
+            }
+            if let Some(batch) = slot.take() {
+                let device_id = batch.device_id.clone();
+                if let Err(e) = commit_batch(&ctx, batch).await {
+                    error!(
+                        "Failed to commit expired transaction batch for device {}: {}",
+                        device_id, e
+                    );
+                }
+            }
+        }
+    }
+}
+
+/// Cierra `trip_id` con la posición del mensaje y registra `end_alert`
+/// (`ignition_off` o `forced_end`). Publica el viaje salvo que se descarte
+/// por distancia mínima.
+#[allow(clippy::too_many_arguments)]
+async fn end_trip(
+    tx: &mut sqlx::Transaction<'_, Postgres>,
+    ctx: &ProcessorContext,
+    settings: &DeviceSettings,
+    state: &DeviceState,
+    data: &MessageData,
+    trip_id: Uuid,
+    end_alert: &str,
+    events: &mut Vec<TripEvent>,
+) -> anyhow::Result<()> {
+    let device_id_str = data.device_id.as_str();
+    let correlation_id = data.correlation_id;
+    let severity = alert_severity(data.severity);
+    let timestamp = data.timestamp;
+    let position = data.position();
+    let lat = position.map(|(lat, _)| lat);
+    let lon = position.map(|(_, lon)| lon);
+    let speed = data.speed;
+    let odometer_meters = data.odometer_meters;
+    let alert_type = data.alert.as_deref();
+
+    let ended = sqlx::query(queries::UPDATE_TRIP_END)
+        .bind(timestamp)
+        .bind(lat)
+        .bind(lon)
+        .bind(odometer_meters)
+        .bind(trip_id)
+        .fetch_optional(&mut **tx)
         .await?;
+    // Ruta acumulada más el tramo hasta el punto de apagado
+    let route_meters = state.current_trip_route_meters
+        + data
+            .position()
+            .map(|end| route_step_meters(state.current_trip_route_position, end))
+            .unwrap_or(0.0);
+    let closed = ClosedTrip::from_end_row(trip_id, ended.as_ref(), route_meters, position);
+    let odometer_delta = closed.odometer_delta;
 
-    let (mut last_trip_id, current_ignition_status): (Option<Uuid>, Option<bool>) =
-        match active_trip_row {
-            Some(row) => (
-                row.try_get("current_trip_id").ok(),
-                row.try_get("ignition_on").ok(),
-            ),
-            None => (None, None),
-        };
+    if let Some(percent) = distance_mismatch(
+        route_meters,
+        odometer_delta,
+        ctx.config.distance_mismatch_percent,
+    ) {
+        warn!(
+            "Distance mismatch on trip {} of device {}: route {:.0} m vs odometer {:?} m ({:.0} %)",
+            trip_id, device_id_str, route_meters, odometer_delta, percent
+        );
+        metrics::record_distance_mismatch();
+    }
+
+    sqlx::query(queries::UPDATE_CURRENT_STATE_END_TRIP)
+        .bind(device_id_str)
+        .bind(correlation_id)
+        .bind(timestamp)
+        .bind(lat)
+        .bind(lon)
+        .bind(speed)
+        .bind(ctx.last_updated_at(timestamp))
+        .execute(&mut **tx)
+        .await?;
+
+    let alert_id = Uuid::new_v4();
+    sqlx::query(insert_trip_alert(ctx))
+        .bind(alert_id)
+        .bind(trip_id)
+        .bind(timestamp)
+        .bind(lat)
+        .bind(lon)
+        .bind(end_alert)
+        .bind(data.raw_code)
+        .bind(severity)
+        .bind(device_id_str)
+        .bind(correlation_id)
+        .bind(alert_type)
+        .bind(data.tenant_id.as_deref())
+        .execute(&mut **tx)
+        .await?;
+
+    if finish_closed_trip(tx, ctx, settings, &closed).await? {
+        events.push(TripEvent::Completed { trip_id });
+    }
+    Ok(())
+}
+
+/// Marca (alerta de cierre y de inicio) de un viaje partido por MAX_POINT_GAP_SECONDS
+pub const GAP_SPLIT: &str = "gap_split";
+
+/// Marca de un viaje partido por cambio de conductor (SPLIT_TRIP_ON_DRIVER_CHANGE)
+pub const DRIVER_CHANGE: &str = "driver_change";
+
+/// `activity_type` del apagado recibido sin viaje abierto (RECORD_IGNITION_OFF_NO_TRIP)
+pub const IGNITION_OFF_NO_TRIP: &str = "ignition_off_no_trip";
+
+/// El último punto antes del hueco, tomado del estado actual, para cerrar ahí
+/// el viaje partido por MAX_POINT_GAP_SECONDS, por cambio de conductor o por un
+/// encendido duplicado; así la alerta de cierre no comparte timestamp con la
+/// de inicio del viaje nuevo
+fn pre_gap_point(state: &DeviceState, data: &MessageData) -> MessageData {
+    let mut pre_gap = data.clone();
+    pre_gap.timestamp = state.last_point_at.unwrap_or(data.timestamp);
+    pre_gap.has_position = state.last_lat.is_some() && state.last_lng.is_some();
+    pre_gap.lat = state.last_lat.unwrap_or(0.0);
+    pre_gap.lon = state.last_lng.unwrap_or(0.0);
+    pre_gap.speed = state.last_speed.unwrap_or(0.0);
+    pre_gap.odometer_meters = state.last_odometer_meters.map(f64::from).unwrap_or(0.0);
+    pre_gap.alert = None;
+    pre_gap.raw_code = None;
+    pre_gap.severity = None;
+    pre_gap
+}
+
+/// Abre un viaje nuevo en el mensaje y deja el estado activo; `start_alert` es
+/// la alerta de inicio (`ignition_on`, o `gap_split` si viene de un hueco)
+async fn start_trip(
+    tx: &mut sqlx::Transaction<'_, Postgres>,
+    ctx: &ProcessorContext,
+    data: &MessageData,
+    start_alert: &str,
+    events: &mut Vec<TripEvent>,
+) -> anyhow::Result<()> {
+    let device_id_str = data.device_id.as_str();
+    let correlation_id = data.correlation_id;
+    let timestamp = data.timestamp;
+    let position = data.position();
+    let lat = position.map(|(lat, _)| lat);
+    let lon = position.map(|(_, lon)| lon);
+    let odometer_meters = data.odometer_meters;
+    let trip_id = ctx
+        .trip_id_strategy
+        .trip_id(data.message_uuid, device_id_str, timestamp);
+    info!("Started new trip {} for device {}", trip_id, device_id_str);
+
+    sqlx::query(queries::INSERT_TRIP)
+        .bind(trip_id)
+        .bind(device_id_str)
+        .bind(timestamp)
+        .bind(lat)
+        .bind(lon)
+        .bind(odometer_meters)
+        .bind(data.tenant_id.as_deref())
+        .bind(data.driver_id.as_deref())
+        .execute(&mut **tx)
+        .await?;
+
+    sqlx::query(queries::UPDATE_CURRENT_STATE_NEW_TRIP)
+        .bind(device_id_str)
+        .bind(trip_id)
+        .bind(timestamp)
+        .bind(lat)
+        .bind(lon)
+        .bind(correlation_id)
+        .bind(odometer_meters)
+        .bind(ctx.last_updated_at(timestamp))
+        .bind(data.driver_id.as_deref())
+        .execute(&mut **tx)
+        .await?;
+
+    let alert_id = Uuid::new_v4();
+    sqlx::query(insert_trip_alert(ctx))
+        .bind(alert_id)
+        .bind(trip_id)
+        .bind(timestamp)
+        .bind(lat)
+        .bind(lon)
+        .bind(start_alert)
+        .bind(data.raw_code)
+        .bind(alert_severity(data.severity))
+        .bind(device_id_str)
+        .bind(correlation_id)
+        .bind(data.alert.as_deref())
+        .bind(data.tenant_id.as_deref())
+        .execute(&mut **tx)
+        .await?;
+
+    // Solo un ignition-on que abre viaje; IgnoredIgnitionOn no llega aquí
+    // (salvo DUPLICATE_IGNITION_ON=split, que antes cierra el anterior)
+    events.push(TripEvent::Started { trip_id });
+    Ok(())
+}
+
+/// Inserción en `trip_alerts`; con IDEMPOTENT_ALERTS ignora una alerta ya
+/// guardada con el mismo viaje, hora, tipo y texto original (0 filas afectadas)
+fn insert_trip_alert(ctx: &ProcessorContext) -> &'static str {
+    if ctx.config.idempotent_alerts {
+        queries::INSERT_TRIP_ALERT_IDEMPOTENT
+    } else {
+        queries::INSERT_TRIP_ALERT
+    }
+}
+
+/// Correlation id de una alerta derivada del mensaje (geocerca, idle_stop):
+/// un mensaje puede generar varias con la misma hora, y cada una necesita su
+/// propia fila en `idx_trip_alerts_corr_unique`. Es determinista, así que una
+/// reentrega produce los mismos ids.
+fn derived_correlation_id(correlation_id: Uuid, alert_type: &str, detail: &str) -> Uuid {
+    Uuid::new_v5(
+        &correlation_id,
+        format!("{}:{}", alert_type, detail).as_bytes(),
+    )
+}
+
+/// Registra el mensaje en `device_idle_activity` con `activity_type`
+async fn insert_idle_activity(
+    tx: &mut sqlx::Transaction<'_, Postgres>,
+    ctx: &ProcessorContext,
+    data: &MessageData,
+    activity_type: &str,
+) -> anyhow::Result<()> {
+    let position = data.position();
+    sqlx::query(queries::INSERT_DEVICE_IDLE_ACTIVITY)
+        .bind(Uuid::new_v4())
+        .bind(data.device_id.as_str())
+        .bind(data.timestamp)
+        .bind(position.map(|(lat, _)| lat))
+        .bind(position.map(|(_, lon)| lon))
+        .bind(activity_type)
+        .bind(data.raw_code)
+        .bind(alert_severity(data.severity))
+        .bind(idle_metadata(
+            data.metadata.as_ref(),
+            &ctx.config.instance_id,
+        ))
+        .bind(data.correlation_id)
+        .bind(data.tenant_id.as_deref())
+        .bind(position.map(|_| data.location_source.as_str()))
+        .bind(data.speed)
+        .bind(data.heading)
+        .execute(&mut **tx)
+        .await?;
+    Ok(())
+}
+
+/// Aplica las reglas de viaje a un mensaje sobre `tx`, sin confirmarla
+async fn apply_message(
+    tx: &mut sqlx::Transaction<'_, Postgres>,
+    ctx: &ProcessorContext,
+    data: &MessageData,
+    payload: &[u8],
+) -> anyhow::Result<(MessageDestination, Vec<TripEvent>)> {
+    let mut events = Vec::new();
+    let device_id_str = data.device_id.as_str();
+    let message_uuid = data.message_uuid;
+    let correlation_id = data.correlation_id;
+    let severity = alert_severity(data.severity);
+    let timestamp = data.timestamp;
+    // Sin coordenadas se guarda NULL en vez de fabricar (0, 0)
+    let position = data.position();
+    let lat = position.map(|(lat, _)| lat);
+    let lon = position.map(|(_, lon)| lon);
+    let speed = data.speed;
+    let heading = data.heading;
+    let odometer_meters = data.odometer_meters;
+    let alert_type = data.alert.as_deref();
+
+    if ctx.config.raw_payload_retention {
+        let raw = (!data.raw.is_empty()).then_some(data.raw.as_str());
+        sqlx::query(queries::INSERT_MESSAGE_ARCHIVE)
+            .bind(message_uuid)
+            .bind(device_id_str)
+            .bind(timestamp)
+            .bind(raw)
+            .bind(payload)
+            .execute(&mut **tx)
+            .await?;
+    }
+
+    devices::record_device_seen(tx, device_id_str, timestamp).await?;
+
+    // Backfill (REPLAY_CUTOFF): el mensaje queda como actividad histórica sin
+    // leer ni bloquear trip_current_state, y sus igniciones no abren ni
+    // cierran viajes
+    if is_before_replay_cutoff(ctx.config.replay_cutoff.map(|t| t.naive_utc()), timestamp) {
+        debug!(
+            "Message {} of device {} is before REPLAY_CUTOFF, storing history only",
+            message_uuid, device_id_str
+        );
+        insert_idle_activity(tx, ctx, data, alert_type.unwrap_or("gps_idle_point")).await?;
+        return Ok((MessageDestination::Replayed, events));
+    }
+
+    // Umbrales del dispositivo (device_config) o los globales
+    let settings = ctx
+        .device_config
+        .settings(tx, &ctx.config, device_id_str)
+        .await?;
+
+    // 4. Get Active Trip State (FOR UPDATE)
+    let state = devices::lock_device_state(tx, device_id_str)
+        .await?
+        .unwrap_or_default();
+    let mut last_trip_id = state.current_trip_id;
 
     // Rule: ignition_on = true cuando hay viaje activo
-    let is_trip_active = current_ignition_status.unwrap_or(false);
+    let is_trip_active = state.ignition_on;
 
-    // If trip is active but we don't have the ID, fetch it
+    // If trip is active but we don't have the ID, fetch it. Si quedaron varios
+    // viajes abiertos se fusionan en el más antiguo en vez de dejar huérfanos
     if is_trip_active && last_trip_id.is_none() {
-        let open_trip_row = sqlx::query(queries::SELECT_LATEST_OPEN_TRIP)
-            .bind(&device_id_str)
-            .fetch_optional(&mut *tx)
-            .await?;
+        last_trip_id = maintenance::reconcile_open_trips(tx, device_id_str).await?;
+    }
 
-        if let Some(row) = open_trip_row {
-            last_trip_id = row.try_get("trip_id").ok();
-        }
+    // El equipo volvió a reportar antes de que se cerrara su viaje inactivo:
+    // se retira la marca y el viaje continúa
+    if is_trip_active && state.stale_since.is_some() {
+        info!(
+            "Device {} resumed trip {:?} flagged stale since {:?}",
+            device_id_str, last_trip_id, state.stale_since
+        );
+        sqlx::query(queries::CLEAR_CURRENT_STATE_STALE)
+            .bind(device_id_str)
+            .execute(&mut **tx)
+            .await?;
     }
 
     // 5. Determine Destination and Process
-    let destination = determine_destination(alert_type, is_trip_active);
-    debug!(
-        "Message destination for {}: {:?}",
-        device_id_str, destination
-    );
-
-    match destination {
-        MessageDestination::NewTrip => {
-            let trip_id = message_uuid;
-            info!("Started new trip {} for device {}", trip_id, device_id_str);
+    // Se decide una sola vez; `process_message` registra este mismo valor
+    let destination = message_destination(ctx, data, &state);
 
-            sqlx::query(queries::INSERT_TRIP)
+    // El primer conductor que se identifica en un viaje abierto sin conductor
+    // se asigna al viaje
+    if matches!(
+        destination,
+        MessageDestination::TripPoint | MessageDestination::TripAlert
+    ) && state.current_driver_id.is_none()
+    {
+        if let (Some(trip_id), Some(driver_id)) = (last_trip_id, data.driver_id.as_deref()) {
+            sqlx::query(queries::SET_CURRENT_TRIP_DRIVER)
+                .bind(device_id_str)
+                .bind(driver_id)
                 .bind(trip_id)
-                .bind(&device_id_str)
-                .bind(timestamp)
-                .bind(lat)
-                .bind(lon)
-                .bind(odometer_meters)
-                .execute(&mut *tx)
+                .execute(&mut **tx)
                 .await?;
+        }
+    }
 
-            sqlx::query(queries::UPDATE_CURRENT_STATE_NEW_TRIP)
-                .bind(&device_id_str)
-                .bind(trip_id)
-                .bind(timestamp)
-                .bind(lat)
-                .bind(lon)
-                .bind(message_uuid)
-                .bind(odometer_meters)
-                .execute(&mut *tx)
+    match destination {
+        MessageDestination::NewTrip => {
+            start_trip(tx, ctx, data, "ignition_on", &mut events).await?;
+        }
+        MessageDestination::GapSplit => {
+            if let Some(trip_id) = last_trip_id {
+                warn!(
+                    "Device {} silent since {:?}, splitting trip {} (MAX_POINT_GAP_SECONDS={})",
+                    device_id_str, state.last_point_at, trip_id, ctx.config.max_point_gap_seconds
+                );
+                let pre_gap = pre_gap_point(&state, data);
+                end_trip(
+                    tx,
+                    ctx,
+                    &settings,
+                    &state,
+                    &pre_gap,
+                    trip_id,
+                    GAP_SPLIT,
+                    &mut events,
+                )
                 .await?;
-
-            let alert_id = Uuid::new_v4();
-            sqlx::query(queries::INSERT_TRIP_ALERT)
-                .bind(alert_id)
-                .bind(trip_id)
-                .bind(timestamp)
-                .bind(lat)
-                .bind(lon)
-                .bind("ignition_on")
-                .bind(
-                    message
-                        .data
-                        .get("RAW_CODE")
-                        .and_then(|s| s.parse::<i32>().ok()),
+            } else {
+                error!(
+                    "Active trip state without trip_id for gap split: {}",
+                    device_id_str
+                );
+            }
+            start_trip(tx, ctx, data, GAP_SPLIT, &mut events).await?;
+        }
+        MessageDestination::DriverChange => {
+            if let Some(trip_id) = last_trip_id {
+                info!(
+                    "Device {} driver changed from {:?} to {:?}, splitting trip {}",
+                    device_id_str, state.current_driver_id, data.driver_id, trip_id
+                );
+                let last_point = pre_gap_point(&state, data);
+                end_trip(
+                    tx,
+                    ctx,
+                    &settings,
+                    &state,
+                    &last_point,
+                    trip_id,
+                    DRIVER_CHANGE,
+                    &mut events,
                 )
-                .bind(1i16)
-                .bind(&device_id_str)
-                .bind(message_uuid)
-                .execute(&mut *tx)
                 .await?;
+            } else {
+                error!(
+                    "Active trip state without trip_id for driver change: {}",
+                    device_id_str
+                );
+            }
+            start_trip(tx, ctx, data, DRIVER_CHANGE, &mut events).await?;
+        }
+        MessageDestination::SplitTrip => {
+            match last_trip_id {
+                Some(trip_id) => {
+                    warn!(
+                        "Ignition on for device {} with trip {} still open, closing it (DUPLICATE_IGNITION_ON=split)",
+                        device_id_str, trip_id
+                    );
+                    let last_point = pre_gap_point(&state, data);
+                    end_trip(
+                        tx,
+                        ctx,
+                        &settings,
+                        &state,
+                        &last_point,
+                        trip_id,
+                        "missed_ignition_off",
+                        &mut events,
+                    )
+                    .await?;
+                }
+                None => error!(
+                    "Active trip state without trip_id for split trip: {}",
+                    device_id_str
+                ),
+            }
+            start_trip(tx, ctx, data, "ignition_on", &mut events).await?;
         }
         MessageDestination::EndTrip => {
             if let Some(trip_id) = last_trip_id {
                 info!("Ended trip {} for device {}", trip_id, device_id_str);
-
-                sqlx::query(queries::UPDATE_TRIP_END)
-                    .bind(timestamp)
-                    .bind(lat)
-                    .bind(lon)
-                    .bind(odometer_meters)
-                    .bind(trip_id)
-                    .execute(&mut *tx)
-                    .await?;
-
-                sqlx::query(queries::UPDATE_CURRENT_STATE_END_TRIP)
-                    .bind(&device_id_str)
-                    .bind(message_uuid)
-                    .bind(timestamp)
-                    .bind(lat)
-                    .bind(lon)
-                    .bind(speed)
-                    .execute(&mut *tx)
-                    .await?;
-
-                let alert_id = Uuid::new_v4();
-                sqlx::query(queries::INSERT_TRIP_ALERT)
-                    .bind(alert_id)
-                    .bind(trip_id)
-                    .bind(timestamp)
-                    .bind(lat)
-                    .bind(lon)
-                    .bind("ignition_off")
-                    .bind(
-                        message
-                            .data
-                            .get("RAW_CODE")
-                            .and_then(|s| s.parse::<i32>().ok()),
-                    )
-                    .bind(1i16)
-                    .bind(&device_id_str)
-                    .bind(message_uuid)
-                    .execute(&mut *tx)
-                    .await?;
+                end_trip(
+                    tx,
+                    ctx,
+                    &settings,
+                    &state,
+                    data,
+                    trip_id,
+                    "ignition_off",
+                    &mut events,
+                )
+                .await?;
             } else {
                 error!(
                     "Active trip state without trip_id for end trip: {}",
@@ -290,134 +1434,273 @@ pub async fn process_message(pool: &sqlx::Pool<Postgres>, payload: &[u8]) -> any
             }
         }
         MessageDestination::TripAlert => {
-            if let Some(trip_id) = last_trip_id {
+            // Las alertas de ignición se insertan en NewTrip/EndTrip y no pasan por aquí
+            let mut trip_id = last_trip_id;
+            if trip_id.is_some()
+                && is_debounced_alert(tx, ctx, device_id_str, alert_type.unwrap_or(""), timestamp)
+                    .await?
+            {
+                info!(
+                    "Suppressing repeated alert {:?} for device {} within ALERT_DEBOUNCE_SECONDS={}",
+                    alert_type, device_id_str, ctx.config.alert_debounce_seconds
+                );
+                trip_id = None;
+            }
+            let mut stored = true;
+            if let Some(trip_id) = trip_id {
                 let alert_id = Uuid::new_v4();
-                sqlx::query(queries::INSERT_TRIP_ALERT)
+                let inserted = sqlx::query(insert_trip_alert(ctx))
                     .bind(alert_id)
                     .bind(trip_id)
                     .bind(timestamp)
                     .bind(lat)
                     .bind(lon)
-                    .bind(alert_type.unwrap_or(""))
-                    .bind(
-                        message
-                            .data
-                            .get("RAW_CODE")
-                            .and_then(|s| s.parse::<i32>().ok()),
-                    )
-                    .bind(1i16)
-                    .bind(&device_id_str)
-                    .bind(message_uuid)
-                    .execute(&mut *tx)
+                    .bind(normalize_alert(alert_type.unwrap_or("")).as_str())
+                    .bind(data.raw_code)
+                    .bind(severity)
+                    .bind(device_id_str)
+                    .bind(correlation_id)
+                    .bind(alert_type)
+                    .bind(data.tenant_id.as_deref())
+                    .execute(&mut **tx)
                     .await?;
+                // Alerta reentregada: ya se guardó y se aplicó al estado
+                stored = inserted.rows_affected() > 0;
+                if !stored {
+                    metrics::record_duplicate_alert();
+                    info!(
+                        "Alert {:?} of device {} at {} already stored for trip {}, skipping",
+                        alert_type, device_id_str, timestamp, trip_id
+                    );
+                }
             }
 
-            sqlx::query(queries::UPDATE_CURRENT_STATE_POINT)
-                .bind(&device_id_str)
-                .bind(timestamp)
-                .bind(lat)
-                .bind(lon)
-                .bind(speed)
-                .bind(message_uuid)
-                .bind(odometer_meters)
-                .execute(&mut *tx)
-                .await?;
+            if stored {
+                update_state_point(tx, ctx, &settings, &state, data).await?;
+            }
         }
         MessageDestination::TripPoint => {
-            if let Some(trip_id) = last_trip_id {
-                sqlx::query(queries::INSERT_TRIP_POINT)
-                    .bind(trip_id)
-                    .bind(&device_id_str)
-                    .bind(timestamp)
-                    .bind(lat)
-                    .bind(lon)
-                    .bind(speed)
-                    .bind(heading)
-                    .bind(odometer_meters)
-                    .bind(message_uuid)
-                    .execute(&mut *tx)
-                    .await?;
-            }
+            if is_duplicate_counter(state.last_msg_counter, data.msg_counter) {
+                info!(
+                    "Skipping duplicate/out-of-order point for device {}: MSG_COUNTER {:?} <= last {:?}",
+                    device_id_str, data.msg_counter, state.last_msg_counter
+                );
+            } else {
+                if let Some(trip_id) = last_trip_id {
+                    // La ruta se acumula con todos los puntos, se guarden o no
+                    if let Some(current) = position {
+                        sqlx::query(queries::ADVANCE_CURRENT_TRIP_ROUTE)
+                            .bind(device_id_str)
+                            .bind(route_step_meters(
+                                state.current_trip_route_position,
+                                current,
+                            ))
+                            .bind(current.0)
+                            .bind(current.1)
+                            .execute(&mut **tx)
+                            .await?;
+                    }
 
-            sqlx::query(queries::UPDATE_CURRENT_STATE_POINT)
-                .bind(&device_id_str)
-                .bind(timestamp)
-                .bind(lat)
-                .bind(lon)
-                .bind(speed)
-                .bind(message_uuid)
-                .bind(odometer_meters)
-                .execute(&mut *tx)
-                .await?;
+                    match position {
+                        Some(_)
+                            if !ctx.point_sampler.should_store(
+                                device_id_str,
+                                trip_id,
+                                settings.point_sample_every_n,
+                            ) =>
+                        {
+                            debug!(
+                                "Point {} of device {} skipped by POINT_SAMPLE_EVERY_N",
+                                message_uuid, device_id_str
+                            );
+                        }
+                        Some((point_lat, point_lon)) => {
+                            sqlx::query(queries::INSERT_TRIP_POINT)
+                                .bind(trip_id)
+                                .bind(device_id_str)
+                                .bind(timestamp)
+                                .bind(point_lat)
+                                .bind(point_lon)
+                                .bind(speed)
+                                .bind(heading)
+                                .bind(odometer_meters)
+                                .bind(correlation_id)
+                                .bind(data.altitude)
+                                .bind(data.tenant_id.as_deref())
+                                .bind(data.location_source.as_str())
+                                .execute(&mut **tx)
+                                .await?;
+                            sqlx::query(queries::INCREMENT_CURRENT_TRIP_POINT_COUNT)
+                                .bind(device_id_str)
+                                .execute(&mut **tx)
+                                .await?;
+                        }
+                        None => debug!(
+                            "Message {} for device {} has no coordinates, no trip point stored",
+                            message_uuid, device_id_str
+                        ),
+                    }
+
+                    let previous = state.last_lat.zip(state.last_lng);
+                    let transitions = position
+                        .map(|current| detect_transitions(&ctx.geofences, previous, current))
+                        .unwrap_or_default();
+                    for (fence, event) in transitions {
+                        info!(
+                            "Device {} {} geofence {}",
+                            device_id_str,
+                            event.alert_type(),
+                            fence.name
+                        );
+                        sqlx::query(insert_trip_alert(ctx))
+                            .bind(Uuid::new_v4())
+                            .bind(trip_id)
+                            .bind(timestamp)
+                            .bind(lat)
+                            .bind(lon)
+                            .bind(event.alert_type())
+                            .bind(data.raw_code)
+                            .bind(DEFAULT_ALERT_SEVERITY)
+                            .bind(device_id_str)
+                            .bind(derived_correlation_id(
+                                correlation_id,
+                                event.alert_type(),
+                                &fence.name,
+                            ))
+                            .bind(fence.name.as_str())
+                            .bind(data.tenant_id.as_deref())
+                            .execute(&mut **tx)
+                            .await?;
+                    }
+
+                    if crosses_idle_threshold(
+                        state.last_idle_seconds,
+                        data.idle_time_seconds,
+                        settings.idle_stop_threshold_seconds,
+                    ) {
+                        info!(
+                            "Idle stop for device {} on trip {} (IDLE_TIME {:?} s)",
+                            device_id_str, trip_id, data.idle_time_seconds
+                        );
+                        sqlx::query(insert_trip_alert(ctx))
+                            .bind(Uuid::new_v4())
+                            .bind(trip_id)
+                            .bind(timestamp)
+                            .bind(lat)
+                            .bind(lon)
+                            .bind("idle_stop")
+                            .bind(data.raw_code)
+                            .bind(DEFAULT_ALERT_SEVERITY)
+                            .bind(device_id_str)
+                            .bind(derived_correlation_id(correlation_id, "idle_stop", ""))
+                            .bind(alert_type)
+                            .bind(data.tenant_id.as_deref())
+                            .execute(&mut **tx)
+                            .await?;
+                    }
+                }
+
+                if let Some(idle_time) = data.idle_time_seconds {
+                    sqlx::query(queries::UPDATE_CURRENT_STATE_IDLE_TIME)
+                        .bind(device_id_str)
+                        .bind(idle_time)
+                        .execute(&mut **tx)
+                        .await?;
+                }
+
+                if let Some(counter) = data.msg_counter {
+                    sqlx::query(queries::UPDATE_CURRENT_STATE_MSG_COUNTER)
+                        .bind(device_id_str)
+                        .bind(i64::from(counter.value))
+                        .execute(&mut **tx)
+                        .await?;
+                }
+
+                update_state_point(tx, ctx, &settings, &state, data).await?;
+            }
         }
         MessageDestination::IdleActivity => {
-            let idle_id = Uuid::new_v4();
-            let activity_type = alert_type.unwrap_or("gps_idle_point");
-
-            let metadata_json = if let Some(m) = message.metadata {
-                serde_json::json!({
-                    "worker_id": m.worker_id,
-                    "received_epoch": m.received_epoch,
-                    "decoded_epoch": m.decoded_epoch,
-                    "bytes": m.bytes,
-                    "client_ip": m.client_ip,
-                    "client_port": m.client_port
-                })
-            } else {
-                serde_json::Value::Null
+            // Movimiento sin viaje activo (remolque): se marca para que quede
+            // como rastro continuo en vez de un punto ocioso más
+            let moving = is_moving_while_off(
+                state.last_lat.zip(state.last_lng),
+                position,
+                ctx.config.tow_detection_meters,
+            );
+            let activity_type = match alert_type {
+                Some(alert) => alert,
+                None if moving => {
+                    warn!(
+                        "Device {} moved more than TOW_DETECTION_METERS={} without an active trip",
+                        device_id_str, ctx.config.tow_detection_meters
+                    );
+                    MOVEMENT_WHILE_OFF
+                }
+                None => "gps_idle_point",
             };
 
-            sqlx::query(queries::INSERT_DEVICE_IDLE_ACTIVITY)
-                .bind(idle_id)
-                .bind(&device_id_str)
-                .bind(timestamp)
-                .bind(lat)
-                .bind(lon)
-                .bind(activity_type)
-                .bind(
-                    message
-                        .data
-                        .get("RAW_CODE")
-                        .and_then(|s| s.parse::<i32>().ok()),
-                )
-                .bind(1i16)
-                .bind(metadata_json)
-                .bind(message_uuid)
-                .execute(&mut *tx)
-                .await?;
+            insert_idle_activity(tx, ctx, data, activity_type).await?;
 
-            sqlx::query(queries::UPDATE_CURRENT_STATE_POINT)
-                .bind(&device_id_str)
-                .bind(timestamp)
-                .bind(lat)
-                .bind(lon)
-                .bind(speed)
-                .bind(message_uuid)
-                .bind(odometer_meters)
-                .execute(&mut *tx)
-                .await?;
+            update_state_point(tx, ctx, &settings, &state, data).await?;
+        }
+        MessageDestination::ForcedEndTrip => {
+            // Con la ignición apagada puede quedar un viaje abierto sin
+            // current_trip_id: se busca directamente en `trips`
+            let trip_id = match last_trip_id {
+                Some(trip_id) => Some(trip_id),
+                None => maintenance::reconcile_open_trips(tx, device_id_str).await?,
+            };
+            match trip_id {
+                Some(trip_id) => {
+                    warn!(
+                        "Force-closing trip {} for device {} (FORCE_TRIP_END)",
+                        trip_id, device_id_str
+                    );
+                    end_trip(
+                        tx,
+                        ctx,
+                        &settings,
+                        &state,
+                        data,
+                        trip_id,
+                        "forced_end",
+                        &mut events,
+                    )
+                    .await?;
+                }
+                None => {
+                    info!(
+                        "FORCE_TRIP_END for device {} without an open trip, nothing to close",
+                        device_id_str
+                    );
+                    update_state_point(tx, ctx, &settings, &state, data).await?;
+                }
+            }
         }
         MessageDestination::IgnoredIgnitionOn | MessageDestination::IgnoredIgnitionOff => {
             info!(
                 "Ignored ignition event ({:?}) for device {}",
                 destination, device_id_str
             );
-            sqlx::query(queries::UPDATE_CURRENT_STATE_POINT)
-                .bind(&device_id_str)
-                .bind(timestamp)
-                .bind(lat)
-                .bind(lon)
-                .bind(speed)
-                .bind(message_uuid)
-                .bind(odometer_meters)
-                .execute(&mut *tx)
-                .await?;
+            if destination == MessageDestination::IgnoredIgnitionOff
+                && ctx.config.record_ignition_off_no_trip
+            {
+                insert_idle_activity(tx, ctx, data, IGNITION_OFF_NO_TRIP).await?;
+            }
+            update_state_point(tx, ctx, &settings, &state, data).await?;
+        }
+        MessageDestination::StateUpdate => {
+            debug!(
+                "Message {} of device {} routed by MSG_CLASS {:?} to current state only",
+                message_uuid, device_id_str, data.msg_class
+            );
+            update_state_point(tx, ctx, &settings, &state, data).await?;
         }
+        // Se resuelve antes de leer el estado; determine_destination no lo devuelve
+        MessageDestination::Replayed => {}
     }
 
-    tx.commit().await?;
-
-    Ok(())
+    Ok((destination, events))
 }
 
 #[cfg(test)]
@@ -557,6 +1840,103 @@ mod tests {
         assert_eq!(dest, MessageDestination::IdleActivity);
     }
 
+    #[test]
+    fn test_destination_force_trip_end() {
+        assert!(is_force_trip_end(Some("FORCE_TRIP_END")));
+        assert!(is_force_trip_end(Some(" force trip end ")));
+        assert!(!is_force_trip_end(Some("ENGINE OFF")));
+        assert!(!is_force_trip_end(None));
+
+        // Con o sin viaje activo: el cierre se resuelve al aplicar el mensaje
+        let dest = determine_destination(Some("FORCE_TRIP_END"), true);
+        assert_eq!(dest, MessageDestination::ForcedEndTrip);
+        let dest = determine_destination(Some("FORCE_TRIP_END"), false);
+        assert_eq!(dest, MessageDestination::ForcedEndTrip);
+    }
+
+    #[test]
+    fn test_duplicate_ignition_on_modes() {
+        assert_eq!(
+            DuplicateIgnitionOn::parse("SPLIT"),
+            DuplicateIgnitionOn::Split
+        );
+        assert_eq!(DuplicateIgnitionOn::parse(""), DuplicateIgnitionOn::Ignore);
+        assert_eq!(
+            DuplicateIgnitionOn::parse("bogus"),
+            DuplicateIgnitionOn::Ignore
+        );
+
+        let duplicate = determine_destination(Some("ENGINE ON"), true);
+        assert_eq!(
+            apply_duplicate_ignition_on(duplicate, DuplicateIgnitionOn::Ignore),
+            MessageDestination::IgnoredIgnitionOn
+        );
+        assert_eq!(
+            apply_duplicate_ignition_on(duplicate, DuplicateIgnitionOn::Split),
+            MessageDestination::SplitTrip
+        );
+        assert_eq!(
+            apply_duplicate_ignition_on(MessageDestination::NewTrip, DuplicateIgnitionOn::Split),
+            MessageDestination::NewTrip
+        );
+    }
+
+    #[test]
+    fn test_route_by_class() {
+        use MessageDestination::*;
+
+        assert_eq!(
+            route_by_class(TripPoint, Some(ClassRoute::State)),
+            StateUpdate
+        );
+        assert_eq!(
+            route_by_class(IdleActivity, Some(ClassRoute::State)),
+            StateUpdate
+        );
+        assert_eq!(
+            route_by_class(TripPoint, Some(ClassRoute::Alert)),
+            TripAlert
+        );
+        assert_eq!(
+            route_by_class(TripPoint, Some(ClassRoute::Point)),
+            TripPoint
+        );
+        assert_eq!(route_by_class(TripPoint, None), TripPoint);
+        // Ignición y alertas con texto no cambian
+        assert_eq!(route_by_class(NewTrip, Some(ClassRoute::State)), NewTrip);
+        assert_eq!(route_by_class(EndTrip, Some(ClassRoute::State)), EndTrip);
+        assert_eq!(
+            route_by_class(TripAlert, Some(ClassRoute::State)),
+            TripAlert
+        );
+    }
+
+    // ==================== Tests de distancia mínima de viaje ====================
+
+    #[test]
+    fn test_should_discard_trip_below_minimum() {
+        // Viaje de 3 metros con mínimo de 50 -> descartar
+        assert!(should_discard_trip(Some(3.0), 50.0));
+    }
+
+    #[test]
+    fn test_should_keep_trip_at_or_above_minimum() {
+        assert!(!should_discard_trip(Some(50.0), 50.0));
+        assert!(!should_discard_trip(Some(1200.0), 50.0));
+    }
+
+    #[test]
+    fn test_should_keep_all_trips_with_default_minimum() {
+        // Mínimo 0 (default) -> conservar todos
+        assert!(!should_discard_trip(Some(0.0), 0.0));
+        assert!(!should_discard_trip(Some(3.0), 0.0));
+    }
+
+    #[test]
+    fn test_should_keep_trip_without_known_distance() {
+        assert!(!should_discard_trip(None, 50.0));
+    }
+
     // ==================== Test del mensaje específico de Queclink ====================
 
     #[test]
@@ -573,4 +1953,476 @@ mod tests {
             "El mensaje GTVGN de Queclink con 'Turn On' debe crear un nuevo trip"
         );
     }
+
+    // ==================== Tests de dead-letter ====================
+
+    use crate::config::AppConfig;
+    use crate::dead_letter::DeadLetterSink;
+    use crate::models::siscom::v1::KafkaMessage;
+    use crate::passthrough::PayloadTap;
+    use prost::Message;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Default)]
+    struct RecordingSink {
+        sent: Mutex<Vec<(DeadLetterReason, Vec<u8>)>>,
+    }
+
+    impl DeadLetterSink for RecordingSink {
+        fn send(&self, reason: DeadLetterReason, payload: &[u8]) {
+            self.sent.lock().unwrap().push((reason, payload.to_vec()));
+        }
+    }
+
+    /// Pool perezoso hacia un puerto cerrado: no conecta hasta que se usa y,
+    /// si se usa, falla en cuanto vence el `acquire_timeout`.
+    fn unconnected_pools() -> ShardedPool {
+        ShardedPool::single(
+            sqlx::postgres::PgPoolOptions::new()
+                .acquire_timeout(Duration::from_millis(50))
+                .connect_lazy("postgres://localhost:1/unreachable")
+                .unwrap(),
+        )
+    }
+
+    // ==================== Tests de metadata idle ====================
+
+    #[test]
+    fn test_idle_metadata_includes_instance_id() {
+        let metadata = Metadata {
+            worker_id: 3,
+            ..Default::default()
+        };
+
+        let json = idle_metadata(Some(&metadata), "trips-7f9c");
+        assert_eq!(json["instance_id"], "trips-7f9c");
+        assert_eq!(json["worker_id"], 3);
+
+        let json = idle_metadata(None, "trips-7f9c");
+        assert_eq!(json["instance_id"], "trips-7f9c");
+    }
+
+    // ==================== Tests de timeout ====================
+
+    #[tokio::test]
+    async fn test_process_timeout_aborts_slow_processing() {
+        let before = metrics::failure_count(FailureKind::Timeout);
+
+        let result = with_process_timeout(Duration::from_millis(10), Uuid::new_v4(), async {
+            tokio::time::sleep(Duration::from_secs(5)).await;
+        })
+        .await;
+
+        assert!(result.is_none());
+        assert!(metrics::failure_count(FailureKind::Timeout) > before);
+    }
+
+    #[tokio::test]
+    async fn test_process_timeout_passes_through_fast_processing() {
+        let result =
+            with_process_timeout(Duration::from_secs(5), Uuid::new_v4(), async { 42 }).await;
+        assert_eq!(result, Some(42));
+
+        let unlimited = with_process_timeout(Duration::ZERO, Uuid::new_v4(), async { 7 }).await;
+        assert_eq!(unlimited, Some(7));
+    }
+
+    #[derive(Default)]
+    struct RecordingTap {
+        tapped: Mutex<Vec<Vec<u8>>>,
+    }
+
+    impl PayloadTap for RecordingTap {
+        fn tap(&self, payload: &[u8]) {
+            self.tapped.lock().unwrap().push(payload.to_vec());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_passthrough_fires_for_every_payload() {
+        let tap = Arc::new(RecordingTap::default());
+        let ctx = ProcessorContext::new(AppConfig::development())
+            .with_passthrough(Some(tap.clone() as Arc<dyn PayloadTap>));
+        let pools = unconnected_pools();
+
+        // Ninguno llega a la base de datos: basura y mensajes sin `data`
+        let payloads = vec![
+            b"not a protobuf".to_vec(),
+            KafkaMessage::default().encode_to_vec(),
+            vec![0xff, 0x00, 0x13],
+        ];
+        for payload in &payloads {
+            process_message(&pools, &ctx, payload).await.unwrap();
+        }
+
+        assert_eq!(*tap.tapped.lock().unwrap(), payloads);
+    }
+
+    #[tokio::test]
+    async fn test_message_without_data_goes_to_dead_letter() {
+        let sink = Arc::new(RecordingSink::default());
+        let ctx = ProcessorContext::new(AppConfig::development())
+            .with_dead_letter(Some(sink.clone() as Arc<dyn DeadLetterSink>));
+        let pools = unconnected_pools();
+
+        let payload = KafkaMessage {
+            uuid: Uuid::new_v4().to_string(),
+            metadata: Some(Metadata {
+                worker_id: 1,
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+        .encode_to_vec();
+
+        let destination = process_message(&pools, &ctx, &payload).await.unwrap();
+        assert_eq!(destination, None);
+
+        let sent = sink.sent.lock().unwrap();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].0, DeadLetterReason::MissingData);
+        assert_eq!(sent[0].1, payload);
+    }
+
+    #[tokio::test]
+    async fn test_oversized_payload_goes_to_dead_letter() {
+        let sink = Arc::new(RecordingSink::default());
+        let mut config = AppConfig::development();
+        config.max_payload_bytes = 64;
+        let ctx = ProcessorContext::new(config)
+            .with_dead_letter(Some(sink.clone() as Arc<dyn DeadLetterSink>));
+        let pools = unconnected_pools();
+
+        // Ni siquiera es un mensaje válido: no se intenta decodificar
+        let payload = vec![0xff; 65];
+        let destination = process_message(&pools, &ctx, &payload).await.unwrap();
+        assert_eq!(destination, None);
+
+        let sent = sink.sent.lock().unwrap();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].0, DeadLetterReason::Oversized);
+        assert_eq!(sent[0].1, payload);
+    }
+
+    #[tokio::test]
+    async fn test_gzip_bomb_goes_to_dead_letter() {
+        use flate2::write::GzEncoder;
+        use flate2::Compression;
+        use std::io::Write;
+
+        let sink = Arc::new(RecordingSink::default());
+        let mut config = AppConfig::development();
+        config.max_payload_bytes = 64 * 1024;
+        let ctx = ProcessorContext::new(config)
+            .with_dead_letter(Some(sink.clone() as Arc<dyn DeadLetterSink>));
+        let pools = unconnected_pools();
+
+        // Pasa el límite comprimido pero se infla a 1 MiB
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&vec![0; 1 << 20]).unwrap();
+        let payload = encoder.finish().unwrap();
+        assert!(!reject_oversized(&ctx, &payload));
+
+        let destination = process_message(&pools, &ctx, &payload).await.unwrap();
+        assert_eq!(destination, None);
+
+        let sent = sink.sent.lock().unwrap();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].0, DeadLetterReason::Oversized);
+        assert_eq!(sent[0].1, payload);
+    }
+
+    #[test]
+    fn test_payload_at_limit_is_accepted() {
+        let mut config = AppConfig::development();
+        config.max_payload_bytes = 64;
+        let ctx = ProcessorContext::new(config);
+
+        assert!(!reject_oversized(&ctx, &[0; 64]));
+        assert!(reject_oversized(&ctx, &[0; 65]));
+
+        let mut config = AppConfig::development();
+        config.max_payload_bytes = 0;
+        assert!(!reject_oversized(&ProcessorContext::new(config), &[0; 65]));
+    }
+
+    // ==================== Tests de versión de esquema ====================
+
+    #[test]
+    fn test_is_schema_too_old() {
+        // Demasiado vieja
+        assert!(is_schema_too_old(Some(1), 2));
+        // Aceptable
+        assert!(!is_schema_too_old(Some(2), 2));
+        assert!(!is_schema_too_old(Some(3), 2));
+        // Sin versión: formato legado, se procesa
+        assert!(!is_schema_too_old(None, 2));
+        // Sin mínimo configurado
+        assert!(!is_schema_too_old(Some(0), 0));
+    }
+
+    #[tokio::test]
+    async fn test_schema_too_old_goes_to_dead_letter() {
+        let sink = Arc::new(RecordingSink::default());
+        let mut config = AppConfig::development();
+        config.min_schema_version = 2;
+        let ctx = ProcessorContext::new(config)
+            .with_dead_letter(Some(sink.clone() as Arc<dyn DeadLetterSink>));
+        let pools = unconnected_pools();
+
+        let payload = KafkaMessage {
+            uuid: Uuid::new_v4().to_string(),
+            data: std::collections::HashMap::from([
+                ("DEVICE_ID".to_string(), "LEGACY-001".to_string()),
+                ("PROTOCOL_VERSION".to_string(), "1".to_string()),
+            ]),
+            ..Default::default()
+        }
+        .encode_to_vec();
+
+        let destination = process_message(&pools, &ctx, &payload).await.unwrap();
+        assert_eq!(destination, None);
+
+        let sent = sink.sent.lock().unwrap();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].0, DeadLetterReason::SchemaTooOld);
+        assert_eq!(sent[0].1, payload);
+    }
+
+    #[tokio::test]
+    async fn test_payload_missing_required_field_goes_to_dead_letter() {
+        let sink = Arc::new(RecordingSink::default());
+        let mut config = AppConfig::development();
+        config.enable_schema_validation = true;
+        config.payload_schema = vec![
+            "DEVICE_ID:string".to_string(),
+            "GPS_EPOCH:integer".to_string(),
+        ];
+        let ctx = ProcessorContext::new(config)
+            .with_dead_letter(Some(sink.clone() as Arc<dyn DeadLetterSink>));
+        let pools = unconnected_pools();
+
+        let payload = KafkaMessage {
+            uuid: Uuid::new_v4().to_string(),
+            data: std::collections::HashMap::from([(
+                "DEVICE_ID".to_string(),
+                "NO-EPOCH-001".to_string(),
+            )]),
+            ..Default::default()
+        }
+        .encode_to_vec();
+
+        let destination = process_message(&pools, &ctx, &payload).await.unwrap();
+        assert_eq!(destination, None);
+
+        let sent = sink.sent.lock().unwrap();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].0, DeadLetterReason::SchemaInvalid);
+        assert_eq!(sent[0].1, payload);
+    }
+
+    fn poison_payload(device_id: &str) -> Vec<u8> {
+        KafkaMessage {
+            uuid: Uuid::new_v4().to_string(),
+            data: std::collections::HashMap::from([
+                ("DEVICE_ID".to_string(), device_id.to_string()),
+                ("GPS_EPOCH".to_string(), "1700000000".to_string()),
+            ]),
+            ..Default::default()
+        }
+        .encode_to_vec()
+    }
+
+    #[tokio::test]
+    async fn test_failing_message_is_dead_lettered_after_max_attempts() {
+        let sink = Arc::new(RecordingSink::default());
+        let tap = Arc::new(RecordingTap::default());
+        let mut config = AppConfig::development();
+        config.max_message_attempts = 3;
+        config.kafka_backoff_max_ms = 10;
+        let ctx = ProcessorContext::new(config)
+            .with_dead_letter(Some(sink.clone() as Arc<dyn DeadLetterSink>))
+            .with_passthrough(Some(tap.clone() as Arc<dyn PayloadTap>));
+        // Base de datos inalcanzable: cada intento falla al persistir
+        let pools = unconnected_pools();
+        let payload = poison_payload("POISON-001");
+
+        let message = decode_message(&ctx, &payload, &[]).unwrap();
+        let destination = process_message_with_attempts(&pools, &ctx, &payload, message)
+            .await
+            .unwrap();
+        assert_eq!(destination, None);
+
+        // Se decodifica una vez: el passthrough no ve los reintentos
+        assert_eq!(tap.tapped.lock().unwrap().len(), 1);
+        let sent = sink.sent.lock().unwrap();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].0, DeadLetterReason::MaxAttempts);
+        assert_eq!(sent[0].1, payload);
+    }
+
+    #[tokio::test]
+    async fn test_without_max_attempts_the_error_is_returned() {
+        let sink = Arc::new(RecordingSink::default());
+        let ctx = ProcessorContext::new(AppConfig::development())
+            .with_dead_letter(Some(sink.clone() as Arc<dyn DeadLetterSink>));
+        let pools = unconnected_pools();
+        let payload = poison_payload("POISON-002");
+
+        let message = decode_message(&ctx, &payload, &[]).unwrap();
+        assert!(
+            process_message_with_attempts(&pools, &ctx, &payload, message)
+                .await
+                .is_err()
+        );
+        assert!(sink.sent.lock().unwrap().is_empty());
+    }
+
+    // ==================== Tests de posición por celda ====================
+
+    use crate::processor::cell_location::{CellInfo, CellLocator};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[derive(Default)]
+    struct CountingLocator {
+        calls: AtomicUsize,
+    }
+
+    impl CellLocator for CountingLocator {
+        fn locate(&self, _cell: &CellInfo) -> Option<(f64, f64)> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            Some((19.4326, -99.1332))
+        }
+    }
+
+    fn cell_message_data(fields: &[(&str, &str)]) -> MessageData {
+        let mut data = std::collections::HashMap::from([
+            ("DEVICE_ID".to_string(), "CELL-001".to_string()),
+            ("MCC".to_string(), "334".to_string()),
+            ("MNC".to_string(), "20".to_string()),
+            ("LAC".to_string(), "0x1A2B".to_string()),
+            ("CID".to_string(), "40961".to_string()),
+        ]);
+        for (key, value) in fields {
+            data.insert(key.to_string(), value.to_string());
+        }
+        let message = KafkaMessage {
+            uuid: Uuid::new_v4().to_string(),
+            data,
+            ..Default::default()
+        };
+        extract_message(message, &crate::clock::SystemClock).unwrap()
+    }
+
+    #[test]
+    fn test_cell_locator_positions_only_messages_without_gps() {
+        let locator = Arc::new(CountingLocator::default());
+        let ctx = ProcessorContext::new(AppConfig::development())
+            .with_cell_locator(Some(locator.clone() as Arc<dyn CellLocator>));
+
+        let mut gps = cell_message_data(&[("LATITUD", "20.65"), ("LONGITUD", "-100.39")]);
+        locate_by_cell(&ctx, &mut gps);
+        assert_eq!(locator.calls.load(Ordering::SeqCst), 0);
+        assert_eq!(gps.location_source, LocationSource::Gps);
+        assert_eq!(gps.lat, 20.65);
+
+        let mut no_gps = cell_message_data(&[]);
+        locate_by_cell(&ctx, &mut no_gps);
+        assert_eq!(locator.calls.load(Ordering::SeqCst), 1);
+        assert!(no_gps.has_position);
+        assert_eq!((no_gps.lat, no_gps.lon), (19.4326, -99.1332));
+        assert_eq!(no_gps.location_source, LocationSource::Cell);
+    }
+
+    #[test]
+    fn test_without_cell_locator_message_stays_without_position() {
+        let ctx = ProcessorContext::new(AppConfig::development());
+        let mut data = cell_message_data(&[]);
+
+        locate_by_cell(&ctx, &mut data);
+
+        assert!(!data.has_position);
+        assert_eq!(data.location_source, LocationSource::Gps);
+    }
+
+    // ==================== Tests de rate limit ====================
+
+    fn message_data(alert: Option<&str>) -> MessageData {
+        let mut data =
+            std::collections::HashMap::from([("DEVICE_ID".to_string(), "FLOOD-001".to_string())]);
+        if let Some(alert) = alert {
+            data.insert("ALERT".to_string(), alert.to_string());
+        }
+        let message = KafkaMessage {
+            uuid: Uuid::new_v4().to_string(),
+            data,
+            ..Default::default()
+        };
+        extract_message(message, &crate::clock::SystemClock).unwrap()
+    }
+
+    #[test]
+    fn test_ignition_bypasses_rate_limit() {
+        let mut config = AppConfig::development();
+        config.per_device_rate_limit = 1.0;
+        config.per_device_rate_burst = 1.0;
+        let ctx = ProcessorContext::new(config);
+        let now = Instant::now();
+
+        assert!(!is_rate_limited(&ctx, &message_data(None), now));
+        assert!(is_rate_limited(&ctx, &message_data(None), now));
+        assert!(is_rate_limited(&ctx, &message_data(Some("SPEEDING")), now));
+
+        // Con el bucket vacío, encendido y apagado siguen pasando
+        assert!(!is_rate_limited(
+            &ctx,
+            &message_data(Some("ENGINE ON")),
+            now
+        ));
+        assert!(!is_rate_limited(&ctx, &message_data(Some("Turn Off")), now));
+    }
+
+    #[test]
+    fn test_only_duplicate_trip_points_are_noop() {
+        let mut config = AppConfig::development();
+        config.skip_noop_transactions = true;
+        let ctx = ProcessorContext::new(config);
+        let state = DeviceState {
+            ignition_on: true,
+            last_msg_counter: Some(0x10),
+            ..Default::default()
+        };
+        let with_counter = |alert: Option<&str>, counter: &str| {
+            let mut data = message_data(alert);
+            data.msg_counter = crate::processor::sequence::MsgCounter::parse(counter);
+            data
+        };
+
+        assert!(is_noop_message(&ctx, &with_counter(None, "0010"), &state));
+        assert!(!is_noop_message(&ctx, &with_counter(None, "0011"), &state));
+        // Apagados y alertas siempre escriben
+        assert!(!is_noop_message(
+            &ctx,
+            &with_counter(Some("ENGINE OFF"), "0010"),
+            &state
+        ));
+        assert!(!is_noop_message(
+            &ctx,
+            &with_counter(Some("SPEEDING"), "0010"),
+            &state
+        ));
+        // Sin viaje activo el mensaje va a actividad idle
+        let idle = DeviceState {
+            ignition_on: false,
+            ..state.clone()
+        };
+        assert!(!is_noop_message(&ctx, &with_counter(None, "0010"), &idle));
+
+        let disabled = ProcessorContext::new(AppConfig::development());
+        assert!(!is_noop_message(
+            &disabled,
+            &with_counter(None, "0010"),
+            &state
+        ));
+    }
 }
@@ -1 +1,29 @@
+pub mod adapters;
+pub mod alert_codes;
+pub mod alerts;
+pub mod cell_location;
+pub mod compression;
+pub mod context;
+pub mod device_config;
+pub mod device_filter;
+pub mod distance;
+pub mod extract;
+pub mod geo;
+pub mod geofence;
+pub mod idle;
+pub mod json_input;
+#[cfg(all(test, feature = "db-tests"))]
+mod lifecycle_tests;
 pub mod message_processor;
+pub mod msg_class;
+pub mod payload_schema;
+pub mod point_sampler;
+pub mod rate_limit;
+pub mod sequence;
+pub mod state;
+pub mod tenant;
+pub mod timezone;
+pub mod tow;
+pub mod trip_id;
+pub mod trip_stats;
+pub mod tx_batch;
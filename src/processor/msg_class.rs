@@ -0,0 +1,126 @@
+use crate::models::siscom::v1::MessageClass;
+use std::collections::HashMap;
+use tracing::warn;
+
+/// Cómo se trata un mensaje según su MSG_CLASS (`MSG_CLASS_ROUTES`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClassRoute {
+    /// Punto del viaje o actividad idle (comportamiento normal)
+    Point,
+    /// Solo actualiza `trip_current_state`, sin insertar punto ni actividad
+    State,
+    /// Se guarda como alerta aunque no traiga ALERT
+    Alert,
+}
+
+impl ClassRoute {
+    fn parse(s: &str) -> Option<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "point" => Some(ClassRoute::Point),
+            "state" => Some(ClassRoute::State),
+            "alert" => Some(ClassRoute::Alert),
+            _ => None,
+        }
+    }
+}
+
+/// Mapa MSG_CLASS → ruta, p. ej. `STATUS:state,ALERT:alert,GPS:point`. Sin
+/// entradas (o con una clase no mapeada) el mensaje sigue las reglas normales.
+#[derive(Debug, Default)]
+pub struct MsgClassRoutes {
+    routes: HashMap<String, ClassRoute>,
+}
+
+impl MsgClassRoutes {
+    /// Carga las entradas `CLASE:ruta`; entradas inválidas se ignoran con un aviso
+    pub fn parse(entries: &[String]) -> Self {
+        let routes = entries
+            .iter()
+            .filter_map(|entry| {
+                let parsed = entry.split_once(':').and_then(|(class, route)| {
+                    Some((class_key(class)?, ClassRoute::parse(route)?))
+                });
+                if parsed.is_none() {
+                    warn!("Ignoring invalid MSG_CLASS route '{}'", entry);
+                }
+                parsed
+            })
+            .collect();
+        Self { routes }
+    }
+
+    /// Ruta configurada para la clase del mensaje, si hay
+    pub fn route(&self, msg_class: Option<&str>) -> Option<ClassRoute> {
+        self.routes.get(&class_key(msg_class?)?).copied()
+    }
+}
+
+/// Nombre de la clase en mayúsculas; los valores numéricos del enum
+/// `MessageClass` (1 = STATUS, 3 = ALERT, ...) se traducen a su nombre
+fn class_key(class: &str) -> Option<String> {
+    let class = class.trim();
+    if class.is_empty() {
+        return None;
+    }
+    let name = class
+        .parse::<i32>()
+        .ok()
+        .and_then(|n| MessageClass::try_from(n).ok())
+        .map(|c| c.as_str_name().to_string())
+        .unwrap_or_else(|| class.to_uppercase());
+    Some(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn routes() -> MsgClassRoutes {
+        MsgClassRoutes::parse(&[
+            "STATUS:state".to_string(),
+            "alert:Alert".to_string(),
+            "GPS:point".to_string(),
+            "POSITION:point".to_string(),
+        ])
+    }
+
+    #[test]
+    fn test_routes_by_class_name() {
+        let routes = routes();
+
+        assert_eq!(routes.route(Some("STATUS")), Some(ClassRoute::State));
+        assert_eq!(routes.route(Some(" status ")), Some(ClassRoute::State));
+        assert_eq!(routes.route(Some("ALERT")), Some(ClassRoute::Alert));
+        assert_eq!(routes.route(Some("Position")), Some(ClassRoute::Point));
+    }
+
+    #[test]
+    fn test_numeric_class_uses_enum_name() {
+        let routes = routes();
+
+        assert_eq!(routes.route(Some("1")), Some(ClassRoute::State));
+        assert_eq!(routes.route(Some("3")), Some(ClassRoute::Alert));
+        assert_eq!(routes.route(Some("2")), None);
+    }
+
+    #[test]
+    fn test_unmapped_or_missing_class_has_no_route() {
+        let routes = routes();
+
+        assert_eq!(routes.route(Some("EVENT")), None);
+        assert_eq!(routes.route(Some("")), None);
+        assert_eq!(routes.route(None), None);
+        assert_eq!(MsgClassRoutes::default().route(Some("STATUS")), None);
+    }
+
+    #[test]
+    fn test_invalid_entries_are_ignored() {
+        let routes = MsgClassRoutes::parse(&[
+            "STATUS".to_string(),
+            ":state".to_string(),
+            "STATUS:discard".to_string(),
+        ]);
+
+        assert_eq!(routes.route(Some("STATUS")), None);
+    }
+}
@@ -0,0 +1,225 @@
+use crate::processor::extract::{parse_latitude, parse_longitude};
+use std::collections::HashMap;
+use std::fmt;
+use tracing::warn;
+
+/// Tipo esperado del valor (texto) de un campo del mapa `data`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FieldType {
+    String,
+    Integer,
+    Number,
+    Boolean,
+    /// Número o con sufijo N/S (`20.65N`)
+    Latitude,
+    /// Número o con sufijo E/W (`100.39W`)
+    Longitude,
+}
+
+impl FieldType {
+    fn parse(s: &str) -> Option<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "string" => Some(FieldType::String),
+            "integer" => Some(FieldType::Integer),
+            "number" => Some(FieldType::Number),
+            "boolean" => Some(FieldType::Boolean),
+            "latitude" => Some(FieldType::Latitude),
+            "longitude" => Some(FieldType::Longitude),
+            _ => None,
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            FieldType::String => "string",
+            FieldType::Integer => "integer",
+            FieldType::Number => "number",
+            FieldType::Boolean => "boolean",
+            FieldType::Latitude => "latitude",
+            FieldType::Longitude => "longitude",
+        }
+    }
+
+    fn matches(&self, value: &str) -> bool {
+        let value = value.trim();
+        match self {
+            FieldType::String => !value.is_empty(),
+            FieldType::Integer => value.parse::<i64>().is_ok(),
+            FieldType::Number => value.parse::<f64>().is_ok_and(f64::is_finite),
+            FieldType::Boolean => {
+                matches!(value.to_lowercase().as_str(), "true" | "false" | "1" | "0")
+            }
+            FieldType::Latitude => parse_latitude(value).is_some(),
+            FieldType::Longitude => parse_longitude(value).is_some(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct FieldRule {
+    name: String,
+    field_type: FieldType,
+    required: bool,
+}
+
+/// Error de un campo del payload, p. ej. `GPS_EPOCH: expected integer, got 'abc'`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FieldError {
+    Missing(String),
+    WrongType {
+        field: String,
+        expected: FieldType,
+        value: String,
+    },
+}
+
+impl fmt::Display for FieldError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FieldError::Missing(field) => write!(f, "{}: missing required field", field),
+            FieldError::WrongType {
+                field,
+                expected,
+                value,
+            } => write!(
+                f,
+                "{}: expected {}, got '{}'",
+                field,
+                expected.as_str(),
+                value
+            ),
+        }
+    }
+}
+
+/// Validación ligera del mapa `data` (campos requeridos y tipos) antes de
+/// procesar (`ENABLE_SCHEMA_VALIDATION`). Cada entrada de `PAYLOAD_SCHEMA` es
+/// `CAMPO:tipo` (requerido) o `CAMPO?:tipo` (solo se valida si viene), con
+/// tipo `string`, `integer`, `number`, `boolean`, `latitude` o `longitude`.
+#[derive(Debug, Default)]
+pub struct PayloadSchema {
+    rules: Vec<FieldRule>,
+}
+
+impl PayloadSchema {
+    /// Carga las entradas del esquema; entradas inválidas se ignoran con un aviso
+    pub fn parse(entries: &[String]) -> Self {
+        let rules = entries
+            .iter()
+            .filter_map(|entry| {
+                let parsed = entry.split_once(':').and_then(|(field, field_type)| {
+                    let field = field.trim();
+                    let (name, required) = match field.strip_suffix('?') {
+                        Some(name) => (name.trim(), false),
+                        None => (field, true),
+                    };
+                    if name.is_empty() {
+                        return None;
+                    }
+                    Some(FieldRule {
+                        name: name.to_string(),
+                        field_type: FieldType::parse(field_type)?,
+                        required,
+                    })
+                });
+                if parsed.is_none() {
+                    warn!("Ignoring invalid PAYLOAD_SCHEMA entry '{}'", entry);
+                }
+                parsed
+            })
+            .collect();
+        Self { rules }
+    }
+
+    /// Todos los errores del payload (vacío si es válido)
+    pub fn validate(&self, data: &HashMap<String, String>) -> Vec<FieldError> {
+        self.rules
+            .iter()
+            .filter_map(|rule| match data.get(&rule.name) {
+                None if rule.required => Some(FieldError::Missing(rule.name.clone())),
+                None => None,
+                Some(value) if rule.field_type.matches(value) => None,
+                Some(value) => Some(FieldError::WrongType {
+                    field: rule.name.clone(),
+                    expected: rule.field_type,
+                    value: value.clone(),
+                }),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::DEFAULT_PAYLOAD_SCHEMA;
+
+    fn schema(entries: &[&str]) -> PayloadSchema {
+        PayloadSchema::parse(&entries.iter().map(|e| e.to_string()).collect::<Vec<_>>())
+    }
+
+    fn data(fields: &[(&str, &str)]) -> HashMap<String, String> {
+        fields
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_missing_required_field() {
+        let schema = schema(&["DEVICE_ID:string", "GPS_EPOCH:integer"]);
+
+        let errors = schema.validate(&data(&[("GPS_EPOCH", "1700000000")]));
+
+        assert_eq!(errors, vec![FieldError::Missing("DEVICE_ID".to_string())]);
+        assert_eq!(errors[0].to_string(), "DEVICE_ID: missing required field");
+    }
+
+    #[test]
+    fn test_wrong_types_are_reported_per_field() {
+        let schema = schema(&["GPS_EPOCH:integer", "SPEED?:number", "MOVING?:boolean"]);
+
+        let errors = schema.validate(&data(&[
+            ("GPS_EPOCH", "1700000000.5"),
+            ("SPEED", "fast"),
+            ("MOVING", "1"),
+        ]));
+
+        assert_eq!(
+            errors.iter().map(|e| e.to_string()).collect::<Vec<_>>(),
+            vec![
+                "GPS_EPOCH: expected integer, got '1700000000.5'",
+                "SPEED: expected number, got 'fast'",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_optional_fields_may_be_absent() {
+        let schema = PayloadSchema::parse(
+            &DEFAULT_PAYLOAD_SCHEMA
+                .split(',')
+                .map(str::to_string)
+                .collect::<Vec<_>>(),
+        );
+
+        assert!(schema.validate(&data(&[("DEVICE_ID", "DEV-1")])).is_empty());
+        assert!(schema
+            .validate(&data(&[
+                ("LATITUD", "+20.652494"),
+                ("GPS_EPOCH", "1700000000")
+            ]))
+            .is_empty());
+        assert_eq!(schema.validate(&data(&[("LATITUD", "NaN")])).len(), 1);
+        assert!(schema
+            .validate(&data(&[("LATITUD", "20.65N"), ("LONGITUD", "100.39W")]))
+            .is_empty());
+    }
+
+    #[test]
+    fn test_invalid_entries_are_ignored() {
+        let schema = schema(&["DEVICE_ID", ":string", "SPEED:float", "?:number"]);
+
+        assert!(schema.validate(&HashMap::new()).is_empty());
+    }
+}
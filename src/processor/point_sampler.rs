@@ -0,0 +1,76 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use uuid::Uuid;
+
+/// Guarda solo uno de cada `POINT_SAMPLE_EVERY_N` puntos de un viaje activo
+/// (0 o 1 = todos; puede variar por dispositivo). El contador vive en memoria,
+/// por dispositivo, y vuelve a empezar con cada viaje; cada dispositivo se
+/// procesa siempre en el mismo worker. Encendidos, apagados y alertas no pasan
+/// por aquí.
+#[derive(Default)]
+pub struct PointSampler {
+    seen: Mutex<HashMap<String, (Uuid, u64)>>,
+}
+
+impl PointSampler {
+    /// Cuenta el punto y dice si debe guardarse en `trip_points`: el N-ésimo,
+    /// 2N-ésimo, etc. de cada viaje
+    pub fn should_store(&self, device_id: &str, trip_id: Uuid, every_n: u64) -> bool {
+        if every_n <= 1 {
+            return true;
+        }
+        let mut seen = self.seen.lock().unwrap();
+        let entry = seen.entry(device_id.to_string()).or_insert((trip_id, 0));
+        if entry.0 != trip_id {
+            *entry = (trip_id, 0);
+        }
+        entry.1 += 1;
+        entry.1.is_multiple_of(every_n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_every_third_point_is_stored() {
+        let sampler = PointSampler::default();
+        let trip = Uuid::new_v4();
+
+        let stored: Vec<bool> = (0..7)
+            .map(|_| sampler.should_store("DEV-1", trip, 3))
+            .collect();
+
+        assert_eq!(stored, vec![false, false, true, false, false, true, false]);
+    }
+
+    #[test]
+    fn test_counter_restarts_with_each_trip() {
+        let sampler = PointSampler::default();
+        let first = Uuid::new_v4();
+        assert!(!sampler.should_store("DEV-1", first, 2));
+
+        let second = Uuid::new_v4();
+        assert!(!sampler.should_store("DEV-1", second, 2));
+        assert!(sampler.should_store("DEV-1", second, 2));
+    }
+
+    #[test]
+    fn test_devices_are_counted_separately() {
+        let sampler = PointSampler::default();
+        let trip = Uuid::new_v4();
+
+        assert!(!sampler.should_store("DEV-1", trip, 2));
+        assert!(!sampler.should_store("DEV-2", Uuid::new_v4(), 2));
+        assert!(sampler.should_store("DEV-1", trip, 2));
+    }
+
+    #[test]
+    fn test_disabled_stores_every_point() {
+        for every_n in [0, 1] {
+            let sampler = PointSampler::default();
+            assert!((0..5).all(|_| sampler.should_store("DEV-1", Uuid::nil(), every_n)));
+        }
+    }
+}
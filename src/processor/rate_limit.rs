@@ -0,0 +1,103 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Instant;
+
+/// Token bucket por dispositivo: `rate` mensajes por segundo con ráfagas de
+/// hasta `burst`. Un `rate` de 0 deshabilita el límite.
+pub struct DeviceRateLimiter {
+    rate: f64,
+    burst: f64,
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+struct Bucket {
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl DeviceRateLimiter {
+    /// Sin `burst` (0) la ráfaga es de un segundo de tasa, mínimo un mensaje
+    pub fn new(rate: f64, burst: f64) -> Self {
+        let burst = if burst > 0.0 { burst } else { rate.max(1.0) };
+        Self {
+            rate: rate.max(0.0),
+            burst,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.rate > 0.0
+    }
+
+    /// Consume un token del dispositivo; false si el bucket está vacío
+    pub fn allow(&self, device_id: &str, now: Instant) -> bool {
+        if !self.is_enabled() {
+            return true;
+        }
+        let mut buckets = self.buckets.lock().unwrap();
+        let bucket = buckets.entry(device_id.to_string()).or_insert(Bucket {
+            tokens: self.burst,
+            refilled_at: now,
+        });
+
+        let elapsed = now.saturating_duration_since(bucket.refilled_at);
+        bucket.tokens = (bucket.tokens + elapsed.as_secs_f64() * self.rate).min(self.burst);
+        bucket.refilled_at = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_burst_then_refill() {
+        let limiter = DeviceRateLimiter::new(2.0, 3.0);
+        let start = Instant::now();
+
+        for _ in 0..3 {
+            assert!(limiter.allow("DEV-1", start));
+        }
+        assert!(!limiter.allow("DEV-1", start));
+
+        // 2 mensajes/s: medio segundo repone un token
+        assert!(limiter.allow("DEV-1", start + Duration::from_millis(500)));
+        assert!(!limiter.allow("DEV-1", start + Duration::from_millis(500)));
+
+        // El bucket nunca supera la ráfaga
+        let later = start + Duration::from_secs(60);
+        for _ in 0..3 {
+            assert!(limiter.allow("DEV-1", later));
+        }
+        assert!(!limiter.allow("DEV-1", later));
+    }
+
+    #[test]
+    fn test_devices_have_independent_buckets() {
+        let limiter = DeviceRateLimiter::new(1.0, 1.0);
+        let now = Instant::now();
+
+        assert!(limiter.allow("DEV-1", now));
+        assert!(!limiter.allow("DEV-1", now));
+        assert!(limiter.allow("DEV-2", now));
+    }
+
+    #[test]
+    fn test_disabled_by_default() {
+        let limiter = DeviceRateLimiter::new(0.0, 0.0);
+        let now = Instant::now();
+
+        for _ in 0..1000 {
+            assert!(limiter.allow("DEV-1", now));
+        }
+    }
+}
@@ -0,0 +1,88 @@
+/// Contador de mensajes del equipo (`MSG_COUNTER`/`MSG_NUM`), en hexadecimal.
+/// El módulo depende del número de dígitos: "06C5" da la vuelta en 0x10000.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MsgCounter {
+    pub value: u32,
+    pub modulus: u64,
+}
+
+impl MsgCounter {
+    pub fn parse(value: &str) -> Option<Self> {
+        let value = value.trim();
+        if value.is_empty() || value.len() > 8 {
+            return None;
+        }
+        Some(Self {
+            value: u32::from_str_radix(value, 16).ok()?,
+            modulus: 1u64 << (4 * value.len()),
+        })
+    }
+
+    /// Indica si este contador es posterior a `last`, considerando la vuelta
+    /// del contador (aritmética de números de serie, RFC 1982)
+    pub fn is_after(&self, last: u32) -> bool {
+        let last = u64::from(last) % self.modulus;
+        let diff = (u64::from(self.value) + self.modulus - last) % self.modulus;
+        diff != 0 && diff < self.modulus / 2
+    }
+}
+
+/// Un punto con contador igual o anterior al último visto es un duplicado o
+/// llegó fuera de orden. Sin contador (en el mensaje o en el estado) no se descarta.
+pub fn is_duplicate_counter(last: Option<i64>, current: Option<MsgCounter>) -> bool {
+    match (last, current) {
+        (Some(last), Some(current)) => !current.is_after(last as u32),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_hex_counter() {
+        assert_eq!(
+            MsgCounter::parse("06C5"),
+            Some(MsgCounter {
+                value: 0x06C5,
+                modulus: 0x10000
+            })
+        );
+        assert_eq!(MsgCounter::parse(" ff ").map(|c| c.value), Some(255));
+        assert_eq!(MsgCounter::parse(""), None);
+        assert_eq!(MsgCounter::parse("XYZ"), None);
+    }
+
+    #[test]
+    fn test_duplicate_and_out_of_order_are_detected() {
+        let last = Some(0x06C5);
+
+        assert!(is_duplicate_counter(last, MsgCounter::parse("06C5")));
+        assert!(is_duplicate_counter(last, MsgCounter::parse("06C4")));
+        assert!(!is_duplicate_counter(last, MsgCounter::parse("06C6")));
+    }
+
+    #[test]
+    fn test_wraparound_is_not_a_duplicate() {
+        assert!(!is_duplicate_counter(
+            Some(0xFFFF),
+            MsgCounter::parse("0000")
+        ));
+        assert!(!is_duplicate_counter(
+            Some(0xFFFE),
+            MsgCounter::parse("0003")
+        ));
+        // Justo antes de la vuelta sigue siendo anterior
+        assert!(is_duplicate_counter(
+            Some(0x0002),
+            MsgCounter::parse("FFFF")
+        ));
+    }
+
+    #[test]
+    fn test_missing_counter_is_never_duplicate() {
+        assert!(!is_duplicate_counter(None, MsgCounter::parse("0001")));
+        assert!(!is_duplicate_counter(Some(5), None));
+    }
+}
@@ -0,0 +1,312 @@
+use crate::processor::extract::DeliveryType;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use sqlx::postgres::PgRow;
+use sqlx::Row;
+use tracing::warn;
+use uuid::Uuid;
+
+/// Fila de `trip_current_state` leída al inicio de cada mensaje
+#[derive(Debug, Clone, Default)]
+pub struct DeviceState {
+    pub current_trip_id: Option<Uuid>,
+    pub ignition_on: bool,
+    pub last_point_at: Option<NaiveDateTime>,
+    pub last_lat: Option<f64>,
+    pub last_lng: Option<f64>,
+    pub last_speed: Option<f64>,
+    pub last_odometer_meters: Option<i32>,
+    pub last_msg_counter: Option<i64>,
+    pub last_idle_seconds: Option<f64>,
+    /// Metros recorridos por el viaje abierto sobre todos los puntos recibidos
+    pub current_trip_route_meters: f64,
+    /// Última posición sumada a `current_trip_route_meters`
+    pub current_trip_route_position: Option<(f64, f64)>,
+    /// Desde cuándo el viaje abierto está marcado como inactivo (STALE_TRIP_SECONDS)
+    pub stale_since: Option<NaiveDateTime>,
+    /// DRIVER_ID/IBUTTON del viaje abierto
+    pub current_driver_id: Option<String>,
+}
+
+impl DeviceState {
+    pub fn from_row(row: &PgRow) -> Self {
+        Self {
+            current_trip_id: row.try_get("current_trip_id").ok(),
+            ignition_on: row
+                .try_get::<Option<bool>, _>("ignition_on")
+                .ok()
+                .flatten()
+                .unwrap_or(false),
+            last_point_at: row
+                .try_get::<Option<DateTime<Utc>>, _>("last_point_at")
+                .ok()
+                .flatten()
+                .map(|t| t.naive_utc()),
+            last_lat: row.try_get("last_lat").ok().flatten(),
+            last_lng: row.try_get("last_lng").ok().flatten(),
+            last_speed: row.try_get("last_speed").ok().flatten(),
+            last_odometer_meters: row.try_get("last_odometer_meters").ok().flatten(),
+            last_msg_counter: row.try_get("last_msg_counter").ok().flatten(),
+            last_idle_seconds: row.try_get("last_idle_seconds").ok().flatten(),
+            current_trip_route_meters: row
+                .try_get::<Option<f64>, _>("current_trip_route_meters")
+                .ok()
+                .flatten()
+                .unwrap_or(0.0),
+            current_trip_route_position: row
+                .try_get::<Option<f64>, _>("current_trip_route_lat")
+                .ok()
+                .flatten()
+                .zip(
+                    row.try_get::<Option<f64>, _>("current_trip_route_lng")
+                        .ok()
+                        .flatten(),
+                ),
+            stale_since: row
+                .try_get::<Option<DateTime<Utc>>, _>("stale_since")
+                .ok()
+                .flatten()
+                .map(|t| t.naive_utc()),
+            current_driver_id: row.try_get("current_driver_id").ok().flatten(),
+        }
+    }
+}
+
+/// Decide si un punto debe escribirse en `trip_current_state`.
+///
+/// Con `min_interval_seconds = 0` siempre se escribe. En otro caso solo se
+/// escribe si el dispositivo se movió o si `last_point_at` avanza más que el
+/// intervalo; por eso `last_point_at` puede quedar atrasado hasta ese intervalo
+/// para dispositivos detenidos.
+pub fn should_write_state(
+    min_interval_seconds: u64,
+    previous: &DeviceState,
+    timestamp: NaiveDateTime,
+    lat: f64,
+    lon: f64,
+) -> bool {
+    if min_interval_seconds == 0 {
+        return true;
+    }
+    let Some(last_point_at) = previous.last_point_at else {
+        return true;
+    };
+    let moved = previous.last_lat != Some(lat) || previous.last_lng != Some(lon);
+    moved || (timestamp - last_point_at).num_seconds() > min_interval_seconds as i64
+}
+
+/// Un reporte almacenado (store-and-forward) más viejo que `last_point_at`
+/// no debe reemplazar la última posición conocida.
+pub fn is_late_stored(
+    delivery_type: DeliveryType,
+    previous: &DeviceState,
+    timestamp: NaiveDateTime,
+) -> bool {
+    delivery_type == DeliveryType::Stored
+        && previous
+            .last_point_at
+            .is_some_and(|last_point_at| timestamp < last_point_at)
+}
+
+/// true si entre el último punto y `timestamp` pasaron más de
+/// `max_gap_seconds` (0 = sin límite): el equipo dejó de reportar y el viaje
+/// probablemente abarca dos trayectos (MAX_POINT_GAP_SECONDS)
+pub fn is_point_gap(
+    previous: &DeviceState,
+    timestamp: NaiveDateTime,
+    max_gap_seconds: u64,
+) -> bool {
+    max_gap_seconds > 0
+        && previous.last_point_at.is_some_and(|last_point_at| {
+            (timestamp - last_point_at).num_seconds() > max_gap_seconds as i64
+        })
+}
+
+/// true si el mensaje identifica a un conductor distinto del del viaje
+/// abierto (SPLIT_TRIP_ON_DRIVER_CHANGE). Sin conductor en alguno de los dos
+/// no hay cambio: el primero que se identifica se asigna al viaje.
+pub fn is_driver_change(current: Option<&str>, incoming: Option<&str>) -> bool {
+    match (current, incoming) {
+        (Some(current), Some(incoming)) => !current.eq_ignore_ascii_case(incoming),
+        _ => false,
+    }
+}
+
+/// Mensaje anterior a `REPLAY_CUTOFF` (backfill histórico): se guarda en el
+/// historial pero no debe modificar `trip_current_state`.
+pub fn is_before_replay_cutoff(cutoff: Option<NaiveDateTime>, timestamp: NaiveDateTime) -> bool {
+    cutoff.is_some_and(|cutoff| timestamp < cutoff)
+}
+
+/// Origen de `trip_current_state.last_updated_at` (`LAST_UPDATED_AT_SOURCE`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LastUpdatedAtSource {
+    /// Hora del servidor al procesar el mensaje
+    #[default]
+    Server,
+    /// Hora GPS del mensaje, igual que `last_point_at`; evita órdenes confusos
+    /// entre ambas columnas en equipos con el reloj desfasado
+    Gps,
+}
+
+impl LastUpdatedAtSource {
+    pub fn parse(value: &str) -> Self {
+        match value.trim().to_lowercase().as_str() {
+            "" | "server" | "now" => LastUpdatedAtSource::Server,
+            "gps" => LastUpdatedAtSource::Gps,
+            other => {
+                warn!("Unknown LAST_UPDATED_AT_SOURCE '{}', using server", other);
+                LastUpdatedAtSource::Server
+            }
+        }
+    }
+
+    /// Valor a guardar en `last_updated_at` para un mensaje con hora GPS `gps_time`
+    pub fn resolve(&self, now: DateTime<Utc>, gps_time: NaiveDateTime) -> DateTime<Utc> {
+        match self {
+            LastUpdatedAtSource::Server => now,
+            LastUpdatedAtSource::Gps => gps_time.and_utc(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    fn state_at(timestamp: NaiveDateTime) -> DeviceState {
+        DeviceState {
+            last_point_at: Some(timestamp),
+            last_lat: Some(20.0),
+            last_lng: Some(-100.0),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_always_writes_when_disabled() {
+        let now = Utc::now().naive_utc();
+        assert!(should_write_state(0, &state_at(now), now, 20.0, -100.0));
+    }
+
+    #[test]
+    fn test_suppresses_within_interval() {
+        let now = Utc::now().naive_utc();
+        let state = state_at(now);
+
+        assert!(!should_write_state(60, &state, now, 20.0, -100.0));
+        assert!(!should_write_state(
+            60,
+            &state,
+            now + Duration::seconds(60),
+            20.0,
+            -100.0
+        ));
+    }
+
+    #[test]
+    fn test_writes_after_interval() {
+        let now = Utc::now().naive_utc();
+        assert!(should_write_state(
+            60,
+            &state_at(now),
+            now + Duration::seconds(61),
+            20.0,
+            -100.0
+        ));
+    }
+
+    #[test]
+    fn test_writes_when_device_moved() {
+        let now = Utc::now().naive_utc();
+        assert!(should_write_state(60, &state_at(now), now, 20.001, -100.0));
+    }
+
+    #[test]
+    fn test_late_stored_message_is_detected() {
+        let now = Utc::now().naive_utc();
+        let state = state_at(now);
+        let earlier = now - Duration::hours(3);
+
+        assert!(is_late_stored(DeliveryType::Stored, &state, earlier));
+        assert!(!is_late_stored(DeliveryType::RealTime, &state, earlier));
+        assert!(!is_late_stored(
+            DeliveryType::Stored,
+            &state,
+            now + Duration::seconds(1)
+        ));
+        assert!(!is_late_stored(
+            DeliveryType::Stored,
+            &DeviceState::default(),
+            earlier
+        ));
+    }
+
+    #[test]
+    fn test_point_gap() {
+        let now = Utc::now().naive_utc();
+        let state = state_at(now);
+
+        assert!(is_point_gap(&state, now + Duration::seconds(3601), 3600));
+        assert!(!is_point_gap(&state, now + Duration::seconds(3600), 3600));
+        assert!(!is_point_gap(&state, now - Duration::hours(5), 3600));
+        assert!(!is_point_gap(&state, now + Duration::days(1), 0));
+        assert!(!is_point_gap(
+            &DeviceState::default(),
+            now + Duration::days(1),
+            3600
+        ));
+    }
+
+    #[test]
+    fn test_driver_change() {
+        assert!(is_driver_change(Some("01A2B3C4"), Some("05F6E7D8")));
+        assert!(!is_driver_change(Some("01A2B3C4"), Some("01a2b3c4")));
+        assert!(!is_driver_change(None, Some("01A2B3C4")));
+        assert!(!is_driver_change(Some("01A2B3C4"), None));
+    }
+
+    #[test]
+    fn test_replay_cutoff() {
+        let cutoff = Utc::now().naive_utc();
+
+        assert!(is_before_replay_cutoff(
+            Some(cutoff),
+            cutoff - Duration::seconds(1)
+        ));
+        assert!(!is_before_replay_cutoff(Some(cutoff), cutoff));
+        assert!(!is_before_replay_cutoff(None, cutoff - Duration::days(1)));
+    }
+
+    #[test]
+    fn test_writes_without_previous_point() {
+        let now = Utc::now().naive_utc();
+        assert!(should_write_state(
+            60,
+            &DeviceState::default(),
+            now,
+            20.0,
+            -100.0
+        ));
+    }
+
+    #[test]
+    fn test_last_updated_at_under_each_source() {
+        let now = Utc::now();
+        let gps_time = (now - Duration::minutes(7)).naive_utc();
+
+        assert_eq!(
+            LastUpdatedAtSource::parse("server").resolve(now, gps_time),
+            now
+        );
+        assert_eq!(LastUpdatedAtSource::parse("").resolve(now, gps_time), now);
+        assert_eq!(
+            LastUpdatedAtSource::parse(" GPS ").resolve(now, gps_time),
+            gps_time.and_utc()
+        );
+        assert_eq!(
+            LastUpdatedAtSource::parse("device"),
+            LastUpdatedAtSource::Server
+        );
+    }
+}
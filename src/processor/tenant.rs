@@ -0,0 +1,97 @@
+use std::collections::HashMap;
+use tracing::warn;
+
+/// Cuenta (tenant) dueña de cada equipo, para aislar los datos en despliegues
+/// multi-cliente. `TENANT_MAP` (`device_id:tenant`) tiene prioridad sobre el
+/// TENANT/ACCOUNT_ID del mensaje (o de sus cabeceras Kafka); sin ninguno se usa
+/// `TENANT_DEFAULT`, y sin él los registros quedan sin tenant.
+#[derive(Debug, Default)]
+pub struct TenantResolver {
+    default: Option<String>,
+    per_device: HashMap<String, String>,
+}
+
+impl TenantResolver {
+    /// Carga el tenant por omisión y el mapa por equipo; entradas inválidas se
+    /// ignoran con un aviso
+    pub fn parse(default: &str, entries: &[String]) -> Self {
+        let default = Some(default.trim())
+            .filter(|tenant| !tenant.is_empty())
+            .map(str::to_string);
+        let per_device = entries
+            .iter()
+            .filter_map(|entry| {
+                let parsed = entry
+                    .split_once(':')
+                    .map(|(device_id, tenant)| (device_id.trim(), tenant.trim()))
+                    .filter(|(device_id, tenant)| !device_id.is_empty() && !tenant.is_empty());
+                if parsed.is_none() {
+                    warn!("Ignoring invalid tenant mapping '{}'", entry);
+                }
+                parsed.map(|(device_id, tenant)| (device_id.to_string(), tenant.to_string()))
+            })
+            .collect();
+        Self {
+            default,
+            per_device,
+        }
+    }
+
+    /// Tenant del equipo: el mapa, luego el declarado en el mensaje, luego el
+    /// tenant por omisión
+    pub fn resolve(&self, device_id: &str, from_message: Option<&str>) -> Option<String> {
+        self.per_device
+            .get(device_id)
+            .map(String::as_str)
+            .or(from_message)
+            .or(self.default.as_deref())
+            .map(str::to_string)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mapping_wins_over_message_and_default() {
+        let tenants = TenantResolver::parse("shared", &["DEV-1:acme".to_string()]);
+
+        assert_eq!(
+            tenants.resolve("DEV-1", Some("other")),
+            Some("acme".to_string())
+        );
+        assert_eq!(
+            tenants.resolve("DEV-2", Some("other")),
+            Some("other".to_string())
+        );
+        assert_eq!(tenants.resolve("DEV-2", None), Some("shared".to_string()));
+    }
+
+    #[test]
+    fn test_without_configuration_there_is_no_tenant() {
+        let tenants = TenantResolver::parse("", &[]);
+
+        assert_eq!(tenants.resolve("DEV-1", None), None);
+        assert_eq!(
+            tenants.resolve("DEV-1", Some("acme")),
+            Some("acme".to_string())
+        );
+    }
+
+    #[test]
+    fn test_invalid_entries_are_ignored() {
+        let tenants = TenantResolver::parse(
+            "  ",
+            &[
+                "no-separator".to_string(),
+                ":acme".to_string(),
+                "DEV-1:".to_string(),
+                " DEV-2 : beta ".to_string(),
+            ],
+        );
+
+        assert_eq!(tenants.resolve("DEV-1", None), None);
+        assert_eq!(tenants.resolve("DEV-2", None), Some("beta".to_string()));
+    }
+}
@@ -0,0 +1,144 @@
+use chrono::{LocalResult, NaiveDateTime, Offset, TimeZone};
+use chrono_tz::Tz;
+use std::collections::HashMap;
+use tracing::warn;
+
+/// Zona horaria del reloj de cada equipo, para equipos que reportan GPS_EPOCH
+/// en hora local en vez de UTC. `DEVICE_TIMEZONES` (`device_id:Zona/IANA`)
+/// tiene prioridad; sin entrada se usa `DEVICE_TIMEZONE`, y sin ninguna de las
+/// dos el timestamp ya es UTC.
+#[derive(Debug, Default)]
+pub struct DeviceTimezones {
+    default: Option<Tz>,
+    per_device: HashMap<String, Tz>,
+}
+
+impl DeviceTimezones {
+    /// Carga la zona global y el mapa por equipo; zonas o entradas inválidas
+    /// se ignoran con un aviso
+    pub fn parse(default: &str, entries: &[String]) -> Self {
+        let default = match default.trim() {
+            "" => None,
+            name => parse_tz(name),
+        };
+        let per_device = entries
+            .iter()
+            .filter_map(|entry| {
+                let parsed = entry
+                    .split_once(':')
+                    .and_then(|(device_id, name)| Some((device_id.trim(), parse_tz(name)?)))
+                    .filter(|(device_id, _)| !device_id.is_empty());
+                if parsed.is_none() {
+                    warn!("Ignoring invalid device timezone '{}'", entry);
+                }
+                parsed.map(|(device_id, tz)| (device_id.to_string(), tz))
+            })
+            .collect();
+        Self {
+            default,
+            per_device,
+        }
+    }
+
+    /// Zona del equipo, si tiene una configurada
+    pub fn for_device(&self, device_id: &str) -> Option<Tz> {
+        self.per_device.get(device_id).copied().or(self.default)
+    }
+
+    /// Convierte la hora local del equipo a UTC. En el cambio de horario, una
+    /// hora repetida toma la primera ocurrencia y una hora inexistente se
+    /// convierte con el desfase vigente en ese instante.
+    pub fn to_utc(&self, device_id: &str, local: NaiveDateTime) -> NaiveDateTime {
+        let Some(tz) = self.for_device(device_id) else {
+            return local;
+        };
+        match tz.from_local_datetime(&local) {
+            LocalResult::Single(t) | LocalResult::Ambiguous(t, _) => t.naive_utc(),
+            LocalResult::None => {
+                let offset = tz.offset_from_utc_datetime(&local).fix().local_minus_utc();
+                local - chrono::Duration::seconds(i64::from(offset))
+            }
+        }
+    }
+}
+
+fn parse_tz(name: &str) -> Option<Tz> {
+    let tz = name.trim().parse::<Tz>().ok();
+    if tz.is_none() {
+        warn!("Unknown timezone '{}'", name.trim());
+    }
+    tz
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    fn local(hour: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2024, 1, 15)
+            .unwrap()
+            .and_hms_opt(hour, 0, 0)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_devices_in_different_zones() {
+        let timezones = DeviceTimezones::parse(
+            "",
+            &[
+                "CDMX-1:America/Mexico_City".to_string(),
+                "TIJ-1:America/Tijuana".to_string(),
+            ],
+        );
+
+        // 08:00 local: UTC-6 en Ciudad de México, UTC-8 en Tijuana (invierno)
+        assert_eq!(timezones.to_utc("CDMX-1", local(8)), local(14));
+        assert_eq!(timezones.to_utc("TIJ-1", local(8)), local(16));
+    }
+
+    #[test]
+    fn test_unmapped_device_uses_global_zone() {
+        let timezones = DeviceTimezones::parse(
+            "America/Mexico_City",
+            &["TIJ-1:America/Tijuana".to_string()],
+        );
+
+        assert_eq!(timezones.to_utc("OTHER", local(8)), local(14));
+        assert_eq!(timezones.to_utc("TIJ-1", local(8)), local(16));
+    }
+
+    #[test]
+    fn test_without_zone_timestamp_is_already_utc() {
+        let timezones = DeviceTimezones::parse("", &[]);
+        assert_eq!(timezones.to_utc("ANY", local(8)), local(8));
+    }
+
+    #[test]
+    fn test_invalid_entries_are_ignored() {
+        let timezones = DeviceTimezones::parse(
+            "Mars/Olympus",
+            &[
+                "DEV-1:Not/AZone".to_string(),
+                "no-separator".to_string(),
+                ":America/Tijuana".to_string(),
+            ],
+        );
+        assert_eq!(timezones.for_device("DEV-1"), None);
+        assert_eq!(timezones.for_device(""), None);
+    }
+
+    #[test]
+    fn test_nonexistent_local_time_in_dst_gap() {
+        let timezones = DeviceTimezones::parse("America/Tijuana", &[]);
+        // 2024-03-10 02:30 no existe en Tijuana (salta de 02:00 a 03:00)
+        let gap = NaiveDate::from_ymd_opt(2024, 3, 10)
+            .unwrap()
+            .and_hms_opt(2, 30, 0)
+            .unwrap();
+        assert_eq!(
+            timezones.to_utc("DEV-1", gap),
+            gap + chrono::Duration::hours(8)
+        );
+    }
+}
@@ -0,0 +1,64 @@
+use crate::processor::geo::haversine_meters;
+
+/// `activity_type` en `device_idle_activity` para un punto con movimiento sin
+/// viaje activo (p. ej. vehículo remolcado)
+pub const MOVEMENT_WHILE_OFF: &str = "movement_while_off";
+
+/// Indica si el equipo se desplazó más de `threshold_meters` desde la última
+/// posición conocida sin tener un viaje activo. Sin alguna de las dos
+/// posiciones no hay movimiento que medir. Umbral 0 = deshabilitado.
+pub fn is_moving_while_off(
+    previous: Option<(f64, f64)>,
+    current: Option<(f64, f64)>,
+    threshold_meters: f64,
+) -> bool {
+    if threshold_meters <= 0.0 {
+        return false;
+    }
+    match (previous, current) {
+        (Some((prev_lat, prev_lon)), Some((lat, lon))) => {
+            haversine_meters(prev_lat, prev_lon, lat, lon) > threshold_meters
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_movement_over_threshold_is_detected() {
+        // ~111 m hacia el norte
+        assert!(is_moving_while_off(
+            Some((20.650, -100.390)),
+            Some((20.651, -100.390)),
+            100.0
+        ));
+    }
+
+    #[test]
+    fn test_gps_drift_is_ignored() {
+        // ~11 m de deriva con el vehículo estacionado
+        assert!(!is_moving_while_off(
+            Some((20.6500, -100.390)),
+            Some((20.6501, -100.390)),
+            100.0
+        ));
+    }
+
+    #[test]
+    fn test_missing_position_is_not_movement() {
+        assert!(!is_moving_while_off(None, Some((20.651, -100.390)), 100.0));
+        assert!(!is_moving_while_off(Some((20.650, -100.390)), None, 100.0));
+    }
+
+    #[test]
+    fn test_disabled_with_zero_threshold() {
+        assert!(!is_moving_while_off(
+            Some((20.650, -100.390)),
+            Some((21.650, -100.390)),
+            0.0
+        ));
+    }
+}
@@ -0,0 +1,105 @@
+use chrono::NaiveDateTime;
+use tracing::warn;
+use uuid::Uuid;
+
+/// Namespace para los trip_id deterministas (uuid v5)
+const TRIP_ID_NAMESPACE: Uuid = Uuid::from_u128(0x6f1c_2a8e_4b7d_5e93_a1c0_8d2f_3b4e_5a61);
+
+/// Cómo se genera el trip_id al abrir un viaje (`TRIP_ID_STRATEGY`).
+///
+/// - `message_uuid`: el uuid del mensaje de encendido. Un reenvío del mismo
+///   mensaje produce el mismo trip_id.
+/// - `random`: uuid v4. Cada reenvío abre un viaje distinto, así que la
+///   deduplicación debe hacerse aguas arriba.
+/// - `deterministic`: uuid v5 de device_id + hora de inicio. Es estable aunque
+///   el mensaje se reenvíe con otro uuid.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TripIdStrategy {
+    #[default]
+    MessageUuid,
+    Random,
+    Deterministic,
+}
+
+impl TripIdStrategy {
+    pub fn parse(value: &str) -> Self {
+        match value.trim().to_lowercase().as_str() {
+            "" | "message_uuid" => TripIdStrategy::MessageUuid,
+            "random" => TripIdStrategy::Random,
+            "deterministic" => TripIdStrategy::Deterministic,
+            other => {
+                warn!("Unknown TRIP_ID_STRATEGY '{}', using message_uuid", other);
+                TripIdStrategy::MessageUuid
+            }
+        }
+    }
+
+    pub fn trip_id(&self, message_uuid: Uuid, device_id: &str, start_time: NaiveDateTime) -> Uuid {
+        match self {
+            TripIdStrategy::MessageUuid => message_uuid,
+            TripIdStrategy::Random => Uuid::new_v4(),
+            TripIdStrategy::Deterministic => {
+                let name = format!("{}:{}", device_id, start_time.and_utc().timestamp());
+                Uuid::new_v5(&TRIP_ID_NAMESPACE, name.as_bytes())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{TimeZone, Utc};
+
+    fn start() -> NaiveDateTime {
+        Utc.timestamp_opt(1_700_000_000, 0)
+            .single()
+            .unwrap()
+            .naive_utc()
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            TripIdStrategy::parse("message_uuid"),
+            TripIdStrategy::MessageUuid
+        );
+        assert_eq!(TripIdStrategy::parse("RANDOM"), TripIdStrategy::Random);
+        assert_eq!(
+            TripIdStrategy::parse("deterministic"),
+            TripIdStrategy::Deterministic
+        );
+        assert_eq!(TripIdStrategy::parse("other"), TripIdStrategy::MessageUuid);
+    }
+
+    #[test]
+    fn test_message_uuid_strategy() {
+        let message_uuid = Uuid::new_v4();
+        assert_eq!(
+            TripIdStrategy::MessageUuid.trip_id(message_uuid, "DEV-1", start()),
+            message_uuid
+        );
+    }
+
+    #[test]
+    fn test_random_strategy() {
+        let message_uuid = Uuid::new_v4();
+        let a = TripIdStrategy::Random.trip_id(message_uuid, "DEV-1", start());
+        let b = TripIdStrategy::Random.trip_id(message_uuid, "DEV-1", start());
+
+        assert_ne!(a, message_uuid);
+        assert_ne!(a, b);
+        assert_eq!(a.get_version_num(), 4);
+    }
+
+    #[test]
+    fn test_deterministic_strategy() {
+        let a = TripIdStrategy::Deterministic.trip_id(Uuid::new_v4(), "DEV-1", start());
+        let b = TripIdStrategy::Deterministic.trip_id(Uuid::new_v4(), "DEV-1", start());
+        let other_device = TripIdStrategy::Deterministic.trip_id(Uuid::new_v4(), "DEV-2", start());
+
+        assert_eq!(a, b);
+        assert_ne!(a, other_device);
+        assert_eq!(a.get_version_num(), 5);
+    }
+}
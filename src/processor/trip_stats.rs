@@ -0,0 +1,81 @@
+/// Percentiles de velocidad de los puntos de un viaje, calculados al cerrarlo
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct SpeedPercentiles {
+    pub p50: Option<f64>,
+    pub p85: Option<f64>,
+    pub p95: Option<f64>,
+}
+
+/// Calcula p50/p85/p95 con interpolación lineal (igual que `percentile_cont`).
+/// p85 y p95 requieren al menos 7 y 20 puntos (`1 / (1 - p)`): con menos, el
+/// valor sería prácticamente el máximo y quedan en None.
+pub fn speed_percentiles(speeds: &[f64]) -> SpeedPercentiles {
+    let mut sorted: Vec<f64> = speeds.iter().copied().filter(|s| s.is_finite()).collect();
+    sorted.sort_by(f64::total_cmp);
+
+    SpeedPercentiles {
+        p50: percentile(&sorted, 0.50, 1),
+        p85: percentile(&sorted, 0.85, 7),
+        p95: percentile(&sorted, 0.95, 20),
+    }
+}
+
+fn percentile(sorted: &[f64], p: f64, min_points: usize) -> Option<f64> {
+    if sorted.is_empty() || sorted.len() < min_points {
+        return None;
+    }
+    let rank = p * (sorted.len() - 1) as f64;
+    let lower = rank.floor() as usize;
+    let upper = rank.ceil() as usize;
+    Some(sorted[lower] + (sorted[upper] - sorted[lower]) * (rank - lower as f64))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: Option<f64>, expected: f64) {
+        let actual = actual.expect("percentile missing");
+        assert!(
+            (actual - expected).abs() < 1e-9,
+            "expected {}, got {}",
+            expected,
+            actual
+        );
+    }
+
+    #[test]
+    fn test_known_dataset() {
+        // 1..=20 km/h desordenados; percentile_cont da 10.5, 17.15 y 19.05
+        let speeds: Vec<f64> = (1..=20).rev().map(f64::from).collect();
+        let percentiles = speed_percentiles(&speeds);
+
+        assert_close(percentiles.p50, 10.5);
+        assert_close(percentiles.p85, 17.15);
+        assert_close(percentiles.p95, 19.05);
+    }
+
+    #[test]
+    fn test_few_points_null_higher_percentiles() {
+        let percentiles = speed_percentiles(&[30.0, 50.0, 40.0, 60.0, 20.0]);
+
+        assert_close(percentiles.p50, 40.0);
+        assert_eq!(percentiles.p85, None);
+        assert_eq!(percentiles.p95, None);
+    }
+
+    #[test]
+    fn test_empty_and_single_point() {
+        assert_eq!(speed_percentiles(&[]), SpeedPercentiles::default());
+
+        let percentiles = speed_percentiles(&[42.0]);
+        assert_close(percentiles.p50, 42.0);
+        assert_eq!(percentiles.p85, None);
+    }
+
+    #[test]
+    fn test_non_finite_speeds_are_ignored() {
+        let percentiles = speed_percentiles(&[10.0, f64::NAN, 20.0, f64::INFINITY]);
+        assert_close(percentiles.p50, 15.0);
+    }
+}
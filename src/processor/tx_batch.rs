@@ -0,0 +1,198 @@
+use crate::config::AppConfig;
+use crate::db::sharding::shard_index;
+use crate::processor::message_processor::{MessageDestination, TripEvent};
+use sqlx::{Postgres, Transaction};
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+
+/// Lote abierto: puntos consecutivos de un mismo dispositivo en una sola
+/// transacción, junto con los eventos que se publicarán al confirmarla
+pub struct OpenBatch {
+    pub device_id: String,
+    pub tx: Transaction<'static, Postgres>,
+    pub messages: usize,
+    pub opened_at: Instant,
+    pub events: Vec<TripEvent>,
+}
+
+impl OpenBatch {
+    pub async fn begin(
+        pool: &sqlx::Pool<Postgres>,
+        device_id: &str,
+        now: Instant,
+    ) -> anyhow::Result<Self> {
+        Ok(Self {
+            device_id: device_id.to_string(),
+            tx: pool.begin().await?,
+            messages: 0,
+            opened_at: now,
+            events: Vec::new(),
+        })
+    }
+
+    /// Confirma el lote y devuelve sus eventos pendientes
+    pub async fn commit(self) -> anyhow::Result<Vec<TripEvent>> {
+        self.tx.commit().await?;
+        Ok(self.events)
+    }
+}
+
+/// Agrupa en una transacción los puntos consecutivos de un dispositivo
+/// (`TX_BATCH_SIZE` > 1). Hay un lote por worker: el dispositivo elige el
+/// mismo índice que usa `WorkerPool::dispatch`, así que cada lote lo usa un
+/// solo worker y como mucho hay `WORKER_COUNT` conexiones retenidas.
+pub struct TxBatcher {
+    batch_size: usize,
+    max_age: Duration,
+    slots: Vec<Mutex<Option<OpenBatch>>>,
+}
+
+impl TxBatcher {
+    /// None si el modo está deshabilitado (tamaño 0/1) o en dry run, donde
+    /// cada mensaje se deshace individualmente
+    pub fn from_config(config: &AppConfig) -> Option<Self> {
+        if config.tx_batch_size <= 1 || config.dry_run {
+            return None;
+        }
+        Some(Self {
+            batch_size: config.tx_batch_size,
+            max_age: Duration::from_millis(config.tx_batch_ms),
+            slots: (0..config.worker_count.max(1))
+                .map(|_| Mutex::new(None))
+                .collect(),
+        })
+    }
+
+    pub fn max_age(&self) -> Duration {
+        self.max_age
+    }
+
+    pub fn slot(&self, device_id: &str) -> &Mutex<Option<OpenBatch>> {
+        &self.slots[shard_index(device_id, self.slots.len())]
+    }
+
+    pub fn slots(&self) -> &[Mutex<Option<OpenBatch>>] {
+        &self.slots
+    }
+
+    pub fn should_commit(
+        &self,
+        destination: MessageDestination,
+        batch: &OpenBatch,
+        now: Instant,
+    ) -> bool {
+        should_commit(
+            destination,
+            batch.messages,
+            now.saturating_duration_since(batch.opened_at),
+            self.batch_size,
+            self.max_age,
+        )
+    }
+
+    pub fn is_expired(&self, batch: &OpenBatch, now: Instant) -> bool {
+        now.saturating_duration_since(batch.opened_at) >= self.max_age
+    }
+}
+
+/// Solo los puntos se acumulan. Encendidos, apagados y cualquier otro destino
+/// confirman de inmediato (con los puntos previos), así un límite de viaje
+/// nunca queda a medias ante una caída: como mucho se pierden los últimos
+/// puntos sin confirmar de un viaje abierto.
+pub fn should_commit(
+    destination: MessageDestination,
+    messages: usize,
+    age: Duration,
+    batch_size: usize,
+    max_age: Duration,
+) -> bool {
+    destination != MessageDestination::TripPoint || messages >= batch_size || age >= max_age
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SIZE: usize = 50;
+    const MAX_AGE: Duration = Duration::from_millis(500);
+
+    #[test]
+    fn test_points_accumulate_until_size_or_age() {
+        let young = Duration::from_millis(10);
+
+        assert!(!should_commit(
+            MessageDestination::TripPoint,
+            1,
+            young,
+            SIZE,
+            MAX_AGE
+        ));
+        assert!(!should_commit(
+            MessageDestination::TripPoint,
+            SIZE - 1,
+            young,
+            SIZE,
+            MAX_AGE
+        ));
+        assert!(should_commit(
+            MessageDestination::TripPoint,
+            SIZE,
+            young,
+            SIZE,
+            MAX_AGE
+        ));
+        assert!(should_commit(
+            MessageDestination::TripPoint,
+            1,
+            MAX_AGE,
+            SIZE,
+            MAX_AGE
+        ));
+    }
+
+    #[test]
+    fn test_ignition_forces_commit() {
+        for destination in [
+            MessageDestination::NewTrip,
+            MessageDestination::EndTrip,
+            MessageDestination::IgnoredIgnitionOn,
+            MessageDestination::IgnoredIgnitionOff,
+        ] {
+            assert!(
+                should_commit(destination, 1, Duration::ZERO, SIZE, MAX_AGE),
+                "{:?} must commit immediately",
+                destination
+            );
+        }
+    }
+
+    #[test]
+    fn test_non_point_destinations_commit() {
+        assert!(should_commit(
+            MessageDestination::TripAlert,
+            1,
+            Duration::ZERO,
+            SIZE,
+            MAX_AGE
+        ));
+        assert!(should_commit(
+            MessageDestination::IdleActivity,
+            1,
+            Duration::ZERO,
+            SIZE,
+            MAX_AGE
+        ));
+    }
+
+    #[test]
+    fn test_disabled_without_batch_size_or_in_dry_run() {
+        let mut config = AppConfig::development();
+        assert!(TxBatcher::from_config(&config).is_none());
+
+        config.tx_batch_size = 100;
+        assert!(TxBatcher::from_config(&config).is_some());
+
+        config.dry_run = true;
+        assert!(TxBatcher::from_config(&config).is_none());
+    }
+}
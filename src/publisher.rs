@@ -0,0 +1,94 @@
+use crate::config::AppConfig;
+use crate::db::sharding::ShardedPool;
+use crate::db::{queries, DbPool};
+use crate::event_sink::{EmitFuture, EventSink, OutboundEvent};
+use crate::kafka::base_client_config;
+use crate::models::proto::trip_to_proto;
+use crate::models::trip::Trip;
+use prost::Message;
+use rdkafka::message::{Header, OwnedHeaders};
+use rdkafka::producer::{FutureProducer, FutureRecord};
+use std::time::Duration;
+use tracing::{debug, error, warn};
+use uuid::Uuid;
+
+/// Publishes trips as `siscom.v1.Trip` protobuf messages to Kafka: completed
+/// trips to `KAFKA_TRIP_OUTPUT_TOPIC` and, as soon as they begin, started trips
+/// (no `end_epoch`) to `KAFKA_TRIP_STARTED_TOPIC`. The `event` header tells them apart.
+/// The trip is loaded from the device's shard when the event is emitted.
+pub struct TripPublisher {
+    producer: FutureProducer,
+    pools: ShardedPool,
+    topic: String,
+    started_topic: String,
+}
+
+impl TripPublisher {
+    /// Builds a publisher when either output topic is configured.
+    pub fn from_config(config: &AppConfig, pools: &ShardedPool) -> anyhow::Result<Option<Self>> {
+        if config.kafka_trip_output_topic.is_empty() && config.kafka_trip_started_topic.is_empty() {
+            return Ok(None);
+        }
+        let producer: FutureProducer = base_client_config(config).create()?;
+        Ok(Some(Self {
+            producer,
+            pools: pools.clone(),
+            topic: config.kafka_trip_output_topic.clone(),
+            started_topic: config.kafka_trip_started_topic.clone(),
+        }))
+    }
+
+    /// Loads the trip and publishes it, keyed by device id.
+    async fn publish(
+        &self,
+        pool: &DbPool,
+        trip_id: Uuid,
+        topic: &str,
+        event: &str,
+    ) -> anyhow::Result<()> {
+        if topic.is_empty() {
+            return Ok(());
+        }
+        let trip: Option<Trip> = sqlx::query_as(queries::SELECT_TRIP_BY_ID)
+            .bind(trip_id)
+            .fetch_optional(pool)
+            .await?;
+        let Some(trip) = trip else {
+            warn!("Trip {} not found for publishing", trip_id);
+            return Ok(());
+        };
+
+        let payload = trip_to_proto(&trip).encode_to_vec();
+        let headers = OwnedHeaders::new().insert(Header {
+            key: "event",
+            value: Some(event),
+        });
+        let record = FutureRecord::to(topic)
+            .key(&trip.device_id)
+            .payload(&payload)
+            .headers(headers);
+        self.producer
+            .send(record, Duration::from_secs(5))
+            .await
+            .map_err(|(e, _)| e)?;
+
+        debug!("Published {} {} to {}", event, trip_id, topic);
+        Ok(())
+    }
+}
+
+impl EventSink for TripPublisher {
+    fn emit<'a>(&'a self, event: &'a OutboundEvent) -> EmitFuture<'a> {
+        Box::pin(async move {
+            let topic = match event.name {
+                "trip_started" => &self.started_topic,
+                "trip_completed" => &self.topic,
+                _ => return,
+            };
+            let pool = self.pools.for_device(&event.key);
+            if let Err(e) = self.publish(pool, event.trip_id, topic, event.name).await {
+                error!("Failed to publish {} {}: {}", event.name, event.trip_id, e);
+            }
+        })
+    }
+}
@@ -0,0 +1,75 @@
+use std::time::{Duration, Instant};
+
+/// Tracks when the last message was received so the consumer can detect a
+/// stale connection that never reports an error.
+pub struct Watchdog {
+    timeout: Duration,
+    last_message_at: Instant,
+}
+
+impl Watchdog {
+    pub fn new(timeout: Duration, now: Instant) -> Self {
+        Self {
+            timeout,
+            last_message_at: now,
+        }
+    }
+
+    /// Records activity, restarting the staleness timer.
+    pub fn touch(&mut self, now: Instant) {
+        self.last_message_at = now;
+    }
+
+    #[cfg(test)]
+    pub fn is_stale(&self, now: Instant) -> bool {
+        now.saturating_duration_since(self.last_message_at) >= self.timeout
+    }
+
+    /// Time left before the connection is considered stale.
+    pub fn remaining(&self, now: Instant) -> Duration {
+        self.timeout
+            .saturating_sub(now.saturating_duration_since(self.last_message_at))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_not_stale_before_timeout() {
+        let start = Instant::now();
+        let watchdog = Watchdog::new(Duration::from_secs(60), start);
+
+        assert!(!watchdog.is_stale(start));
+        assert!(!watchdog.is_stale(start + Duration::from_secs(59)));
+        assert_eq!(
+            watchdog.remaining(start + Duration::from_secs(20)),
+            Duration::from_secs(40)
+        );
+    }
+
+    #[test]
+    fn test_stale_after_timeout() {
+        let start = Instant::now();
+        let watchdog = Watchdog::new(Duration::from_secs(60), start);
+
+        assert!(watchdog.is_stale(start + Duration::from_secs(60)));
+        assert!(watchdog.is_stale(start + Duration::from_secs(120)));
+        assert_eq!(
+            watchdog.remaining(start + Duration::from_secs(120)),
+            Duration::ZERO
+        );
+    }
+
+    #[test]
+    fn test_touch_resets_timer() {
+        let start = Instant::now();
+        let mut watchdog = Watchdog::new(Duration::from_secs(60), start);
+
+        watchdog.touch(start + Duration::from_secs(50));
+
+        assert!(!watchdog.is_stale(start + Duration::from_secs(100)));
+        assert!(watchdog.is_stale(start + Duration::from_secs(110)));
+    }
+}
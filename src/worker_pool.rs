@@ -0,0 +1,182 @@
+use crate::db::sharding::shard_index;
+use std::future::Future;
+use std::pin::Pin;
+use tokio::sync::mpsc;
+#[cfg(test)]
+use tokio::task::JoinHandle;
+
+/// Fixed set of worker tasks fed by bounded channels. Items with the same
+/// key always go to the same worker, so per-device order is preserved, and a
+/// full queue makes `dispatch` wait instead of growing memory.
+pub struct WorkerPool<T> {
+    senders: Vec<mpsc::Sender<T>>,
+    #[cfg(test)]
+    handles: Vec<JoinHandle<()>>,
+    /// `PROCESS_INLINE`: no workers, `dispatch` runs the handler itself
+    inline: Option<InlineHandler<T>>,
+}
+
+type InlineHandler<T> = Box<dyn Fn(T) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync>;
+
+impl<T: Send + 'static> WorkerPool<T> {
+    pub fn spawn<F, Fut>(workers: usize, queue_capacity: usize, handler: F) -> Self
+    where
+        F: Fn(T) -> Fut + Clone + Send + 'static,
+        Fut: Future<Output = ()> + Send,
+    {
+        let workers = workers.max(1);
+        let mut senders = Vec::with_capacity(workers);
+        #[cfg(test)]
+        let mut handles = Vec::with_capacity(workers);
+        for _ in 0..workers {
+            let (tx, mut rx) = mpsc::channel::<T>(queue_capacity.max(1));
+            let handler = handler.clone();
+            let handle = tokio::spawn(async move {
+                while let Some(item) = rx.recv().await {
+                    handler(item).await;
+                }
+            });
+            #[cfg(test)]
+            handles.push(handle);
+            // Without `shutdown` there is nothing to join: the worker is detached
+            #[cfg(not(test))]
+            drop(handle);
+            senders.push(tx);
+        }
+        Self {
+            senders,
+            #[cfg(test)]
+            handles,
+            inline: None,
+        }
+    }
+
+    /// Pool without workers: each `dispatch` awaits the handler before
+    /// returning, so messages are processed strictly in arrival order.
+    pub fn inline<F, Fut>(handler: F) -> Self
+    where
+        F: Fn(T) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        Self {
+            senders: Vec::new(),
+            #[cfg(test)]
+            handles: Vec::new(),
+            inline: Some(Box::new(move |item| Box::pin(handler(item)))),
+        }
+    }
+
+    /// Queues an item on the worker owning `key`, waiting while that queue is full.
+    pub async fn dispatch(&self, key: &str, item: T) -> anyhow::Result<()> {
+        if let Some(handler) = &self.inline {
+            handler(item).await;
+            return Ok(());
+        }
+        let worker = shard_index(key, self.senders.len());
+        self.senders[worker]
+            .send(item)
+            .await
+            .map_err(|_| anyhow::anyhow!("worker {} stopped", worker))
+    }
+
+    /// Closes the queues and waits for the workers to drain them.
+    #[cfg(test)]
+    pub async fn shutdown(self) {
+        drop(self.senders);
+        for handle in self.handles {
+            let _ = handle.await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+    use tokio::sync::Notify;
+
+    #[tokio::test]
+    async fn test_same_key_is_processed_in_order() {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let recorder = seen.clone();
+        let pool = WorkerPool::spawn(4, 8, move |payload: Vec<u8>| {
+            let recorder = recorder.clone();
+            async move {
+                // Jitter between messages so a reordering would show up
+                tokio::time::sleep(Duration::from_millis(u64::from(payload[1] % 3))).await;
+                recorder.lock().unwrap().push((payload[0], payload[1]));
+            }
+        });
+
+        for seq in 0..50u8 {
+            for device in 0..3u8 {
+                pool.dispatch(&format!("DEV-{}", device), vec![device, seq])
+                    .await
+                    .unwrap();
+            }
+        }
+        pool.shutdown().await;
+
+        let seen = seen.lock().unwrap();
+        assert_eq!(seen.len(), 150);
+        for device in 0..3u8 {
+            let order: Vec<u8> = seen
+                .iter()
+                .filter(|(d, _)| *d == device)
+                .map(|(_, seq)| *seq)
+                .collect();
+            assert_eq!(order, (0..50).collect::<Vec<u8>>());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_inline_processes_in_arrival_order() {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let recorder = seen.clone();
+        let pool = WorkerPool::inline(move |payload: Vec<u8>| {
+            let recorder = recorder.clone();
+            async move {
+                // Earlier messages take longer; only inline processing keeps them first
+                tokio::time::sleep(Duration::from_millis(u64::from(10 - payload[0]))).await;
+                recorder.lock().unwrap().push(payload[0]);
+            }
+        });
+
+        for seq in 0..10u8 {
+            pool.dispatch(&format!("DEV-{}", seq), vec![seq])
+                .await
+                .unwrap();
+            // Already processed when dispatch returns
+            assert_eq!(seen.lock().unwrap().last(), Some(&seq));
+        }
+        pool.shutdown().await;
+
+        assert_eq!(*seen.lock().unwrap(), (0..10).collect::<Vec<u8>>());
+    }
+
+    #[tokio::test]
+    async fn test_dispatch_waits_when_queue_is_full() {
+        let release = Arc::new(Notify::new());
+        let gate = release.clone();
+        let pool = WorkerPool::spawn(1, 1, move |_payload: Vec<u8>| {
+            let gate = gate.clone();
+            async move { gate.notified().await }
+        });
+
+        // One held by the worker, one filling the queue
+        pool.dispatch("DEV", vec![1]).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        pool.dispatch("DEV", vec![2]).await.unwrap();
+
+        let blocked =
+            tokio::time::timeout(Duration::from_millis(50), pool.dispatch("DEV", vec![3])).await;
+        assert!(blocked.is_err(), "dispatch should wait on a full queue");
+
+        release.notify_one();
+        tokio::time::timeout(Duration::from_secs(1), pool.dispatch("DEV", vec![3]))
+            .await
+            .expect("dispatch should proceed once the worker drains")
+            .unwrap();
+    }
+}